@@ -1,9 +1,9 @@
 extern crate bindgen;
-use std::process::Command;
 use std::env;
 use std::path::PathBuf;
-use std::fs;
 
+#[cfg(target_os = "windows")]
+use std::{fs, process::Command};
 #[cfg(target_os = "windows")]
 use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
 
@@ -22,10 +22,18 @@ fn main() {
         .derive_default(true)
         
         .allowlist_function("tsk_error_get")
+        .allowlist_function("tsk_error_reset")
+        .allowlist_function("tsk_error_set_errno")
+        .allowlist_function("tsk_error_set_errstr")
+        .allowlist_function("tsk_fprintf")
+        .allowlist_var("TSK_ERR_IMG_.*")
         
         .allowlist_function("tsk_img_open_utf8_sing")
+        .allowlist_function("tsk_img_open_utf8")
         .allowlist_function("tsk_img_open_external")
         .allowlist_function("tsk_img_close")
+        .allowlist_function("tsk_img_read")
+        .allowlist_function("tsk_img_type_supported")
 
         .allowlist_function("tsk_vs_open")
         .allowlist_function("tsk_vs_close")
@@ -52,6 +60,9 @@ fn main() {
         .allowlist_function("tsk_fs_dir_close")
         .allowlist_function("tsk_fs_dir_get_name")
         
+        .allowlist_type("TSK_IMG_TYPE_ENUM")
+        .allowlist_type("TSK_VS_TYPE_ENUM")
+        .allowlist_type("TSK_VS_PART_FLAG_ENUM")
         .allowlist_type("TSK_FS_TYPE_ENUM")
         .allowlist_type("TSK_FS_META_FLAG_ENUM")
         .allowlist_type("TSK_FS_ATTR_TYPE_ENUM")
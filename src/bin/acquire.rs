@@ -0,0 +1,500 @@
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use clap::{App, Arg, ArgMatches};
+use tsk::img_error_map::{BadSectorPolicy, BadSectorReader, ErrorMap};
+use tsk::img_hash::{HashAlgorithm, ImageHash, ImageHasher};
+use tsk::tsk_img::TskImg;
+use tsk::tsk_img_reader::ReadSeek;
+
+static VERSION: &str = "0.1.0";
+
+
+/// Parse a size with an optional K, M, G or T (1024 based) suffix
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1u64 << 10),
+        'M' => (&value[..value.len() - 1], 1u64 << 20),
+        'G' => (&value[..value.len() - 1], 1u64 << 30),
+        'T' => (&value[..value.len() - 1], 1u64 << 40),
+        _ => (value, 1)
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+
+fn is_a_size(value: String) -> Result<(), String> {
+    match parse_size(&value) {
+        Some(size) if size > 0 => Ok(()),
+        _ => Err("Expected a positive size (e.g. 4096, 64K, 2G).".to_owned()),
+    }
+}
+
+
+fn is_a_non_negative_number(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err("Expected value to be a positive number.".to_owned()),
+    }
+}
+
+
+/// Create and return an App that is used to parse the command line params
+/// that were specified by the user.
+///
+fn get_argument_parser<'a, 'b>() -> App<'a, 'b> {
+    let source_arg = Arg::with_name("source")
+        .short("-s")
+        .long("source")
+        .required(true)
+        .value_name("SOURCE")
+        .takes_value(true)
+        .help("The block device or file to acquire (opened read-only).");
+
+    let output_arg = Arg::with_name("output")
+        .short("-o")
+        .long("output")
+        .required(true)
+        .value_name("OUTPUT")
+        .takes_value(true)
+        .help("The raw output image. Split images get .001, .002, ... appended.");
+
+    let split_arg = Arg::with_name("split")
+        .long("split")
+        .value_name("SIZE")
+        .takes_value(true)
+        .validator(is_a_size)
+        .help("Split the output into segments of SIZE bytes (e.g. 2G).");
+
+    let hash_arg = Arg::with_name("hash")
+        .long("hash")
+        .value_name("ALGORITHM")
+        .takes_value(true)
+        .multiple(true)
+        .use_delimiter(true)
+        .possible_values(&["md5", "sha1", "sha256"])
+        .default_value("md5,sha1")
+        .help("The hashes to compute while reading.");
+
+    let sector_size_arg = Arg::with_name("sector_size")
+        .long("sector-size")
+        .value_name("BYTES")
+        .takes_value(true)
+        .default_value("512")
+        .validator(is_a_size)
+        .help("The unit in which unreadable data is retried and zero-filled.");
+
+    let block_size_arg = Arg::with_name("block_size")
+        .long("block-size")
+        .value_name("BYTES")
+        .takes_value(true)
+        .default_value("1M")
+        .validator(is_a_size)
+        .help("The size of the reads of the source.");
+
+    let retries_arg = Arg::with_name("retries")
+        .long("retries")
+        .value_name("COUNT")
+        .takes_value(true)
+        .default_value("3")
+        .validator(is_a_non_negative_number)
+        .help("How often a failed read is retried before it is zero-filled.");
+
+    let log_arg = Arg::with_name("log")
+        .long("log")
+        .value_name("LOG")
+        .takes_value(true)
+        .help("The acquisition log (default: OUTPUT.log).");
+
+    let verify_arg = Arg::with_name("verify")
+        .long("verify")
+        .help("Open the written image as a TskImg and verify its hashes.");
+
+    App::new("acquire")
+        .version(VERSION)
+        .author("Matthew Seyer <https://github.com/forensicmatt/libtsk-rs>")
+        .about("Acquire a device or file into a hashed raw image.")
+        .arg(source_arg)
+        .arg(output_arg)
+        .arg(split_arg)
+        .arg(hash_arg)
+        .arg(sector_size_arg)
+        .arg(block_size_arg)
+        .arg(retries_arg)
+        .arg(log_arg)
+        .arg(verify_arg)
+}
+
+
+/// A stream that retries failed reads before giving up
+struct RetryStream {
+    inner: File,
+    retries: usize,
+    /// Number of reads that failed and were retried
+    retried: Arc<AtomicU64>
+}
+impl Read for RetryStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let offset = self.inner.stream_position()?;
+        let mut attempt = 0;
+        loop {
+            match self.inner.read(buf) {
+                Err(e) if e.kind() != std::io::ErrorKind::Interrupted && attempt < self.retries => {
+                    attempt += 1;
+                    self.retried.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("\nRead of {} bytes at offset {} failed ({}), retry {} of {}", buf.len(), offset, e, attempt, self.retries);
+                    self.inner.seek(SeekFrom::Start(offset))?;
+                },
+                result => return result
+            }
+        }
+    }
+}
+impl Seek for RetryStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+
+/// Writes the output image, starting a new segment every split_size bytes
+struct SegmentWriter {
+    output: PathBuf,
+    split_size: Option<u64>,
+    segments: Vec<PathBuf>,
+    current: Option<(File, u64)>
+}
+impl SegmentWriter {
+    fn new(output: &Path, split_size: Option<u64>) -> Self {
+        Self {
+            output: output.to_path_buf(),
+            split_size,
+            segments: Vec::new(),
+            current: None
+        }
+    }
+
+    /// Create the next segment. Existing files are never overwritten.
+    fn next_segment(&mut self) -> std::io::Result<()> {
+        let path = match self.split_size {
+            Some(_) => {
+                let mut name = self.output.clone().into_os_string();
+                name.push(format!(".{:03}", self.segments.len() + 1));
+                PathBuf::from(name)
+            },
+            None => self.output.clone()
+        };
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        self.segments.push(path);
+        self.current = Some((file, 0));
+        Ok(())
+    }
+
+    fn write_all(&mut self, mut data: &[u8]) -> std::io::Result<()> {
+        while !data.is_empty() {
+            let full = match (&self.current, self.split_size) {
+                (None, _) => true,
+                (Some((_, written)), Some(split_size)) => *written >= split_size,
+                (Some(_), None) => false
+            };
+            if full {
+                self.finish_segment()?;
+                self.next_segment()?;
+            }
+
+            let (file, written) = self.current.as_mut().unwrap();
+            let len = match self.split_size {
+                Some(split_size) => ((split_size - *written) as usize).min(data.len()),
+                None => data.len()
+            };
+            file.write_all(&data[..len])?;
+            *written += len as u64;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    fn finish_segment(&mut self) -> std::io::Result<()> {
+        if let Some((file, _)) = self.current.take() {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Flush the last segment and return the paths of all segments
+    fn finish(mut self) -> std::io::Result<Vec<PathBuf>> {
+        if self.segments.is_empty() {
+            // An empty source still results in an (empty) image
+            self.next_segment()?;
+        }
+        self.finish_segment()?;
+        Ok(self.segments)
+    }
+}
+
+
+/// Format a time as an RFC 3339 UTC timestamp, e.g. 2024-05-01T13:37:00Z
+fn rfc3339(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, second_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // The civil date of a day count since 1970-01-01, in eras of 400 years
+    // starting on March 1st (see Howard Hinnant's chrono-compatible
+    // date algorithms)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day,
+        second_of_day / 3600, second_of_day / 60 % 60, second_of_day % 60
+    )
+}
+
+
+/// Everything that goes into the acquisition log
+struct Acquisition {
+    source: String,
+    size: u64,
+    sector_size: u32,
+    segments: Vec<PathBuf>,
+    hashes: Vec<ImageHash>,
+    error_map: ErrorMap,
+    retried_reads: u64,
+    started: SystemTime,
+    finished: SystemTime,
+    verified: Option<Result<bool, String>>
+}
+impl Acquisition {
+    fn to_log(&self) -> String {
+        let mut log = String::new();
+        log.push_str(&format!("acquire {}\n\n", VERSION));
+        log.push_str(&format!("Source:\t\t{}\n", self.source));
+        log.push_str(&format!("Size in bytes:\t{}\n", self.size));
+        log.push_str(&format!("Sector size:\t{}\n", self.sector_size));
+        log.push_str(&format!("Started:\t{}\n", rfc3339(self.started)));
+        log.push_str(&format!("Finished:\t{}\n", rfc3339(self.finished)));
+
+        log.push_str("\nOutput segments:\n");
+        for segment in &self.segments {
+            log.push_str(&format!("  {}\n", segment.display()));
+        }
+
+        log.push_str("\nHashes:\n");
+        for hash in &self.hashes {
+            log.push_str(&format!("  {}:\t{}\n", hash.algorithm, hash.value));
+        }
+
+        log.push_str(&format!("\nRetried reads:\t{}\n", self.retried_reads));
+        log.push_str(&format!("Zero-filled bytes:\t{}\n", self.error_map.total_bytes()));
+        for range in self.error_map.ranges() {
+            log.push_str(&format!(
+                "  offset {} length {} (sectors {}-{}): {}\n",
+                range.offset,
+                range.length,
+                range.offset / self.sector_size as u64,
+                (range.end() - 1) / self.sector_size as u64,
+                range.error
+            ));
+        }
+
+        let verification = match &self.verified {
+            None => "not requested".to_string(),
+            Some(Ok(true)) => "verified".to_string(),
+            Some(Ok(false)) => "FAILED: the hashes of the image do not match".to_string(),
+            Some(Err(e)) => format!("FAILED: {}", e)
+        };
+        log.push_str(&format!("\nVerification:\t{}\n", verification));
+        log
+    }
+}
+
+
+/// The acquisition log of an acquisition that failed after it started
+fn failure_log(source: &str, started: SystemTime, error: &str) -> String {
+    let mut log = String::new();
+    log.push_str(&format!("acquire {}\n\n", VERSION));
+    log.push_str(&format!("Source:\t\t{}\n", source));
+    log.push_str(&format!("Started:\t{}\n", rfc3339(started)));
+    log.push_str(&format!("Failed:\t\t{}\n", rfc3339(SystemTime::now())));
+    log.push_str(&format!("\nError:\t{}\n", error));
+    log
+}
+
+
+/// Open the written image through libtsk and compare its hashes
+fn verify_image(segments: &[PathBuf], hashes: &[ImageHash]) -> Result<bool, String> {
+    let tsk_img = match segments {
+        [single] => TskImg::from_utf8_sing(single),
+        _ => TskImg::from_utf8_split_discover(&segments[0])
+    }.map_err(|e| format!("Error opening the image: {}", e.message))?;
+
+    let verification = tsk_img.verify(hashes, |p| {
+        if let Some(fraction) = p.fraction() {
+            eprint!("\rVerifying: {:5.1}%", fraction * 100.0);
+        }
+        true
+    }).map_err(|e| format!("Error verifying the image: {}", e.message))?;
+    eprintln!();
+    Ok(verification.is_verified())
+}
+
+
+fn acquire(options: &ArgMatches) -> Result<bool, String> {
+    let source = options.value_of("source").expect("No source was provided!");
+    let output = PathBuf::from(options.value_of("output").expect("No output was provided!"));
+    let size_option = |name: &str| options.value_of(name).and_then(parse_size);
+    let split_size = size_option("split");
+    let sector_size = size_option("sector_size").expect("used validator");
+    let sector_size: u32 = sector_size.try_into()
+        .map_err(|_| format!("The sector size {} is too large.", sector_size))?;
+    let block_size = size_option("block_size").expect("used validator") as usize;
+    let retries = options.value_of("retries")
+        .map(|value| value.parse::<usize>().expect("used validator"))
+        .expect("no retries");
+    let algorithms: Vec<HashAlgorithm> = options.values_of("hash")
+        .expect("no hash")
+        .filter_map(HashAlgorithm::from_name)
+        .collect();
+    let log_path = options.value_of("log")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            let mut name = output.clone().into_os_string();
+            name.push(".log");
+            PathBuf::from(name)
+        });
+
+    let started = SystemTime::now();
+    let file = File::open(source)
+        .map_err(|e| format!("Error opening {}: {}", source, e))?;
+    let retried = Arc::new(AtomicU64::new(0));
+    let mut stream = RetryStream { inner: file, retries, retried: retried.clone() };
+    // Block devices report their size when seeking to the end
+    let size = ReadSeek::stream_len(&mut stream)
+        .map_err(|e| format!("Error getting the size of {}: {}", source, e))?;
+
+    // The log is created once the source could be opened, so that a wrong
+    // source leaves nothing behind, and before anything is written, so that
+    // an existing log is never overwritten. Failures from here on are
+    // written into it.
+    let mut log_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&log_path)
+        .map_err(|e| format!("Error creating the log {}: {}", log_path.display(), e))?;
+
+    let result = (|| {
+        let mut reader = BadSectorReader::new(Box::new(stream), size, sector_size, BadSectorPolicy::ZeroFill)
+            .map_err(|e| e.message)?;
+        let mut hasher = ImageHasher::new(&algorithms);
+        let mut writer = SegmentWriter::new(&output, split_size);
+
+        let timer = Instant::now();
+        let mut buffer = vec![0u8; block_size];
+        let mut done: u64 = 0;
+        loop {
+            let bytes_read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("Error reading {} at offset {}: {}", source, done, e))
+            };
+            hasher.update(&buffer[..bytes_read]);
+            writer.write_all(&buffer[..bytes_read])
+                .map_err(|e| format!("Error writing the image: {}", e))?;
+            done += bytes_read as u64;
+
+            let seconds = timer.elapsed().as_secs_f64().max(0.001);
+            eprint!(
+                "\rAcquired {} of {} bytes ({:5.1}%, {:.1} MiB/s)",
+                done, size,
+                if size == 0 { 100.0 } else { done as f64 * 100.0 / size as f64 },
+                done as f64 / seconds / (1024.0 * 1024.0)
+            );
+        }
+        eprintln!();
+        if done != size {
+            return Err(format!("{} ended after {} of {} bytes.", source, done, size));
+        }
+
+        let segments = writer.finish()
+            .map_err(|e| format!("Error writing the image: {}", e))?;
+        let hashes = hasher.finalize();
+        for hash in &hashes {
+            println!("{}: {}", hash.algorithm, hash.value);
+        }
+
+        let verified = if options.is_present("verify") {
+            Some(verify_image(&segments, &hashes))
+        } else {
+            None
+        };
+
+        Ok(Acquisition {
+            source: source.to_string(),
+            size,
+            sector_size,
+            segments,
+            hashes,
+            error_map: reader.error_map(),
+            retried_reads: retried.load(Ordering::Relaxed),
+            started,
+            finished: SystemTime::now(),
+            verified
+        })
+    })();
+
+    let log = match &result {
+        Ok(acquisition) => acquisition.to_log(),
+        Err(e) => failure_log(source, started, e)
+    };
+    log_file.write_all(log.as_bytes())
+        .map_err(|e| format!("Error writing the log {}: {}", log_path.display(), e))?;
+    eprintln!("Acquisition log written to {}", log_path.display());
+
+    let acquisition = result?;
+    if !acquisition.error_map.is_empty() {
+        eprintln!(
+            "{} unreadable bytes in {} ranges were zero-filled.",
+            acquisition.error_map.total_bytes(),
+            acquisition.error_map.ranges().len()
+        );
+    }
+    Ok(!matches!(acquisition.verified, Some(Ok(false)) | Some(Err(_))))
+}
+
+
+fn main() {
+    let arg_parser = get_argument_parser();
+    let options = arg_parser.get_matches();
+
+    match acquire(&options) {
+        Ok(true) => {},
+        Ok(false) => {
+            eprintln!("Verification of the image failed.");
+            std::process::exit(2);
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
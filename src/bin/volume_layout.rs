@@ -1,5 +1,6 @@
 use clap::{App, Arg};
 use tsk::tsk_img::TskImg;
+use tsk::tsk_vs::TskVsType;
 
 static VERSION: &str = "0.1.0";
 
@@ -16,11 +17,21 @@ fn get_argument_parser<'a, 'b>() -> App<'a, 'b> {
         .takes_value(true)
         .help("The source");
 
+    let vs_type_arg = Arg::with_name("vs_type")
+        .short("-t")
+        .long("vs-type")
+        .value_name("TYPE")
+        .takes_value(true)
+        .possible_values(&["detect", "dos", "mbr", "bsd", "sun", "mac", "apm", "gpt", "apfs"])
+        .default_value("detect")
+        .help("The volume system type (use gpt to skip the MBR of a hybrid MBR/GPT disk)");
+
     App::new("volume_layout")
         .version(VERSION)
         .author("Matthew Seyer <https://github.com/forensicmatt/libtsk-rs>")
         .about("Print the layout of a given source.")
         .arg(source_arg)
+        .arg(vs_type_arg)
 }
 
 
@@ -32,8 +43,14 @@ fn main() {
     let tsk_img = TskImg::from_utf8_sing(source_location)
         .expect("Could not create TskImg");
 
-    let tsk_vs = tsk_img.get_vs_from_offset(0)
+    let vs_type = options.value_of("vs_type")
+        .and_then(TskVsType::from_name)
+        .expect("Invalid volume system type");
+
+    let tsk_vs = tsk_img.get_vs_from_offset_with_type(0, vs_type)
         .expect("Could not open TskVs at offset 0");
+    println!("{:?}", tsk_vs);
+
     let part_iter = tsk_vs.get_partition_iter()
         .expect("Could not get partition iterator for TskVs");
 
@@ -6,6 +6,9 @@ pub enum ErrorType {
     TskFsAttr,
     TskFsName,
     TskFsDir,
+    TskVsPart,
+    ImgFormat,
+    Cancelled,
     Generic
 }
 #[derive(Debug)]
@@ -17,7 +20,7 @@ impl TskError {
     /// Error function for TskFsDir operations
     pub fn tsk_fs_dir_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::TskFsDir,
         }
     }
@@ -25,7 +28,7 @@ impl TskError {
     /// Error function for TskFsName operations
     pub fn tsk_fs_name_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::TskFsName,
         }
     }
@@ -33,15 +36,39 @@ impl TskError {
     /// Error function for TskFsAttr operations
     pub fn tsk_attr_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::TskFsAttr,
         }
     }
 
+    /// Error function for TskVsPart operations
+    pub fn tsk_vs_part_error(message: String) -> Self {
+        Self {
+            message,
+            kind: ErrorType::TskVsPart,
+        }
+    }
+
+    /// Error function for image format readers
+    pub fn img_format_error(message: String) -> Self {
+        Self {
+            message,
+            kind: ErrorType::ImgFormat,
+        }
+    }
+
+    /// Error function for operations cancelled by the caller
+    pub fn cancelled(message: String) -> Self {
+        Self {
+            message,
+            kind: ErrorType::Cancelled,
+        }
+    }
+
     /// A Generic error
     pub fn generic(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::Generic,
         }
     }
@@ -49,7 +76,7 @@ impl TskError {
     /// Error originating form a lib tsk call
     pub fn lib_tsk_error(message: String) -> Self {
         Self {
-            message: message,
+            message,
             kind: ErrorType::LibTskError,
         }
     }
@@ -57,7 +84,7 @@ impl TskError {
         /// Error function for TskFsMeta operations
         pub fn tsk_fs_meta_error(message: String) -> Self {
             Self {
-                message: message,
+                message,
                 kind: ErrorType::TskFsMeta,
             }
         }
@@ -65,7 +92,7 @@ impl TskError {
         /// Error function for TskFsFile operations
         pub fn tsk_fs_file_error(message: String) -> Self {
             Self {
-                message: message,
+                message,
                 kind: ErrorType::TskFsFile,
             }
         }
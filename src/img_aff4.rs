@@ -0,0 +1,1068 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::{
+    read_exact_at,
+    file_size
+};
+use crate::img_turtle::{parse_turtle, Term, RDF_TYPE};
+
+
+/// The AFF4 standard namespace
+const AFF4_NAMESPACE: &str = "http://aff4.org/Schema#";
+/// The namespace used by older (pyaff4 legacy) volumes
+const AFF4_LEGACY_NAMESPACE: &str = "http://afflib.org/2009/aff4#";
+/// Zip record signatures
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR: u32 = 0x0706_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP_CENTRAL_DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+/// Size of a map entry (mapped offset, length, target offset, target id)
+const MAP_ENTRY_SIZE: usize = 28;
+/// Maximum depth of maps referring to other maps
+const MAX_STREAM_DEPTH: usize = 8;
+
+
+/// Get the local name of an AFF4 schema IRI
+fn aff4_local_name(iri: &str) -> Option<&str> {
+    iri.strip_prefix(AFF4_NAMESPACE)
+        .or_else(|| iri.strip_prefix(AFF4_LEGACY_NAMESPACE))
+}
+
+
+/// Decode %XX escapes of a zip member name
+fn percent_decode(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(byte) = hex {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+
+/// A member of the zip container
+#[derive(Debug, Clone)]
+struct ZipMember {
+    name: String,
+    method: u16,
+    compressed_size: u64,
+    size: u64,
+    local_header_offset: u64
+}
+impl ZipMember {
+    /// Read the whole (decompressed) member
+    fn read_all(&self, file: &mut File) -> std::io::Result<Vec<u8>> {
+        let data_offset = self.data_offset(file)?;
+        // The sizes come from the central directory, the stored data has to
+        // be in the container and deflate can not expand more than 1032:1
+        let container_size = file_size(file)?;
+        let in_container = data_offset.checked_add(self.compressed_size)
+            .is_some_and(|end| end <= container_size);
+        if !in_container || self.size > self.compressed_size.saturating_mul(1032) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "zip member {} has invalid sizes ({} bytes compressed to {})",
+                    self.name, self.size, self.compressed_size
+                )
+            ));
+        }
+        let mut compressed = vec![0u8; self.compressed_size as usize];
+        read_exact_at(file, data_offset, &mut compressed)?;
+        match self.method {
+            0 => Ok(compressed),
+            8 => {
+                let mut data = Vec::with_capacity(self.size as usize);
+                DeflateDecoder::new(&compressed[..]).take(self.size + 1).read_to_end(&mut data)?;
+                if data.len() as u64 > self.size {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("zip member {} inflates past its size of {} bytes", self.name, self.size)
+                    ));
+                }
+                Ok(data)
+            },
+            method => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("zip member {} uses unsupported compression method {}", self.name, method)
+            ))
+        }
+    }
+
+    /// Offset of the member data, after the local header
+    fn data_offset(&self, file: &mut File) -> std::io::Result<u64> {
+        let mut header = [0u8; 30];
+        read_exact_at(file, self.local_header_offset, &mut header)?;
+        if u32::from_le_bytes(header[0..4].try_into().unwrap()) != ZIP_LOCAL_HEADER {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid local header for zip member {}", self.name)
+            ));
+        }
+        let name_length = u16::from_le_bytes(header[26..28].try_into().unwrap()) as u64;
+        let extra_length = u16::from_le_bytes(header[28..30].try_into().unwrap()) as u64;
+        Ok(self.local_header_offset + 30 + name_length + extra_length)
+    }
+}
+
+
+/// Read the central directory of a zip file
+fn read_zip_directory(file: &mut File) -> std::io::Result<(Vec<ZipMember>, String)> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+    let size = file_size(file)?;
+    let tail_size = size.min(22 + 0xffff);
+    let mut tail = vec![0u8; tail_size as usize];
+    read_exact_at(file, size - tail_size, &mut tail)?;
+
+    let eocd = (0..tail.len().saturating_sub(21)).rev()
+        .find(|i| u32::from_le_bytes(tail[*i..*i + 4].try_into().unwrap()) == ZIP_END_OF_CENTRAL_DIRECTORY)
+        .ok_or_else(|| invalid("no zip end of central directory record"))?;
+    let record = &tail[eocd..];
+    let comment_length = u16::from_le_bytes(record[20..22].try_into().unwrap()) as usize;
+    let comment = String::from_utf8_lossy(&record[22..(22 + comment_length).min(record.len())]).to_string();
+    let mut entry_count = u16::from_le_bytes(record[10..12].try_into().unwrap()) as u64;
+    let mut directory_size = u32::from_le_bytes(record[12..16].try_into().unwrap()) as u64;
+    let mut directory_offset = u32::from_le_bytes(record[16..20].try_into().unwrap()) as u64;
+
+    let eocd_offset = size - tail_size + eocd as u64;
+    if (entry_count == 0xffff || directory_offset == 0xffff_ffff) && eocd_offset >= 20 {
+        let mut locator = [0u8; 20];
+        read_exact_at(file, eocd_offset - 20, &mut locator)?;
+        if u32::from_le_bytes(locator[0..4].try_into().unwrap()) == ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR {
+            let zip64_offset = u64::from_le_bytes(locator[8..16].try_into().unwrap());
+            let mut zip64 = [0u8; 56];
+            read_exact_at(file, zip64_offset, &mut zip64)?;
+            if u32::from_le_bytes(zip64[0..4].try_into().unwrap()) != ZIP64_END_OF_CENTRAL_DIRECTORY {
+                return Err(invalid("invalid zip64 end of central directory record"));
+            }
+            entry_count = u64::from_le_bytes(zip64[32..40].try_into().unwrap());
+            directory_size = u64::from_le_bytes(zip64[40..48].try_into().unwrap());
+            directory_offset = u64::from_le_bytes(zip64[48..56].try_into().unwrap());
+        }
+    }
+    if directory_offset + directory_size > size {
+        return Err(invalid("zip central directory is outside of the file"));
+    }
+
+    let mut directory = vec![0u8; directory_size as usize];
+    read_exact_at(file, directory_offset, &mut directory)?;
+    let mut members = Vec::new();
+    let mut position = 0;
+    for _ in 0..entry_count {
+        let entry = directory.get(position..position + 46)
+            .ok_or_else(|| invalid("truncated zip central directory"))?;
+        if u32::from_le_bytes(entry[0..4].try_into().unwrap()) != ZIP_CENTRAL_DIRECTORY_ENTRY {
+            return Err(invalid("invalid zip central directory entry"));
+        }
+        let method = u16::from_le_bytes(entry[10..12].try_into().unwrap());
+        let mut compressed_size = u32::from_le_bytes(entry[20..24].try_into().unwrap()) as u64;
+        let mut member_size = u32::from_le_bytes(entry[24..28].try_into().unwrap()) as u64;
+        let name_length = u16::from_le_bytes(entry[28..30].try_into().unwrap()) as usize;
+        let extra_length = u16::from_le_bytes(entry[30..32].try_into().unwrap()) as usize;
+        let comment_length = u16::from_le_bytes(entry[32..34].try_into().unwrap()) as usize;
+        let mut local_header_offset = u32::from_le_bytes(entry[42..46].try_into().unwrap()) as u64;
+
+        let name_start = position + 46;
+        let extra_start = name_start + name_length;
+        let next = extra_start + extra_length + comment_length;
+        if next > directory.len() {
+            return Err(invalid("truncated zip central directory"));
+        }
+        let name = String::from_utf8_lossy(&directory[name_start..extra_start]).to_string();
+
+        // The zip64 extra field holds the values that did not fit, in order
+        let mut extra = &directory[extra_start..extra_start + extra_length];
+        while extra.len() >= 4 {
+            let id = u16::from_le_bytes(extra[0..2].try_into().unwrap());
+            let length = (u16::from_le_bytes(extra[2..4].try_into().unwrap()) as usize).min(extra.len() - 4);
+            if id == 0x0001 {
+                let mut values = extra[4..4 + length].chunks_exact(8)
+                    .map(|v| u64::from_le_bytes(v.try_into().unwrap()));
+                for value in [&mut member_size, &mut compressed_size, &mut local_header_offset].iter_mut() {
+                    if **value == 0xffff_ffff {
+                        if let Some(v) = values.next() {
+                            **value = v;
+                        }
+                    }
+                }
+            }
+            extra = &extra[4 + length..];
+        }
+
+        members.push(ZipMember {
+            name,
+            method,
+            compressed_size,
+            size: member_size,
+            local_header_offset
+        });
+        position = next;
+    }
+
+    Ok((members, comment))
+}
+
+
+/// A property of an AFF4 object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aff4Property {
+    /// The predicate IRI
+    pub predicate: String,
+    /// The value (an IRI or the literal text)
+    pub value: String,
+    /// The datatype IRI of a typed literal
+    pub datatype: Option<String>
+}
+
+
+/// A hash stored in the AFF4 metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aff4Hash {
+    /// The hash algorithm (MD5, SHA1, SHA256, SHA512, blake2b, ...)
+    pub algorithm: String,
+    /// The hash as hex
+    pub value: String
+}
+
+
+/// An object described in the AFF4 metadata (information.turtle)
+#[derive(Debug, Clone)]
+pub struct Aff4Object {
+    /// The URN of the object
+    pub urn: String,
+    /// The rdf types of the object
+    pub types: Vec<String>,
+    /// All other properties of the object
+    pub properties: Vec<Aff4Property>
+}
+impl Aff4Object {
+    /// Check for an AFF4 type by its local name (e.g. "ImageStream")
+    pub fn is_a(&self, aff4_type: &str) -> bool {
+        self.types.iter().any(|t| aff4_local_name(t) == Some(aff4_type))
+    }
+
+    /// All values of an AFF4 property by its local name (e.g. "hash")
+    pub fn values(&self, name: &str) -> Vec<&Aff4Property> {
+        self.properties.iter()
+            .filter(|p| aff4_local_name(&p.predicate) == Some(name))
+            .collect()
+    }
+
+    /// The first value of an AFF4 property by its local name
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.properties.iter()
+            .find(|p| aff4_local_name(&p.predicate) == Some(name))
+            .map(|p| p.value.as_str())
+    }
+
+    /// The first value of an AFF4 property parsed as an integer
+    pub fn value_u64(&self, name: &str) -> Option<u64> {
+        self.value(name).and_then(|v| v.trim().parse().ok())
+    }
+
+    /// The size (aff4:size)
+    pub fn size(&self) -> Option<u64> {
+        self.value_u64("size")
+    }
+
+    /// The stored hashes (aff4:hash), named after their datatype
+    pub fn hashes(&self) -> Vec<Aff4Hash> {
+        self.values("hash").into_iter()
+            .map(|p| Aff4Hash {
+                algorithm: p.datatype.as_deref()
+                    .map(|d| aff4_local_name(d).unwrap_or(d).to_string())
+                    .unwrap_or_default(),
+                value: p.value.to_lowercase()
+            })
+            .collect()
+    }
+}
+
+
+/// Compression of the chunks of an ImageStream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aff4Compression {
+    /// Chunks are stored as is
+    Stored,
+    /// Raw deflate (RFC 1951)
+    Deflate,
+    /// zlib (RFC 1950)
+    Zlib,
+    /// Raw snappy blocks
+    Snappy,
+    /// lz4 blocks
+    Lz4,
+    /// zstd frames
+    Zstd
+}
+impl Aff4Compression {
+    fn from_iri(iri: &str) -> Option<Self> {
+        let iri = iri.to_lowercase();
+        if iri.contains("snappy") {
+            Some(Aff4Compression::Snappy)
+        } else if iri.contains("lz4") {
+            Some(Aff4Compression::Lz4)
+        } else if iri.contains("zstd") {
+            Some(Aff4Compression::Zstd)
+        } else if iri.contains("rfc1950") || iri.contains("zlib") {
+            Some(Aff4Compression::Zlib)
+        } else if iri.contains("rfc1951") || iri.contains("deflate") {
+            Some(Aff4Compression::Deflate)
+        } else if iri.contains("nullcompressor") || iri.contains("stored") {
+            Some(Aff4Compression::Stored)
+        } else {
+            None
+        }
+    }
+
+    /// Decompress a chunk. Stream decoders stop one byte past chunk_size so
+    /// that the caller can reject chunks that inflate past it.
+    fn decompress(&self, data: &[u8], chunk_size: usize) -> std::io::Result<Vec<u8>> {
+        let limit = chunk_size as u64 + 1;
+        let mut chunk = Vec::with_capacity(chunk_size);
+        match self {
+            Aff4Compression::Stored => chunk.extend_from_slice(data),
+            Aff4Compression::Deflate => { DeflateDecoder::new(data).take(limit).read_to_end(&mut chunk)?; },
+            Aff4Compression::Zlib => { ZlibDecoder::new(data).take(limit).read_to_end(&mut chunk)?; },
+            Aff4Compression::Zstd => { zstd::stream::read::Decoder::new(data)?.take(limit).read_to_end(&mut chunk)?; },
+            Aff4Compression::Snappy => chunk = snappy_decompress(data, chunk_size)?,
+            Aff4Compression::Lz4 => chunk = lz4_decompress(data, chunk_size)?
+        }
+        Ok(chunk)
+    }
+}
+
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+
+/// Copy a back reference, which may overlap the bytes it produces
+fn copy_back_reference(output: &mut Vec<u8>, offset: usize, length: usize) -> std::io::Result<()> {
+    if offset == 0 || offset > output.len() {
+        return Err(invalid_data("back reference is outside of the decompressed data"));
+    }
+    let start = output.len() - offset;
+    for i in 0..length {
+        let byte = output[start + i];
+        output.push(byte);
+    }
+    Ok(())
+}
+
+
+/// Decompress a raw snappy block
+fn snappy_decompress(data: &[u8], max_size: usize) -> std::io::Result<Vec<u8>> {
+    let truncated = || invalid_data("snappy data is truncated");
+    let mut position = 0;
+    let mut length = 0usize;
+    for shift in (0..35).step_by(7) {
+        let byte = *data.get(position).ok_or_else(truncated)?;
+        position += 1;
+        length |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if length > max_size {
+        return Err(invalid_data("snappy data is larger than a chunk"));
+    }
+
+    let mut output = Vec::with_capacity(length);
+    while position < data.len() {
+        let tag = data[position] as usize;
+        position += 1;
+        match tag & 0x3 {
+            0 => {
+                let mut literal_length = tag >> 2;
+                if literal_length >= 60 {
+                    let bytes = literal_length - 59;
+                    let value = data.get(position..position + bytes).ok_or_else(truncated)?;
+                    literal_length = value.iter().rev().fold(0, |v, b| v << 8 | *b as usize);
+                    position += bytes;
+                }
+                let literal = data.get(position..position + literal_length + 1).ok_or_else(truncated)?;
+                output.extend_from_slice(literal);
+                position += literal_length + 1;
+            },
+            1 => {
+                let low = *data.get(position).ok_or_else(truncated)? as usize;
+                position += 1;
+                copy_back_reference(&mut output, (tag >> 5) << 8 | low, 4 + ((tag >> 2) & 0x7))?;
+            },
+            kind => {
+                let bytes = if kind == 2 { 2 } else { 4 };
+                let value = data.get(position..position + bytes).ok_or_else(truncated)?;
+                let offset = value.iter().rev().fold(0, |v, b| v << 8 | *b as usize);
+                position += bytes;
+                copy_back_reference(&mut output, offset, (tag >> 2) + 1)?;
+            }
+        }
+        if output.len() > length {
+            return Err(invalid_data("snappy data is larger than its stated length"));
+        }
+    }
+    if output.len() != length {
+        return Err(truncated());
+    }
+    Ok(output)
+}
+
+
+/// Decompress an lz4 block
+fn lz4_decompress(data: &[u8], max_size: usize) -> std::io::Result<Vec<u8>> {
+    let truncated = || invalid_data("lz4 data is truncated");
+    let mut output = Vec::with_capacity(max_size);
+    let mut position = 0;
+
+    let read_length = |position: &mut usize, mut length: usize| -> std::io::Result<usize> {
+        if length == 15 {
+            loop {
+                let byte = *data.get(*position).ok_or_else(truncated)?;
+                *position += 1;
+                length += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    };
+
+    while position < data.len() {
+        let token = data[position] as usize;
+        position += 1;
+        let literal_length = read_length(&mut position, token >> 4)?;
+        let literal = data.get(position..position + literal_length).ok_or_else(truncated)?;
+        output.extend_from_slice(literal);
+        position += literal_length;
+        if position == data.len() {
+            break;
+        }
+
+        let offset = data.get(position..position + 2).ok_or_else(truncated)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        position += 2;
+        let match_length = read_length(&mut position, token & 0xf)? + 4;
+        if output.len() + match_length > max_size {
+            return Err(invalid_data("lz4 data is larger than a chunk"));
+        }
+        copy_back_reference(&mut output, offset, match_length)?;
+    }
+    Ok(output)
+}
+
+
+/// An AFF4 ImageStream: chunks stored in bevy members with an index each
+struct ImageStream {
+    urn: String,
+    chunk_size: u64,
+    chunks_in_segment: u64,
+    compression: Aff4Compression,
+    members: Arc<HashMap<String, ZipMember>>,
+    /// The most recently used bevy index (bevy, (offset, length) of each chunk)
+    index_cache: Option<(u64, Vec<(u64, u64)>)>,
+    /// The most recently used bevy when bevies are zip compressed
+    bevy_cache: Option<(u64, Vec<u8>)>,
+    /// The most recently decompressed chunk
+    chunk_cache: Option<(u64, Vec<u8>)>
+}
+impl ImageStream {
+    fn member(&self, name: &str) -> std::io::Result<&ZipMember> {
+        self.members.get(name).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("AFF4 volume has no member {}", name)
+        ))
+    }
+
+    fn bevy_index(&mut self, file: &mut File, bevy: u64) -> std::io::Result<&[(u64, u64)]> {
+        if self.index_cache.as_ref().map(|c| c.0) != Some(bevy) {
+            let bevy_size = self.member(&format!("{}/{:08}", self.urn, bevy))?.size;
+            let data = self.member(&format!("{}/{:08}.index", self.urn, bevy))?.read_all(file)?;
+
+            let entries = if data.len() % 12 == 0 {
+                data.chunks_exact(12)
+                    .map(|e| (
+                        u64::from_le_bytes(e[0..8].try_into().unwrap()),
+                        u32::from_le_bytes(e[8..12].try_into().unwrap()) as u64
+                    ))
+                    .collect()
+            } else {
+                // Legacy indexes only hold u32 chunk offsets
+                let offsets: Vec<u64> = data.chunks_exact(4)
+                    .map(|e| u32::from_le_bytes(e.try_into().unwrap()) as u64)
+                    .collect();
+                offsets.iter().enumerate()
+                    .map(|(i, offset)| {
+                        let end = offsets.get(i + 1).copied().unwrap_or(bevy_size);
+                        (*offset, end.saturating_sub(*offset))
+                    })
+                    .collect()
+            };
+            self.index_cache = Some((bevy, entries));
+        }
+        Ok(&self.index_cache.as_ref().unwrap().1)
+    }
+
+    fn chunk(&mut self, file: &mut File, chunk: u64) -> std::io::Result<&[u8]> {
+        if self.chunk_cache.as_ref().map(|c| c.0) != Some(chunk) {
+            let bevy = chunk / self.chunks_in_segment;
+            let index = (chunk % self.chunks_in_segment) as usize;
+            let (offset, length) = *self.bevy_index(file, bevy)?
+                .get(index)
+                .ok_or_else(|| invalid_data("chunk is missing from the bevy index"))?;
+
+            let member = self.member(&format!("{}/{:08}", self.urn, bevy))?.clone();
+            if offset.checked_add(length).is_none_or(|end| end > member.size) {
+                return Err(invalid_data("chunk is outside of its bevy"));
+            }
+            // Chunks that do not compress are stored as is, so no stored
+            // chunk is larger than the chunk size
+            if length > self.chunk_size {
+                return Err(invalid_data("chunk is larger than the chunk size"));
+            }
+            let compressed = if member.method == 0 {
+                let data_offset = member.data_offset(file)?;
+                let mut compressed = vec![0u8; length as usize];
+                read_exact_at(file, data_offset + offset, &mut compressed)?;
+                compressed
+            } else {
+                if self.bevy_cache.as_ref().map(|c| c.0) != Some(bevy) {
+                    self.bevy_cache = Some((bevy, member.read_all(file)?));
+                }
+                self.bevy_cache.as_ref().unwrap().1[offset as usize..(offset + length) as usize].to_vec()
+            };
+
+            // Chunks that do not compress are stored as is
+            let mut data = if length == self.chunk_size {
+                compressed
+            } else {
+                self.compression.decompress(&compressed, self.chunk_size as usize)?
+            };
+            if data.len() as u64 > self.chunk_size {
+                return Err(invalid_data("chunk is larger than the chunk size"));
+            }
+            data.resize(self.chunk_size as usize, 0);
+            self.chunk_cache = Some((chunk, data));
+        }
+        Ok(&self.chunk_cache.as_ref().unwrap().1)
+    }
+
+    fn read_at(&mut self, file: &mut File, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let chunk_offset = (offset % self.chunk_size) as usize;
+        let wanted = (self.chunk_size as usize - chunk_offset).min(buf.len());
+        let data = self.chunk(file, offset / self.chunk_size)?;
+        buf[..wanted].copy_from_slice(&data[chunk_offset..chunk_offset + wanted]);
+        Ok(wanted)
+    }
+}
+
+
+/// An entry of an AFF4 map
+#[derive(Debug, Clone, Copy)]
+struct MapEntry {
+    mapped_offset: u64,
+    length: u64,
+    target_offset: u64,
+    target: usize
+}
+
+
+/// The source of a stream's data
+enum Aff4Source {
+    Image(ImageStream),
+    Map {
+        entries: Vec<MapEntry>,
+        targets: Vec<(u64, Aff4Source)>,
+        /// Source for the gaps between entries
+        gap: Option<Box<Aff4Source>>
+    },
+    /// Symbolic streams repeat a pattern (aff4:Zero, aff4:UnknownData, ...)
+    Pattern(Vec<u8>)
+}
+impl Aff4Source {
+    /// Read from offset, returning fewer bytes than buf at source boundaries
+    fn read_at(&mut self, file: &mut File, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Aff4Source::Image(stream) => stream.read_at(file, offset, buf),
+            Aff4Source::Pattern(pattern) => {
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    *byte = pattern[((offset + i as u64) % pattern.len() as u64) as usize];
+                }
+                Ok(buf.len())
+            },
+            Aff4Source::Map { entries, targets, gap } => {
+                let index = entries.partition_point(|e| e.mapped_offset + e.length <= offset);
+                match entries.get(index) {
+                    Some(entry) if entry.mapped_offset <= offset => {
+                        let within = offset - entry.mapped_offset;
+                        let wanted = (entry.length - within).min(buf.len() as u64) as usize;
+                        let (target_size, target) = &mut targets[entry.target];
+                        let target_offset = entry.target_offset + within;
+                        if target_offset + wanted as u64 > *target_size {
+                            return Err(invalid_data("map entry is outside of its target stream"));
+                        }
+                        target.read_at(file, target_offset, &mut buf[..wanted])
+                    },
+                    next => {
+                        let gap_end = next.map(|e| e.mapped_offset).unwrap_or(u64::MAX);
+                        let wanted = (gap_end - offset).min(buf.len() as u64) as usize;
+                        match gap {
+                            Some(gap) => gap.read_at(file, offset, &mut buf[..wanted]),
+                            None => {
+                                buf[..wanted].iter_mut().for_each(|b| *b = 0);
+                                Ok(wanted)
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+/// An AFF4 volume (a zip container with RDF metadata and image streams).
+///
+/// Single file volumes written by the AFF4 standard tools (Evimetry,
+/// pyaff4, aff4imager) are supported. Images are read through maps and
+/// ImageStreams with snappy, lz4, zlib, deflate, zstd or stored chunks.
+///
+pub struct Aff4Volume {
+    path: PathBuf,
+    volume_urn: String,
+    /// Zip members keyed by the URN they store
+    members: Arc<HashMap<String, ZipMember>>,
+    objects: BTreeMap<String, Aff4Object>
+}
+impl Aff4Volume {
+    /// Open an AFF4 volume and parse its metadata
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading AFF4 volume {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let (zip_members, comment) = read_zip_directory(&mut file).map_err(io_error)?;
+
+        let by_name: HashMap<&str, &ZipMember> = zip_members.iter()
+            .map(|m| (m.name.as_str(), m))
+            .collect();
+        let volume_urn = match by_name.get("container.description") {
+            Some(member) => String::from_utf8_lossy(&member.read_all(&mut file).map_err(io_error)?)
+                .trim().to_string(),
+            None => comment.trim().to_string()
+        };
+        if volume_urn.is_empty() {
+            return Err(TskError::img_format_error(
+                format!("{} is not an AFF4 volume (no container.description).", path.display())
+            ));
+        }
+
+        let turtle = by_name.get("information.turtle")
+            .ok_or_else(|| TskError::img_format_error(
+                format!("AFF4 volume {} has no information.turtle.", path.display())
+            ))?
+            .read_all(&mut file).map_err(io_error)?;
+        let triples = parse_turtle(&String::from_utf8_lossy(&turtle))
+            .map_err(|e| TskError::img_format_error(
+                format!("Error parsing information.turtle of {}: {}", path.display(), e)
+            ))?;
+
+        let mut objects: BTreeMap<String, Aff4Object> = BTreeMap::new();
+        for (subject, predicate, object) in triples {
+            let entry = objects.entry(subject.clone())
+                .or_insert_with(|| Aff4Object {
+                    urn: subject,
+                    types: Vec::new(),
+                    properties: Vec::new()
+                });
+            if predicate == RDF_TYPE {
+                entry.types.push(object.value().to_string());
+            } else {
+                let datatype = match &object {
+                    Term::Literal { datatype, .. } => datatype.clone(),
+                    Term::Iri(_) => None
+                };
+                entry.properties.push(Aff4Property {
+                    predicate,
+                    value: object.value().to_string(),
+                    datatype
+                });
+            }
+        }
+
+        // Members are named after the URN of what they store, relative to
+        // the volume when the URN is inside of it
+        let members = zip_members.into_iter()
+            .map(|member| {
+                let name = percent_decode(&member.name);
+                let urn = if name.starts_with("aff4:") {
+                    name
+                } else {
+                    format!("{}/{}", volume_urn, name)
+                };
+                (urn, member)
+            })
+            .collect();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            volume_urn,
+            members: Arc::new(members),
+            objects
+        })
+    }
+
+    /// The URN of the volume
+    pub fn volume_urn(&self) -> &str {
+        &self.volume_urn
+    }
+
+    /// The path of the volume
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// All objects described in the metadata
+    pub fn objects(&self) -> impl Iterator<Item = &Aff4Object> {
+        self.objects.values()
+    }
+
+    /// Get an object by its URN
+    pub fn object(&self, urn: &str) -> Option<&Aff4Object> {
+        self.objects.get(urn)
+    }
+
+    /// The images in the volume. These are the aff4:Image objects, or the
+    /// maps and ImageStreams that are not used by another stream if the
+    /// volume has no image objects.
+    pub fn images(&self) -> Vec<&Aff4Object> {
+        let images: Vec<&Aff4Object> = self.objects.values()
+            .filter(|o| o.is_a("Image"))
+            .collect();
+        if !images.is_empty() {
+            return images;
+        }
+
+        let mut used = Vec::new();
+        for object in self.objects.values().filter(|o| o.is_a("Map")) {
+            used.extend(self.map_targets(&object.urn).unwrap_or_default());
+        }
+        self.objects.values()
+            .filter(|o| (o.is_a("Map") || o.is_a("ImageStream")) && !used.contains(&o.urn))
+            .collect()
+    }
+
+    /// Open the first image of the volume
+    pub fn open_image(&self) -> Result<Aff4Reader, TskError> {
+        let image = self.images().first()
+            .map(|o| o.urn.clone())
+            .ok_or_else(|| TskError::img_format_error(
+                format!("AFF4 volume {} has no images.", self.path.display())
+            ))?;
+        self.open_stream(&image)
+    }
+
+    /// Open an image, map or ImageStream by its URN
+    pub fn open_stream(&self, urn: &str) -> Result<Aff4Reader, TskError> {
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading AFF4 volume {}: {}", self.path.display(), e)
+        );
+        let (size, source) = self.resolve(urn, 0)?;
+        let file = File::open(&self.path).map_err(io_error)?;
+
+        Ok(Aff4Reader {
+            urn: urn.to_string(),
+            metadata: self.objects.get(urn).cloned(),
+            file,
+            size,
+            source,
+            offset: 0
+        })
+    }
+
+    /// The target URNs of a map (its idx member)
+    fn map_targets(&self, urn: &str) -> Result<Vec<String>, TskError> {
+        let member = self.members.get(&format!("{}/idx", urn))
+            .ok_or_else(|| TskError::img_format_error(
+                format!("AFF4 map {} has no idx member.", urn)
+            ))?;
+        let mut file = File::open(&self.path).map_err(|e| TskError::img_format_error(
+            format!("Error reading AFF4 volume {}: {}", self.path.display(), e)
+        ))?;
+        let data = member.read_all(&mut file).map_err(|e| TskError::img_format_error(
+            format!("Error reading idx of AFF4 map {}: {}", urn, e)
+        ))?;
+        Ok(String::from_utf8_lossy(&data)
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect())
+    }
+
+    /// Build the source of a stream, returning its size
+    fn resolve(&self, urn: &str, depth: usize) -> Result<(u64, Aff4Source), TskError> {
+        if depth > MAX_STREAM_DEPTH {
+            return Err(TskError::img_format_error(
+                format!("AFF4 stream {} is nested deeper than {}.", urn, MAX_STREAM_DEPTH)
+            ));
+        }
+
+        // Symbolic streams
+        if let Some(name) = aff4_local_name(urn).or_else(|| urn.strip_prefix("aff4:")) {
+            let pattern = match name {
+                "Zero" => Some(vec![0u8]),
+                "UnknownData" => Some(b"UNKNOWN".to_vec()),
+                "UnreadableData" => Some(b"UNREADABLEDATA".to_vec()),
+                _ => name.strip_prefix("SymbolicStream")
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .map(|byte| vec![byte])
+            };
+            if let Some(pattern) = pattern {
+                return Ok((u64::MAX, Aff4Source::Pattern(pattern)));
+            }
+        }
+
+        let object = self.objects.get(urn)
+            .ok_or_else(|| TskError::img_format_error(
+                format!("AFF4 volume {} has no metadata for {}.", self.path.display(), urn)
+            ))?;
+
+        if object.is_a("ImageStream") {
+            return self.resolve_image_stream(object);
+        }
+        if object.is_a("Map") {
+            return self.resolve_map(object, depth);
+        }
+
+        // An image is read through its data stream, or through the stream
+        // that targets it
+        let data_stream = object.value("dataStream")
+            .map(|s| s.to_string())
+            .or_else(|| self.objects.values()
+                .find(|o| (o.is_a("Map") || o.is_a("ImageStream")) && o.value("target") == Some(urn))
+                .map(|o| o.urn.clone()))
+            .ok_or_else(|| TskError::img_format_error(
+                format!("AFF4 object {} has no data stream.", urn)
+            ))?;
+        self.resolve(&data_stream, depth + 1)
+    }
+
+    fn resolve_image_stream(&self, object: &Aff4Object) -> Result<(u64, Aff4Source), TskError> {
+        let urn = &object.urn;
+        let size = object.size().ok_or_else(|| TskError::img_format_error(
+            format!("AFF4 ImageStream {} has no size.", urn)
+        ))?;
+        let chunk_size = object.value_u64("chunkSize").unwrap_or(32 * 1024);
+        let chunks_in_segment = object.value_u64("chunksInSegment").unwrap_or(2048);
+        if chunk_size == 0 || chunk_size > 64 * 1024 * 1024 || chunks_in_segment == 0 {
+            return Err(TskError::img_format_error(
+                format!("AFF4 ImageStream {} has an invalid chunk layout.", urn)
+            ));
+        }
+        let compression = match object.value("compressionMethod") {
+            Some(method) => Aff4Compression::from_iri(method).ok_or_else(|| TskError::img_format_error(
+                format!("AFF4 ImageStream {} uses unsupported compression {}.", urn, method)
+            ))?,
+            None => Aff4Compression::Zlib
+        };
+
+        if size > 0 && !self.members.contains_key(&format!("{}/{:08}", urn, 0)) {
+            return Err(TskError::img_format_error(format!(
+                "AFF4 ImageStream {} is not stored in {} (stored in {}).",
+                urn, self.path.display(), object.value("stored").unwrap_or("an unknown volume")
+            )));
+        }
+
+        Ok((size, Aff4Source::Image(ImageStream {
+            urn: urn.clone(),
+            chunk_size,
+            chunks_in_segment,
+            compression,
+            members: self.members.clone(),
+            index_cache: None,
+            bevy_cache: None,
+            chunk_cache: None
+        })))
+    }
+
+    fn resolve_map(&self, object: &Aff4Object, depth: usize) -> Result<(u64, Aff4Source), TskError> {
+        let urn = &object.urn;
+        let size = object.size().ok_or_else(|| TskError::img_format_error(
+            format!("AFF4 map {} has no size.", urn)
+        ))?;
+        let member = self.members.get(&format!("{}/map", urn))
+            .ok_or_else(|| TskError::img_format_error(
+                format!("AFF4 map {} has no map member.", urn)
+            ))?;
+        let mut file = File::open(&self.path).map_err(|e| TskError::img_format_error(
+            format!("Error reading AFF4 volume {}: {}", self.path.display(), e)
+        ))?;
+        let data = member.read_all(&mut file).map_err(|e| TskError::img_format_error(
+            format!("Error reading AFF4 map {}: {}", urn, e)
+        ))?;
+
+        let target_urns = self.map_targets(urn)?;
+        let mut entries = Vec::new();
+        for entry in data.chunks_exact(MAP_ENTRY_SIZE) {
+            let entry = MapEntry {
+                mapped_offset: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                length: u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+                target_offset: u64::from_le_bytes(entry[16..24].try_into().unwrap()),
+                target: u32::from_le_bytes(entry[24..28].try_into().unwrap()) as usize
+            };
+            if entry.target >= target_urns.len() {
+                return Err(TskError::img_format_error(
+                    format!("AFF4 map {} refers to missing target {}.", urn, entry.target)
+                ));
+            }
+            // Reads add offsets within an entry to both of its offsets
+            if entry.mapped_offset.checked_add(entry.length).is_none() ||
+                entry.target_offset.checked_add(entry.length).is_none() {
+                return Err(TskError::img_format_error(
+                    format!("AFF4 map {} has an entry past the maximum stream size.", urn)
+                ));
+            }
+            if entry.length > 0 {
+                entries.push(entry);
+            }
+        }
+        entries.sort_by_key(|e| e.mapped_offset);
+        if entries.windows(2).any(|p| p[0].mapped_offset + p[0].length > p[1].mapped_offset) {
+            return Err(TskError::img_format_error(
+                format!("AFF4 map {} has overlapping entries.", urn)
+            ));
+        }
+
+        let targets = target_urns.iter()
+            .map(|target| self.resolve(target, depth + 1))
+            .collect::<Result<Vec<_>, TskError>>()?;
+        let gap = match object.value("mapGapDefaultStream") {
+            Some(gap) => Some(Box::new(self.resolve(gap, depth + 1)?.1)),
+            None => None
+        };
+
+        Ok((size, Aff4Source::Map { entries, targets, gap }))
+    }
+}
+impl std::fmt::Debug for Aff4Volume {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Aff4Volume")
+            .field("path", &self.path)
+            .field("volume_urn", &self.volume_urn)
+            .field("members", &self.members.len())
+            .field("objects", &self.objects.len())
+            .finish()
+    }
+}
+
+
+/// A Read + Seek reader of an image, map or ImageStream in an AFF4 volume.
+/// It can be handed to `TskImgReadSeek::from_read_seek`.
+///
+pub struct Aff4Reader {
+    urn: String,
+    metadata: Option<Aff4Object>,
+    file: File,
+    size: u64,
+    source: Aff4Source,
+    /// Offset of the next read in the image stream
+    offset: u64
+}
+impl Aff4Reader {
+    /// Open the first image of an AFF4 volume
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        Aff4Volume::open(path)?.open_image()
+    }
+
+    /// The URN of the stream
+    pub fn urn(&self) -> &str {
+        &self.urn
+    }
+
+    /// The metadata of the stream
+    pub fn metadata(&self) -> Option<&Aff4Object> {
+        self.metadata.as_ref()
+    }
+
+    /// The size of the stream
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+impl std::fmt::Debug for Aff4Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Aff4Reader")
+            .field("urn", &self.urn)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+impl ImgMetadataSource for Aff4Reader {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("AFF4");
+        metadata.description = Some(self.urn.clone());
+        if let Some(object) = &self.metadata {
+            for property in &object.properties {
+                let name = aff4_local_name(&property.predicate).unwrap_or(&property.predicate);
+                let name = match (name, property.datatype.as_deref()) {
+                    ("hash", Some(datatype)) => aff4_local_name(datatype).unwrap_or(datatype).to_string(),
+                    (name, _) => name.to_string()
+                };
+                metadata.add_detail(name, property.value.clone());
+            }
+        }
+        metadata
+    }
+}
+impl Read for Aff4Reader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let wanted = (self.size - self.offset).min(buf.len() as u64) as usize;
+        let read = self.source.read_at(&mut self.file, self.offset, &mut buf[..wanted])?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+impl Seek for Aff4Reader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+        if offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position"
+            ));
+        }
+        self.offset = offset as u64;
+        Ok(self.offset)
+    }
+}
@@ -0,0 +1,224 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::img_common::{RangeSet, SetRange};
+pub use crate::img_hash::{HashAlgorithm, ImageHash};
+
+
+/// A range of the image that was read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditRange {
+    /// Offset of the range in the image
+    pub offset: u64,
+    /// Length of the range in bytes
+    pub length: u64
+}
+impl AuditRange {
+    /// The offset following the range
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+}
+impl SetRange for AuditRange {
+    fn bounds(&self) -> (u64, u64) {
+        (self.offset, self.end())
+    }
+
+    fn with_bounds(&self, offset: u64, end: u64) -> Self {
+        AuditRange { offset, length: end - offset }
+    }
+}
+
+
+/// The ranges of an image that were read. Adjacent and overlapping ranges
+/// are coalesced, so the trail documents which bytes were accessed rather
+/// than how often.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditTrail {
+    source: String,
+    image_size: u64,
+    read_count: u64,
+    bytes_read: u64,
+    ranges: RangeSet<AuditRange>
+}
+impl AuditTrail {
+    /// Create an empty trail for an image
+    pub fn new<S: Into<String>>(source: S, image_size: u64) -> Self {
+        Self {
+            source: source.into(),
+            image_size,
+            ..Default::default()
+        }
+    }
+
+    /// The source of the image
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The size of the image
+    pub fn image_size(&self) -> u64 {
+        self.image_size
+    }
+
+    /// The number of recorded reads
+    pub fn read_count(&self) -> u64 {
+        self.read_count
+    }
+
+    /// The number of bytes of all recorded reads, counting bytes read more
+    /// than once every time
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// The coalesced ranges ordered by offset
+    pub fn ranges(&self) -> &[AuditRange] {
+        self.ranges.ranges()
+    }
+
+    /// True if nothing was read
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The number of distinct bytes that were read
+    pub fn covered_bytes(&self) -> u64 {
+        self.ranges.covered_bytes()
+    }
+
+    /// Record a read of length bytes at offset
+    pub fn record(&mut self, offset: u64, length: u64) {
+        if length == 0 {
+            return;
+        }
+        self.read_count += 1;
+        self.bytes_read += length;
+        self.ranges.insert(AuditRange { offset, length });
+    }
+
+    /// Forget all recorded reads
+    pub fn clear(&mut self) {
+        self.read_count = 0;
+        self.bytes_read = 0;
+        self.ranges.clear();
+    }
+
+    /// Export the trail as a JSON document
+    pub fn to_json(&self, hash: Option<&ImageHash>) -> String {
+        let hash = match hash {
+            Some(h) => format!(
+                "{{\"algorithm\": \"{}\", \"value\": \"{}\"}}",
+                h.algorithm, json_escape(&h.value)
+            ),
+            None => "null".to_string()
+        };
+        let ranges: Vec<String> = self.ranges().iter()
+            .map(|r| format!("    {{\"offset\": {}, \"length\": {}}}", r.offset, r.length))
+            .collect();
+        let ranges = if ranges.is_empty() {
+            "[]".to_string()
+        } else {
+            format!("[\n{}\n  ]", ranges.join(",\n"))
+        };
+
+        format!(
+            "{{\n  \"source\": \"{}\",\n  \"image_size\": {},\n  \"read_count\": {},\n  \"bytes_read\": {},\n  \"covered_bytes\": {},\n  \"hash\": {},\n  \"ranges\": {}\n}}\n",
+            json_escape(&self.source),
+            self.image_size,
+            self.read_count,
+            self.bytes_read,
+            self.covered_bytes(),
+            hash,
+            ranges
+        )
+    }
+
+    /// Export the trail as CSV with one row per range. The image columns are
+    /// repeated on every row so that each row stands on its own.
+    pub fn to_csv(&self, hash: Option<&ImageHash>) -> String {
+        let (algorithm, value) = match hash {
+            Some(h) => (h.algorithm.name(), h.value.as_str()),
+            None => ("", "")
+        };
+        let mut csv = "source,image_size,hash_algorithm,hash,offset,length\n".to_string();
+        for range in self.ranges() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                csv_escape(&self.source),
+                self.image_size,
+                algorithm,
+                csv_escape(value),
+                range.offset,
+                range.length
+            ));
+        }
+        csv
+    }
+}
+
+
+/// Escape a string for a JSON string literal
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+
+/// Quote a CSV field if it needs quoting
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+
+/// The audit switch and trail shared between the read callback of a
+/// TskImgReadSeek and the images reading through it
+#[derive(Debug, Default)]
+pub(crate) struct AuditLog {
+    enabled: AtomicBool,
+    trail: Mutex<AuditTrail>
+}
+impl AuditLog {
+    pub(crate) fn new(source: &str, image_size: u64) -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            trail: Mutex::new(AuditTrail::new(source, image_size))
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed)
+    }
+
+    pub(crate) fn record(&self, offset: u64, length: u64) {
+        if self.is_enabled() {
+            self.trail.lock().unwrap().record(offset, length);
+        }
+    }
+
+    pub(crate) fn trail(&self) -> AuditTrail {
+        self.trail.lock().unwrap().clone()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.trail.lock().unwrap().clear()
+    }
+}
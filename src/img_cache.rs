@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::errors::TskError;
+use crate::img_common::read_fill;
+use crate::tsk_img_reader::ReadSeek;
+
+
+/// Settings of a BlockCache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Maximum number of bytes of cached blocks
+    pub cache_size: usize,
+    /// Size of the cached blocks
+    pub block_size: usize,
+    /// Number of blocks following a missed block to read along with it
+    pub read_ahead: usize
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            cache_size: 16 * 1024 * 1024,
+            block_size: 64 * 1024,
+            read_ahead: 1
+        }
+    }
+}
+
+
+/// A snapshot of the statistics of a BlockCache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Block lookups served from the cache
+    pub hits: u64,
+    /// Block lookups that had to read the source
+    pub misses: u64,
+    /// Blocks that were read ahead of a miss
+    pub read_ahead_blocks: u64,
+    /// Blocks dropped to make room for new blocks
+    pub evictions: u64,
+    /// Number of reads of the source
+    pub source_reads: u64,
+    /// Number of bytes read from the source
+    pub source_bytes: u64
+}
+impl CacheStats {
+    /// The fraction of block lookups served from the cache
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+}
+
+
+/// Counters shared between a BlockCache and the images reading through it
+#[derive(Debug, Default)]
+pub(crate) struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    read_ahead_blocks: AtomicU64,
+    evictions: AtomicU64,
+    source_reads: AtomicU64,
+    source_bytes: AtomicU64
+}
+impl CacheCounters {
+    pub(crate) fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            read_ahead_blocks: self.read_ahead_blocks.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            source_reads: self.source_reads.load(Ordering::Relaxed),
+            source_bytes: self.source_bytes.load(Ordering::Relaxed)
+        }
+    }
+
+    fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+
+/// A Read + Seek LRU cache of fixed size blocks of another ReadSeek. Reads
+/// are served from cached blocks; a missed block is read from the source
+/// together with up to `read_ahead` following blocks.
+///
+pub struct BlockCache {
+    stream: Box<dyn ReadSeek>,
+    size: u64,
+    config: CacheConfig,
+    max_blocks: usize,
+    /// Cached blocks (block index -> (last use, data))
+    blocks: HashMap<u64, (u64, Vec<u8>)>,
+    /// Block indexes ordered by last use
+    lru: BTreeMap<u64, u64>,
+    /// Use counter for the LRU order
+    tick: u64,
+    counters: Arc<CacheCounters>,
+    /// Offset in the stream that the next read starts at
+    offset: u64
+}
+impl BlockCache {
+    /// Cache a stream of size bytes
+    ///
+    pub fn new(stream: Box<dyn ReadSeek>, size: u64, config: CacheConfig) -> Result<Self, TskError> {
+        if config.block_size == 0 {
+            return Err(TskError::generic(
+                "The cache block size can not be 0.".to_string()
+            ));
+        }
+        if config.cache_size < config.block_size {
+            return Err(TskError::generic(
+                format!("The cache size {} is smaller than the block size {}.", config.cache_size, config.block_size)
+            ));
+        }
+        Ok(Self {
+            stream,
+            size,
+            config,
+            max_blocks: config.cache_size / config.block_size,
+            blocks: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            counters: Arc::new(CacheCounters::default()),
+            offset: 0
+        })
+    }
+
+    /// The settings of the cache
+    pub fn config(&self) -> CacheConfig {
+        self.config
+    }
+
+    /// The size of the cached stream
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The statistics of the cache
+    pub fn stats(&self) -> CacheStats {
+        self.counters.snapshot()
+    }
+
+    /// Drop all cached blocks
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.lru.clear();
+    }
+
+    pub(crate) fn counters(&self) -> Arc<CacheCounters> {
+        self.counters.clone()
+    }
+
+    /// Mark a block as most recently used
+    fn touch(&mut self, block: u64) {
+        self.tick += 1;
+        if let Some(entry) = self.blocks.get_mut(&block) {
+            self.lru.remove(&entry.0);
+            entry.0 = self.tick;
+            self.lru.insert(self.tick, block);
+        }
+    }
+
+    fn insert(&mut self, block: u64, data: Vec<u8>) {
+        while self.blocks.len() >= self.max_blocks {
+            let (oldest, evicted) = match self.lru.iter().next() {
+                Some((tick, block)) => (*tick, *block),
+                None => break
+            };
+            self.lru.remove(&oldest);
+            self.blocks.remove(&evicted);
+            CacheCounters::add(&self.counters.evictions, 1);
+        }
+        self.tick += 1;
+        self.lru.insert(self.tick, block);
+        self.blocks.insert(block, (self.tick, data));
+    }
+
+    /// Read blocks [first, first + count) from the source into the cache
+    fn load(&mut self, first: u64, count: u64) -> std::io::Result<()> {
+        let block_size = self.config.block_size as u64;
+        let start = first * block_size;
+        let end = ((first + count) * block_size).min(self.size);
+        let mut data = vec![0u8; (end - start) as usize];
+
+        self.stream.seek(SeekFrom::Start(start))?;
+        let filled = read_fill(&mut self.stream, &mut data)?;
+        CacheCounters::add(&self.counters.source_reads, 1);
+        CacheCounters::add(&self.counters.source_bytes, filled as u64);
+        if filled < data.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("Source ended at offset {} before the end of the cached size {}", start + filled as u64, self.size)
+            ));
+        }
+
+        for (i, block_data) in data.chunks(self.config.block_size).enumerate() {
+            self.insert(first + i as u64, block_data.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Make sure a block is cached
+    fn fetch(&mut self, block: u64) -> std::io::Result<()> {
+        if self.blocks.contains_key(&block) {
+            CacheCounters::add(&self.counters.hits, 1);
+            self.touch(block);
+            return Ok(());
+        }
+        CacheCounters::add(&self.counters.misses, 1);
+
+        // Read ahead up to the next cached block, the end of the stream or
+        // the capacity of the cache
+        let block_count = self.size.div_ceil(self.config.block_size as u64);
+        let mut count = 1;
+        while count <= self.config.read_ahead as u64 &&
+            (count as usize) < self.max_blocks &&
+            block + count < block_count &&
+            !self.blocks.contains_key(&(block + count)) {
+            count += 1;
+        }
+        // A failing read ahead block must not fail the requested block, so
+        // retry the requested block alone when the larger read fails
+        match self.load(block, count) {
+            Ok(()) => CacheCounters::add(&self.counters.read_ahead_blocks, count - 1),
+            Err(e) if count > 1 => {
+                debug!("Read ahead of {} blocks at block {} failed ({}), reading the block alone", count, block, e);
+                self.load(block, 1)?;
+            },
+            Err(e) => return Err(e)
+        }
+        // The requested block must outlive the read ahead blocks
+        self.touch(block);
+        Ok(())
+    }
+}
+impl std::fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+         .field("size", &self.size)
+         .field("config", &self.config)
+         .field("cached_blocks", &self.blocks.len())
+         .field("stats", &self.stats())
+         .finish()
+    }
+}
+impl Read for BlockCache {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = ((self.size - self.offset) as usize).min(buf.len());
+
+        let block_size = self.config.block_size as u64;
+        let mut copied = 0;
+        while copied < remaining {
+            let offset = self.offset + copied as u64;
+            let block = offset / block_size;
+            self.fetch(block)?;
+            let data = &self.blocks[&block].1;
+            let within = (offset % block_size) as usize;
+            let len = (data.len() - within).min(remaining - copied);
+            buf[copied..copied + len].copy_from_slice(&data[within..within + len]);
+            copied += len;
+        }
+
+        self.offset += copied as u64;
+        Ok(copied)
+    }
+}
+impl Seek for BlockCache {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
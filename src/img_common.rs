@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+
+/// Read exactly buf.len() bytes at an offset of a stream
+pub(crate) fn read_exact_at<R: Read + Seek + ?Sized>(stream: &mut R, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+    stream.seek(SeekFrom::Start(offset))?;
+    stream.read_exact(buf)
+}
+
+
+/// Get the size of a file
+pub(crate) fn file_size(file: &File) -> std::io::Result<u64> {
+    Ok(file.metadata()?.len())
+}
+
+
+/// Format a byte slice as a lower case hex string
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+
+/// Decode UTF-16 little endian bytes up to the first null character
+pub(crate) fn utf16le_to_string(bytes: &[u8]) -> String {
+    let utf16: Vec<u16> = bytes.chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&utf16)
+}
+
+
+/// Decode UTF-16 big endian bytes up to the first null character
+pub(crate) fn utf16be_to_string(bytes: &[u8]) -> String {
+    let utf16: Vec<u16> = bytes.chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    String::from_utf16_lossy(&utf16)
+}
+
+
+/// Format a GUID stored in its on-disk (mixed endian) layout
+pub(crate) fn format_guid(bytes: &[u8; 16]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8], bytes[9], bytes[10], bytes[11],
+        bytes[12], bytes[13], bytes[14], bytes[15]
+    )
+}
+
+
+/// Locate the parent (backing) file of a differencing image.
+///
+/// Each candidate is a path as stored in the child image. Candidates can be
+/// absolute, relative to the child, Windows style (`.\parent.vhd`,
+/// `C:\images\parent.vhd`) or file URLs. When a candidate does not exist as
+/// given, its file name is looked up next to the child and then in each of the
+/// search directories.
+///
+pub(crate) fn find_parent_file(child: &Path, candidates: &[String], search_dirs: &[PathBuf]) -> Option<PathBuf> {
+    let child_dir = child.parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default();
+
+    let mut file_names = Vec::new();
+    for candidate in candidates {
+        let mut normalized = candidate.trim_end_matches('\0').replace('\\', "/");
+        if let Some(stripped) = normalized.strip_prefix("file://") {
+            normalized = stripped.trim_start_matches("localhost").to_string();
+        }
+        if normalized.is_empty() {
+            continue;
+        }
+
+        // Windows drive letter paths only make sense for their file name
+        let has_drive = normalized.len() > 1 && normalized.as_bytes()[1] == b':';
+        if !has_drive {
+            let path = PathBuf::from(&normalized);
+            let path = if path.is_absolute() {
+                path
+            } else {
+                child_dir.join(path)
+            };
+            if path.is_file() && path != child {
+                return Some(path);
+            }
+        }
+
+        if let Some(name) = normalized.rsplit('/').next() {
+            if !name.is_empty() {
+                file_names.push(name.to_string());
+            }
+        }
+    }
+
+    for name in file_names {
+        for dir in std::iter::once(&child_dir).chain(search_dirs.iter()) {
+            let path = dir.join(&name);
+            if path.is_file() && path != child {
+                return Some(path);
+            }
+        }
+    }
+
+    None
+}
+
+
+/// Read until buf is full or the stream ends, returning the number of bytes
+/// read
+pub(crate) fn read_fill<R: Read + ?Sized>(stream: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(filled)
+}
+
+
+/// A range of an image that can be kept in a RangeSet
+pub(crate) trait SetRange {
+    /// The offset of the range and the offset following it
+    fn bounds(&self) -> (u64, u64);
+
+    /// A copy of the range covering [offset, end) instead
+    fn with_bounds(&self, offset: u64, end: u64) -> Self;
+}
+
+
+/// Ranges ordered by offset in which adjacent and overlapping ranges are
+/// merged. A merged range keeps the values of the first range it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RangeSet<R> {
+    ranges: Vec<R>
+}
+impl<R> Default for RangeSet<R> {
+    fn default() -> Self {
+        Self { ranges: Vec::new() }
+    }
+}
+impl<R: SetRange> RangeSet<R> {
+    /// The ranges ordered by offset
+    pub(crate) fn ranges(&self) -> &[R] {
+        &self.ranges
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The number of bytes covered by the ranges
+    pub(crate) fn covered_bytes(&self) -> u64 {
+        self.ranges.iter()
+            .map(|r| {
+                let (offset, end) = r.bounds();
+                end - offset
+            })
+            .sum()
+    }
+
+    /// The ranges that overlap [offset, offset + length)
+    pub(crate) fn ranges_in(&self, offset: u64, length: u64) -> &[R] {
+        let end = offset.saturating_add(length);
+        let first = self.ranges.partition_point(|r| r.bounds().1 <= offset);
+        let last = self.ranges.partition_point(|r| r.bounds().0 < end);
+        &self.ranges[first..last.max(first)]
+    }
+
+    /// Add a range, merging it with the ranges it touches. Empty ranges are
+    /// ignored.
+    pub(crate) fn insert(&mut self, range: R) {
+        let (offset, end) = range.bounds();
+        if end <= offset {
+            return;
+        }
+        let first = self.ranges.partition_point(|r| r.bounds().1 < offset);
+        let last = self.ranges.partition_point(|r| r.bounds().0 <= end);
+        let merged = if first < last {
+            let start = offset.min(self.ranges[first].bounds().0);
+            let end = end.max(self.ranges[last - 1].bounds().1);
+            self.ranges[first].with_bounds(start, end)
+        } else {
+            range
+        };
+        self.ranges.splice(first..last, std::iter::once(merged));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.ranges.clear();
+    }
+}
@@ -0,0 +1,952 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use flate2::Compression;
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::{
+    read_exact_at,
+    file_size
+};
+use crate::img_inflate::{AccessPoint, Inflater};
+
+
+/// Default distance between gzip access points in uncompressed bytes
+pub const DEFAULT_GZIP_SPAN: u64 = 8 * 1024 * 1024;
+/// Magic of a persisted gzip index
+const GZIP_INDEX_MAGIC: &[u8; 8] = b"TSKGZIX2";
+/// Bytes at the start and end of a gzip file that its index fingerprints
+const GZIP_FINGERPRINT_SIZE: u64 = 4096;
+/// Magic of a zstd frame
+const ZSTD_MAGIC: u32 = 0xfd2f_b528;
+/// Skippable zstd frames use magics 0x184d2a50 to 0x184d2a5f
+const ZSTD_SKIPPABLE_MASK: u32 = 0xffff_fff0;
+const ZSTD_SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+/// Magic of the skippable frame that holds a seek table
+const ZSTD_SEEK_TABLE_MAGIC: u32 = 0x184d_2a5e;
+/// Magic at the end of the seek table footer
+const ZSTD_SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+/// Magic at the start of an xz stream
+const XZ_MAGIC: &[u8; 6] = b"\xfd7zXZ\x00";
+/// Magic at the end of an xz stream
+const XZ_FOOTER_MAGIC: &[u8; 2] = b"YZ";
+
+
+/// The compression format of a compressed image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressedFormat {
+    /// gzip (one or more members)
+    Gzip,
+    /// zstd, preferably in the seekable format
+    Zstd,
+    /// xz (one or more streams)
+    Xz
+}
+impl CompressedFormat {
+    /// Detect the format from the first bytes of a file
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            return Some(CompressedFormat::Gzip);
+        }
+        if header.starts_with(XZ_MAGIC) {
+            return Some(CompressedFormat::Xz);
+        }
+        if header.len() >= 4 {
+            let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            if magic == ZSTD_MAGIC || magic & ZSTD_SKIPPABLE_MASK == ZSTD_SKIPPABLE_MAGIC {
+                return Some(CompressedFormat::Zstd);
+            }
+        }
+        None
+    }
+}
+
+
+/// A gzip access point with its window kept deflate compressed
+#[derive(Debug, Clone)]
+struct GzipIndexPoint {
+    uncompressed_offset: u64,
+    bit_offset: u64,
+    member_start: bool,
+    window: Vec<u8>
+}
+
+
+/// A random access index of a gzip file. Building the index inflates the
+/// whole file once (verifying the crc of every member) and records a point
+/// about every span bytes where decompression can restart. The index can be
+/// saved next to the image so later opens do not need to inflate it again.
+///
+#[derive(Clone)]
+pub struct GzipIndex {
+    compressed_size: u64,
+    /// crc32 of the first and last bytes of the gzip file, which hold the
+    /// first member header and the last member trailer
+    fingerprint: u64,
+    uncompressed_size: u64,
+    span: u64,
+    points: Vec<GzipIndexPoint>
+}
+impl GzipIndex {
+    /// Build the index of a gzip file with an access point about every span
+    /// uncompressed bytes.
+    ///
+    pub fn build(path: impl AsRef<Path>, span: u64) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error indexing gzip {}: {}", path.display(), e)
+        );
+        if span == 0 {
+            return Err(TskError::img_format_error(
+                "gzip index span must be greater than 0.".to_string()
+            ));
+        }
+
+        let mut file = File::open(path).map_err(io_error)?;
+        let compressed_size = file_size(&file).map_err(io_error)?;
+        let fingerprint = gzip_fingerprint(&mut file, compressed_size).map_err(io_error)?;
+        file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        let (points, uncompressed_size) = Inflater::build_index(file, span)
+            .map_err(io_error)?;
+
+        let points = points.into_iter()
+            .map(|point| {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+                encoder.write_all(&point.window)?;
+                Ok(GzipIndexPoint {
+                    uncompressed_offset: point.uncompressed_offset,
+                    bit_offset: point.bit_offset,
+                    member_start: point.member_start,
+                    window: encoder.finish()?
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+            .map_err(io_error)?;
+
+        Ok(Self {
+            compressed_size,
+            fingerprint,
+            uncompressed_size,
+            span,
+            points
+        })
+    }
+
+    /// Load an index saved with `GzipIndex::save`
+    ///
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading gzip index {}: {}", path.display(), e)
+        );
+        let data = std::fs::read(path).map_err(io_error)?;
+        let format_error = || TskError::img_format_error(
+            format!("{} is not a valid gzip index.", path.display())
+        );
+
+        let mut cursor = Cursor::new(&data[..]);
+        let mut magic = [0u8; 8];
+        cursor.read_exact(&mut magic).map_err(|_| format_error())?;
+        if &magic != GZIP_INDEX_MAGIC {
+            return Err(format_error());
+        }
+
+        let read_u64 = |cursor: &mut Cursor<&[u8]>| -> Result<u64, TskError> {
+            let mut value = [0u8; 8];
+            cursor.read_exact(&mut value).map_err(|_| format_error())?;
+            Ok(u64::from_le_bytes(value))
+        };
+        let compressed_size = read_u64(&mut cursor)?;
+        let fingerprint = read_u64(&mut cursor)?;
+        let uncompressed_size = read_u64(&mut cursor)?;
+        let span = read_u64(&mut cursor)?;
+        let count = read_u64(&mut cursor)?;
+
+        let mut points = Vec::new();
+        for _ in 0..count {
+            let uncompressed_offset = read_u64(&mut cursor)?;
+            let bit_offset = read_u64(&mut cursor)?;
+            let window_length = read_u64(&mut cursor)?;
+            let member_start = window_length & 1 << 63 > 0;
+            let window_length = (window_length & !(1 << 63)) as usize;
+            if window_length > data.len() - cursor.position() as usize {
+                return Err(format_error());
+            }
+            let mut window = vec![0u8; window_length];
+            cursor.read_exact(&mut window).map_err(|_| format_error())?;
+
+            points.push(GzipIndexPoint {
+                uncompressed_offset,
+                bit_offset,
+                member_start,
+                window
+            });
+        }
+
+        let ordered = points.windows(2)
+            .all(|pair| pair[0].uncompressed_offset < pair[1].uncompressed_offset);
+        if points.first().map(|p| p.uncompressed_offset) != Some(0) || !ordered {
+            return Err(format_error());
+        }
+
+        Ok(Self {
+            compressed_size,
+            fingerprint,
+            uncompressed_size,
+            span,
+            points
+        })
+    }
+
+    /// Save the index to a file
+    ///
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TskError> {
+        let path = path.as_ref();
+        let mut data = Vec::new();
+        data.extend_from_slice(GZIP_INDEX_MAGIC);
+        data.extend_from_slice(&self.compressed_size.to_le_bytes());
+        data.extend_from_slice(&self.fingerprint.to_le_bytes());
+        data.extend_from_slice(&self.uncompressed_size.to_le_bytes());
+        data.extend_from_slice(&self.span.to_le_bytes());
+        data.extend_from_slice(&(self.points.len() as u64).to_le_bytes());
+        for point in self.points.iter() {
+            let mut window_length = point.window.len() as u64;
+            if point.member_start {
+                window_length |= 1 << 63;
+            }
+            data.extend_from_slice(&point.uncompressed_offset.to_le_bytes());
+            data.extend_from_slice(&point.bit_offset.to_le_bytes());
+            data.extend_from_slice(&window_length.to_le_bytes());
+            data.extend_from_slice(&point.window);
+        }
+
+        std::fs::write(path, data).map_err(|e| TskError::img_format_error(
+            format!("Error writing gzip index {}: {}", path.display(), e)
+        ))
+    }
+
+    /// Size of the gzip file the index was built from
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    /// Size of the decompressed data
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// The distance between access points in uncompressed bytes
+    pub fn span(&self) -> u64 {
+        self.span
+    }
+
+    /// Number of access points
+    pub fn point_count(&self) -> usize {
+        self.points.len()
+    }
+}
+impl std::fmt::Debug for GzipIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GzipIndex")
+            .field("compressed_size", &self.compressed_size)
+            .field("uncompressed_size", &self.uncompressed_size)
+            .field("span", &self.span)
+            .field("points", &self.points.len())
+            .finish()
+    }
+}
+
+
+/// An independently decompressable zstd frame or xz block
+#[derive(Debug, Clone)]
+struct Frame {
+    compressed_offset: u64,
+    /// Size in the file, including xz block padding and check
+    compressed_size: u64,
+    uncompressed_offset: u64,
+    uncompressed_size: u64,
+    /// xz block unpadded size and stream flags
+    xz: Option<(u64, [u8; 2])>
+}
+
+
+/// Where decompression of a reader can start
+#[derive(Debug)]
+enum Points {
+    Gzip(GzipIndex),
+    Frames(Vec<Frame>)
+}
+
+
+/// The decoder used by the most recent read
+struct ActiveDecoder {
+    point: usize,
+    position: u64,
+    decoder: Box<dyn Read>
+}
+
+
+/// A Read + Seek reader of a gzip, zstd or xz compressed image.
+///
+/// gzip files are indexed with a `GzipIndex`. zstd files are split at their
+/// frames, using the seek table of the zstd seekable format when there is
+/// one, and xz files at the blocks listed in the stream index. Only one
+/// decoder is kept at a time, so memory use is bounded by the index and a
+/// single frame's decoder state no matter the image size. Sequential reads
+/// continue the current decoder; seeking backwards or into another frame
+/// restarts decompression from the nearest point before the offset.
+///
+pub struct CompressedReader {
+    path: PathBuf,
+    format: CompressedFormat,
+    size: u64,
+    points: Points,
+    active: Option<ActiveDecoder>,
+    /// Offset of the next read in the decompressed image
+    offset: u64
+}
+impl CompressedReader {
+    /// Open a compressed image, detecting the format from its magic. gzip
+    /// files are indexed in memory with `DEFAULT_GZIP_SPAN`.
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let mut header = Vec::new();
+        (&mut file).take(6).read_to_end(&mut header).map_err(io_error)?;
+
+        match CompressedFormat::detect(&header) {
+            Some(CompressedFormat::Gzip) => Self::from_gzip_index(
+                path, GzipIndex::build(path, DEFAULT_GZIP_SPAN)?
+            ),
+            Some(CompressedFormat::Zstd) => Self::open_zstd(path),
+            Some(CompressedFormat::Xz) => Self::open_xz(path),
+            None => Err(TskError::img_format_error(
+                format!("{} is not a gzip, zstd or xz file.", path.display())
+            ))
+        }
+    }
+
+    /// Open a gzip image using a persisted index. The index is loaded from
+    /// index_path if it exists and matches the image, otherwise it is built
+    /// and saved there.
+    ///
+    pub fn open_gzip_indexed(path: impl AsRef<Path>, index_path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let index_path = index_path.as_ref();
+
+        if index_path.exists() {
+            match GzipIndex::load(index_path) {
+                Ok(index) => match Self::from_gzip_index(path, index) {
+                    Ok(reader) => return Ok(reader),
+                    Err(e) => warn!("Rebuilding gzip index {}: {:?}", index_path.display(), e)
+                },
+                Err(e) => warn!("Rebuilding gzip index {}: {:?}", index_path.display(), e)
+            }
+        }
+
+        let index = GzipIndex::build(path, DEFAULT_GZIP_SPAN)?;
+        index.save(index_path)?;
+        Self::from_gzip_index(path, index)
+    }
+
+    /// Open a gzip image with an index built for it. The index is rejected
+    /// when the size or the fingerprint of its first and last bytes do not
+    /// match the file.
+    ///
+    pub fn from_gzip_index(path: impl AsRef<Path>, index: GzipIndex) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading gzip {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let compressed_size = file_size(&file).map_err(io_error)?;
+        if compressed_size != index.compressed_size {
+            return Err(TskError::img_format_error(format!(
+                "gzip index is for a {} byte file but {} is {} bytes.",
+                index.compressed_size, path.display(), compressed_size
+            )));
+        }
+        if gzip_fingerprint(&mut file, compressed_size).map_err(io_error)? != index.fingerprint {
+            return Err(TskError::img_format_error(format!(
+                "gzip index is for another file of the same size as {}.", path.display()
+            )));
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            format: CompressedFormat::Gzip,
+            size: index.uncompressed_size,
+            points: Points::Gzip(index),
+            active: None,
+            offset: 0
+        })
+    }
+
+    /// Open a zstd image. The frames are taken from the seek table of the
+    /// seekable format, or found by walking the frame headers if there is none.
+    ///
+    pub fn open_zstd(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading zstd {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+
+        let frames = match read_zstd_seek_table(&mut file).map_err(io_error)? {
+            Some(frames) => frames,
+            None => {
+                debug!("{} has no zstd seek table, scanning frames.", path.display());
+                scan_zstd_frames(&mut file).map_err(io_error)?
+            }
+        };
+
+        Ok(Self::from_frames(path, CompressedFormat::Zstd, frames))
+    }
+
+    /// Open an xz image. The blocks are taken from the index of each stream.
+    ///
+    pub fn open_xz(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading xz {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let frames = read_xz_blocks(&mut file).map_err(io_error)?;
+
+        Ok(Self::from_frames(path, CompressedFormat::Xz, frames))
+    }
+
+    fn from_frames(path: &Path, format: CompressedFormat, frames: Vec<Frame>) -> Self {
+        let size = frames.last()
+            .map(|f| f.uncompressed_offset + f.uncompressed_size)
+            .unwrap_or(0);
+
+        Self {
+            path: path.to_path_buf(),
+            format,
+            size,
+            points: Points::Frames(frames),
+            active: None,
+            offset: 0
+        }
+    }
+
+    /// The compression format
+    pub fn format(&self) -> CompressedFormat {
+        self.format
+    }
+
+    /// Size of the decompressed image
+    pub fn uncompressed_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Number of points where decompression can start (gzip access points,
+    /// zstd frames or xz blocks)
+    pub fn access_point_count(&self) -> usize {
+        match &self.points {
+            Points::Gzip(index) => index.points.len(),
+            Points::Frames(frames) => frames.len()
+        }
+    }
+
+    /// The path of the compressed file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The index of the last point at or before offset
+    fn point_for(&self, offset: u64) -> usize {
+        match &self.points {
+            Points::Gzip(index) => index.points
+                .partition_point(|p| p.uncompressed_offset <= offset)
+                .saturating_sub(1),
+            Points::Frames(frames) => frames
+                .partition_point(|f| f.uncompressed_offset + f.uncompressed_size <= offset)
+        }
+    }
+
+    /// Uncompressed offset where a point starts
+    fn point_start(&self, point: usize) -> u64 {
+        match &self.points {
+            Points::Gzip(index) => index.points[point].uncompressed_offset,
+            Points::Frames(frames) => frames[point].uncompressed_offset
+        }
+    }
+
+    /// Uncompressed offset where the data of a point's decoder ends
+    fn point_end(&self, point: usize) -> u64 {
+        match &self.points {
+            Points::Gzip(_) => self.size,
+            Points::Frames(frames) => frames[point].uncompressed_offset + frames[point].uncompressed_size
+        }
+    }
+
+    fn open_decoder(&self, point: usize) -> std::io::Result<Box<dyn Read>> {
+        let mut file = File::open(&self.path)?;
+        match &self.points {
+            Points::Gzip(index) => {
+                let point = &index.points[point];
+                let mut window = Vec::new();
+                DeflateDecoder::new(&point.window[..]).read_to_end(&mut window)?;
+                file.seek(SeekFrom::Start(point.bit_offset / 8))?;
+                Ok(Box::new(Inflater::from_point(file, &AccessPoint {
+                    uncompressed_offset: point.uncompressed_offset,
+                    bit_offset: point.bit_offset,
+                    member_start: point.member_start,
+                    window
+                })?))
+            },
+            Points::Frames(frames) => {
+                let frame = &frames[point];
+                file.seek(SeekFrom::Start(frame.compressed_offset))?;
+                let data = file.take(frame.compressed_size);
+                match frame.xz {
+                    None => Ok(Box::new(zstd::stream::read::Decoder::new(data)?.single_frame())),
+                    Some((unpadded_size, flags)) => {
+                        let (header, trailer) = xz_single_block_stream(
+                            flags, unpadded_size, frame.uncompressed_size
+                        );
+                        let stream = Cursor::new(header).chain(data).chain(Cursor::new(trailer));
+                        Ok(Box::new(xz2::read::XzDecoder::new(stream)))
+                    }
+                }
+            }
+        }
+    }
+}
+impl std::fmt::Debug for CompressedReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressedReader")
+            .field("path", &self.path)
+            .field("format", &self.format)
+            .field("size", &self.size)
+            .field("access_points", &self.access_point_count())
+            .finish()
+    }
+}
+impl ImgMetadataSource for CompressedReader {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new(match self.format {
+            CompressedFormat::Gzip => "gzip",
+            CompressedFormat::Zstd => "zstd",
+            CompressedFormat::Xz => "xz"
+        });
+        metadata.description = Some(self.path.display().to_string());
+        metadata.segments.push(self.path.clone());
+        metadata.add_detail("Uncompressed Size", self.size.to_string());
+        metadata.add_detail("Access Points", self.access_point_count().to_string());
+        metadata
+    }
+}
+impl Read for CompressedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let offset = self.offset;
+        let point = self.point_for(offset);
+        let continues = match self.active.as_ref() {
+            Some(active) => active.position <= offset && (
+                active.point == point ||
+                (self.format == CompressedFormat::Gzip && active.position >= self.point_start(point))
+            ),
+            None => false
+        };
+        if !continues {
+            self.active = Some(ActiveDecoder {
+                point,
+                position: self.point_start(point),
+                decoder: self.open_decoder(point)?
+            });
+        }
+
+        let end = self.point_end(self.active.as_ref().unwrap().point);
+        let active = self.active.as_mut().unwrap();
+        let truncated = || std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "compressed data ended before the expected size"
+        );
+
+        let mut scratch = [0u8; 64 * 1024];
+        while active.position < offset {
+            let skip = (offset - active.position).min(scratch.len() as u64) as usize;
+            let read = active.decoder.read(&mut scratch[..skip])?;
+            if read == 0 {
+                return Err(truncated());
+            }
+            active.position += read as u64;
+        }
+
+        let wanted = (end - offset).min(buf.len() as u64) as usize;
+        let read = active.decoder.read(&mut buf[..wanted])?;
+        if read == 0 {
+            return Err(truncated());
+        }
+        active.position += read as u64;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+impl Seek for CompressedReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+        if offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position"
+            ));
+        }
+        self.offset = offset as u64;
+        Ok(self.offset)
+    }
+}
+
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+
+/// Read the seek table of a zstd seekable file, None if there is none
+fn read_zstd_seek_table(file: &mut File) -> std::io::Result<Option<Vec<Frame>>> {
+    let size = file_size(file)?;
+    if size < 9 {
+        return Ok(None);
+    }
+    let mut footer = [0u8; 9];
+    read_exact_at(file, size - 9, &mut footer)?;
+    if u32::from_le_bytes(footer[5..9].try_into().unwrap()) != ZSTD_SEEKABLE_MAGIC {
+        return Ok(None);
+    }
+
+    let frame_count = u32::from_le_bytes(footer[0..4].try_into().unwrap()) as u64;
+    let descriptor = footer[4];
+    let entry_size = if descriptor & 0x80 > 0 { 12 } else { 8 };
+    let table_size = frame_count * entry_size + 9;
+    if table_size + 8 > size {
+        return Err(invalid_data("zstd seek table is larger than the file"));
+    }
+
+    let table_start = size - table_size - 8;
+    let mut table = vec![0u8; (table_size + 8) as usize];
+    read_exact_at(file, table_start, &mut table)?;
+    let magic = u32::from_le_bytes(table[0..4].try_into().unwrap());
+    let frame_size = u32::from_le_bytes(table[4..8].try_into().unwrap()) as u64;
+    if magic != ZSTD_SEEK_TABLE_MAGIC || frame_size != table_size {
+        return Err(invalid_data("invalid zstd seek table frame header"));
+    }
+
+    let mut frames = Vec::new();
+    let mut compressed_offset = 0;
+    let mut uncompressed_offset = 0;
+    for entry in table[8..8 + (frame_count * entry_size) as usize].chunks(entry_size as usize) {
+        let compressed_size = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as u64;
+        let uncompressed_size = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as u64;
+        if uncompressed_size > 0 {
+            frames.push(Frame {
+                compressed_offset,
+                compressed_size,
+                uncompressed_offset,
+                uncompressed_size,
+                xz: None
+            });
+        }
+        compressed_offset += compressed_size;
+        uncompressed_offset += uncompressed_size;
+    }
+    if compressed_offset > table_start {
+        return Err(invalid_data("zstd seek table frames run past the seek table"));
+    }
+
+    Ok(Some(frames))
+}
+
+
+/// Find the frames of a zstd file by walking the frame and block headers.
+/// Frames without a content size are decompressed to measure them.
+fn scan_zstd_frames(file: &mut File) -> std::io::Result<Vec<Frame>> {
+    let size = file_size(file)?;
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    let mut uncompressed_offset = 0;
+
+    while offset < size {
+        let mut header = [0u8; 18];
+        let available = (size - offset).min(header.len() as u64) as usize;
+        read_exact_at(file, offset, &mut header[..available])?;
+        if available < 8 {
+            return Err(invalid_data(format!("truncated zstd frame at offset {}", offset)));
+        }
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+
+        if magic & ZSTD_SKIPPABLE_MASK == ZSTD_SKIPPABLE_MAGIC {
+            offset += 8 + u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+            continue;
+        }
+        if magic != ZSTD_MAGIC {
+            return Err(invalid_data(format!("invalid zstd frame magic {:08x} at offset {}", magic, offset)));
+        }
+
+        let descriptor = header[4];
+        let single_segment = descriptor & 0x20 > 0;
+        let has_checksum = descriptor & 0x04 > 0;
+        let dictionary_id_size = [0usize, 1, 2, 4][(descriptor & 0x3) as usize];
+        let content_size_size = match descriptor >> 6 {
+            0 => if single_segment { 1 } else { 0 },
+            1 => 2,
+            2 => 4,
+            _ => 8
+        };
+        let content_size_offset = 5 + if single_segment { 0 } else { 1 } + dictionary_id_size;
+        let mut content_size = None;
+        if content_size_size > 0 {
+            let mut value = [0u8; 8];
+            value[..content_size_size].copy_from_slice(
+                &header[content_size_offset..content_size_offset + content_size_size]
+            );
+            let mut value = u64::from_le_bytes(value);
+            if content_size_size == 2 {
+                value += 256;
+            }
+            content_size = Some(value);
+        }
+
+        // Walk the blocks to find the end of the frame
+        let mut block_offset = offset + (content_size_offset + content_size_size) as u64;
+        loop {
+            let mut block_header = [0u8; 4];
+            read_exact_at(file, block_offset, &mut block_header[..3])?;
+            let block_header = u32::from_le_bytes(block_header);
+            let is_last = block_header & 1 > 0;
+            let block_size = (block_header >> 3) as u64;
+            block_offset += 3 + match (block_header >> 1) & 0x3 {
+                0 | 2 => block_size,
+                1 => 1,
+                _ => return Err(invalid_data(format!("reserved zstd block type at offset {}", block_offset)))
+            };
+            if is_last {
+                break;
+            }
+        }
+        if has_checksum {
+            block_offset += 4;
+        }
+        if block_offset > size {
+            return Err(invalid_data(format!("truncated zstd frame at offset {}", offset)));
+        }
+
+        let compressed_size = block_offset - offset;
+        let uncompressed_size = match content_size {
+            Some(content_size) => content_size,
+            None => {
+                let mut frame = file.try_clone()?;
+                frame.seek(SeekFrom::Start(offset))?;
+                let mut decoder = zstd::stream::read::Decoder::new(frame.take(compressed_size))?.single_frame();
+                std::io::copy(&mut decoder, &mut std::io::sink())?
+            }
+        };
+
+        if uncompressed_size > 0 {
+            frames.push(Frame {
+                compressed_offset: offset,
+                compressed_size,
+                uncompressed_offset,
+                uncompressed_size,
+                xz: None
+            });
+        }
+        offset = block_offset;
+        uncompressed_offset += uncompressed_size;
+    }
+
+    Ok(frames)
+}
+
+
+/// Size of the integrity check of an xz check type
+fn xz_check_size(check_type: u8) -> u64 {
+    match check_type {
+        0 => 0,
+        1..=3 => 4,
+        4..=6 => 8,
+        7..=9 => 16,
+        10..=12 => 32,
+        _ => 64
+    }
+}
+
+
+/// Decode an xz variable length integer
+fn xz_varint(data: &[u8], position: &mut usize) -> std::io::Result<u64> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *data.get(*position)
+            .ok_or_else(|| invalid_data("truncated xz index"))?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("invalid xz variable length integer"))
+}
+
+
+fn xz_put_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+
+/// Fingerprint a gzip file by the crc32 of its first and last bytes, which
+/// cover the header (with the mtime) of the first member and the crc32 and
+/// size trailer of the last member.
+fn gzip_fingerprint(file: &mut File, size: u64) -> std::io::Result<u64> {
+    let length = size.min(GZIP_FINGERPRINT_SIZE);
+    let mut head = vec![0u8; length as usize];
+    read_exact_at(file, 0, &mut head)?;
+    let mut tail = vec![0u8; length as usize];
+    read_exact_at(file, size - length, &mut tail)?;
+    Ok(((crc32(&head) as u64) << 32) | crc32(&tail) as u64)
+}
+
+
+/// Find the blocks of an xz file from the index of each stream, walking the
+/// streams backwards from the end of the file.
+fn read_xz_blocks(file: &mut File) -> std::io::Result<Vec<Frame>> {
+    let mut end = file_size(file)?;
+    let mut streams = Vec::new();
+
+    while end > 0 {
+        if end < 24 {
+            return Err(invalid_data(format!("truncated xz stream ending at offset {}", end)));
+        }
+        let mut footer = [0u8; 12];
+        read_exact_at(file, end - 12, &mut footer)?;
+        if footer[8..12] == [0u8; 4] {
+            // Stream padding
+            end -= 4;
+            continue;
+        }
+        if &footer[10..12] != XZ_FOOTER_MAGIC || crc32(&footer[4..10]) != u32::from_le_bytes(footer[0..4].try_into().unwrap()) {
+            return Err(invalid_data(format!("invalid xz stream footer at offset {}", end - 12)));
+        }
+        let flags = [footer[8], footer[9]];
+        let index_size = (u32::from_le_bytes(footer[4..8].try_into().unwrap()) as u64 + 1) * 4;
+        if index_size + 24 > end {
+            return Err(invalid_data("xz index is larger than the stream"));
+        }
+        let index_offset = end - 12 - index_size;
+        let mut index = vec![0u8; index_size as usize];
+        read_exact_at(file, index_offset, &mut index)?;
+        let stored_crc = u32::from_le_bytes(index[index.len() - 4..].try_into().unwrap());
+        if index[0] != 0 || crc32(&index[..index.len() - 4]) != stored_crc {
+            return Err(invalid_data(format!("invalid xz index at offset {}", index_offset)));
+        }
+
+        let mut position = 1;
+        let count = xz_varint(&index, &mut position)?;
+        let mut blocks = Vec::new();
+        let mut blocks_size = 0u64;
+        for _ in 0..count {
+            let unpadded_size = xz_varint(&index, &mut position)?;
+            let uncompressed_size = xz_varint(&index, &mut position)?;
+            let padded_size = match unpadded_size.checked_add(3) {
+                Some(size) if unpadded_size > xz_check_size(flags[1] & 0xf) => size & !3,
+                _ => return Err(invalid_data(format!("invalid xz block size in index at offset {}", index_offset)))
+            };
+            blocks.push((blocks_size, padded_size, unpadded_size, uncompressed_size));
+            blocks_size = blocks_size.checked_add(padded_size)
+                .ok_or_else(|| invalid_data("xz blocks are larger than the stream"))?;
+        }
+        if blocks_size.checked_add(12).is_none_or(|size| size > index_offset) {
+            return Err(invalid_data("xz blocks are larger than the stream"));
+        }
+
+        let stream_start = index_offset - blocks_size - 12;
+        let mut header = [0u8; 12];
+        read_exact_at(file, stream_start, &mut header)?;
+        if &header[0..6] != XZ_MAGIC || header[6..8] != flags {
+            return Err(invalid_data(format!("invalid xz stream header at offset {}", stream_start)));
+        }
+
+        streams.push(
+            blocks.into_iter()
+                .map(|(offset, padded_size, unpadded_size, uncompressed_size)| Frame {
+                    compressed_offset: stream_start + 12 + offset,
+                    compressed_size: padded_size,
+                    uncompressed_offset: 0,
+                    uncompressed_size,
+                    xz: Some((unpadded_size, flags))
+                })
+                .collect::<Vec<_>>()
+        );
+        end = stream_start;
+    }
+
+    let mut frames = Vec::new();
+    let mut uncompressed_offset = 0;
+    for stream in streams.into_iter().rev() {
+        for mut frame in stream {
+            frame.uncompressed_offset = uncompressed_offset;
+            uncompressed_offset = uncompressed_offset.checked_add(frame.uncompressed_size)
+                .ok_or_else(|| invalid_data("xz streams are larger than the maximum image size"))?;
+            if frame.uncompressed_size > 0 {
+                frames.push(frame);
+            }
+        }
+    }
+    Ok(frames)
+}
+
+
+/// The stream header and the index and footer that wrap a single xz block
+/// into a stream the decoder accepts.
+fn xz_single_block_stream(flags: [u8; 2], unpadded_size: u64, uncompressed_size: u64) -> (Vec<u8>, Vec<u8>) {
+    let mut header = XZ_MAGIC.to_vec();
+    header.extend_from_slice(&flags);
+    header.extend_from_slice(&crc32(&flags).to_le_bytes());
+
+    let mut index = vec![0u8];
+    xz_put_varint(&mut index, 1);
+    xz_put_varint(&mut index, unpadded_size);
+    xz_put_varint(&mut index, uncompressed_size);
+    while index.len() % 4 != 0 {
+        index.push(0);
+    }
+    let index_crc = crc32(&index);
+    index.extend_from_slice(&index_crc.to_le_bytes());
+
+    let mut footer_fields = ((index.len() / 4 - 1) as u32).to_le_bytes().to_vec();
+    footer_fields.extend_from_slice(&flags);
+    let mut trailer = index;
+    trailer.extend_from_slice(&crc32(&footer_fields).to_le_bytes());
+    trailer.extend_from_slice(&footer_fields);
+    trailer.extend_from_slice(XZ_FOOTER_MAGIC);
+
+    (header, trailer)
+}
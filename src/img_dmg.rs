@@ -0,0 +1,662 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use bzip2::read::BzDecoder;
+use flate2::read::ZlibDecoder;
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::{
+    read_exact_at,
+    file_size
+};
+use crate::img_lzfse::lzfse_decompress;
+
+
+/// UDIF sector size
+const SECTOR_SIZE: u64 = 512;
+/// Size of the koly trailer
+const KOLY_SIZE: usize = 512;
+/// Size of the mish block header before the chunk entries
+const MISH_HEADER_SIZE: usize = 0xcc;
+/// Size of a mish chunk entry
+const MISH_CHUNK_SIZE: usize = 40;
+/// Largest compressed chunk accepted, hdiutil writes chunks of at most 2048 sectors
+const MAX_CHUNK_SECTORS: u64 = 2048;
+/// Magics of encrypted images
+const ENCRYPTED_MAGICS: [&[u8; 8]; 2] = [b"encrcdsa", b"cdsaencr"];
+/// Chunk types
+const CHUNK_ZERO: u32 = 0x0000_0000;
+const CHUNK_RAW: u32 = 0x0000_0001;
+const CHUNK_IGNORE: u32 = 0x0000_0002;
+const CHUNK_ADC: u32 = 0x8000_0004;
+const CHUNK_ZLIB: u32 = 0x8000_0005;
+const CHUNK_BZIP2: u32 = 0x8000_0006;
+const CHUNK_LZFSE: u32 = 0x8000_0007;
+const CHUNK_LZMA: u32 = 0x8000_0008;
+const CHUNK_COMMENT: u32 = 0x7fff_fffe;
+const CHUNK_TERMINATOR: u32 = 0xffff_ffff;
+
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+
+/// A partition (blkx entry) of a DMG
+#[derive(Debug, Clone)]
+pub struct DmgPartition {
+    /// The partition name, for example "Apple_HFS : 2"
+    pub name: String,
+    /// The first sector of the partition in the image
+    pub first_sector: u64,
+    /// The number of sectors of the partition
+    pub sector_count: u64
+}
+
+
+/// A run of sectors stored in one chunk
+#[derive(Debug, Clone)]
+struct Chunk {
+    kind: u32,
+    /// First sector in the image
+    sector: u64,
+    sector_count: u64,
+    /// Offset of the chunk data in the file
+    offset: u64,
+    length: u64
+}
+impl Chunk {
+    fn end_sector(&self) -> u64 {
+        self.sector + self.sector_count
+    }
+}
+
+
+/// A Read + Seek reader of the disk image stored in an Apple UDIF (.dmg)
+/// file. Raw, zero, ADC, zlib, bzip2, LZFSE and LZMA chunks are supported.
+/// Sectors that are not described by any chunk read as zeros.
+///
+pub struct DmgReader {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    partitions: Vec<DmgPartition>,
+    /// Chunks ordered by sector
+    chunks: Vec<Chunk>,
+    /// The most recently decompressed chunk (chunk index, data)
+    chunk_cache: Option<(usize, Vec<u8>)>,
+    /// Offset of the next read in the disk image
+    offset: u64
+}
+impl DmgReader {
+    /// Open a UDIF image
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading dmg {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let file_length = file_size(&file).map_err(io_error)?;
+        if file_length < KOLY_SIZE as u64 {
+            return Err(TskError::img_format_error(
+                format!("{} is too small to be a dmg.", path.display())
+            ));
+        }
+
+        let mut magic = [0u8; 8];
+        read_exact_at(&mut file, 0, &mut magic).map_err(io_error)?;
+        if ENCRYPTED_MAGICS.iter().any(|m| **m == magic) {
+            return Err(TskError::img_format_error(
+                format!("{} is an encrypted dmg, which is not supported.", path.display())
+            ));
+        }
+
+        // The koly trailer is normally at the end of the file, older images
+        // have it at the start
+        let mut koly = [0u8; KOLY_SIZE];
+        read_exact_at(&mut file, file_length - KOLY_SIZE as u64, &mut koly).map_err(io_error)?;
+        if &koly[0..4] != b"koly" {
+            read_exact_at(&mut file, 0, &mut koly).map_err(io_error)?;
+        }
+        if &koly[0..4] != b"koly" {
+            return Err(TskError::img_format_error(
+                format!("{} is not a dmg (no koly trailer).", path.display())
+            ));
+        }
+
+        let be_u32 = |d: &[u8], o: usize| u32::from_be_bytes(d[o..o + 4].try_into().unwrap());
+        let be_u64 = |d: &[u8], o: usize| u64::from_be_bytes(d[o..o + 8].try_into().unwrap());
+
+        let data_fork_offset = be_u64(&koly, 0x18);
+        let segment_count = be_u32(&koly, 0x3c);
+        let xml_offset = be_u64(&koly, 0xd8);
+        let xml_length = be_u64(&koly, 0xe0);
+        let sector_count = be_u64(&koly, 0x1ec);
+
+        if segment_count > 1 {
+            return Err(TskError::img_format_error(
+                format!("{} is segment {} of {}, segmented dmgs are not supported.",
+                    path.display(), be_u32(&koly, 0x38), segment_count)
+            ));
+        }
+        if xml_length == 0 {
+            return Err(TskError::img_format_error(
+                format!("{} has no XML property list, resource fork only dmgs are not supported.", path.display())
+            ));
+        }
+        if xml_offset.checked_add(xml_length).is_none_or(|end| end > file_length) {
+            return Err(TskError::img_format_error(
+                format!("The XML property list of {} is outside of the file.", path.display())
+            ));
+        }
+
+        let mut xml = vec![0u8; xml_length as usize];
+        read_exact_at(&mut file, xml_offset, &mut xml).map_err(io_error)?;
+        let xml = String::from_utf8_lossy(&xml);
+        let entries = read_blkx_entries(&xml).map_err(io_error)?;
+
+        let mut partitions = Vec::with_capacity(entries.len());
+        let mut chunks = Vec::new();
+        for (name, mish) in entries {
+            let (partition, mut partition_chunks) = read_mish(&name, &mish, data_fork_offset).map_err(io_error)?;
+            if let Some(chunk) = partition_chunks.iter()
+                .find(|c| c.offset.checked_add(c.length).is_none_or(|end| end > file_length)) {
+                return Err(TskError::img_format_error(
+                    format!("A chunk of {} in {} is outside of the file (offset {}).",
+                        partition.name, path.display(), chunk.offset)
+                ));
+            }
+            partitions.push(partition);
+            chunks.append(&mut partition_chunks);
+        }
+        chunks.sort_by_key(|c| c.sector);
+
+        let size = partitions.iter()
+            .map(|p| p.first_sector + p.sector_count)
+            .chain(std::iter::once(sector_count))
+            .max()
+            .unwrap_or(0)
+            .checked_mul(SECTOR_SIZE)
+            .ok_or(TskError::img_format_error(
+                format!("{} has an invalid sector count {}.", path.display(), sector_count)
+            ))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            partitions,
+            chunks,
+            chunk_cache: None,
+            offset: 0
+        })
+    }
+
+    /// The size of the disk image
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The partitions (blkx entries) of the image
+    pub fn partitions(&self) -> &[DmgPartition] {
+        &self.partitions
+    }
+
+    /// The path of the dmg file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the decompressed data of a chunk
+    fn chunk_data(&mut self, index: usize) -> std::io::Result<&[u8]> {
+        if self.chunk_cache.as_ref().map(|c| c.0) != Some(index) {
+            let chunk = &self.chunks[index];
+            let mut compressed = vec![0u8; chunk.length as usize];
+            read_exact_at(&mut self.file, chunk.offset, &mut compressed)?;
+            let expected = (chunk.sector_count * SECTOR_SIZE) as usize;
+            let mut data = match chunk.kind {
+                CHUNK_ADC => adc_decompress(&compressed, expected)?,
+                CHUNK_ZLIB => {
+                    let mut data = Vec::with_capacity(expected);
+                    ZlibDecoder::new(&compressed[..]).take(expected as u64).read_to_end(&mut data)?;
+                    data
+                },
+                CHUNK_BZIP2 => {
+                    let mut data = Vec::with_capacity(expected);
+                    BzDecoder::new(&compressed[..]).take(expected as u64).read_to_end(&mut data)?;
+                    data
+                },
+                CHUNK_LZFSE => lzfse_decompress(&compressed, expected)?,
+                CHUNK_LZMA => {
+                    let stream = xz2::stream::Stream::new_auto_decoder(u64::MAX, 0)
+                        .map_err(|e| invalid_data(format!("lzma: {}", e)))?;
+                    let mut data = Vec::with_capacity(expected);
+                    xz2::read::XzDecoder::new_stream(&compressed[..], stream)
+                        .take(expected as u64)
+                        .read_to_end(&mut data)?;
+                    data
+                },
+                kind => return Err(invalid_data(format!("unsupported dmg chunk type 0x{:08x}", kind)))
+            };
+            if data.len() < expected {
+                debug!("dmg chunk at offset {} decompressed to {} of {} bytes", chunk.offset, data.len(), expected);
+            }
+            data.resize(expected, 0);
+            self.chunk_cache = Some((index, data));
+        }
+        Ok(&self.chunk_cache.as_ref().unwrap().1)
+    }
+
+    /// Read from the chunk containing offset, or zeros up to the next chunk
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let sector = offset / SECTOR_SIZE;
+        let index = self.chunks.partition_point(|c| c.end_sector() <= sector);
+        let chunk = match self.chunks.get(index) {
+            Some(chunk) if chunk.sector <= sector => chunk,
+            next => {
+                let end = next.map(|c| c.sector * SECTOR_SIZE).unwrap_or(self.size);
+                let len = ((end - offset) as usize).min(buf.len());
+                buf[..len].iter_mut().for_each(|b| *b = 0);
+                return Ok(len);
+            }
+        };
+
+        let chunk_start = chunk.sector * SECTOR_SIZE;
+        let within = (offset - chunk_start) as usize;
+        let len = ((chunk.sector_count * SECTOR_SIZE) as usize - within).min(buf.len());
+        match chunk.kind {
+            CHUNK_ZERO | CHUNK_IGNORE => buf[..len].iter_mut().for_each(|b| *b = 0),
+            CHUNK_RAW => {
+                let available = (chunk.length as usize).saturating_sub(within).min(len);
+                let file_offset = chunk.offset + within as u64;
+                read_exact_at(&mut self.file, file_offset, &mut buf[..available])?;
+                buf[available..len].iter_mut().for_each(|b| *b = 0);
+            },
+            _ => {
+                let data = self.chunk_data(index)?;
+                buf[..len].copy_from_slice(&data[within..within + len]);
+            }
+        }
+        Ok(len)
+    }
+}
+impl std::fmt::Debug for DmgReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DmgReader")
+         .field("path", &self.path)
+         .field("size", &self.size)
+         .field("partitions", &self.partitions)
+         .field("chunks", &self.chunks.len())
+         .finish()
+    }
+}
+impl ImgMetadataSource for DmgReader {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("DMG (UDIF)");
+        metadata.description = Some(self.path.display().to_string());
+        metadata.segments.push(self.path.clone());
+        for partition in &self.partitions {
+            metadata.add_detail(
+                "Partition",
+                format!("{} (sector {}, {} sectors)", partition.name, partition.first_sector, partition.sector_count)
+            );
+        }
+        metadata
+    }
+}
+impl Read for DmgReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = ((self.size - self.offset) as usize).min(buf.len());
+
+        let bytes_read = self.read_at(self.offset, &mut buf[..remaining])?;
+        self.offset += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl Seek for DmgReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+
+/// A Read + Seek reader of a sparsebundle directory. The image is stored in
+/// band files of `bands/` named by their index in hex. Missing bands and the
+/// unwritten end of a band read as zeros.
+///
+pub struct SparseBundleReader {
+    path: PathBuf,
+    size: u64,
+    band_size: u64,
+    /// The open band (band index, file, file size)
+    band: Option<(u64, Option<File>, u64)>,
+    /// Offset of the next read in the bundle
+    offset: u64
+}
+impl SparseBundleReader {
+    /// Open a sparsebundle directory
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let info_path = path.join("Info.plist");
+        let info = std::fs::read(&info_path).map_err(|e| TskError::img_format_error(
+            format!("Error reading sparsebundle {}: {}", info_path.display(), e)
+        ))?;
+        let info = String::from_utf8_lossy(&info);
+
+        let integer = |key: &str| plist_value(&info, key, "integer")
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .ok_or_else(|| TskError::img_format_error(
+                format!("{} has no valid {} integer.", info_path.display(), key)
+            ));
+        let band_size = integer("band-size")?;
+        let size = integer("size")?;
+        if band_size == 0 {
+            return Err(TskError::img_format_error(
+                format!("{} has a band size of 0.", info_path.display())
+            ));
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            size,
+            band_size,
+            band: None,
+            offset: 0
+        })
+    }
+
+    /// The size of the disk image
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The size of the band files
+    pub fn band_size(&self) -> u64 {
+        self.band_size
+    }
+
+    /// The path of the sparsebundle directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read from the band containing offset
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let band_index = offset / self.band_size;
+        let within = offset % self.band_size;
+        let len = ((self.band_size - within) as usize).min(buf.len());
+
+        if self.band.as_ref().map(|b| b.0) != Some(band_index) {
+            let band_path = self.path.join("bands").join(format!("{:x}", band_index));
+            let band = match File::open(&band_path) {
+                Ok(file) => {
+                    let band_length = file_size(&file)?;
+                    (band_index, Some(file), band_length)
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (band_index, None, 0),
+                Err(e) => return Err(e)
+            };
+            self.band = Some(band);
+        }
+
+        let (_, file, band_length) = self.band.as_mut().unwrap();
+        let available = band_length.saturating_sub(within).min(len as u64) as usize;
+        if let Some(file) = file {
+            read_exact_at(file, within, &mut buf[..available])?;
+        }
+        buf[available..len].iter_mut().for_each(|b| *b = 0);
+        Ok(len)
+    }
+}
+impl std::fmt::Debug for SparseBundleReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SparseBundleReader")
+         .field("path", &self.path)
+         .field("size", &self.size)
+         .field("band_size", &self.band_size)
+         .finish()
+    }
+}
+impl ImgMetadataSource for SparseBundleReader {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("sparsebundle");
+        metadata.description = Some(self.path.display().to_string());
+        metadata.add_detail("Band Size", self.band_size.to_string());
+        metadata
+    }
+}
+impl Read for SparseBundleReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = ((self.size - self.offset) as usize).min(buf.len());
+
+        let bytes_read = self.read_at(self.offset, &mut buf[..remaining])?;
+        self.offset += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl Seek for SparseBundleReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+
+/// Get the text of the `<tag>` element that follows `<key>key</key>`
+fn plist_value<'a>(xml: &'a str, key: &str, tag: &str) -> Option<&'a str> {
+    let key_element = format!("<key>{}</key>", key);
+    let after_key = &xml[xml.find(&key_element)? + key_element.len()..];
+    let open = format!("<{}>", tag);
+    let value = after_key.trim_start().strip_prefix(&open)?;
+    let close = format!("</{}>", tag);
+    Some(&value[..value.find(&close)?])
+}
+
+
+/// Replace the predefined XML entities
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+
+/// Decode base64, ignoring whitespace
+fn base64_decode(text: &str) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let mut accum = 0u32;
+    let mut accum_bits = 0;
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ if byte.is_ascii_whitespace() => continue,
+            _ => return Err(invalid_data(format!("invalid base64 character 0x{:02x}", byte)))
+        };
+        accum = accum << 6 | value as u32;
+        accum_bits += 6;
+        if accum_bits >= 8 {
+            accum_bits -= 8;
+            data.push((accum >> accum_bits) as u8);
+        }
+    }
+    Ok(data)
+}
+
+
+/// Get the (name, mish data) of the blkx entries of the XML property list
+fn read_blkx_entries(xml: &str) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    let blkx = &xml[xml.find("<key>blkx</key>")
+        .ok_or_else(|| invalid_data("the property list has no blkx key"))?..];
+    let array_start = blkx.find("<array>")
+        .ok_or_else(|| invalid_data("the blkx key has no array"))?;
+    let array_end = blkx.find("</array>")
+        .ok_or_else(|| invalid_data("the blkx array is not terminated"))?;
+    let mut array = &blkx[array_start..array_end];
+
+    let mut entries = Vec::new();
+    while let Some(start) = array.find("<dict>") {
+        let end = array[start..].find("</dict>")
+            .ok_or_else(|| invalid_data("a blkx entry is not terminated"))? + start;
+        let dict = &array[start..end];
+        let data = plist_value(dict, "Data", "data")
+            .ok_or_else(|| invalid_data("a blkx entry has no Data"))?;
+        let name = plist_value(dict, "CFName", "string")
+            .or_else(|| plist_value(dict, "Name", "string"))
+            .map(xml_unescape)
+            .unwrap_or_default();
+        entries.push((name, base64_decode(data)?));
+        array = &array[end..];
+    }
+    Ok(entries)
+}
+
+
+/// Read a mish block into its partition and chunks
+fn read_mish(name: &str, mish: &[u8], data_fork_offset: u64) -> std::io::Result<(DmgPartition, Vec<Chunk>)> {
+    if mish.len() < MISH_HEADER_SIZE || &mish[0..4] != b"mish" {
+        return Err(invalid_data(format!("invalid mish block for {}", name)));
+    }
+    let be_u32 = |o: usize| u32::from_be_bytes(mish[o..o + 4].try_into().unwrap());
+    let be_u64 = |o: usize| u64::from_be_bytes(mish[o..o + 8].try_into().unwrap());
+
+    let first_sector = be_u64(0x08);
+    let sector_count = be_u64(0x10);
+    let data_offset = be_u64(0x18);
+    let chunk_count = be_u32(0xc8) as usize;
+    if mish.len() < MISH_HEADER_SIZE + chunk_count * MISH_CHUNK_SIZE {
+        return Err(invalid_data(format!("truncated mish block for {}", name)));
+    }
+
+    // Sector numbers must stay addressable as byte offsets
+    let sectors_end = |first: u64, count: u64| first.checked_add(count)
+        .filter(|end| end.checked_mul(SECTOR_SIZE).is_some());
+    if sectors_end(first_sector, sector_count).is_none() {
+        return Err(invalid_data(format!("{} has an invalid sector range", name)));
+    }
+
+    let mut chunks = Vec::with_capacity(chunk_count);
+    for i in 0..chunk_count {
+        let entry = MISH_HEADER_SIZE + i * MISH_CHUNK_SIZE;
+        let kind = be_u32(entry);
+        match kind {
+            CHUNK_COMMENT | CHUNK_TERMINATOR => continue,
+            CHUNK_ZERO | CHUNK_RAW | CHUNK_IGNORE => (),
+            CHUNK_ADC | CHUNK_ZLIB | CHUNK_BZIP2 | CHUNK_LZFSE | CHUNK_LZMA => {
+                if be_u64(entry + 16) > MAX_CHUNK_SECTORS {
+                    return Err(invalid_data(format!(
+                        "{} has a compressed chunk of {} sectors, at most {} are supported",
+                        name, be_u64(entry + 16), MAX_CHUNK_SECTORS
+                    )));
+                }
+            },
+            kind => return Err(invalid_data(format!("{} has an unsupported chunk type 0x{:08x}", name, kind)))
+        }
+        let sector = first_sector.checked_add(be_u64(entry + 8))
+            .filter(|sector| sectors_end(*sector, be_u64(entry + 16)).is_some())
+            .ok_or_else(|| invalid_data(format!("{} has a chunk with an invalid sector range", name)))?;
+        let offset = data_fork_offset.checked_add(data_offset)
+            .and_then(|o| o.checked_add(be_u64(entry + 24)))
+            .ok_or_else(|| invalid_data(format!("{} has a chunk with an invalid offset", name)))?;
+        let chunk = Chunk {
+            kind,
+            sector,
+            sector_count: be_u64(entry + 16),
+            offset,
+            length: be_u64(entry + 32)
+        };
+        if chunk.sector_count > 0 {
+            chunks.push(chunk);
+        }
+    }
+
+    Ok((
+        DmgPartition {
+            name: name.to_string(),
+            first_sector,
+            sector_count
+        },
+        chunks
+    ))
+}
+
+
+/// Decompress an Apple Data Compression (ADC) chunk
+fn adc_decompress(data: &[u8], max_size: usize) -> std::io::Result<Vec<u8>> {
+    let truncated = || invalid_data("adc chunk is truncated");
+    let mut output = Vec::with_capacity(max_size);
+    let mut position = 0;
+
+    while position < data.len() && output.len() < max_size {
+        let byte = data[position];
+        let (length, distance) = if byte & 0x80 != 0 {
+            let length = (byte & 0x7f) as usize + 1;
+            let literal = data.get(position + 1..position + 1 + length).ok_or_else(truncated)?;
+            output.extend_from_slice(literal);
+            position += 1 + length;
+            continue;
+        } else if byte & 0x40 != 0 {
+            let distance = data.get(position + 1..position + 3).ok_or_else(truncated)?;
+            position += 3;
+            ((byte & 0x3f) as usize + 4, u16::from_be_bytes([distance[0], distance[1]]) as usize + 1)
+        } else {
+            let low = *data.get(position + 1).ok_or_else(truncated)? as usize;
+            position += 2;
+            (((byte & 0x3f) >> 2) as usize + 3, (((byte & 3) as usize) << 8 | low) + 1)
+        };
+
+        if distance > output.len() {
+            return Err(invalid_data("adc match distance is outside of the output"));
+        }
+        let start = output.len() - distance;
+        for i in 0..length {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+
+    output.truncate(max_size);
+    Ok(output)
+}
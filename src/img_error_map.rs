@@ -0,0 +1,253 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use crate::errors::TskError;
+use crate::img_common::{read_fill, RangeSet, SetRange};
+use crate::tsk_img_reader::ReadSeek;
+
+
+/// What to do when a range of the source can not be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BadSectorPolicy {
+    /// Fail the read, which fails the libtsk call that needed it
+    #[default]
+    Fail,
+    /// Retry the failed read sector by sector and read the unreadable
+    /// sectors as zeros
+    ZeroFill,
+    /// Read the whole failed read as zeros without retrying it. This is
+    /// faster on media where every failing read is slow, but loses the
+    /// readable sectors around the bad ones. Below a block cache a failed
+    /// read is a whole cache block (and its read-ahead).
+    Skip
+}
+
+
+/// A range of the image that could not be read and was substituted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorRange {
+    /// Offset of the range in the image
+    pub offset: u64,
+    /// Length of the range in bytes
+    pub length: u64,
+    /// The error of the first failed read of the range
+    pub error: String
+}
+impl ErrorRange {
+    /// The offset following the range
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+}
+impl SetRange for ErrorRange {
+    fn bounds(&self) -> (u64, u64) {
+        (self.offset, self.end())
+    }
+
+    fn with_bounds(&self, offset: u64, end: u64) -> Self {
+        ErrorRange { offset, length: end - offset, error: self.error.clone() }
+    }
+}
+
+
+/// The ranges of an image that were substituted because they could not be
+/// read. Adjacent and overlapping ranges are merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorMap {
+    ranges: RangeSet<ErrorRange>
+}
+impl ErrorMap {
+    /// The substituted ranges ordered by offset
+    pub fn ranges(&self) -> &[ErrorRange] {
+        self.ranges.ranges()
+    }
+
+    /// True if no range was substituted
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The number of bytes that were substituted
+    pub fn total_bytes(&self) -> u64 {
+        self.ranges.covered_bytes()
+    }
+
+    /// The ranges that overlap [offset, offset + length)
+    pub fn ranges_in(&self, offset: u64, length: u64) -> &[ErrorRange] {
+        self.ranges.ranges_in(offset, length)
+    }
+
+    /// True if any byte of [offset, offset + length) was substituted. Use it
+    /// with the image offsets of the runs of a file to find out whether the
+    /// file touched unreadable sectors.
+    pub fn overlaps(&self, offset: u64, length: u64) -> bool {
+        !self.ranges_in(offset, length).is_empty()
+    }
+
+    /// Add a range, merging it with the ranges it touches
+    pub fn insert(&mut self, offset: u64, length: u64, error: impl Into<String>) {
+        self.ranges.insert(ErrorRange { offset, length, error: error.into() });
+    }
+
+    /// Forget all ranges
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+}
+
+
+/// The policy and error map shared between a BadSectorReader and the
+/// images reading through it
+#[derive(Debug, Default)]
+pub(crate) struct BadSectorState {
+    policy: Mutex<BadSectorPolicy>,
+    map: Mutex<ErrorMap>
+}
+impl BadSectorState {
+    pub(crate) fn policy(&self) -> BadSectorPolicy {
+        *self.policy.lock().unwrap()
+    }
+
+    pub(crate) fn set_policy(&self, policy: BadSectorPolicy) {
+        *self.policy.lock().unwrap() = policy;
+    }
+
+    pub(crate) fn error_map(&self) -> ErrorMap {
+        self.map.lock().unwrap().clone()
+    }
+
+    fn record(&self, offset: u64, length: u64, error: &std::io::Error) {
+        warn!("Substituting {} unreadable bytes at offset {}: {}", length, offset, error);
+        self.map.lock().unwrap().insert(offset, length, error.to_string());
+    }
+}
+
+
+/// A Read + Seek wrapper that applies a BadSectorPolicy to the read errors of
+/// its stream and records the substituted ranges in an ErrorMap
+///
+pub struct BadSectorReader {
+    stream: Box<dyn ReadSeek>,
+    size: u64,
+    sector_size: u64,
+    state: Arc<BadSectorState>,
+    /// Where the next read starts in the stream
+    offset: u64
+}
+impl BadSectorReader {
+    /// Wrap a stream of size bytes. Failed reads are retried in units of
+    /// sector_size with the ZeroFill policy.
+    ///
+    pub fn new(stream: Box<dyn ReadSeek>, size: u64, sector_size: u32, policy: BadSectorPolicy) -> Result<Self, TskError> {
+        if sector_size == 0 {
+            return Err(TskError::generic(
+                "The sector size can not be 0.".to_string()
+            ));
+        }
+        let state = BadSectorState::default();
+        state.set_policy(policy);
+        Ok(Self {
+            stream,
+            size,
+            sector_size: sector_size as u64,
+            state: Arc::new(state),
+            offset: 0
+        })
+    }
+
+    /// The policy for unreadable ranges
+    pub fn policy(&self) -> BadSectorPolicy {
+        self.state.policy()
+    }
+
+    /// Change the policy for unreadable ranges
+    pub fn set_policy(&self, policy: BadSectorPolicy) {
+        self.state.set_policy(policy)
+    }
+
+    /// The ranges substituted so far
+    pub fn error_map(&self) -> ErrorMap {
+        self.state.error_map()
+    }
+
+    pub(crate) fn state(&self) -> Arc<BadSectorState> {
+        self.state.clone()
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.seek(SeekFrom::Start(offset))?;
+        read_fill(&mut self.stream, buf)
+    }
+
+    /// Read buf at offset, retrying sector by sector
+    fn read_sectors(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let sector_end = (position / self.sector_size + 1) * self.sector_size;
+            let len = ((sector_end - position) as usize).min(buf.len() - done);
+            match self.read_at(position, &mut buf[done..done + len]) {
+                Ok(n) if n < len => return Ok(done + n),
+                Ok(_) => {},
+                Err(e) => {
+                    buf[done..done + len].iter_mut().for_each(|b| *b = 0);
+                    self.state.record(position, len as u64, &e);
+                }
+            }
+            done += len;
+        }
+        Ok(done)
+    }
+}
+impl std::fmt::Debug for BadSectorReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BadSectorReader")
+         .field("size", &self.size)
+         .field("sector_size", &self.sector_size)
+         .field("policy", &self.policy())
+         .field("error_map", &self.error_map())
+         .finish()
+    }
+}
+impl Read for BadSectorReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = ((self.size - self.offset) as usize).min(buf.len());
+        let offset = self.offset;
+
+        let bytes_read = match self.read_at(offset, &mut buf[..remaining]) {
+            Ok(n) => n,
+            Err(e) => match self.policy() {
+                BadSectorPolicy::Fail => return Err(e),
+                BadSectorPolicy::Skip => {
+                    buf[..remaining].iter_mut().for_each(|b| *b = 0);
+                    self.state.record(offset, remaining as u64, &e);
+                    remaining
+                },
+                BadSectorPolicy::ZeroFill => self.read_sectors(offset, &mut buf[..remaining])?
+            }
+        };
+        self.offset += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl Seek for BadSectorReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
@@ -0,0 +1,1105 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use flate2::read::ZlibDecoder;
+use md5::{Md5, Digest};
+use sha1::Sha1;
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::{read_exact_at, to_hex};
+
+
+/// EWF (E01) file header signature
+const EVF_SIGNATURE: &[u8; 8] = b"EVF\x09\x0d\x0a\xff\x00";
+/// EWF (L01) logical evidence file header signature
+const LVF_SIGNATURE: &[u8; 8] = b"LVF\x09\x0d\x0a\xff\x00";
+/// EWF2 (Ex01) file header signature
+const EVF2_SIGNATURE: &[u8; 8] = b"EVF2\x0d\x0a\x81\x00";
+
+/// Size of an EWF section descriptor
+const SECTION_DESCRIPTOR_SIZE: u64 = 76;
+/// Size of an EWF2 section descriptor
+const SECTION_DESCRIPTOR_SIZE_V2: u64 = 64;
+/// Size of the EWF2 file header
+const FILE_HEADER_SIZE_V2: u64 = 32;
+
+/// EWF2 section types
+const V2_SECTION_DEVICE_INFORMATION: u32 = 0x01;
+const V2_SECTION_CASE_DATA: u32 = 0x02;
+const V2_SECTION_SECTOR_TABLE: u32 = 0x04;
+const V2_SECTION_ERROR_TABLE: u32 = 0x05;
+const V2_SECTION_MD5_HASH: u32 = 0x08;
+const V2_SECTION_SHA1_HASH: u32 = 0x09;
+
+/// EWF2 section data flag for encrypted data
+const V2_SECTION_FLAG_ENCRYPTED: u32 = 0x02;
+
+/// EWF2 chunk flags
+const V2_CHUNK_COMPRESSED: u32 = 0x01;
+const V2_CHUNK_HAS_CHECKSUM: u32 = 0x02;
+const V2_CHUNK_PATTERN_FILL: u32 = 0x04;
+
+/// Largest chunk size accepted when opening an image
+const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+/// Largest decompressed size of a header or case data section
+const MAX_HEADER_SIZE: u64 = 16 * 1024 * 1024;
+
+
+/// Compute the adler32 checksum EWF uses for descriptors, tables and chunks.
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    // 5552 is the largest n such that the sums do not overflow a u32
+    for block in data.chunks(5552) {
+        for byte in block {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+
+    (b << 16) | a
+}
+
+
+/// The EWF flavour of a segment set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EwfFormat {
+    /// EWF version 1 (E01, S01, L01)
+    Ewf1,
+    /// EWF version 2 (Ex01, Lx01)
+    Ewf2
+}
+
+
+/// Compression method used by the chunks of an EWF2 image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EwfCompression {
+    Deflate,
+    Bzip2
+}
+
+
+/// Acquisition and media metadata stored in an EWF image
+#[derive(Debug, Clone, Default)]
+pub struct EwfMetadata {
+    /// Case number (c / cn)
+    pub case_number: Option<String>,
+    /// Evidence number (n / en)
+    pub evidence_number: Option<String>,
+    /// Description (a / de)
+    pub description: Option<String>,
+    /// Examiner name (e / ex)
+    pub examiner_name: Option<String>,
+    /// Notes (t / nt)
+    pub notes: Option<String>,
+    /// Version of the acquisition software (av)
+    pub acquisition_software_version: Option<String>,
+    /// Operating system used during acquisition (ov / os)
+    pub acquisition_os: Option<String>,
+    /// Acquisition date as stored in the image (m / at)
+    pub acquisition_date: Option<String>,
+    /// System date as stored in the image (u)
+    pub system_date: Option<String>,
+    /// Media model (md), EWF2 only
+    pub model: Option<String>,
+    /// Media serial number (sn), EWF2 only
+    pub serial_number: Option<String>,
+    /// The EWF media type (removable, fixed, optical, logical, memory)
+    pub media_type: Option<u8>,
+    /// Bytes per sector
+    pub bytes_per_sector: u32,
+    /// Number of sectors
+    pub sector_count: u64,
+    /// Sectors per chunk
+    pub sectors_per_chunk: u32,
+    /// The segment set identifier (GUID)
+    pub set_identifier: Option<[u8; 16]>,
+    /// MD5 of the media stored at acquisition time
+    pub stored_md5: Option<[u8; 16]>,
+    /// SHA1 of the media stored at acquisition time
+    pub stored_sha1: Option<[u8; 20]>,
+    /// Sector ranges (first sector, number of sectors) that could not be read at acquisition time
+    pub acquisition_errors: Vec<(u64, u64)>,
+    /// Every identifier/value pair of the header sections
+    pub header_values: BTreeMap<String, String>
+}
+impl EwfMetadata {
+    /// Get the stored MD5 as a hex string
+    pub fn stored_md5_hex(&self) -> Option<String> {
+        self.stored_md5.as_ref().map(|h| to_hex(h))
+    }
+
+    /// Get the stored SHA1 as a hex string
+    pub fn stored_sha1_hex(&self) -> Option<String> {
+        self.stored_sha1.as_ref().map(|h| to_hex(h))
+    }
+
+    /// Set the named fields from the parsed identifier/value pairs. The first
+    /// identifier of each list that has a value wins.
+    fn apply_header_values(&mut self) {
+        fn lookup(values: &BTreeMap<String, String>, keys: &[&str]) -> Option<String> {
+            keys.iter()
+                .filter_map(|k| values.get(*k))
+                .find(|v| !v.is_empty())
+                .cloned()
+        }
+
+        let values = &self.header_values;
+        self.case_number = lookup(values, &["c", "cn"]);
+        self.evidence_number = lookup(values, &["n", "en"]);
+        self.description = lookup(values, &["a", "de"]);
+        self.examiner_name = lookup(values, &["e", "ex"]);
+        self.notes = lookup(values, &["t", "nt"]);
+        self.acquisition_software_version = lookup(values, &["av"]);
+        self.acquisition_os = lookup(values, &["ov", "os"]);
+        self.acquisition_date = lookup(values, &["m", "at"]);
+        self.system_date = lookup(values, &["u"]);
+        self.model = lookup(values, &["md"]);
+        self.serial_number = lookup(values, &["sn"]);
+    }
+}
+
+
+/// The result of comparing the stored EWF hashes against the media data
+#[derive(Debug, Clone)]
+pub struct EwfVerification {
+    /// MD5 computed over the media data
+    pub computed_md5: [u8; 16],
+    /// SHA1 computed over the media data
+    pub computed_sha1: [u8; 20],
+    /// MD5 stored in the image
+    pub stored_md5: Option<[u8; 16]>,
+    /// SHA1 stored in the image
+    pub stored_sha1: Option<[u8; 20]>
+}
+impl EwfVerification {
+    /// Some(true) if the stored MD5 matches, None if no MD5 was stored
+    pub fn md5_matches(&self) -> Option<bool> {
+        self.stored_md5.map(|h| h == self.computed_md5)
+    }
+
+    /// Some(true) if the stored SHA1 matches, None if no SHA1 was stored
+    pub fn sha1_matches(&self) -> Option<bool> {
+        self.stored_sha1.map(|h| h == self.computed_sha1)
+    }
+
+    /// True if at least one hash was stored and every stored hash matches
+    pub fn is_verified(&self) -> bool {
+        let results: Vec<bool> = vec![self.md5_matches(), self.sha1_matches()]
+            .into_iter()
+            .flatten()
+            .collect();
+        !results.is_empty() && results.iter().all(|m| *m)
+    }
+}
+
+
+/// A single segment file of an EWF set
+#[derive(Debug)]
+struct EwfSegment {
+    path: PathBuf,
+    file: File,
+    size: u64
+}
+
+
+/// A chunk table. The table entries are read when a chunk of the table is
+/// needed so that large images do not need the whole table in memory.
+#[derive(Debug, Clone)]
+struct EwfTable {
+    /// Index of the segment that contains the table and its chunks
+    segment: usize,
+    /// First chunk number described by this table
+    first_chunk: u64,
+    /// Number of entries in the table
+    entry_count: u64,
+    /// Offset of the first entry in the segment file
+    entries_offset: u64,
+    /// Base offset for the (EWF1) chunk offsets
+    base_offset: u64,
+    /// Offset where the chunk data of the last entry ends (EWF1)
+    chunks_end: u64
+}
+
+
+/// The location of the data of a single chunk
+#[derive(Debug, Clone, Copy)]
+enum ChunkLocation {
+    /// Chunk data stored in a segment file
+    Stored {
+        segment: usize,
+        offset: u64,
+        size: u64,
+        compressed: bool,
+        has_checksum: bool
+    },
+    /// The chunk is filled with an 8 byte pattern (EWF2)
+    Pattern([u8; 8])
+}
+
+
+/// A Read + Seek reader of the media data stored in an EWF (E01/Ex01) segment
+/// set. It can be handed to `TskImgReadSeek::from_read_seek`.
+///
+pub struct EwfReader {
+    format: EwfFormat,
+    compression: EwfCompression,
+    segments: Vec<EwfSegment>,
+    tables: Vec<EwfTable>,
+    metadata: EwfMetadata,
+    chunk_size: u64,
+    chunk_count: u64,
+    media_size: u64,
+    /// Entries of the most recently used table (table index, raw entries)
+    table_cache: Option<(usize, Vec<u8>)>,
+    /// The most recently read chunk (chunk number, data)
+    chunk_cache: Option<(u64, Vec<u8>)>,
+    /// Offset of the next read in the media
+    offset: u64
+}
+impl EwfReader {
+    /// Open an EWF segment set from its first segment file (.E01/.Ex01/.s01).
+    /// The remaining segments are discovered with `find_ewf_segments`.
+    ///
+    pub fn open(first_segment: impl AsRef<Path>) -> Result<Self, TskError> {
+        let segments = find_ewf_segments(first_segment);
+        Self::from_segments(&segments)
+    }
+
+    /// Open an EWF segment set from a list of segment files. The segments are
+    /// ordered by the segment number stored in their file header.
+    ///
+    pub fn from_segments<P: AsRef<Path>>(paths: &[P]) -> Result<Self, TskError> {
+        if paths.is_empty() {
+            return Err(TskError::img_format_error(
+                "Unable to open EWF image: no segments were provided.".to_string()
+            ));
+        }
+
+        // Open each segment and read its file header
+        let mut format = None;
+        let mut compression = EwfCompression::Deflate;
+        let mut numbered_segments = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = path.as_ref();
+            let mut file = File::open(path)
+                .map_err(|e| TskError::img_format_error(
+                    format!("Unable to open EWF segment {}: {}", path.display(), e)
+                ))?;
+            let size = file.metadata()
+                .map_err(|e| TskError::img_format_error(
+                    format!("Unable to get size of EWF segment {}: {}", path.display(), e)
+                ))?.len();
+
+            let mut header = [0u8; 32];
+            read_exact_at(&mut file, 0, &mut header[0..13])
+                .map_err(|e| TskError::img_format_error(
+                    format!("Unable to read EWF header of {}: {}", path.display(), e)
+                ))?;
+
+            let (segment_format, segment_number) = if &header[0..8] == EVF_SIGNATURE || &header[0..8] == LVF_SIGNATURE {
+                (EwfFormat::Ewf1, u16::from_le_bytes([header[9], header[10]]) as u32)
+            } else if &header[0..8] == EVF2_SIGNATURE {
+                read_exact_at(&mut file, 0, &mut header)
+                    .map_err(|e| TskError::img_format_error(
+                        format!("Unable to read EWF2 header of {}: {}", path.display(), e)
+                    ))?;
+                compression = match u16::from_le_bytes([header[10], header[11]]) {
+                    2 => EwfCompression::Bzip2,
+                    _ => EwfCompression::Deflate
+                };
+                (EwfFormat::Ewf2, u32::from_le_bytes(header[12..16].try_into().unwrap()))
+            } else {
+                return Err(TskError::img_format_error(
+                    format!("{} is not an EWF segment (bad signature {:02x?}).", path.display(), &header[0..8])
+                ));
+            };
+
+            match format {
+                None => format = Some(segment_format),
+                Some(f) if f != segment_format => {
+                    return Err(TskError::img_format_error(
+                        format!("EWF segment {} is {:?} but the set is {:?}.", path.display(), segment_format, f)
+                    ));
+                },
+                Some(_) => {}
+            }
+
+            numbered_segments.push((segment_number, EwfSegment {
+                path: path.to_path_buf(),
+                file,
+                size
+            }));
+        }
+        numbered_segments.sort_by_key(|(number, _)| *number);
+        let segments = numbered_segments.into_iter()
+            .map(|(_, segment)| segment)
+            .collect();
+
+        let mut reader = Self {
+            format: format.unwrap(),
+            compression,
+            segments,
+            tables: Vec::new(),
+            metadata: EwfMetadata::default(),
+            chunk_size: 0,
+            chunk_count: 0,
+            media_size: 0,
+            table_cache: None,
+            chunk_cache: None,
+            offset: 0
+        };
+
+        match reader.format {
+            EwfFormat::Ewf1 => reader.read_sections_v1()?,
+            EwfFormat::Ewf2 => reader.read_sections_v2()?
+        }
+        reader.metadata.apply_header_values();
+
+        if reader.metadata.bytes_per_sector == 0 || reader.metadata.sectors_per_chunk == 0 {
+            return Err(TskError::img_format_error(
+                format!("EWF image {} has no valid media information.", reader.segments[0].path.display())
+            ));
+        }
+        reader.chunk_size = reader.metadata.bytes_per_sector as u64 * reader.metadata.sectors_per_chunk as u64;
+        if !reader.metadata.sectors_per_chunk.is_power_of_two() || reader.chunk_size > MAX_CHUNK_SIZE {
+            return Err(TskError::img_format_error(
+                format!(
+                    "EWF image {} has an unsupported chunk of {} sectors of {} bytes.",
+                    reader.segments[0].path.display(), reader.metadata.sectors_per_chunk, reader.metadata.bytes_per_sector
+                )
+            ));
+        }
+        reader.media_size = reader.metadata.sector_count.checked_mul(reader.metadata.bytes_per_sector as u64)
+            .ok_or_else(|| TskError::img_format_error(
+                format!(
+                    "EWF image {} has an invalid media size of {} sectors of {} bytes.",
+                    reader.segments[0].path.display(), reader.metadata.sector_count, reader.metadata.bytes_per_sector
+                )
+            ))?;
+
+        // Every chunk of the media needs an entry in a table
+        let needed_chunks = reader.media_size.div_ceil(reader.chunk_size);
+        if reader.chunk_count < needed_chunks {
+            return Err(TskError::img_format_error(
+                format!(
+                    "EWF image {} is incomplete: tables describe {} of {} chunks.",
+                    reader.segments[0].path.display(), reader.chunk_count, needed_chunks
+                )
+            ));
+        }
+
+        Ok(reader)
+    }
+
+    /// Walk the (forward linked) sections of every EWF1 segment
+    fn read_sections_v1(&mut self) -> Result<(), TskError> {
+        for segment_index in 0..self.segments.len() {
+            let segment_size = self.segments[segment_index].size;
+            let mut section_offset = 13u64;
+            let mut last_sectors_end = 0u64;
+
+            while section_offset + SECTION_DESCRIPTOR_SIZE <= segment_size {
+                let mut descriptor = [0u8; SECTION_DESCRIPTOR_SIZE as usize];
+                self.read_segment(segment_index, section_offset, &mut descriptor)?;
+
+                let stored_checksum = u32::from_le_bytes(descriptor[72..76].try_into().unwrap());
+                if adler32(&descriptor[0..72]) != stored_checksum {
+                    return Err(TskError::img_format_error(
+                        format!(
+                            "Section descriptor at offset {} of {} has a bad checksum.",
+                            section_offset, self.segments[segment_index].path.display()
+                        )
+                    ));
+                }
+
+                let section_type = String::from_utf8_lossy(&descriptor[0..16])
+                    .trim_end_matches('\0')
+                    .to_string();
+                let next_offset = u64::from_le_bytes(descriptor[16..24].try_into().unwrap());
+                let section_size = u64::from_le_bytes(descriptor[24..32].try_into().unwrap());
+                let data_offset = section_offset + SECTION_DESCRIPTOR_SIZE;
+                let data_size = section_size.saturating_sub(SECTION_DESCRIPTOR_SIZE);
+
+                trace!("EWF section {} at offset {} (size {})", section_type, section_offset, section_size);
+
+                match section_type.as_str() {
+                    "header" | "header2" | "xheader" => {
+                        let data = self.read_segment_vec(segment_index, data_offset, data_size)?;
+                        // header2 values take precedence over header values
+                        if let Ok(text) = decompress_header(&data) {
+                            let overwrite = section_type != "header";
+                            parse_header_values(&text, &mut self.metadata.header_values, overwrite);
+                        }
+                    },
+                    "volume" | "disk" | "data" => {
+                        let data = self.read_segment_vec(segment_index, data_offset, data_size.min(1052))?;
+                        self.parse_volume_v1(&data);
+                    },
+                    "sectors" => {
+                        last_sectors_end = section_offset + section_size;
+                    },
+                    "table" => {
+                        let mut header = [0u8; 24];
+                        self.read_segment(segment_index, data_offset, &mut header)?;
+                        let entry_count = u32::from_le_bytes(header[0..4].try_into().unwrap()) as u64;
+                        let base_offset = u64::from_le_bytes(header[8..16].try_into().unwrap());
+
+                        // The last chunk ends with the sectors section, or at
+                        // the table itself if no sectors section preceded it.
+                        let chunks_end = if last_sectors_end > 0 {
+                            last_sectors_end
+                        } else {
+                            section_offset
+                        };
+
+                        self.tables.push(EwfTable {
+                            segment: segment_index,
+                            first_chunk: self.chunk_count,
+                            entry_count,
+                            entries_offset: data_offset + 24,
+                            base_offset,
+                            chunks_end
+                        });
+                        self.chunk_count += entry_count;
+                    },
+                    "digest" => {
+                        let mut data = [0u8; 36];
+                        self.read_segment(segment_index, data_offset, &mut data)?;
+                        set_if_not_zero(&mut self.metadata.stored_md5, &data[0..16]);
+                        set_if_not_zero(&mut self.metadata.stored_sha1, &data[16..36]);
+                    },
+                    "hash" => {
+                        let mut data = [0u8; 16];
+                        self.read_segment(segment_index, data_offset, &mut data)?;
+                        if self.metadata.stored_md5.is_none() {
+                            set_if_not_zero(&mut self.metadata.stored_md5, &data);
+                        }
+                    },
+                    "error2" => {
+                        let data = self.read_segment_vec(segment_index, data_offset, data_size)?;
+                        if data.len() >= 4 {
+                            let count = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+                            for entry in data.get(520..).unwrap_or(&[]).chunks_exact(8).take(count) {
+                                let first_sector = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+                                let sector_count = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+                                self.metadata.acquisition_errors.push((first_sector as u64, sector_count as u64));
+                            }
+                        }
+                    },
+                    "done" | "next" => break,
+                    _ => {}
+                }
+
+                // Guard against sections pointing at themselves or backwards
+                if next_offset <= section_offset {
+                    break;
+                }
+                section_offset = next_offset;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse the EWF1 volume section data
+    fn parse_volume_v1(&mut self, data: &[u8]) {
+        if data.len() >= 1052 {
+            // EWF-E01 volume section
+            self.metadata.media_type = Some(data[0]);
+            self.metadata.sectors_per_chunk = u32::from_le_bytes(data[8..12].try_into().unwrap());
+            self.metadata.bytes_per_sector = u32::from_le_bytes(data[12..16].try_into().unwrap());
+            self.metadata.sector_count = u64::from_le_bytes(data[16..24].try_into().unwrap());
+            let mut set_identifier = [0u8; 16];
+            set_identifier.copy_from_slice(&data[64..80]);
+            self.metadata.set_identifier = Some(set_identifier);
+        } else if data.len() >= 20 {
+            // EWF-S01 volume section
+            self.metadata.sectors_per_chunk = u32::from_le_bytes(data[8..12].try_into().unwrap());
+            self.metadata.bytes_per_sector = u32::from_le_bytes(data[12..16].try_into().unwrap());
+            self.metadata.sector_count = u32::from_le_bytes(data[16..20].try_into().unwrap()) as u64;
+        }
+    }
+
+    /// Walk the (backward linked) sections of every EWF2 segment
+    fn read_sections_v2(&mut self) -> Result<(), TskError> {
+        for segment_index in 0..self.segments.len() {
+            let segment_size = self.segments[segment_index].size;
+            if segment_size < FILE_HEADER_SIZE_V2 + SECTION_DESCRIPTOR_SIZE_V2 {
+                return Err(TskError::img_format_error(
+                    format!("EWF2 segment {} is too small.", self.segments[segment_index].path.display())
+                ));
+            }
+
+            // Collect the sections from the end of the segment to its start
+            let mut sections = Vec::new();
+            let mut descriptor_offset = segment_size - SECTION_DESCRIPTOR_SIZE_V2;
+            loop {
+                let mut descriptor = [0u8; SECTION_DESCRIPTOR_SIZE_V2 as usize];
+                self.read_segment(segment_index, descriptor_offset, &mut descriptor)?;
+
+                let stored_checksum = u32::from_le_bytes(descriptor[60..64].try_into().unwrap());
+                if adler32(&descriptor[0..60]) != stored_checksum {
+                    return Err(TskError::img_format_error(
+                        format!(
+                            "EWF2 section descriptor at offset {} of {} has a bad checksum.",
+                            descriptor_offset, self.segments[segment_index].path.display()
+                        )
+                    ));
+                }
+
+                let section_type = u32::from_le_bytes(descriptor[0..4].try_into().unwrap());
+                let data_flags = u32::from_le_bytes(descriptor[4..8].try_into().unwrap());
+                let previous_offset = u64::from_le_bytes(descriptor[8..16].try_into().unwrap());
+                let data_size = u64::from_le_bytes(descriptor[16..24].try_into().unwrap());
+                let data_offset = descriptor_offset.checked_sub(data_size)
+                    .ok_or(TskError::img_format_error(
+                        format!("EWF2 section at offset {} has an invalid data size {}.", descriptor_offset, data_size)
+                    ))?;
+
+                sections.push((section_type, data_flags, data_offset, data_size));
+
+                if previous_offset < FILE_HEADER_SIZE_V2 || previous_offset >= descriptor_offset {
+                    break;
+                }
+                descriptor_offset = previous_offset;
+            }
+            sections.reverse();
+
+            for (section_type, data_flags, data_offset, data_size) in sections {
+                trace!("EWF2 section 0x{:x} at offset {} (size {})", section_type, data_offset, data_size);
+
+                if data_flags & V2_SECTION_FLAG_ENCRYPTED > 0 {
+                    return Err(TskError::img_format_error(
+                        format!("EWF2 image {} is encrypted.", self.segments[segment_index].path.display())
+                    ));
+                }
+
+                match section_type {
+                    V2_SECTION_DEVICE_INFORMATION | V2_SECTION_CASE_DATA => {
+                        let data = self.read_segment_vec(segment_index, data_offset, data_size)?;
+                        if let Ok(text) = decompress_header(&data) {
+                            parse_header_values(&text, &mut self.metadata.header_values, true);
+                        }
+                    },
+                    V2_SECTION_SECTOR_TABLE => {
+                        let mut header = [0u8; 32];
+                        self.read_segment(segment_index, data_offset, &mut header)?;
+                        let entry_count = u32::from_le_bytes(header[8..12].try_into().unwrap()) as u64;
+                        self.tables.push(EwfTable {
+                            segment: segment_index,
+                            first_chunk: self.chunk_count,
+                            entry_count,
+                            entries_offset: data_offset + 32,
+                            base_offset: 0,
+                            chunks_end: 0
+                        });
+                        self.chunk_count += entry_count;
+                    },
+                    V2_SECTION_ERROR_TABLE => {
+                        let data = self.read_segment_vec(segment_index, data_offset, data_size)?;
+                        if data.len() >= 32 {
+                            let count = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+                            for entry in data[32..].chunks_exact(16).take(count) {
+                                let first_sector = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+                                let sector_count = u32::from_le_bytes(entry[8..12].try_into().unwrap());
+                                self.metadata.acquisition_errors.push((first_sector, sector_count as u64));
+                            }
+                        }
+                    },
+                    V2_SECTION_MD5_HASH => {
+                        let mut data = [0u8; 16];
+                        self.read_segment(segment_index, data_offset, &mut data)?;
+                        set_if_not_zero(&mut self.metadata.stored_md5, &data);
+                    },
+                    V2_SECTION_SHA1_HASH => {
+                        let mut data = [0u8; 20];
+                        self.read_segment(segment_index, data_offset, &mut data)?;
+                        set_if_not_zero(&mut self.metadata.stored_sha1, &data);
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        // The media values are stored as text in EWF2
+        let values = &self.metadata.header_values;
+        let number = |key: &str| values.get(key).and_then(|v| v.trim().parse::<u64>().ok());
+        self.metadata.bytes_per_sector = number("bp").unwrap_or(512) as u32;
+        self.metadata.sectors_per_chunk = number("sb").unwrap_or(64) as u32;
+        self.metadata.sector_count = number("ts").unwrap_or(0);
+        if self.metadata.sector_count == 0 {
+            // Without a sector count the media size follows from the tables
+            self.metadata.sector_count = self.chunk_count * self.metadata.sectors_per_chunk as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Get the EWF format of the segment set
+    pub fn format(&self) -> EwfFormat {
+        self.format
+    }
+
+    /// Get the metadata of the image
+    pub fn metadata(&self) -> &EwfMetadata {
+        &self.metadata
+    }
+
+    /// Get the size of the media in bytes
+    pub fn media_size(&self) -> u64 {
+        self.media_size
+    }
+
+    /// Get the number of bytes per sector
+    pub fn bytes_per_sector(&self) -> u32 {
+        self.metadata.bytes_per_sector
+    }
+
+    /// Get the size of a chunk in bytes
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Get the paths of the segment files in segment order
+    pub fn segment_paths(&self) -> Vec<PathBuf> {
+        self.segments.iter()
+            .map(|s| s.path.clone())
+            .collect()
+    }
+
+    /// Hash all of the media data and compare the result with the MD5 and
+    /// SHA1 stored in the image.
+    ///
+    pub fn verify(&mut self) -> Result<EwfVerification, TskError> {
+        let mut md5 = Md5::new();
+        let mut sha1 = Sha1::new();
+
+        let media_size = self.media_size;
+        let chunk_size = self.chunk_size;
+        for chunk_number in 0..media_size.div_ceil(chunk_size) {
+            let chunk_len = (media_size - chunk_number * chunk_size).min(chunk_size) as usize;
+            let chunk = self.read_chunk(chunk_number)
+                .map_err(|e| TskError::img_format_error(
+                    format!("Error reading chunk {} during verification: {}", chunk_number, e)
+                ))?;
+            md5.update(&chunk[..chunk_len]);
+            sha1.update(&chunk[..chunk_len]);
+        }
+
+        Ok(EwfVerification {
+            computed_md5: md5.finalize().into(),
+            computed_sha1: sha1.finalize().into(),
+            stored_md5: self.metadata.stored_md5,
+            stored_sha1: self.metadata.stored_sha1
+        })
+    }
+
+    /// Read bytes from a segment file at a given offset
+    fn read_segment(&mut self, segment: usize, offset: u64, buf: &mut [u8]) -> Result<(), TskError> {
+        let segment = &mut self.segments[segment];
+        read_exact_at(&mut segment.file, offset, buf)
+            .map_err(|e| TskError::img_format_error(
+                format!("Error reading {} bytes at offset {} of {}: {}", buf.len(), offset, segment.path.display(), e)
+            ))
+    }
+
+    /// Read `size` bytes from a segment file at a given offset
+    fn read_segment_vec(&mut self, segment: usize, offset: u64, size: u64) -> Result<Vec<u8>, TskError> {
+        if offset.checked_add(size).is_none_or(|end| end > self.segments[segment].size) {
+            return Err(TskError::img_format_error(
+                format!(
+                    "Section at offset {} (size {}) is beyond the end of {}.",
+                    offset, size, self.segments[segment].path.display()
+                )
+            ));
+        }
+        let mut buf = vec![0u8; size as usize];
+        self.read_segment(segment, offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Get the location of a chunk's data
+    fn chunk_location(&mut self, chunk_number: u64) -> std::io::Result<ChunkLocation> {
+        let table_index = match self.tables.binary_search_by(|t| {
+            if chunk_number < t.first_chunk {
+                std::cmp::Ordering::Greater
+            } else if chunk_number >= t.first_chunk + t.entry_count {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        }) {
+            Ok(i) => i,
+            Err(_) => return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("No EWF table contains chunk {}", chunk_number)
+            ))
+        };
+        let table = self.tables[table_index].clone();
+        let entry_size: u64 = match self.format {
+            EwfFormat::Ewf1 => 4,
+            EwfFormat::Ewf2 => 16
+        };
+
+        // Load the entries of the table if they are not the cached ones
+        if self.table_cache.as_ref().map(|(i, _)| *i) != Some(table_index) {
+            let entries = self.read_segment_vec(table.segment, table.entries_offset, table.entry_count * entry_size)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.message))?;
+            self.table_cache = Some((table_index, entries));
+        }
+        let entries = &self.table_cache.as_ref().unwrap().1;
+        let index = (chunk_number - table.first_chunk) as usize;
+
+        match self.format {
+            EwfFormat::Ewf1 => {
+                let entry_at = |i: usize| u32::from_le_bytes(entries[i * 4..i * 4 + 4].try_into().unwrap());
+                let entry = entry_at(index);
+                let invalid_offset = || std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("EWF chunk {} has an invalid offset", chunk_number)
+                );
+                let offset = table.base_offset.checked_add((entry & 0x7fff_ffff) as u64)
+                    .ok_or_else(invalid_offset)?;
+                let end = if index + 1 < table.entry_count as usize {
+                    table.base_offset.checked_add((entry_at(index + 1) & 0x7fff_ffff) as u64)
+                        .ok_or_else(invalid_offset)?
+                } else {
+                    table.chunks_end
+                };
+                let size = end.checked_sub(offset)
+                    .ok_or(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("EWF chunk {} has an invalid range {}..{}", chunk_number, offset, end)
+                    ))?;
+                let compressed = entry & 0x8000_0000 > 0;
+
+                Ok(ChunkLocation::Stored {
+                    segment: table.segment,
+                    offset,
+                    size,
+                    compressed,
+                    has_checksum: !compressed
+                })
+            },
+            EwfFormat::Ewf2 => {
+                let entry = &entries[index * 16..index * 16 + 16];
+                let flags = u32::from_le_bytes(entry[12..16].try_into().unwrap());
+                if flags & V2_CHUNK_PATTERN_FILL > 0 {
+                    let mut pattern = [0u8; 8];
+                    pattern.copy_from_slice(&entry[0..8]);
+                    return Ok(ChunkLocation::Pattern(pattern));
+                }
+
+                Ok(ChunkLocation::Stored {
+                    segment: table.segment,
+                    offset: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                    size: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64,
+                    compressed: flags & V2_CHUNK_COMPRESSED > 0,
+                    has_checksum: flags & V2_CHUNK_HAS_CHECKSUM > 0
+                })
+            }
+        }
+    }
+
+    /// Read (and decompress) a chunk. The returned buffer is chunk_size long.
+    fn read_chunk(&mut self, chunk_number: u64) -> std::io::Result<&[u8]> {
+        if self.chunk_cache.as_ref().map(|(n, _)| *n) == Some(chunk_number) {
+            return Ok(&self.chunk_cache.as_ref().unwrap().1);
+        }
+
+        let chunk_size = self.chunk_size as usize;
+        let mut data = match self.chunk_location(chunk_number)? {
+            ChunkLocation::Pattern(pattern) => {
+                pattern.iter()
+                    .cycle()
+                    .take(chunk_size)
+                    .copied()
+                    .collect()
+            },
+            ChunkLocation::Stored { segment, offset, size, compressed, has_checksum } => {
+                // A stored chunk is at most a chunk and its checksum, larger
+                // sizes come from corrupt tables
+                let segment_size = self.segments[segment].size;
+                if size > self.chunk_size + 4 || offset.checked_add(size).is_none_or(|end| end > segment_size) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        TskError::img_format_error(format!(
+                            "EWF chunk {} of {} bytes at offset {} does not fit the chunk size {} or segment {}",
+                            chunk_number, size, offset, self.chunk_size, self.segments[segment].path.display()
+                        )).message
+                    ));
+                }
+                let mut raw = vec![0u8; size as usize];
+                read_exact_at(&mut self.segments[segment].file, offset, &mut raw)?;
+
+                if compressed {
+                    // Decode at most one byte more than a chunk to detect
+                    // chunks that inflate past the chunk size
+                    let limit = self.chunk_size + 1;
+                    let mut data = Vec::with_capacity(chunk_size);
+                    match self.compression {
+                        EwfCompression::Deflate => {
+                            ZlibDecoder::new(&raw[..]).take(limit).read_to_end(&mut data)?;
+                        },
+                        EwfCompression::Bzip2 => {
+                            bzip2::read::BzDecoder::new(&raw[..]).take(limit).read_to_end(&mut data)?;
+                        }
+                    }
+                    if data.len() > chunk_size {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            TskError::img_format_error(format!(
+                                "EWF chunk {} inflates past the chunk size {}", chunk_number, chunk_size
+                            )).message
+                        ));
+                    }
+                    data
+                } else {
+                    if has_checksum && raw.len() >= 4 {
+                        let data_len = raw.len() - 4;
+                        let stored = u32::from_le_bytes(raw[data_len..].try_into().unwrap());
+                        if adler32(&raw[..data_len]) != stored {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                format!("EWF chunk {} has a bad checksum", chunk_number)
+                            ));
+                        }
+                        raw.truncate(data_len);
+                    }
+                    raw
+                }
+            }
+        };
+
+        // The last chunk of the media can be shorter than the chunk size
+        data.resize(chunk_size, 0);
+        self.chunk_cache = Some((chunk_number, data));
+        Ok(&self.chunk_cache.as_ref().unwrap().1)
+    }
+}
+impl std::fmt::Debug for EwfReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EwfReader")
+         .field("format", &self.format)
+         .field("segments", &self.segment_paths())
+         .field("media_size", &self.media_size)
+         .field("chunk_size", &self.chunk_size)
+         .field("chunk_count", &self.chunk_count)
+         .field("metadata", &self.metadata)
+         .finish()
+    }
+}
+impl ImgMetadataSource for EwfReader {
+    fn sector_size(&self) -> u32 {
+        self.metadata.bytes_per_sector
+    }
+
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new(match self.format {
+            EwfFormat::Ewf1 => "EWF",
+            EwfFormat::Ewf2 => "EWF2"
+        });
+        metadata.segments = self.segment_paths();
+        metadata.description = metadata.segments.first()
+            .map(|p| p.display().to_string());
+
+        let m = &self.metadata;
+        metadata.add_optional_detail("Case Number", m.case_number.clone());
+        metadata.add_optional_detail("Evidence Number", m.evidence_number.clone());
+        metadata.add_optional_detail("Description", m.description.clone());
+        metadata.add_optional_detail("Examiner Name", m.examiner_name.clone());
+        metadata.add_optional_detail("Notes", m.notes.clone());
+        metadata.add_optional_detail("Acquisition Date", m.acquisition_date.clone());
+        metadata.add_optional_detail("System Date", m.system_date.clone());
+        metadata.add_optional_detail("Acquisition Software Version", m.acquisition_software_version.clone());
+        metadata.add_optional_detail("Acquisition OS", m.acquisition_os.clone());
+        metadata.add_optional_detail("Model", m.model.clone());
+        metadata.add_optional_detail("Serial Number", m.serial_number.clone());
+        metadata.add_detail("Bytes per Sector", m.bytes_per_sector.to_string());
+        metadata.add_detail("Chunk Size", self.chunk_size.to_string());
+        metadata.add_optional_detail("MD5", m.stored_md5_hex());
+        metadata.add_optional_detail("SHA1", m.stored_sha1_hex());
+        if !m.acquisition_errors.is_empty() {
+            metadata.add_detail("Acquisition Errors", m.acquisition_errors.len().to_string());
+        }
+        metadata
+    }
+}
+impl Read for EwfReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.media_size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let chunk_number = self.offset / self.chunk_size;
+        let chunk_offset = (self.offset % self.chunk_size) as usize;
+        let remaining = (self.media_size - self.offset) as usize;
+
+        let chunk = self.read_chunk(chunk_number)?;
+        let to_copy = buf.len()
+            .min(chunk.len() - chunk_offset)
+            .min(remaining);
+        buf[..to_copy].copy_from_slice(&chunk[chunk_offset..chunk_offset + to_copy]);
+
+        self.offset += to_copy as u64;
+        Ok(to_copy)
+    }
+}
+impl Seek for EwfReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.media_size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+
+/// Find the segment files of an EWF set given its first segment. Segment
+/// extensions follow the EWF naming scheme (E01..E99, EAA..EZZ, FAA..).
+///
+pub fn find_ewf_segments(first_segment: impl AsRef<Path>) -> Vec<PathBuf> {
+    let first_segment = first_segment.as_ref();
+    let mut segments = vec![first_segment.to_path_buf()];
+
+    let extension = match first_segment.extension().and_then(|e| e.to_str()) {
+        Some(e) => e.to_string(),
+        None => return segments
+    };
+
+    // The EWF2 extensions have an x between the type letter and the number
+    let (prefix, first_number) = if extension.len() == 4 && extension[1..2].eq_ignore_ascii_case("x") {
+        (&extension[0..2], &extension[2..])
+    } else if extension.len() == 3 {
+        (&extension[0..1], &extension[1..])
+    } else {
+        return segments;
+    };
+    if first_number != "01" {
+        return segments;
+    }
+
+    let upper = extension.chars().next().map(|c| c.is_ascii_uppercase()).unwrap_or(true);
+    for segment_number in 2.. {
+        let next_extension = match ewf_segment_extension(prefix, segment_number, upper) {
+            Some(e) => e,
+            None => break
+        };
+        let next = first_segment.with_extension(next_extension);
+        if !next.is_file() {
+            break;
+        }
+        segments.push(next);
+    }
+
+    segments
+}
+
+
+/// Get the extension for a segment number (E01..E99, EAA..EZZ, FAA..ZZZ)
+fn ewf_segment_extension(prefix: &str, segment_number: u32, upper: bool) -> Option<String> {
+    if segment_number < 100 {
+        return Some(format!("{}{:02}", prefix, segment_number));
+    }
+
+    // After 99 the first letter and the two digits become a base 26 counter
+    let value = segment_number - 100;
+    let first = prefix.as_bytes()[0].to_ascii_uppercase() as u32 + value / (26 * 26);
+    if first > b'Z' as u32 {
+        return None;
+    }
+    let second = b'A' as u32 + (value / 26) % 26;
+    let third = b'A' as u32 + value % 26;
+
+    let mut extension = String::new();
+    extension.push(first as u8 as char);
+    if prefix.len() == 2 {
+        extension.push('x');
+    }
+    extension.push(second as u8 as char);
+    extension.push(third as u8 as char);
+
+    if upper {
+        Some(extension.to_ascii_uppercase().replace('X', "x"))
+    } else {
+        Some(extension.to_ascii_lowercase())
+    }
+}
+
+
+/// Set a hash if the stored bytes are not all zero (no hash)
+fn set_if_not_zero<const N: usize>(hash: &mut Option<[u8; N]>, bytes: &[u8]) {
+    if bytes.len() == N && bytes.iter().any(|b| *b != 0) {
+        let mut value = [0u8; N];
+        value.copy_from_slice(bytes);
+        *hash = Some(value);
+    }
+}
+
+
+/// Decompress header section data and decode it as text. Header2 and EWF2
+/// headers are UTF-16 little endian with a byte order mark.
+fn decompress_header(data: &[u8]) -> std::io::Result<String> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(data).take(MAX_HEADER_SIZE + 1).read_to_end(&mut decompressed)?;
+    if decompressed.len() as u64 > MAX_HEADER_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "EWF header decompresses to more than the maximum header size."
+        ));
+    }
+
+    if decompressed.len() >= 2 && decompressed[0] == 0xff && decompressed[1] == 0xfe {
+        let utf16: Vec<u16> = decompressed[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&utf16))
+    } else {
+        Ok(String::from_utf8_lossy(&decompressed).to_string())
+    }
+}
+
+
+/// Parse the identifier and value lines that follow a category name line
+/// (e.g. "main") in header text.
+fn parse_header_values(text: &str, values: &mut BTreeMap<String, String>, overwrite: bool) {
+    let lines: Vec<&str> = text.split('\n')
+        .map(|l| l.trim_end_matches('\r'))
+        .collect();
+
+    for i in 0..lines.len().saturating_sub(2) {
+        if lines[i] != "main" {
+            continue;
+        }
+
+        let identifiers: Vec<&str> = lines[i + 1].split('\t').collect();
+        let line_values: Vec<&str> = lines[i + 2].split('\t').collect();
+        for (identifier, value) in identifiers.iter().zip(line_values.iter()) {
+            if identifier.is_empty() {
+                continue;
+            }
+            if overwrite || !values.contains_key(*identifier) {
+                values.insert(identifier.to_string(), value.to_string());
+            }
+        }
+    }
+}
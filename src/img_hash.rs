@@ -0,0 +1,312 @@
+use std::io::Read;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::Sha256;
+use crate::errors::TskError;
+use crate::img_aff4::Aff4Hash;
+use crate::img_common::to_hex;
+use crate::img_ewf::EwfMetadata;
+
+
+/// Size of the reads used to hash an image
+const HASH_CHUNK_SIZE: usize = 1024 * 1024;
+
+
+/// A hash algorithm for ImageHash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256
+}
+impl HashAlgorithm {
+    /// All supported algorithms
+    pub fn all() -> Vec<HashAlgorithm> {
+        vec![HashAlgorithm::Md5, HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+    }
+
+    /// The lower case name of the algorithm as used in the exports
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256"
+        }
+    }
+
+    /// Look up an algorithm by name, ignoring case and dashes (e.g. "SHA-1")
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.replace('-', "").to_lowercase().as_str() {
+            "md5" => Some(HashAlgorithm::Md5),
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None
+        }
+    }
+}
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+
+/// The hash of an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageHash {
+    pub algorithm: HashAlgorithm,
+    /// Lower case hex digest
+    pub value: String
+}
+impl ImageHash {
+    /// Create an ImageHash from a hex digest in any case
+    pub fn new<S: AsRef<str>>(algorithm: HashAlgorithm, value: S) -> Self {
+        Self { algorithm, value: value.as_ref().trim().to_lowercase() }
+    }
+
+    /// Hash everything readable from reader. Use a reader of its own (for
+    /// example a separate handle of the evidence file) so that hashing does
+    /// not show up in the audit trail.
+    ///
+    pub fn compute(reader: &mut dyn Read, algorithm: HashAlgorithm) -> Result<Self, TskError> {
+        let mut hashes = hash_stream(reader, None, &[algorithm], &mut |_| true)?;
+        Ok(hashes.remove(0))
+    }
+
+    /// The hashes stored in an EWF image at acquisition time
+    pub fn from_ewf_metadata(metadata: &EwfMetadata) -> Vec<Self> {
+        let mut hashes = Vec::new();
+        if let Some(md5) = metadata.stored_md5_hex() {
+            hashes.push(Self::new(HashAlgorithm::Md5, md5));
+        }
+        if let Some(sha1) = metadata.stored_sha1_hex() {
+            hashes.push(Self::new(HashAlgorithm::Sha1, sha1));
+        }
+        hashes
+    }
+
+    /// The hashes stored in AFF4 metadata. Hashes of unsupported algorithms
+    /// (SHA512, blake2b, ...) are left out.
+    pub fn from_aff4_hashes(hashes: &[Aff4Hash]) -> Vec<Self> {
+        hashes.iter()
+            .filter_map(|h| HashAlgorithm::from_name(&h.algorithm)
+                .map(|a| Self::new(a, &h.value)))
+            .collect()
+    }
+}
+
+
+/// The progress of hashing an image, passed to the progress callback after
+/// every chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashProgress {
+    /// Number of bytes hashed so far
+    pub bytes_hashed: u64,
+    /// Number of bytes to hash, if known
+    pub total_bytes: Option<u64>
+}
+impl HashProgress {
+    /// The hashed fraction between 0.0 and 1.0, if the total is known
+    pub fn fraction(&self) -> Option<f64> {
+        self.total_bytes.map(|total| match total {
+            0 => 1.0,
+            total => self.bytes_hashed as f64 / total as f64
+        })
+    }
+}
+
+
+/// Computes several hashes over the same data in a single pass
+#[derive(Default)]
+pub struct ImageHasher {
+    md5: Option<Md5>,
+    sha1: Option<Sha1>,
+    sha256: Option<Sha256>
+}
+impl ImageHasher {
+    /// Create a hasher for the given algorithms
+    pub fn new(algorithms: &[HashAlgorithm]) -> Self {
+        let mut hasher = Self::default();
+        for algorithm in algorithms {
+            match algorithm {
+                HashAlgorithm::Md5 => hasher.md5 = Some(Md5::new()),
+                HashAlgorithm::Sha1 => hasher.sha1 = Some(Sha1::new()),
+                HashAlgorithm::Sha256 => hasher.sha256 = Some(Sha256::new())
+            }
+        }
+        hasher
+    }
+
+    /// Hash the next bytes
+    pub fn update(&mut self, data: &[u8]) {
+        if let Some(md5) = self.md5.as_mut() {
+            md5.update(data);
+        }
+        if let Some(sha1) = self.sha1.as_mut() {
+            sha1.update(data);
+        }
+        if let Some(sha256) = self.sha256.as_mut() {
+            sha256.update(data);
+        }
+    }
+
+    /// The hashes in the order MD5, SHA-1, SHA-256
+    pub fn finalize(self) -> Vec<ImageHash> {
+        let mut hashes = Vec::new();
+        if let Some(md5) = self.md5 {
+            hashes.push(ImageHash::new(HashAlgorithm::Md5, to_hex(&md5.finalize())));
+        }
+        if let Some(sha1) = self.sha1 {
+            hashes.push(ImageHash::new(HashAlgorithm::Sha1, to_hex(&sha1.finalize())));
+        }
+        if let Some(sha256) = self.sha256 {
+            hashes.push(ImageHash::new(HashAlgorithm::Sha256, to_hex(&sha256.finalize())));
+        }
+        hashes
+    }
+}
+impl std::fmt::Debug for ImageHasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageHasher")
+         .field("md5", &self.md5.is_some())
+         .field("sha1", &self.sha1.is_some())
+         .field("sha256", &self.sha256.is_some())
+         .finish()
+    }
+}
+
+
+/// Hash a stream with several algorithms in a single pass. The progress
+/// callback is called after every chunk and cancels hashing by returning
+/// false. If total_bytes is given, a stream that ends early is an error.
+///
+pub fn hash_stream(
+    reader: &mut dyn Read,
+    total_bytes: Option<u64>,
+    algorithms: &[HashAlgorithm],
+    progress: &mut dyn FnMut(HashProgress) -> bool
+) -> Result<Vec<ImageHash>, TskError> {
+    if algorithms.is_empty() {
+        return Err(TskError::generic(
+            "No hash algorithm was given.".to_string()
+        ));
+    }
+
+    let mut hasher = ImageHasher::new(algorithms);
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    let mut bytes_hashed: u64 = 0;
+    loop {
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(TskError::generic(
+                format!("Error reading the image for hashing at offset {}: {}", bytes_hashed, e)
+            ))
+        };
+        hasher.update(&buffer[..bytes_read]);
+        bytes_hashed += bytes_read as u64;
+
+        if !progress(HashProgress { bytes_hashed, total_bytes }) {
+            return Err(TskError::cancelled(
+                format!("Hashing was cancelled after {} bytes.", bytes_hashed)
+            ));
+        }
+    }
+
+    if let Some(total) = total_bytes {
+        if bytes_hashed != total {
+            return Err(TskError::generic(
+                format!("The image ended after {} of {} bytes while hashing.", bytes_hashed, total)
+            ));
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+
+/// The comparison of a computed hash with the expected one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashMatch {
+    pub algorithm: HashAlgorithm,
+    /// The computed hex digest
+    pub computed: String,
+    /// The expected hex digest, if one was given for the algorithm
+    pub expected: Option<String>
+}
+impl HashMatch {
+    /// Some(true) if the hashes match, None if nothing was expected
+    pub fn matches(&self) -> Option<bool> {
+        self.expected.as_ref().map(|e| *e == self.computed)
+    }
+}
+
+
+/// The result of verifying an image against expected hashes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashVerification {
+    /// Number of bytes that were hashed
+    pub bytes_hashed: u64,
+    /// One entry per computed hash
+    pub results: Vec<HashMatch>
+}
+impl HashVerification {
+    /// Compare computed hashes with expected ones
+    pub fn new(bytes_hashed: u64, computed: &[ImageHash], expected: &[ImageHash]) -> Self {
+        let results = computed.iter()
+            .map(|c| HashMatch {
+                algorithm: c.algorithm,
+                computed: c.value.clone(),
+                expected: expected.iter()
+                    .find(|e| e.algorithm == c.algorithm)
+                    .map(|e| e.value.to_lowercase())
+            })
+            .collect();
+        Self { bytes_hashed, results }
+    }
+
+    /// The result for an algorithm
+    pub fn result(&self, algorithm: HashAlgorithm) -> Option<&HashMatch> {
+        self.results.iter().find(|r| r.algorithm == algorithm)
+    }
+
+    /// The computed hashes
+    pub fn computed(&self) -> Vec<ImageHash> {
+        self.results.iter()
+            .map(|r| ImageHash::new(r.algorithm, &r.computed))
+            .collect()
+    }
+
+    /// The results whose expected hash does not match
+    pub fn mismatches(&self) -> Vec<&HashMatch> {
+        self.results.iter()
+            .filter(|r| r.matches() == Some(false))
+            .collect()
+    }
+
+    /// True if at least one hash was expected and every expected hash matches
+    pub fn is_verified(&self) -> bool {
+        let results: Vec<bool> = self.results.iter()
+            .filter_map(|r| r.matches())
+            .collect();
+        !results.is_empty() && results.iter().all(|m| *m)
+    }
+}
+
+
+/// Hash a stream with all supported algorithms in a single pass and compare
+/// the result with the expected hashes
+///
+pub fn verify_stream(
+    reader: &mut dyn Read,
+    total_bytes: Option<u64>,
+    expected: &[ImageHash],
+    progress: &mut dyn FnMut(HashProgress) -> bool
+) -> Result<HashVerification, TskError> {
+    let mut bytes_hashed = 0;
+    let computed = hash_stream(reader, total_bytes, &HashAlgorithm::all(), &mut |p| {
+        bytes_hashed = p.bytes_hashed;
+        progress(p)
+    })?;
+    Ok(HashVerification::new(bytes_hashed, &computed, expected))
+}
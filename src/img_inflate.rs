@@ -0,0 +1,595 @@
+use std::io::Read;
+use flate2::Crc;
+
+
+/// Size of the deflate history window
+pub(crate) const WINDOW_SIZE: usize = 32 * 1024;
+/// Number of bits resolved by the fast huffman lookup table
+const FAST_BITS: u32 = 10;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+/// Order of the code length code lengths in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+
+/// Reads the LSB first bit stream of deflate data and keeps track of the
+/// absolute bit position in the source.
+struct BitReader<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    bit_buf: u64,
+    bit_count: u32,
+    /// Absolute offset of the next byte taken from buf
+    byte_position: u64
+}
+impl<R: Read> BitReader<R> {
+    fn new(inner: R, byte_position: u64) -> Self {
+        Self {
+            inner,
+            buf: vec![0u8; 64 * 1024],
+            pos: 0,
+            len: 0,
+            bit_buf: 0,
+            bit_count: 0,
+            byte_position
+        }
+    }
+
+    /// Load bytes into the bit buffer. Stops early at the end of the input.
+    fn fill(&mut self) -> std::io::Result<()> {
+        while self.bit_count <= 56 {
+            if self.pos == self.len {
+                self.len = loop {
+                    match self.inner.read(&mut self.buf) {
+                        Ok(n) => break n,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e)
+                    }
+                };
+                self.pos = 0;
+                if self.len == 0 {
+                    break;
+                }
+            }
+            self.bit_buf |= (self.buf[self.pos] as u64) << self.bit_count;
+            self.pos += 1;
+            self.byte_position += 1;
+            self.bit_count += 8;
+        }
+        Ok(())
+    }
+
+    /// Get the next n bits without consuming them, padding with zeros past
+    /// the end of the input.
+    fn peek(&mut self, n: u32) -> std::io::Result<u32> {
+        if self.bit_count < n {
+            self.fill()?;
+        }
+        Ok((self.bit_buf & ((1u64 << n) - 1)) as u32)
+    }
+
+    fn consume(&mut self, n: u32) -> std::io::Result<()> {
+        if n > self.bit_count {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "deflate stream is truncated"
+            ));
+        }
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> std::io::Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+        let value = self.peek(n)?;
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    /// Drop the bits up to the next byte boundary
+    fn align_to_byte(&mut self) {
+        let drop = self.bit_count % 8;
+        self.bit_buf >>= drop;
+        self.bit_count -= drop;
+    }
+
+    /// Absolute position of the next unread bit
+    fn bit_position(&self) -> u64 {
+        self.byte_position * 8 - self.bit_count as u64
+    }
+
+    /// True if there is unread input left
+    fn has_input(&mut self) -> std::io::Result<bool> {
+        self.fill()?;
+        Ok(self.bit_count > 0)
+    }
+
+    /// Append n bytes to out. The reader must be byte aligned.
+    fn read_bytes(&mut self, out: &mut Vec<u8>, mut n: usize) -> std::io::Result<()> {
+        while n > 0 && self.bit_count >= 8 {
+            out.push(self.bits(8)? as u8);
+            n -= 1;
+        }
+        while n > 0 {
+            if self.pos == self.len {
+                self.len = self.inner.read(&mut self.buf)?;
+                self.pos = 0;
+                if self.len == 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "deflate stored block is truncated"
+                    ));
+                }
+            }
+            let available = (self.len - self.pos).min(n);
+            out.extend_from_slice(&self.buf[self.pos..self.pos + available]);
+            self.pos += available;
+            self.byte_position += available as u64;
+            n -= available;
+        }
+        Ok(())
+    }
+}
+
+
+/// A canonical huffman code with a lookup table for short codes
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+    /// (symbol << 4) | length for codes up to FAST_BITS long, 0 if longer
+    fast: Vec<u16>
+}
+impl Huffman {
+    fn new(lengths: &[u8]) -> std::io::Result<Self> {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left: i32 = 1;
+        for count in counts.iter().skip(1) {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(invalid_data("over-subscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        let mut next_code = [0u32; 16];
+        let mut code = 0u32;
+        for bits in 1..16 {
+            code = (code + counts[bits - 1] as u32) << 1;
+            next_code[bits] = code;
+        }
+        let mut fast = vec![0u16; 1 << FAST_BITS];
+        for (symbol, length) in lengths.iter().enumerate() {
+            let length = *length as u32;
+            if length == 0 {
+                continue;
+            }
+            let code = next_code[length as usize];
+            next_code[length as usize] += 1;
+            if length <= FAST_BITS {
+                let reversed = code.reverse_bits() >> (32 - length);
+                let mut index = reversed as usize;
+                while index < fast.len() {
+                    fast[index] = ((symbol as u16) << 4) | length as u16;
+                    index += 1 << length;
+                }
+            }
+        }
+
+        Ok(Self { counts, symbols, fast })
+    }
+
+    fn decode<R: Read>(&self, bits: &mut BitReader<R>) -> std::io::Result<u16> {
+        let entry = self.fast[bits.peek(FAST_BITS)? as usize];
+        if entry != 0 {
+            bits.consume((entry & 0xf) as u32)?;
+            return Ok(entry >> 4);
+        }
+
+        // Codes longer than the lookup table are decoded a bit at a time
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid huffman code"))
+    }
+}
+
+
+/// Where the inflater is in the gzip stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InflateState {
+    MemberHeader,
+    BlockStart,
+    Done
+}
+
+
+/// A point in a gzip stream where decompression can start
+#[derive(Debug, Clone)]
+pub(crate) struct AccessPoint {
+    /// Offset in the decompressed data
+    pub uncompressed_offset: u64,
+    /// Absolute bit offset in the gzip file
+    pub bit_offset: u64,
+    /// True if the point is the start of a gzip member header
+    pub member_start: bool,
+    /// The (uncompressed) history window preceding the point
+    pub window: Vec<u8>
+}
+
+
+/// Collects access points while inflating a whole gzip file
+struct PointRecorder {
+    span: u64,
+    points: Vec<AccessPoint>
+}
+
+
+/// A gzip decompressor that can start at an access point and can record
+/// access points while it inflates.
+pub(crate) struct Inflater<R: Read> {
+    bits: BitReader<R>,
+    /// History window followed by data that has not been read yet
+    buffer: Vec<u8>,
+    read_pos: usize,
+    state: InflateState,
+    /// Uncompressed offset of the end of buffer
+    total_out: u64,
+    /// CRC and size of the current member, if members are verified
+    verify: Option<(Crc, u32)>,
+    recorder: Option<PointRecorder>,
+    fixed: Option<(Huffman, Huffman)>
+}
+impl<R: Read> Inflater<R> {
+    /// Start inflating at an access point. The reader must be positioned at
+    /// the byte containing the point's bit offset.
+    pub fn from_point(inner: R, point: &AccessPoint) -> std::io::Result<Self> {
+        let mut bits = BitReader::new(inner, point.bit_offset / 8);
+        bits.bits((point.bit_offset % 8) as u32)?;
+
+        Ok(Self {
+            bits,
+            buffer: point.window.clone(),
+            read_pos: point.window.len(),
+            state: if point.member_start { InflateState::MemberHeader } else { InflateState::BlockStart },
+            total_out: point.uncompressed_offset,
+            verify: None,
+            recorder: None,
+            fixed: None
+        })
+    }
+
+    /// Inflate a whole gzip stream, verifying every member and recording
+    /// access points about every span bytes of uncompressed data. Returns
+    /// the points and the uncompressed size.
+    pub fn build_index(inner: R, span: u64) -> std::io::Result<(Vec<AccessPoint>, u64)> {
+        let start = AccessPoint {
+            uncompressed_offset: 0,
+            bit_offset: 0,
+            member_start: true,
+            window: Vec::new()
+        };
+        let mut inflater = Self::from_point(inner, &start)?;
+        inflater.verify = Some((Crc::new(), 0));
+        inflater.recorder = Some(PointRecorder {
+            span,
+            points: vec![start]
+        });
+
+        let mut scratch = vec![0u8; 256 * 1024];
+        while inflater.read(&mut scratch)? > 0 {}
+
+        let points = inflater.recorder.take().unwrap().points;
+        Ok((points, inflater.total_out))
+    }
+
+    /// Decode the next header, block or trailer
+    fn step(&mut self) -> std::io::Result<()> {
+        match self.state {
+            InflateState::MemberHeader => {
+                if !self.bits.has_input()? || self.bits.peek(8)? == 0 {
+                    // End of the file, possibly followed by zero padding
+                    self.state = InflateState::Done;
+                    return Ok(());
+                }
+                self.read_member_header()?;
+                if let Some(verify) = self.verify.as_mut() {
+                    *verify = (Crc::new(), 0);
+                }
+                self.state = InflateState::BlockStart;
+            },
+            InflateState::BlockStart => {
+                self.record_point();
+
+                let block_start = self.buffer.len();
+                let is_final = self.inflate_block()?;
+
+                let new_bytes = self.buffer.len() - block_start;
+                self.total_out += new_bytes as u64;
+                if let Some((crc, size)) = self.verify.as_mut() {
+                    crc.update(&self.buffer[block_start..]);
+                    *size = size.wrapping_add(new_bytes as u32);
+                }
+
+                if is_final {
+                    self.read_member_trailer()?;
+                    self.state = InflateState::MemberHeader;
+                }
+            },
+            InflateState::Done => {}
+        }
+        Ok(())
+    }
+
+    /// Record an access point if a span of data was decoded since the last one
+    fn record_point(&mut self) {
+        let total_out = self.total_out;
+        let bit_offset = self.bits.bit_position();
+        let window_start = self.buffer.len().saturating_sub(WINDOW_SIZE);
+        if let Some(recorder) = self.recorder.as_mut() {
+            let last = recorder.points.last().map(|p| p.uncompressed_offset).unwrap_or(0);
+            if total_out - last >= recorder.span {
+                recorder.points.push(AccessPoint {
+                    uncompressed_offset: total_out,
+                    bit_offset,
+                    member_start: false,
+                    window: self.buffer[window_start..].to_vec()
+                });
+            }
+        }
+    }
+
+    fn read_member_header(&mut self) -> std::io::Result<()> {
+        let id1 = self.bits.bits(8)?;
+        let id2 = self.bits.bits(8)?;
+        let method = self.bits.bits(8)?;
+        if id1 != 0x1f || id2 != 0x8b {
+            return Err(invalid_data(format!(
+                "invalid gzip member header at bit offset {}", self.bits.bit_position() - 24
+            )));
+        }
+        if method != 8 {
+            return Err(invalid_data(format!("unsupported gzip compression method {}", method)));
+        }
+        let flags = self.bits.bits(8)?;
+        // mtime, extra flags and os
+        for _ in 0..6 {
+            self.bits.bits(8)?;
+        }
+        if flags & 0x04 > 0 {
+            let extra_length = self.bits.bits(16)?;
+            for _ in 0..extra_length {
+                self.bits.bits(8)?;
+            }
+        }
+        // File name and comment are null terminated
+        for flag in &[0x08, 0x10] {
+            if flags & flag > 0 {
+                while self.bits.bits(8)? != 0 {}
+            }
+        }
+        if flags & 0x02 > 0 {
+            self.bits.bits(16)?;
+        }
+        Ok(())
+    }
+
+    fn read_member_trailer(&mut self) -> std::io::Result<()> {
+        self.bits.align_to_byte();
+        let stored_crc = self.bits.bits(16)? | self.bits.bits(16)? << 16;
+        let stored_size = self.bits.bits(16)? | self.bits.bits(16)? << 16;
+
+        if let Some((crc, size)) = self.verify.as_ref() {
+            if crc.sum() != stored_crc || *size != stored_size {
+                return Err(invalid_data(format!(
+                    "gzip member ending at bit offset {} failed verification (crc {:08x}/{:08x}, size {}/{})",
+                    self.bits.bit_position(), crc.sum(), stored_crc, size, stored_size
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Inflate one deflate block into the buffer. Returns true for the final block.
+    fn inflate_block(&mut self) -> std::io::Result<bool> {
+        let is_final = self.bits.bits(1)? == 1;
+        match self.bits.bits(2)? {
+            0 => {
+                self.bits.align_to_byte();
+                let length = self.bits.bits(16)?;
+                let complement = self.bits.bits(16)?;
+                if length != !complement & 0xffff {
+                    return Err(invalid_data("stored block length does not match its complement"));
+                }
+                self.bits.read_bytes(&mut self.buffer, length as usize)?;
+            },
+            1 => {
+                let (literals, distances) = match self.fixed.take() {
+                    Some(tables) => tables,
+                    None => fixed_tables()?
+                };
+                let result = self.inflate_codes(&literals, &distances);
+                self.fixed = Some((literals, distances));
+                result?;
+            },
+            2 => {
+                let (literals, distances) = self.read_dynamic_tables()?;
+                self.inflate_codes(&literals, &distances)?;
+            },
+            _ => return Err(invalid_data("invalid deflate block type"))
+        }
+        Ok(is_final)
+    }
+
+    fn read_dynamic_tables(&mut self) -> std::io::Result<(Huffman, Huffman)> {
+        let literal_count = self.bits.bits(5)? as usize + 257;
+        let distance_count = self.bits.bits(5)? as usize + 1;
+        let code_length_count = self.bits.bits(4)? as usize + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(invalid_data("invalid dynamic block code counts"));
+        }
+
+        let mut code_length_lengths = [0u8; 19];
+        for index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+            code_length_lengths[*index] = self.bits.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_length_lengths)?;
+
+        let mut lengths = vec![0u8; literal_count + distance_count];
+        let mut index = 0;
+        while index < lengths.len() {
+            let symbol = code_lengths.decode(&mut self.bits)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    if index == 0 {
+                        return Err(invalid_data("repeat of a missing code length"));
+                    }
+                    (lengths[index - 1], 3 + self.bits.bits(2)? as usize)
+                },
+                17 => (0, 3 + self.bits.bits(3)? as usize),
+                _ => (0, 11 + self.bits.bits(7)? as usize)
+            };
+            if index + repeat > lengths.len() {
+                return Err(invalid_data("too many code lengths"));
+            }
+            for length in lengths[index..index + repeat].iter_mut() {
+                *length = value;
+            }
+            index += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(invalid_data("dynamic block has no end of block code"));
+        }
+
+        Ok((
+            Huffman::new(&lengths[..literal_count])?,
+            Huffman::new(&lengths[literal_count..])?
+        ))
+    }
+
+    fn inflate_codes(&mut self, literals: &Huffman, distances: &Huffman) -> std::io::Result<()> {
+        loop {
+            let symbol = literals.decode(&mut self.bits)? as usize;
+            if symbol < 256 {
+                self.buffer.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(());
+            }
+
+            let symbol = symbol - 257;
+            if symbol >= 29 {
+                return Err(invalid_data("invalid length symbol"));
+            }
+            let length = LENGTH_BASE[symbol] as usize + self.bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+            let symbol = distances.decode(&mut self.bits)? as usize;
+            if symbol >= 30 {
+                return Err(invalid_data("invalid distance symbol"));
+            }
+            let distance = DIST_BASE[symbol] as usize + self.bits.bits(DIST_EXTRA[symbol] as u32)? as usize;
+            if distance > self.buffer.len() {
+                return Err(invalid_data(format!("distance {} is too far back", distance)));
+            }
+
+            let start = self.buffer.len() - distance;
+            if distance >= length {
+                self.buffer.extend_from_within(start..start + length);
+            } else {
+                for i in 0..length {
+                    let byte = self.buffer[start + i];
+                    self.buffer.push(byte);
+                }
+            }
+        }
+    }
+}
+impl<R: Read> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.read_pos == self.buffer.len() {
+            if self.state == InflateState::Done {
+                return Ok(0);
+            }
+
+            // Keep only the history window before decoding more
+            if self.buffer.len() > WINDOW_SIZE {
+                let excess = self.buffer.len() - WINDOW_SIZE;
+                self.buffer.drain(..excess);
+                self.read_pos = self.buffer.len();
+            }
+            self.step()?;
+        }
+
+        let available = (self.buffer.len() - self.read_pos).min(buf.len());
+        buf[..available].copy_from_slice(&self.buffer[self.read_pos..self.read_pos + available]);
+        self.read_pos += available;
+        Ok(available)
+    }
+}
+
+
+/// The huffman tables of fixed blocks
+fn fixed_tables() -> std::io::Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5u8; 30])?))
+}
@@ -0,0 +1,481 @@
+use std::convert::TryInto;
+
+
+/// Block magics
+const MAGIC_END: u32 = 0x2478_7662;            // "bvx$"
+const MAGIC_UNCOMPRESSED: u32 = 0x2d78_7662;   // "bvx-"
+const MAGIC_V1: u32 = 0x3178_7662;             // "bvx1"
+const MAGIC_V2: u32 = 0x3278_7662;             // "bvx2"
+const MAGIC_LZVN: u32 = 0x6e78_7662;           // "bvxn"
+
+/// Symbol and state counts of the FSE tables
+const L_SYMBOLS: usize = 20;
+const M_SYMBOLS: usize = 20;
+const D_SYMBOLS: usize = 64;
+const LITERAL_SYMBOLS: usize = 256;
+const L_STATES: usize = 64;
+const M_STATES: usize = 64;
+const D_STATES: usize = 256;
+const LITERAL_STATES: usize = 1024;
+/// Maximum number of literals in a block
+const LITERALS_PER_BLOCK: usize = 4 * 10000;
+
+const L_EXTRA_BITS: [u8; L_SYMBOLS] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 3, 5, 8];
+const L_BASE_VALUE: [u32; L_SYMBOLS] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 20, 28, 60];
+const M_EXTRA_BITS: [u8; M_SYMBOLS] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 5, 8, 11];
+const M_BASE_VALUE: [u32; M_SYMBOLS] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 24, 56, 312];
+
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("lzfse: {}", message))
+}
+
+
+/// Distance symbols come in groups of four with 0 to 15 extra bits
+fn d_tables() -> ([u8; D_SYMBOLS], [u32; D_SYMBOLS]) {
+    let mut bits = [0u8; D_SYMBOLS];
+    let mut base = [0u32; D_SYMBOLS];
+    let mut value = 0u32;
+    for symbol in 0..D_SYMBOLS {
+        bits[symbol] = (symbol / 4) as u8;
+        base[symbol] = value;
+        value += 1 << bits[symbol];
+    }
+    (bits, base)
+}
+
+
+/// Read a frequency value of a v2 header from the low bits of accum,
+/// returning (value, number of bits used)
+fn decode_frequency(bits: u32) -> (u16, u32) {
+    const NBITS: [u8; 32] = [
+        2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3, 2, 14,
+        2, 3, 2, 5, 2, 3, 2, 8, 2, 3, 2, 5, 2, 3, 2, 14
+    ];
+    const VALUE: [u8; 32] = [
+        0, 2, 1, 4, 0, 3, 1, 0, 0, 2, 1, 5, 0, 3, 1, 0,
+        0, 2, 1, 6, 0, 3, 1, 0, 0, 2, 1, 7, 0, 3, 1, 0
+    ];
+    let index = (bits & 31) as usize;
+    match NBITS[index] {
+        8 => (8 + ((bits >> 4) & 0xf) as u16, 8),
+        14 => (24 + ((bits >> 4) & 0x3ff) as u16, 14),
+        nbits => (VALUE[index] as u16, nbits as u32)
+    }
+}
+
+
+/// A backwards bit stream as written by the FSE encoder
+struct FseInput<'a> {
+    data: &'a [u8],
+    /// Bytes of data not yet loaded
+    position: usize,
+    accum: u64,
+    accum_bits: u32
+}
+impl<'a> FseInput<'a> {
+    /// Start at the end of data. bits (-7..=0) is the number of padding
+    /// bits in the final byte.
+    fn new(data: &'a [u8], bits: i32) -> std::io::Result<Self> {
+        let (load, accum_bits) = if bits != 0 { (8, (64 + bits) as u32) } else { (7, 56) };
+        if data.len() < load || !(56..64).contains(&accum_bits) {
+            return Err(invalid_data("invalid fse stream"));
+        }
+        let position = data.len() - load;
+        let mut bytes = [0u8; 8];
+        bytes[..load].copy_from_slice(&data[position..]);
+        let accum = u64::from_le_bytes(bytes);
+        if accum_bits < 64 && accum >> accum_bits != 0 {
+            return Err(invalid_data("invalid fse stream"));
+        }
+        Ok(Self { data, position, accum, accum_bits })
+    }
+
+    /// Load whole bytes until there are at least 56 bits
+    fn flush(&mut self) -> std::io::Result<()> {
+        let nbits = (63 - self.accum_bits) & !7;
+        let nbytes = (nbits / 8) as usize;
+        if nbytes == 0 {
+            return Ok(());
+        }
+        if nbytes > self.position {
+            return Err(invalid_data("fse stream is truncated"));
+        }
+        self.position -= nbytes;
+        let mut bytes = [0u8; 8];
+        bytes[..nbytes].copy_from_slice(&self.data[self.position..self.position + nbytes]);
+        self.accum = (self.accum << nbits) | u64::from_le_bytes(bytes);
+        self.accum_bits += nbits;
+        Ok(())
+    }
+
+    fn pull(&mut self, n: u32) -> std::io::Result<u64> {
+        if n > self.accum_bits {
+            return Err(invalid_data("fse stream is truncated"));
+        }
+        self.accum_bits -= n;
+        let result = self.accum >> self.accum_bits;
+        self.accum &= (1u64 << self.accum_bits).wrapping_sub(1);
+        Ok(result)
+    }
+}
+
+
+/// (symbol, bits, delta) for every state of a literal table
+fn literal_table(frequencies: &[u16]) -> std::io::Result<Vec<(u8, u32, i32)>> {
+    let mut table = Vec::with_capacity(LITERAL_STATES);
+    for_each_state(LITERAL_STATES, frequencies, |symbol, bits, delta| table.push((symbol as u8, bits, delta)))?;
+    table.resize(LITERAL_STATES, (0, 0, 0));
+    Ok(table)
+}
+
+
+/// (total bits, value bits, delta, base) for every state of an L, M or D table
+fn value_table(states: usize, frequencies: &[u16], extra_bits: &[u8], base: &[u32]) -> std::io::Result<Vec<(u32, u32, i32, u32)>> {
+    let mut table = Vec::with_capacity(states);
+    for_each_state(states, frequencies, |symbol, bits, delta| {
+        let value_bits = extra_bits[symbol] as u32;
+        table.push((bits + value_bits, value_bits, delta, base[symbol]))
+    })?;
+    table.resize(states, (0, 0, 0, 0));
+    Ok(table)
+}
+
+
+/// Spread the states over the symbols as the FSE decoder tables do
+fn for_each_state(states: usize, frequencies: &[u16], mut add: impl FnMut(usize, u32, i32)) -> std::io::Result<()> {
+    let states_clz = (states as u32).leading_zeros();
+    let mut total = 0;
+    for (symbol, frequency) in frequencies.iter().enumerate() {
+        let frequency = *frequency as u32;
+        if frequency == 0 {
+            continue;
+        }
+        total += frequency as usize;
+        if total > states {
+            return Err(invalid_data("frequencies exceed the number of states"));
+        }
+        let k = frequency.leading_zeros() - states_clz;
+        let j0 = ((2 * states as u32) >> k) - frequency;
+        for j in 0..frequency {
+            if j < j0 {
+                add(symbol, k, (((frequency + j) << k) as i32) - states as i32);
+            } else {
+                add(symbol, k - 1, ((j - j0) << (k - 1)) as i32);
+            }
+        }
+    }
+    Ok(())
+}
+
+
+/// The fields of a compressed block header
+struct BlockHeader {
+    n_raw_bytes: usize,
+    n_literals: usize,
+    n_matches: usize,
+    n_literal_payload_bytes: usize,
+    n_lmd_payload_bytes: usize,
+    literal_bits: i32,
+    literal_state: [u16; 4],
+    lmd_bits: i32,
+    l_state: u16,
+    m_state: u16,
+    d_state: u16,
+    /// L, M, D and literal frequencies
+    frequencies: Vec<u16>,
+    header_size: usize
+}
+
+
+fn u32_at(data: &[u8], offset: usize) -> std::io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| invalid_data("block header is truncated"))
+}
+
+
+fn read_v1_header(data: &[u8]) -> std::io::Result<BlockHeader> {
+    let frequency_count = L_SYMBOLS + M_SYMBOLS + D_SYMBOLS + LITERAL_SYMBOLS;
+    // The header struct is padded to a multiple of 4 bytes
+    let header_size = (50 + 2 * frequency_count + 3) & !3;
+    if data.len() < header_size {
+        return Err(invalid_data("block header is truncated"));
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    Ok(BlockHeader {
+        n_raw_bytes: u32_at(data, 4)? as usize,
+        n_literals: u32_at(data, 12)? as usize,
+        n_matches: u32_at(data, 16)? as usize,
+        n_literal_payload_bytes: u32_at(data, 20)? as usize,
+        n_lmd_payload_bytes: u32_at(data, 24)? as usize,
+        literal_bits: u32_at(data, 28)? as i32,
+        literal_state: [u16_at(32), u16_at(34), u16_at(36), u16_at(38)],
+        lmd_bits: u32_at(data, 40)? as i32,
+        l_state: u16_at(44),
+        m_state: u16_at(46),
+        d_state: u16_at(48),
+        frequencies: (0..frequency_count).map(|i| u16_at(50 + 2 * i)).collect(),
+        header_size
+    })
+}
+
+
+fn read_v2_header(data: &[u8]) -> std::io::Result<BlockHeader> {
+    if data.len() < 32 {
+        return Err(invalid_data("block header is truncated"));
+    }
+    let packed: Vec<u64> = data[8..32].chunks_exact(8)
+        .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+        .collect();
+    let field = |v: u64, offset: u32, bits: u32| ((v >> offset) & ((1 << bits) - 1)) as usize;
+
+    let header_size = field(packed[2], 0, 32);
+    if header_size < 32 || header_size > data.len() {
+        return Err(invalid_data("invalid block header size"));
+    }
+
+    // Frequencies are stored with a variable length code
+    let frequency_count = L_SYMBOLS + M_SYMBOLS + D_SYMBOLS + LITERAL_SYMBOLS;
+    let mut frequencies = vec![0u16; frequency_count];
+    if header_size > 32 {
+        let mut source = data[32..header_size].iter();
+        let mut accum = 0u32;
+        let mut accum_bits = 0;
+        for frequency in frequencies.iter_mut() {
+            while accum_bits + 8 <= 32 {
+                match source.next() {
+                    Some(byte) => {
+                        accum |= (*byte as u32) << accum_bits;
+                        accum_bits += 8;
+                    },
+                    None => break
+                }
+            }
+            let (value, bits) = decode_frequency(accum);
+            if bits > accum_bits {
+                return Err(invalid_data("frequency table is truncated"));
+            }
+            *frequency = value;
+            accum >>= bits;
+            accum_bits -= bits;
+        }
+        if accum_bits >= 8 || source.next().is_some() {
+            return Err(invalid_data("invalid frequency table"));
+        }
+    }
+
+    Ok(BlockHeader {
+        n_raw_bytes: u32_at(data, 4)? as usize,
+        n_literals: field(packed[0], 0, 20),
+        n_literal_payload_bytes: field(packed[0], 20, 20),
+        n_matches: field(packed[0], 40, 20),
+        literal_bits: field(packed[0], 60, 3) as i32 - 7,
+        literal_state: [
+            field(packed[1], 0, 10) as u16,
+            field(packed[1], 10, 10) as u16,
+            field(packed[1], 20, 10) as u16,
+            field(packed[1], 30, 10) as u16
+        ],
+        n_lmd_payload_bytes: field(packed[1], 40, 20),
+        lmd_bits: field(packed[1], 60, 3) as i32 - 7,
+        l_state: field(packed[2], 32, 10) as u16,
+        m_state: field(packed[2], 42, 10) as u16,
+        d_state: field(packed[2], 52, 10) as u16,
+        frequencies,
+        header_size
+    })
+}
+
+
+/// Copy a match, which may overlap the bytes it produces
+fn copy_match(output: &mut Vec<u8>, distance: usize, length: usize) -> std::io::Result<()> {
+    if distance == 0 || distance > output.len() {
+        return Err(invalid_data("match distance is outside of the output"));
+    }
+    let start = output.len() - distance;
+    for i in 0..length {
+        let byte = output[start + i];
+        output.push(byte);
+    }
+    Ok(())
+}
+
+
+/// Decode an FSE compressed (v1 or v2) block, returning the bytes used
+fn decode_fse_block(data: &[u8], header: &BlockHeader, output: &mut Vec<u8>) -> std::io::Result<usize> {
+    let payload_end = header.header_size + header.n_literal_payload_bytes + header.n_lmd_payload_bytes;
+    if payload_end > data.len() {
+        return Err(invalid_data("block payload is truncated"));
+    }
+    if header.n_literals > LITERALS_PER_BLOCK || header.n_literals & 3 != 0 {
+        return Err(invalid_data("invalid literal count"));
+    }
+    let (l_frequencies, rest) = header.frequencies.split_at(L_SYMBOLS);
+    let (m_frequencies, rest) = rest.split_at(M_SYMBOLS);
+    let (d_frequencies, literal_frequencies) = rest.split_at(D_SYMBOLS);
+
+    // Literals are decoded with four interleaved states
+    let literal_table = literal_table(literal_frequencies)?;
+    let literal_payload = &data[header.header_size..header.header_size + header.n_literal_payload_bytes];
+    let mut input = FseInput::new(literal_payload, header.literal_bits)?;
+    let mut states = header.literal_state;
+    let mut literals = Vec::with_capacity(header.n_literals);
+    for _ in 0..header.n_literals / 4 {
+        input.flush()?;
+        for state in states.iter_mut() {
+            let (symbol, bits, delta) = *literal_table.get(*state as usize)
+                .ok_or_else(|| invalid_data("invalid literal state"))?;
+            literals.push(symbol);
+            *state = (delta + input.pull(bits)? as i32) as u16;
+        }
+    }
+
+    let (d_extra_bits, d_base_value) = d_tables();
+    let l_table = value_table(L_STATES, l_frequencies, &L_EXTRA_BITS, &L_BASE_VALUE)?;
+    let m_table = value_table(M_STATES, m_frequencies, &M_EXTRA_BITS, &M_BASE_VALUE)?;
+    let d_table = value_table(D_STATES, d_frequencies, &d_extra_bits, &d_base_value)?;
+    let lmd_payload = &data[header.header_size + header.n_literal_payload_bytes..payload_end];
+    let mut input = FseInput::new(lmd_payload, header.lmd_bits)?;
+    let mut l_state = header.l_state;
+    let mut m_state = header.m_state;
+    let mut d_state = header.d_state;
+    let block_start = output.len();
+
+    let decode_value = |table: &[(u32, u32, i32, u32)], state: &mut u16, input: &mut FseInput| -> std::io::Result<usize> {
+        let (total_bits, value_bits, delta, base) = *table.get(*state as usize)
+            .ok_or_else(|| invalid_data("invalid lmd state"))?;
+        let bits = input.pull(total_bits)?;
+        *state = (delta + (bits >> value_bits) as i32) as u16;
+        Ok((base as u64 + (bits & ((1u64 << value_bits) - 1))) as usize)
+    };
+
+    let mut literal_position = 0;
+    let mut distance = 0;
+    for _ in 0..header.n_matches {
+        input.flush()?;
+        let literal_count = decode_value(&l_table, &mut l_state, &mut input)?;
+        let match_length = decode_value(&m_table, &mut m_state, &mut input)?;
+        let new_distance = decode_value(&d_table, &mut d_state, &mut input)?;
+        if new_distance != 0 {
+            distance = new_distance;
+        }
+
+        let literal = literals.get(literal_position..literal_position + literal_count)
+            .ok_or_else(|| invalid_data("literal run is outside of the literals"))?;
+        output.extend_from_slice(literal);
+        literal_position += literal_count;
+        if output.len() - block_start + match_length > header.n_raw_bytes {
+            return Err(invalid_data("block is larger than its raw size"));
+        }
+        copy_match(output, distance, match_length)?;
+    }
+
+    if output.len() - block_start != header.n_raw_bytes {
+        return Err(invalid_data("block is smaller than its raw size"));
+    }
+    Ok(payload_end)
+}
+
+
+/// Decode an LZVN payload
+fn decode_lzvn(data: &[u8], raw_size: usize, output: &mut Vec<u8>) -> std::io::Result<()> {
+    let truncated = || invalid_data("lzvn payload is truncated");
+    let end = output.len() + raw_size;
+    let mut position = 0;
+    let mut distance = 0;
+
+    loop {
+        let opcode = *data.get(position).ok_or_else(truncated)? as usize;
+        let byte = |offset: usize| data.get(position + offset).map(|b| *b as usize).ok_or_else(truncated);
+        let (opcode_length, literal_count, match_length, new_distance) = match opcode {
+            // End of stream
+            0x06 => break,
+            // No operation
+            0x0e | 0x16 => (1, 0, 0, None),
+            // Large literal
+            0xe0 => (2, byte(1)? + 16, 0, None),
+            // Small literal
+            0xe1..=0xef => (1, opcode & 0xf, 0, None),
+            // Large match
+            0xf0 => (2, 0, byte(1)? + 16, None),
+            // Small match
+            0xf1..=0xff => (1, 0, opcode & 0xf, None),
+            // Medium distance
+            0xa0..=0xbf => {
+                let b1 = byte(1)?;
+                let b2 = byte(2)?;
+                (3, (opcode >> 3) & 3, ((opcode & 7) << 2 | (b1 & 3)) + 3, Some(b1 >> 2 | b2 << 6))
+            },
+            0x1e | 0x26 | 0x2e | 0x36 | 0x3e | 0x70..=0x7f => {
+                return Err(invalid_data("undefined lzvn opcode"));
+            },
+            // Large distance
+            _ if opcode & 7 == 7 => (3, opcode >> 6, ((opcode >> 3) & 7) + 3, Some(byte(1)? | byte(2)? << 8)),
+            // Previous distance
+            _ if opcode & 7 == 6 => (1, opcode >> 6, ((opcode >> 3) & 7) + 3, None),
+            // Small distance
+            _ => (2, opcode >> 6, ((opcode >> 3) & 7) + 3, Some((opcode & 7) << 8 | byte(1)?))
+        };
+        position += opcode_length;
+
+        let literal = data.get(position..position + literal_count).ok_or_else(truncated)?;
+        if output.len() + literal_count + match_length > end {
+            return Err(invalid_data("lzvn payload is larger than its raw size"));
+        }
+        output.extend_from_slice(literal);
+        position += literal_count;
+        if let Some(new_distance) = new_distance {
+            distance = new_distance;
+        }
+        if match_length > 0 {
+            copy_match(output, distance, match_length)?;
+        }
+    }
+
+    if output.len() != end {
+        return Err(invalid_data("lzvn payload is smaller than its raw size"));
+    }
+    Ok(())
+}
+
+
+/// Decompress an LZFSE stream (a sequence of uncompressed, LZVN and FSE
+/// blocks ending with an end of stream block)
+pub(crate) fn lzfse_decompress(data: &[u8], max_size: usize) -> std::io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut position = 0;
+
+    loop {
+        let block = &data[position..];
+        let magic = u32_at(block, 0)?;
+        let raw_size = match magic {
+            MAGIC_END => break,
+            MAGIC_UNCOMPRESSED | MAGIC_LZVN | MAGIC_V1 | MAGIC_V2 => u32_at(block, 4)? as usize,
+            _ => return Err(invalid_data("invalid block magic"))
+        };
+        if output.len() + raw_size > max_size {
+            return Err(invalid_data("data is larger than expected"));
+        }
+
+        position += match magic {
+            MAGIC_UNCOMPRESSED => {
+                let raw = block.get(8..8 + raw_size)
+                    .ok_or_else(|| invalid_data("uncompressed block is truncated"))?;
+                output.extend_from_slice(raw);
+                8 + raw_size
+            },
+            MAGIC_LZVN => {
+                let payload_size = u32_at(block, 8)? as usize;
+                let payload = block.get(12..12 + payload_size)
+                    .ok_or_else(|| invalid_data("lzvn block is truncated"))?;
+                decode_lzvn(payload, raw_size, &mut output)?;
+                12 + payload_size
+            },
+            MAGIC_V1 => decode_fse_block(block, &read_v1_header(block)?, &mut output)?,
+            _ => decode_fse_block(block, &read_v2_header(block)?, &mut output)?
+        };
+    }
+
+    Ok(output)
+}
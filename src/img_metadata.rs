@@ -0,0 +1,106 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+
+/// Descriptive metadata of an image source. It is rendered by the imgstat
+/// callback of a TskImgReadSeek and returned by `TskImg::metadata`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImgMetadata {
+    /// Name of the image format (e.g. "EWF", "VHDX")
+    pub format: String,
+    /// A description of the source, such as the path of the image
+    pub description: Option<String>,
+    /// The files making up the image in order, e.g. the segments of a split
+    /// image or the chain of a differencing disk
+    pub segments: Vec<PathBuf>,
+    /// Acquisition details and format specific values as name/value pairs,
+    /// in display order
+    pub details: Vec<(String, String)>
+}
+impl ImgMetadata {
+    /// Create metadata for an image format
+    pub fn new<S: Into<String>>(format: S) -> Self {
+        Self {
+            format: format.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Add a name/value pair to the details
+    pub fn add_detail<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.details.push((name.into(), value.into()));
+    }
+
+    /// Add a name/value pair to the details if there is a value
+    pub fn add_optional_detail<N: Into<String>, V: Into<String>>(&mut self, name: N, value: Option<V>) {
+        if let Some(value) = value {
+            self.add_detail(name, value);
+        }
+    }
+
+    /// The first value of a detail
+    pub fn detail(&self, name: &str) -> Option<&str> {
+        self.details.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Render the metadata the way libtsk's imgstat reports images
+    pub fn to_imgstat(&self, size: u64, sector_size: u32) -> String {
+        let mut output = String::new();
+        output.push_str("IMAGE FILE INFORMATION\n");
+        output.push_str("--------------------------------------------\n");
+        output.push_str(&format!("Image Type:\t\t{}\n", self.format));
+        if let Some(description) = &self.description {
+            output.push_str(&format!("Source:\t\t\t{}\n", description));
+        }
+        output.push_str(&format!("\nSize of data in bytes:\t{}\n", size));
+        output.push_str(&format!("Sector size:\t{}\n", sector_size));
+
+        if !self.segments.is_empty() {
+            output.push_str("\nSegments:\n");
+            for (index, segment) in self.segments.iter().enumerate() {
+                output.push_str(&format!("  {}: {}\n", index, segment.display()));
+            }
+        }
+        if !self.details.is_empty() {
+            output.push_str("\nDetails:\n");
+            for (name, value) in &self.details {
+                output.push_str(&format!("  {}: {}\n", name, value));
+            }
+        }
+        output
+    }
+}
+
+
+/// A source that can describe the image it reads, such as the format readers
+/// of this crate. Hand it to `TskImgReadSeek::from_metadata_source` to make
+/// the metadata available through libtsk's imgstat and `TskImg::metadata`.
+pub trait ImgMetadataSource {
+    /// The metadata of the image
+    fn img_metadata(&self) -> ImgMetadata;
+
+    /// The sector size of the image, which libtsk uses as the device sector
+    /// size
+    fn sector_size(&self) -> u32 {
+        512
+    }
+}
+
+
+/// The metadata shared between the imgstat callback of a TskImgReadSeek and
+/// the images reading through it
+#[derive(Debug, Default)]
+pub(crate) struct SharedMetadata {
+    metadata: Mutex<Option<ImgMetadata>>
+}
+impl SharedMetadata {
+    pub(crate) fn get(&self) -> Option<ImgMetadata> {
+        self.metadata.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set(&self, metadata: ImgMetadata) {
+        *self.metadata.lock().unwrap() = Some(metadata);
+    }
+}
@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use memmap2::{Mmap, MmapOptions};
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::file_size;
+
+
+/// Alignment of mapped windows. It covers the page size of common platforms
+/// and the 64 KiB allocation granularity of Windows.
+const WINDOW_ALIGNMENT: u64 = 64 * 1024;
+/// Default size of a mapped window. 64-bit targets map the whole file, 32-bit
+/// targets map windows that fit into their address space.
+#[cfg(target_pointer_width = "64")]
+const DEFAULT_WINDOW_SIZE: u64 = 1 << 40;
+#[cfg(not(target_pointer_width = "64"))]
+const DEFAULT_WINDOW_SIZE: u64 = 256 * 1024 * 1024;
+
+
+/// A Read + Seek reader of a raw image that serves reads from a memory
+/// mapping of the file instead of seeking and reading it. Files larger than
+/// the window size are mapped one window at a time.
+///
+/// The file length is checked whenever a window is mapped: when the file was
+/// truncated after it was opened, reads of windows that are no longer
+/// complete fail instead of touching pages past the end of the file. A
+/// truncation of the window that is already mapped can not be detected, and
+/// reading the lost pages raises SIGBUS on Unix, which is why the
+/// constructors are unsafe.
+///
+pub struct MmapReader {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    window_size: u64,
+    /// The mapped window (file offset, mapping)
+    window: Option<(u64, Mmap)>,
+    /// Offset of the next read in the file
+    offset: u64
+}
+impl MmapReader {
+    /// Open a raw image with the default window size
+    ///
+    /// # Safety
+    /// See `with_window_size`.
+    ///
+    pub unsafe fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        Self::with_window_size(path, DEFAULT_WINDOW_SIZE)
+    }
+
+    /// Open a raw image mapping at most window_size bytes at a time. The
+    /// window size must be a non-zero multiple of 64 KiB.
+    ///
+    /// # Safety
+    /// The file must not be truncated while the reader exists. Reading
+    /// mapped pages that are no longer backed by the file is undefined
+    /// behaviour (SIGBUS on Unix).
+    ///
+    pub unsafe fn with_window_size(path: impl AsRef<Path>, window_size: u64) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        if window_size == 0 || !window_size.is_multiple_of(WINDOW_ALIGNMENT) {
+            return Err(TskError::generic(
+                format!("Invalid mmap window size {}: it must be a multiple of {}.", window_size, WINDOW_ALIGNMENT)
+            ));
+        }
+        // A window must fit into the address space
+        let window_size = window_size.min((usize::MAX as u64 / 2) & !(WINDOW_ALIGNMENT - 1));
+
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error opening {} for mapping: {}", path.display(), e)
+        );
+        let file = File::open(path).map_err(io_error)?;
+        let size = file_size(&file).map_err(io_error)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            window_size,
+            window: None,
+            offset: 0
+        })
+    }
+
+    /// The size of the image when it was opened
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The maximum number of bytes mapped at a time
+    pub fn window_size(&self) -> u64 {
+        self.window_size
+    }
+
+    /// The path of the image
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Map the window containing offset
+    fn map_window(&mut self, offset: u64) -> std::io::Result<()> {
+        let start = offset - offset % self.window_size;
+        if self.window.as_ref().map(|w| w.0) == Some(start) {
+            return Ok(());
+        }
+        // Drop the old mapping first so that two windows are never mapped
+        self.window = None;
+
+        let len = (self.size - start).min(self.window_size);
+        let current_size = file_size(&self.file)?;
+        if current_size < start + len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{} was truncated to {} bytes after it was opened with {} bytes.",
+                    self.path.display(), current_size, self.size
+                )
+            ));
+        }
+
+        // SAFETY: the mapping is read only, the file covered the whole
+        // window when it was mapped and the caller of the constructor
+        // guarantees that it is not truncated afterwards.
+        let mmap = unsafe {
+            MmapOptions::new()
+                .offset(start)
+                .len(len as usize)
+                .map(&self.file)?
+        };
+        self.window = Some((start, mmap));
+        Ok(())
+    }
+}
+impl std::fmt::Debug for MmapReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MmapReader")
+         .field("path", &self.path)
+         .field("size", &self.size)
+         .field("window_size", &self.window_size)
+         .field("window", &self.window.as_ref().map(|(start, m)| (start, m.len())))
+         .finish()
+    }
+}
+impl ImgMetadataSource for MmapReader {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("raw");
+        metadata.description = Some(self.path.display().to_string());
+        metadata.segments.push(self.path.clone());
+        metadata.add_detail("Backend", "mmap");
+        metadata
+    }
+}
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = (self.size - self.offset).min(buf.len() as u64) as usize;
+
+        let mut copied = 0;
+        while copied < remaining {
+            let offset = self.offset + copied as u64;
+            self.map_window(offset)?;
+            let (start, mmap) = self.window.as_ref().unwrap();
+            let within = (offset - start) as usize;
+            let len = (mmap.len() - within).min(remaining - copied);
+            buf[copied..copied + len].copy_from_slice(&mmap[within..within + len]);
+            copied += len;
+        }
+
+        self.offset += copied as u64;
+        Ok(copied)
+    }
+}
+impl Seek for MmapReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+        if new_offset > self.size as i128 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Offset {:?} is greater than image size {}", pos, self.size)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
@@ -0,0 +1,581 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use flate2::read::DeflateDecoder;
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::{
+    read_exact_at,
+    file_size,
+    find_parent_file
+};
+use crate::tsk_img_reader::ReadSeek;
+
+
+/// QCOW magic ("QFI\xfb")
+const QCOW_MAGIC: &[u8; 4] = b"QFI\xfb";
+/// Size of the version 2 header
+const HEADER_SIZE_V2: usize = 72;
+/// Header extension with the backing file format
+const EXTENSION_BACKING_FORMAT: u32 = 0xe279_2aca;
+/// Incompatible feature bits
+const INCOMPATIBLE_DIRTY: u64 = 1 << 0;
+const INCOMPATIBLE_CORRUPT: u64 = 1 << 1;
+const INCOMPATIBLE_EXTERNAL_DATA: u64 = 1 << 2;
+const INCOMPATIBLE_COMPRESSION_TYPE: u64 = 1 << 3;
+const INCOMPATIBLE_EXTENDED_L2: u64 = 1 << 4;
+/// Mask of the offset in L1 and standard L2 entries
+const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+/// L2 entry flag for compressed clusters
+const L2_COMPRESSED: u64 = 1 << 62;
+/// L2 entry flag for clusters that read as zeros (version 3)
+const L2_ZERO: u64 = 1;
+/// Maximum number of backing files in a chain
+const MAX_BACKING_DEPTH: usize = 32;
+/// Maximum number of snapshots (QCOW_MAX_SNAPSHOTS in qemu)
+const MAX_SNAPSHOTS: u32 = 65536;
+
+
+/// An internal snapshot of a qcow2 image
+#[derive(Debug, Clone)]
+pub struct Qcow2Snapshot {
+    /// The snapshot id
+    pub id: String,
+    /// The snapshot name
+    pub name: String,
+    /// Seconds since the epoch when the snapshot was taken
+    pub date_sec: u32,
+    /// Size of the virtual disk when the snapshot was taken
+    pub disk_size: u64,
+    l1_table_offset: u64,
+    l1_size: u32
+}
+
+
+/// A Read + Seek reader of the virtual disk of a qcow2 image. Unallocated
+/// clusters are read from the backing file. The active image or an internal
+/// snapshot can be read.
+///
+pub struct Qcow2Reader {
+    path: PathBuf,
+    file: File,
+    version: u32,
+    cluster_size: u64,
+    /// The virtual disk size of the active image
+    active_size: u64,
+    /// The virtual disk size of the selected image or snapshot
+    size: u64,
+    active_l1: Vec<u64>,
+    /// L1 table of the selected image or snapshot
+    l1_table: Vec<u64>,
+    snapshots: Vec<Qcow2Snapshot>,
+    selected_snapshot: Option<usize>,
+    backing_file: Option<String>,
+    backing_format: Option<String>,
+    backing_path: Option<PathBuf>,
+    backing: Option<Box<dyn ReadSeek>>,
+    backing_size: u64,
+    /// The most recently used L2 table (table offset, entries)
+    l2_cache: Option<(u64, Vec<u64>)>,
+    /// The most recently decompressed cluster (host offset, data)
+    cluster_cache: Option<(u64, Vec<u8>)>,
+    /// Offset of the next read in the guest disk
+    offset: u64
+}
+impl Qcow2Reader {
+    /// Open a qcow2 image. Backing files are looked for relative to the
+    /// image.
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        Self::open_with_search_dirs(path, &[])
+    }
+
+    /// Open a qcow2 image. Backing files are looked for relative to the
+    /// image and then in the search directories.
+    ///
+    pub fn open_with_search_dirs(path: impl AsRef<Path>, search_dirs: &[PathBuf]) -> Result<Self, TskError> {
+        Self::open_chain(path.as_ref(), search_dirs, 0)
+    }
+
+    fn open_chain(path: &Path, search_dirs: &[PathBuf], depth: usize) -> Result<Self, TskError> {
+        if depth > MAX_BACKING_DEPTH {
+            return Err(TskError::img_format_error(
+                format!("qcow2 backing chain of {} is deeper than {}.", path.display(), MAX_BACKING_DEPTH)
+            ));
+        }
+
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading qcow2 {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let file_len = file_size(&file).map_err(io_error)?;
+        let mut header = vec![0u8; HEADER_SIZE_V2];
+        read_exact_at(&mut file, 0, &mut header).map_err(io_error)?;
+        if &header[0..4] != QCOW_MAGIC {
+            return Err(TskError::img_format_error(
+                format!("{} is not a qcow image (bad magic {:02x?}).", path.display(), &header[0..4])
+            ));
+        }
+
+        let be_u32 = |d: &[u8], o: usize| u32::from_be_bytes(d[o..o + 4].try_into().unwrap());
+        let be_u64 = |d: &[u8], o: usize| u64::from_be_bytes(d[o..o + 8].try_into().unwrap());
+
+        let version = be_u32(&header, 4);
+        if version != 2 && version != 3 {
+            return Err(TskError::img_format_error(
+                format!("{} is qcow version {}, only versions 2 and 3 are supported.", path.display(), version)
+            ));
+        }
+        let backing_file_offset = be_u64(&header, 8);
+        let backing_file_size = be_u32(&header, 16) as usize;
+        let cluster_bits = be_u32(&header, 20);
+        let size = be_u64(&header, 24);
+        let crypt_method = be_u32(&header, 32);
+        let l1_size = be_u32(&header, 36);
+        let l1_table_offset = be_u64(&header, 40);
+        let nb_snapshots = be_u32(&header, 60);
+        let snapshots_offset = be_u64(&header, 64);
+
+        if !(9..=21).contains(&cluster_bits) {
+            return Err(TskError::img_format_error(
+                format!("qcow2 {} has an invalid cluster size (2^{}).", path.display(), cluster_bits)
+            ));
+        }
+        if nb_snapshots > MAX_SNAPSHOTS {
+            return Err(TskError::img_format_error(
+                format!("qcow2 {} has {} snapshots, at most {} are supported.", path.display(), nb_snapshots, MAX_SNAPSHOTS)
+            ));
+        }
+        if crypt_method != 0 {
+            return Err(TskError::img_format_error(
+                format!("qcow2 {} is encrypted (method {}).", path.display(), crypt_method)
+            ));
+        }
+
+        // Version 3 headers have feature bits and a header length
+        let mut header_length = HEADER_SIZE_V2;
+        if version == 3 {
+            let mut header_v3 = vec![0u8; 104];
+            read_exact_at(&mut file, 0, &mut header_v3).map_err(io_error)?;
+            let incompatible = be_u64(&header_v3, 72);
+            header_length = be_u32(&header_v3, 100) as usize;
+
+            if incompatible & INCOMPATIBLE_DIRTY > 0 {
+                warn!("qcow2 {} was not closed cleanly, refcounts may be inconsistent.", path.display());
+            }
+            if incompatible & INCOMPATIBLE_CORRUPT > 0 {
+                warn!("qcow2 {} is marked as corrupt.", path.display());
+            }
+            if incompatible & INCOMPATIBLE_EXTERNAL_DATA > 0 {
+                return Err(TskError::img_format_error(
+                    format!("qcow2 {} uses an external data file which is not supported.", path.display())
+                ));
+            }
+            if incompatible & INCOMPATIBLE_EXTENDED_L2 > 0 {
+                return Err(TskError::img_format_error(
+                    format!("qcow2 {} uses extended L2 entries which are not supported.", path.display())
+                ));
+            }
+            if incompatible & INCOMPATIBLE_COMPRESSION_TYPE > 0 {
+                let mut compression_type = [0u8; 1];
+                read_exact_at(&mut file, 104, &mut compression_type).map_err(io_error)?;
+                if compression_type[0] != 0 {
+                    return Err(TskError::img_format_error(
+                        format!("qcow2 {} uses unsupported compression type {}.", path.display(), compression_type[0])
+                    ));
+                }
+            }
+        }
+
+        // Header extensions follow the header
+        let mut backing_format = None;
+        let mut extension_offset = header_length as u64;
+        loop {
+            let mut extension_header = [0u8; 8];
+            if read_exact_at(&mut file, extension_offset, &mut extension_header).is_err() {
+                break;
+            }
+            let extension_type = be_u32(&extension_header, 0);
+            let extension_length = be_u32(&extension_header, 4) as u64;
+            if extension_type == 0 {
+                break;
+            }
+            if extension_type == EXTENSION_BACKING_FORMAT {
+                check_extent(extension_offset + 8, extension_length, file_len).map_err(io_error)?;
+                let mut name = vec![0u8; extension_length as usize];
+                read_exact_at(&mut file, extension_offset + 8, &mut name).map_err(io_error)?;
+                backing_format = Some(String::from_utf8_lossy(&name).to_string());
+            }
+            extension_offset += 8 + extension_length.div_ceil(8) * 8;
+        }
+
+        let active_l1 = read_l1_table(&mut file, l1_table_offset, l1_size, file_len).map_err(io_error)?;
+        let snapshots = read_snapshots(&mut file, snapshots_offset, nb_snapshots, size, file_len).map_err(io_error)?;
+
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            file,
+            version,
+            cluster_size: 1 << cluster_bits,
+            active_size: size,
+            size,
+            l1_table: active_l1.clone(),
+            active_l1,
+            snapshots,
+            selected_snapshot: None,
+            backing_file: None,
+            backing_format,
+            backing_path: None,
+            backing: None,
+            backing_size: 0,
+            l2_cache: None,
+            cluster_cache: None,
+            offset: 0
+        };
+
+        if backing_file_offset > 0 && backing_file_size > 0 {
+            check_extent(backing_file_offset, backing_file_size as u64, file_len).map_err(io_error)?;
+            let mut name = vec![0u8; backing_file_size];
+            read_exact_at(&mut reader.file, backing_file_offset, &mut name).map_err(io_error)?;
+            let backing_file = String::from_utf8_lossy(&name).to_string();
+
+            let backing_path = find_parent_file(path, std::slice::from_ref(&backing_file), search_dirs)
+                .ok_or(TskError::img_format_error(
+                    format!("Unable to find backing file {} of qcow2 {}.", backing_file, path.display())
+                ))?;
+            reader.open_backing(&backing_path, search_dirs, depth)?;
+            reader.backing_file = Some(backing_file);
+            reader.backing_path = Some(backing_path);
+        }
+
+        Ok(reader)
+    }
+
+    /// Open the backing file as a qcow2 image or, for other formats, as raw
+    fn open_backing(&mut self, backing_path: &Path, search_dirs: &[PathBuf], depth: usize) -> Result<(), TskError> {
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading backing file {}: {}", backing_path.display(), e)
+        );
+        let mut file = File::open(backing_path).map_err(io_error)?;
+        let mut magic = [0u8; 4];
+        let is_qcow = file.read(&mut magic).map_err(io_error)? == 4 && &magic == QCOW_MAGIC;
+
+        match self.backing_format.as_deref() {
+            Some("qcow2") | None if is_qcow => {
+                let backing = Self::open_chain(backing_path, search_dirs, depth + 1)?;
+                self.backing_size = backing.size;
+                self.backing = Some(Box::new(backing));
+            },
+            Some("raw") | None => {
+                self.backing_size = file_size(&file).map_err(io_error)?;
+                self.backing = Some(Box::new(file));
+            },
+            Some(format) => {
+                return Err(TskError::img_format_error(
+                    format!(
+                        "Backing file {} of qcow2 {} has unsupported format {}.",
+                        backing_path.display(), self.path.display(), format
+                    )
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the qcow version (2 or 3)
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Get the cluster size in bytes
+    pub fn cluster_size(&self) -> u64 {
+        self.cluster_size
+    }
+
+    /// Get the size of the virtual disk (of the selected snapshot) in bytes
+    pub fn virtual_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get the internal snapshots of the image
+    pub fn snapshots(&self) -> &[Qcow2Snapshot] {
+        &self.snapshots
+    }
+
+    /// Get the snapshot that is being read, None for the active image
+    pub fn selected_snapshot(&self) -> Option<&Qcow2Snapshot> {
+        self.selected_snapshot.map(|i| &self.snapshots[i])
+    }
+
+    /// Read an internal snapshot, selected by its id or name, instead of the
+    /// active image.
+    ///
+    pub fn select_snapshot(&mut self, id_or_name: &str) -> Result<(), TskError> {
+        let index = self.snapshots.iter()
+            .position(|s| s.id == id_or_name)
+            .or_else(|| self.snapshots.iter().position(|s| s.name == id_or_name))
+            .ok_or(TskError::img_format_error(
+                format!("qcow2 {} has no snapshot {}.", self.path.display(), id_or_name)
+            ))?;
+        let snapshot = self.snapshots[index].clone();
+
+        self.l1_table = file_size(&self.file)
+            .and_then(|file_len| read_l1_table(&mut self.file, snapshot.l1_table_offset, snapshot.l1_size, file_len))
+            .map_err(|e| TskError::img_format_error(
+                format!("Error reading L1 table of snapshot {}: {}", id_or_name, e)
+            ))?;
+        self.size = snapshot.disk_size;
+        self.selected_snapshot = Some(index);
+        self.l2_cache = None;
+        Ok(())
+    }
+
+    /// Read the active image instead of a snapshot
+    pub fn select_active(&mut self) {
+        self.l1_table = self.active_l1.clone();
+        self.size = self.active_size;
+        self.selected_snapshot = None;
+        self.l2_cache = None;
+    }
+
+    /// Get the backing file name as stored in the image
+    pub fn backing_file(&self) -> Option<&str> {
+        self.backing_file.as_deref()
+    }
+
+    /// Get the backing file format as stored in the image
+    pub fn backing_format(&self) -> Option<&str> {
+        self.backing_format.as_deref()
+    }
+
+    /// Get the path the backing file was opened from
+    pub fn backing_path(&self) -> Option<&Path> {
+        self.backing_path.as_deref()
+    }
+
+    /// Read from the backing file, or zeros when there is no backing data
+    fn read_backing(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        let backing_size = self.backing_size;
+        match self.backing.as_mut() {
+            Some(backing) if offset < backing_size => {
+                let available = ((backing_size - offset) as usize).min(buf.len());
+                backing.seek(SeekFrom::Start(offset))?;
+                backing.read_exact(&mut buf[..available])?;
+                for b in buf[available..].iter_mut() {
+                    *b = 0;
+                }
+            },
+            _ => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the L2 entry of a cluster, 0 if the cluster is unallocated
+    fn l2_entry(&mut self, cluster: u64) -> std::io::Result<u64> {
+        let l2_entries = self.cluster_size / 8;
+        let l1_index = (cluster / l2_entries) as usize;
+        let l2_offset = match self.l1_table.get(l1_index) {
+            Some(e) => e & OFFSET_MASK,
+            None => return Ok(0)
+        };
+        if l2_offset == 0 {
+            return Ok(0);
+        }
+
+        if self.l2_cache.as_ref().map(|(o, _)| *o) != Some(l2_offset) {
+            let mut table = vec![0u8; self.cluster_size as usize];
+            read_exact_at(&mut self.file, l2_offset, &mut table)?;
+            let table = table.chunks_exact(8)
+                .map(|e| u64::from_be_bytes(e.try_into().unwrap()))
+                .collect();
+            self.l2_cache = Some((l2_offset, table));
+        }
+        Ok(self.l2_cache.as_ref().unwrap().1[(cluster % l2_entries) as usize])
+    }
+
+    /// Read up to the end of a cluster, returning the number of bytes read
+    fn read_cluster(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let cluster = offset / self.cluster_size;
+        let cluster_offset = offset % self.cluster_size;
+        let len = buf.len().min((self.cluster_size - cluster_offset) as usize);
+        let buf = &mut buf[..len];
+
+        let entry = self.l2_entry(cluster)?;
+        if entry & L2_COMPRESSED > 0 {
+            // The host offset and the number of additional 512 byte sectors
+            // share the entry, split depending on the cluster size
+            let cluster_bits = self.cluster_size.trailing_zeros() as u64;
+            let offset_bits = 62 - (cluster_bits - 8);
+            let host_offset = entry & ((1 << offset_bits) - 1);
+            let sectors = (entry & ((1 << 62) - 1)) >> offset_bits;
+            let compressed_size = (sectors + 1) * 512 - (host_offset & 511);
+
+            if self.cluster_cache.as_ref().map(|(o, _)| *o) != Some(host_offset) {
+                let file_len = file_size(&self.file)?;
+                let read_size = compressed_size.min(file_len.saturating_sub(host_offset));
+                let mut compressed = vec![0u8; read_size as usize];
+                read_exact_at(&mut self.file, host_offset, &mut compressed)?;
+
+                let mut data = Vec::with_capacity(self.cluster_size as usize);
+                DeflateDecoder::new(&compressed[..])
+                    .take(self.cluster_size)
+                    .read_to_end(&mut data)?;
+                data.resize(self.cluster_size as usize, 0);
+                self.cluster_cache = Some((host_offset, data));
+            }
+            let data = &self.cluster_cache.as_ref().unwrap().1;
+            buf.copy_from_slice(&data[cluster_offset as usize..cluster_offset as usize + len]);
+            return Ok(len);
+        }
+
+        let host_offset = entry & OFFSET_MASK;
+        if self.version >= 3 && entry & L2_ZERO > 0 {
+            for b in buf.iter_mut() {
+                *b = 0;
+            }
+        } else if host_offset == 0 {
+            self.read_backing(offset, buf)?;
+        } else {
+            read_exact_at(&mut self.file, host_offset + cluster_offset, buf)?;
+        }
+
+        Ok(len)
+    }
+}
+impl std::fmt::Debug for Qcow2Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Qcow2Reader")
+         .field("path", &self.path)
+         .field("version", &self.version)
+         .field("cluster_size", &self.cluster_size)
+         .field("size", &self.size)
+         .field("snapshots", &self.snapshots)
+         .field("selected_snapshot", &self.selected_snapshot())
+         .field("backing_path", &self.backing_path)
+         .finish()
+    }
+}
+impl ImgMetadataSource for Qcow2Reader {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("QCOW2");
+        metadata.description = Some(self.path.display().to_string());
+        metadata.segments.push(self.path.clone());
+        if let Some(backing_path) = &self.backing_path {
+            metadata.segments.push(backing_path.clone());
+        }
+        metadata.add_detail("Version", self.version.to_string());
+        metadata.add_detail("Cluster Size", self.cluster_size.to_string());
+        metadata.add_optional_detail("Backing File", self.backing_file.clone());
+        metadata.add_optional_detail("Backing Format", self.backing_format.clone());
+        metadata.add_detail("Snapshots", self.snapshots.len().to_string());
+        if let Some(snapshot) = self.selected_snapshot() {
+            metadata.add_detail("Selected Snapshot", format!("{} ({})", snapshot.name, snapshot.id));
+        }
+        metadata
+    }
+}
+impl Read for Qcow2Reader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = ((self.size - self.offset) as usize).min(buf.len());
+
+        let bytes_read = self.read_cluster(self.offset, &mut buf[..remaining])?;
+        self.offset += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl Seek for Qcow2Reader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+
+/// Check that a structure of `length` bytes at `offset` lies within the file
+fn check_extent(offset: u64, length: u64, file_len: u64) -> std::io::Result<()> {
+    if offset.checked_add(length).is_none_or(|end| end > file_len) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} bytes at offset {} extend past the end of the file", length, offset)
+        ));
+    }
+    Ok(())
+}
+
+
+/// Read an L1 table
+fn read_l1_table(file: &mut File, offset: u64, entries: u32, file_len: u64) -> std::io::Result<Vec<u64>> {
+    check_extent(offset, entries as u64 * 8, file_len)?;
+    let mut table = vec![0u8; entries as usize * 8];
+    read_exact_at(file, offset, &mut table)?;
+    Ok(table.chunks_exact(8)
+        .map(|e| u64::from_be_bytes(e.try_into().unwrap()))
+        .collect())
+}
+
+
+/// Read the snapshot table
+fn read_snapshots(file: &mut File, offset: u64, count: u32, disk_size: u64, file_len: u64) -> std::io::Result<Vec<Qcow2Snapshot>> {
+    let mut snapshots = Vec::new();
+    let mut entry_offset = offset;
+
+    for _ in 0..count {
+        let mut entry = [0u8; 40];
+        check_extent(entry_offset, 40, file_len)?;
+        read_exact_at(file, entry_offset, &mut entry)?;
+        let l1_table_offset = u64::from_be_bytes(entry[0..8].try_into().unwrap());
+        let l1_size = u32::from_be_bytes(entry[8..12].try_into().unwrap());
+        let id_size = u16::from_be_bytes(entry[12..14].try_into().unwrap()) as usize;
+        let name_size = u16::from_be_bytes(entry[14..16].try_into().unwrap()) as usize;
+        let date_sec = u32::from_be_bytes(entry[16..20].try_into().unwrap());
+        let extra_size = u32::from_be_bytes(entry[36..40].try_into().unwrap()) as usize;
+
+        let variable_size = (extra_size + id_size + name_size) as u64;
+        check_extent(entry_offset + 40, variable_size, file_len)?;
+        let mut variable = vec![0u8; variable_size as usize];
+        file.read_exact(&mut variable)?;
+        let extra = &variable[..extra_size];
+        let snapshot_disk_size = if extra_size >= 16 {
+            u64::from_be_bytes(extra[8..16].try_into().unwrap())
+        } else {
+            disk_size
+        };
+
+        snapshots.push(Qcow2Snapshot {
+            id: String::from_utf8_lossy(&variable[extra_size..extra_size + id_size]).to_string(),
+            name: String::from_utf8_lossy(&variable[extra_size + id_size..]).to_string(),
+            date_sec,
+            disk_size: snapshot_disk_size,
+            l1_table_offset,
+            l1_size
+        });
+
+        // Entries are aligned to 8 bytes
+        let entry_size = 40 + variable.len() as u64;
+        entry_offset += entry_size.div_ceil(8) * 8;
+    }
+
+    Ok(snapshots)
+}
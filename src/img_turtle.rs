@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+
+
+/// The rdf type predicate
+pub(crate) const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+
+
+/// The object of a triple
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Term {
+    Iri(String),
+    Literal {
+        value: String,
+        datatype: Option<String>
+    }
+}
+impl Term {
+    pub fn value(&self) -> &str {
+        match self {
+            Term::Iri(iri) => iri,
+            Term::Literal { value, .. } => value
+        }
+    }
+}
+
+
+/// A parsed triple (subject, predicate, object)
+pub(crate) type Triple = (String, String, Term);
+
+
+/// A parser for the subset of Turtle written by AFF4 tools: prefixes, IRIs,
+/// prefixed names, blank nodes (labelled and property lists), literals with
+/// datatypes or language tags, numbers and booleans. Collections are not
+/// supported.
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+    prefixes: HashMap<String, String>,
+    base: String,
+    blank_count: usize,
+    triples: Vec<Triple>
+}
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.position.min(self.text.len())]
+            .iter()
+            .filter(|b| **b == b'\n')
+            .count() + 1;
+        format!("turtle line {}: {}", line, message)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.position).copied()
+    }
+
+    fn starts_with(&self, token: &str) -> bool {
+        self.text[self.position..].starts_with(token.as_bytes())
+    }
+
+    /// Skip whitespace and comments
+    fn skip_space(&mut self) {
+        while let Some(byte) = self.peek() {
+            if byte.is_ascii_whitespace() {
+                self.position += 1;
+            } else if byte == b'#' {
+                while let Some(byte) = self.peek() {
+                    if byte == b'\n' {
+                        break;
+                    }
+                    self.position += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_space();
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn parse_document(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_space();
+            if self.peek().is_none() {
+                return Ok(());
+            }
+
+            if self.starts_with("@prefix") || self.starts_with("PREFIX") || self.starts_with("prefix") {
+                let sparql = self.peek() != Some(b'@');
+                self.position += if sparql { 6 } else { 7 };
+                self.skip_space();
+                let start = self.position;
+                while let Some(byte) = self.peek() {
+                    if byte == b':' {
+                        break;
+                    }
+                    self.position += 1;
+                }
+                let name = String::from_utf8_lossy(&self.text[start..self.position]).trim().to_string();
+                self.expect(b':')?;
+                self.skip_space();
+                let iri = self.parse_iri_ref()?;
+                self.prefixes.insert(name, iri);
+                if !sparql {
+                    self.expect(b'.')?;
+                }
+            } else if self.starts_with("@base") || self.starts_with("BASE") {
+                let sparql = self.peek() != Some(b'@');
+                self.position += if sparql { 4 } else { 5 };
+                self.skip_space();
+                self.base = self.parse_iri_ref()?;
+                if !sparql {
+                    self.expect(b'.')?;
+                }
+            } else {
+                let subject = self.parse_subject()?;
+                self.skip_space();
+                if self.peek() != Some(b'.') {
+                    self.parse_predicate_objects(&subject)?;
+                }
+                self.expect(b'.')?;
+            }
+        }
+    }
+
+    fn parse_subject(&mut self) -> Result<String, String> {
+        self.skip_space();
+        match self.peek() {
+            Some(b'[') => self.parse_blank_property_list(),
+            _ => match self.parse_term()? {
+                Term::Iri(iri) => Ok(iri),
+                Term::Literal { .. } => Err(self.error("a literal can not be a subject"))
+            }
+        }
+    }
+
+    fn parse_predicate_objects(&mut self, subject: &str) -> Result<(), String> {
+        loop {
+            self.skip_space();
+            let predicate = if self.peek() == Some(b'a') &&
+                self.text.get(self.position + 1).map(|b| b.is_ascii_whitespace()).unwrap_or(false) {
+                self.position += 1;
+                RDF_TYPE.to_string()
+            } else {
+                match self.parse_term()? {
+                    Term::Iri(iri) => iri,
+                    Term::Literal { .. } => return Err(self.error("a literal can not be a predicate"))
+                }
+            };
+
+            loop {
+                self.skip_space();
+                let object = if self.peek() == Some(b'[') {
+                    Term::Iri(self.parse_blank_property_list()?)
+                } else {
+                    self.parse_term()?
+                };
+                self.triples.push((subject.to_string(), predicate.clone(), object));
+                self.skip_space();
+                if self.peek() == Some(b',') {
+                    self.position += 1;
+                } else {
+                    break;
+                }
+            }
+
+            self.skip_space();
+            if self.peek() != Some(b';') {
+                return Ok(());
+            }
+            // Repeated and trailing semicolons are allowed
+            while self.peek() == Some(b';') {
+                self.position += 1;
+                self.skip_space();
+            }
+            if matches!(self.peek(), Some(b'.') | Some(b']')) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parse "[ predicate objects ]" and return the new blank node
+    fn parse_blank_property_list(&mut self) -> Result<String, String> {
+        self.expect(b'[')?;
+        self.blank_count += 1;
+        let node = format!("_:genid{}", self.blank_count);
+        self.skip_space();
+        if self.peek() != Some(b']') {
+            self.parse_predicate_objects(&node)?;
+        }
+        self.expect(b']')?;
+        Ok(node)
+    }
+
+    fn parse_iri_ref(&mut self) -> Result<String, String> {
+        if self.peek() != Some(b'<') {
+            return Err(self.error("expected an IRI"));
+        }
+        self.position += 1;
+        let start = self.position;
+        while let Some(byte) = self.peek() {
+            if byte == b'>' {
+                let iri = String::from_utf8_lossy(&self.text[start..self.position]).to_string();
+                self.position += 1;
+                if !self.base.is_empty() && !iri.contains(':') {
+                    return Ok(format!("{}{}", self.base, iri));
+                }
+                return Ok(iri);
+            }
+            self.position += 1;
+        }
+        Err(self.error("unterminated IRI"))
+    }
+
+    fn parse_term(&mut self) -> Result<Term, String> {
+        self.skip_space();
+        match self.peek() {
+            Some(b'<') => Ok(Term::Iri(self.parse_iri_ref()?)),
+            Some(b'"') | Some(b'\'') => self.parse_literal(),
+            Some(byte) if byte == b'+' || byte == b'-' || byte.is_ascii_digit() => {
+                let start = self.position;
+                self.position += 1;
+                while let Some(byte) = self.peek() {
+                    if byte.is_ascii_digit() || byte == b'.' && self.text.get(self.position + 1)
+                        .map(|b| b.is_ascii_digit()).unwrap_or(false) || byte == b'e' || byte == b'E' {
+                        self.position += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let value = String::from_utf8_lossy(&self.text[start..self.position]).to_string();
+                let datatype = if value.contains(['e', 'E'].as_ref()) {
+                    "double"
+                } else if value.contains('.') {
+                    "decimal"
+                } else {
+                    "integer"
+                };
+                Ok(Term::Literal {
+                    value,
+                    datatype: Some(format!("http://www.w3.org/2001/XMLSchema#{}", datatype))
+                })
+            },
+            Some(_) => {
+                let name = self.parse_name();
+                if name.is_empty() {
+                    return Err(self.error("expected a term"));
+                }
+                if name == "true" || name == "false" {
+                    return Ok(Term::Literal {
+                        value: name,
+                        datatype: Some("http://www.w3.org/2001/XMLSchema#boolean".to_string())
+                    });
+                }
+                if name.starts_with("_:") {
+                    return Ok(Term::Iri(name));
+                }
+                match name.find(':') {
+                    Some(split) => match self.prefixes.get(&name[..split]) {
+                        Some(namespace) => Ok(Term::Iri(format!("{}{}", namespace, &name[split + 1..]))),
+                        None => Err(self.error(&format!("undefined prefix in {}", name)))
+                    },
+                    None => Err(self.error(&format!("unexpected {}", name)))
+                }
+            },
+            None => Err(self.error("unexpected end of document"))
+        }
+    }
+
+    /// Parse a prefixed name or blank node label
+    fn parse_name(&mut self) -> String {
+        let start = self.position;
+        while let Some(byte) = self.peek() {
+            let is_name = byte.is_ascii_alphanumeric() || byte >= 0x80 ||
+                matches!(byte, b'_' | b'-' | b':' | b'%' | b'\\') ||
+                // A dot can be in a name but not end it
+                byte == b'.' && self.text.get(self.position + 1)
+                    .map(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b':'))
+                    .unwrap_or(false);
+            if !is_name {
+                break;
+            }
+            self.position += 1;
+        }
+        String::from_utf8_lossy(&self.text[start..self.position]).replace('\\', "")
+    }
+
+    fn parse_literal(&mut self) -> Result<Term, String> {
+        let quote = self.peek().unwrap();
+        let long = self.text[self.position..].starts_with(&[quote, quote, quote]);
+        self.position += if long { 3 } else { 1 };
+
+        let mut value = Vec::new();
+        loop {
+            let byte = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+            if byte == quote {
+                if !long {
+                    self.position += 1;
+                    break;
+                }
+                if self.text[self.position..].starts_with(&[quote, quote, quote]) {
+                    self.position += 3;
+                    break;
+                }
+            }
+            if byte == b'\\' {
+                self.position += 1;
+                let escaped = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                self.position += 1;
+                match escaped {
+                    b't' => value.push(b'\t'),
+                    b'n' => value.push(b'\n'),
+                    b'r' => value.push(b'\r'),
+                    b'b' => value.push(8),
+                    b'f' => value.push(12),
+                    b'u' | b'U' => {
+                        let digits = if escaped == b'u' { 4 } else { 8 };
+                        let hex = self.text.get(self.position..self.position + digits)
+                            .ok_or_else(|| self.error("truncated unicode escape"))?;
+                        let code = u32::from_str_radix(&String::from_utf8_lossy(hex), 16)
+                            .map_err(|_| self.error("invalid unicode escape"))?;
+                        let character = std::char::from_u32(code).unwrap_or('\u{fffd}');
+                        value.extend_from_slice(character.to_string().as_bytes());
+                        self.position += digits;
+                    },
+                    other => value.push(other)
+                }
+                continue;
+            }
+            if !long && byte == b'\n' {
+                return Err(self.error("newline in string"));
+            }
+            value.push(byte);
+            self.position += 1;
+        }
+        let value = String::from_utf8_lossy(&value).to_string();
+
+        let mut datatype = None;
+        if self.starts_with("^^") {
+            self.position += 2;
+            datatype = match self.parse_term()? {
+                Term::Iri(iri) => Some(iri),
+                Term::Literal { .. } => return Err(self.error("a literal can not be a datatype"))
+            };
+        } else if self.peek() == Some(b'@') {
+            // Language tags are dropped
+            self.position += 1;
+            while let Some(byte) = self.peek() {
+                if !(byte.is_ascii_alphanumeric() || byte == b'-') {
+                    break;
+                }
+                self.position += 1;
+            }
+        }
+
+        Ok(Term::Literal { value, datatype })
+    }
+}
+
+
+/// Parse a Turtle document into triples
+pub(crate) fn parse_turtle(text: &str) -> Result<Vec<Triple>, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        position: 0,
+        prefixes: HashMap::new(),
+        base: String::new(),
+        blank_count: 0,
+        triples: Vec::new()
+    };
+    parser.parse_document()?;
+    Ok(parser.triples)
+}
@@ -0,0 +1,448 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::{
+    read_exact_at,
+    file_size,
+    utf16le_to_string,
+    utf16be_to_string,
+    format_guid,
+    find_parent_file
+};
+
+
+/// VHD footer cookie
+const FOOTER_COOKIE: &[u8; 8] = b"conectix";
+/// VHD dynamic disk header cookie
+const DYNAMIC_HEADER_COOKIE: &[u8; 8] = b"cxsparse";
+/// Size of the VHD footer
+const FOOTER_SIZE: u64 = 512;
+/// Size of the VHD dynamic disk header
+const DYNAMIC_HEADER_SIZE: u64 = 1024;
+/// VHD sector size
+const SECTOR_SIZE: u64 = 512;
+/// BAT entry for a block that is not allocated
+const UNALLOCATED_BLOCK: u32 = 0xffff_ffff;
+/// Maximum number of differencing images in a parent chain
+const MAX_PARENT_DEPTH: usize = 32;
+
+
+/// The VHD disk types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VhdDiskType {
+    Fixed,
+    Dynamic,
+    Differencing
+}
+
+
+/// VHD checksum, the one's complement of the sum of all bytes
+/// excluding the checksum field.
+fn vhd_checksum(data: &[u8], checksum_offset: usize) -> u32 {
+    let sum = data.iter()
+        .enumerate()
+        .filter(|(i, _)| *i < checksum_offset || *i >= checksum_offset + 4)
+        .fold(0u32, |sum, (_, b)| sum.wrapping_add(*b as u32));
+    !sum
+}
+
+
+/// A Read + Seek reader of the virtual disk of a fixed, dynamic or
+/// differencing VHD. Differencing disks read unallocated sectors from their
+/// parent, which is located through the parent locators of the disk.
+///
+pub struct VhdReader {
+    path: PathBuf,
+    file: File,
+    disk_type: VhdDiskType,
+    size: u64,
+    unique_id: [u8; 16],
+    block_size: u64,
+    bitmap_size: u64,
+    bat: Vec<u32>,
+    /// The most recently read sector bitmap (block number, bitmap)
+    bitmap_cache: Option<(u64, Vec<u8>)>,
+    parent: Option<Box<VhdReader>>,
+    /// Offset of the next read in the virtual disk
+    offset: u64
+}
+impl VhdReader {
+    /// Open a VHD. The parents of differencing disks are looked for relative
+    /// to the child disk.
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        Self::open_with_search_dirs(path, &[])
+    }
+
+    /// Open a VHD. The parents of differencing disks are looked for relative
+    /// to the child disk and then in the search directories.
+    ///
+    pub fn open_with_search_dirs(path: impl AsRef<Path>, search_dirs: &[PathBuf]) -> Result<Self, TskError> {
+        Self::open_chain(path.as_ref(), search_dirs, 0)
+    }
+
+    fn open_chain(path: &Path, search_dirs: &[PathBuf], depth: usize) -> Result<Self, TskError> {
+        if depth > MAX_PARENT_DEPTH {
+            return Err(TskError::img_format_error(
+                format!("VHD parent chain of {} is deeper than {}.", path.display(), MAX_PARENT_DEPTH)
+            ));
+        }
+
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading VHD {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let size = file_size(&file).map_err(io_error)?;
+        if size < FOOTER_SIZE {
+            return Err(TskError::img_format_error(
+                format!("{} is too small to be a VHD.", path.display())
+            ));
+        }
+
+        // The footer is at the end of the file. Dynamic disks keep a copy at
+        // the start that is used if the footer is damaged.
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        read_exact_at(&mut file, size - FOOTER_SIZE, &mut footer).map_err(io_error)?;
+        if &footer[0..8] != FOOTER_COOKIE || vhd_checksum(&footer, 64) != u32::from_be_bytes(footer[64..68].try_into().unwrap()) {
+            warn!("VHD footer of {} is invalid, using the footer copy.", path.display());
+            read_exact_at(&mut file, 0, &mut footer).map_err(io_error)?;
+            if &footer[0..8] != FOOTER_COOKIE {
+                return Err(TskError::img_format_error(
+                    format!("{} is not a VHD (no footer found).", path.display())
+                ));
+            }
+            if vhd_checksum(&footer, 64) != u32::from_be_bytes(footer[64..68].try_into().unwrap()) {
+                return Err(TskError::img_format_error(
+                    format!("VHD footer of {} has a bad checksum.", path.display())
+                ));
+            }
+        }
+
+        let data_offset = u64::from_be_bytes(footer[16..24].try_into().unwrap());
+        let current_size = u64::from_be_bytes(footer[48..56].try_into().unwrap());
+        let disk_type = match u32::from_be_bytes(footer[60..64].try_into().unwrap()) {
+            2 => VhdDiskType::Fixed,
+            3 => VhdDiskType::Dynamic,
+            4 => VhdDiskType::Differencing,
+            other => return Err(TskError::img_format_error(
+                format!("VHD {} has an unsupported disk type {}.", path.display(), other)
+            ))
+        };
+        let mut unique_id = [0u8; 16];
+        unique_id.copy_from_slice(&footer[68..84]);
+
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            file,
+            disk_type,
+            size: current_size,
+            unique_id,
+            block_size: 0,
+            bitmap_size: 0,
+            bat: Vec::new(),
+            bitmap_cache: None,
+            parent: None,
+            offset: 0
+        };
+
+        if disk_type == VhdDiskType::Fixed {
+            if current_size > size - FOOTER_SIZE {
+                return Err(TskError::img_format_error(
+                    format!(
+                        "Fixed VHD {} is truncated: disk size {} but only {} bytes of data.",
+                        path.display(), current_size, size - FOOTER_SIZE
+                    )
+                ));
+            }
+            return Ok(reader);
+        }
+
+        // Dynamic disk header
+        let mut header = [0u8; DYNAMIC_HEADER_SIZE as usize];
+        read_exact_at(&mut reader.file, data_offset, &mut header).map_err(io_error)?;
+        if &header[0..8] != DYNAMIC_HEADER_COOKIE {
+            return Err(TskError::img_format_error(
+                format!("VHD {} has no dynamic disk header at offset {}.", path.display(), data_offset)
+            ));
+        }
+        if vhd_checksum(&header, 36) != u32::from_be_bytes(header[36..40].try_into().unwrap()) {
+            return Err(TskError::img_format_error(
+                format!("VHD dynamic disk header of {} has a bad checksum.", path.display())
+            ));
+        }
+
+        let table_offset = u64::from_be_bytes(header[16..24].try_into().unwrap());
+        let max_table_entries = u32::from_be_bytes(header[28..32].try_into().unwrap()) as u64;
+        reader.block_size = u32::from_be_bytes(header[32..36].try_into().unwrap()) as u64;
+        if reader.block_size == 0 || !reader.block_size.is_multiple_of(SECTOR_SIZE) {
+            return Err(TskError::img_format_error(
+                format!("VHD {} has an invalid block size {}.", path.display(), reader.block_size)
+            ));
+        }
+        let sectors_per_block = reader.block_size / SECTOR_SIZE;
+        reader.bitmap_size = sectors_per_block.div_ceil(8).div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+
+        let needed_entries = current_size.div_ceil(reader.block_size);
+        if max_table_entries < needed_entries {
+            return Err(TskError::img_format_error(
+                format!(
+                    "VHD {} has {} BAT entries but needs {}.",
+                    path.display(), max_table_entries, needed_entries
+                )
+            ));
+        }
+        if table_offset.checked_add(needed_entries * 4).is_none_or(|end| end > size) {
+            return Err(TskError::img_format_error(
+                format!("VHD {} BAT extends past the end of the file.", path.display())
+            ));
+        }
+        let mut bat = vec![0u8; (needed_entries * 4) as usize];
+        read_exact_at(&mut reader.file, table_offset, &mut bat).map_err(io_error)?;
+        reader.bat = bat.chunks_exact(4)
+            .map(|e| u32::from_be_bytes(e.try_into().unwrap()))
+            .collect();
+
+        if disk_type == VhdDiskType::Differencing {
+            let mut parent_id = [0u8; 16];
+            parent_id.copy_from_slice(&header[40..56]);
+
+            let candidates = reader.parent_locators(&header);
+            let parent_path = find_parent_file(path, &candidates, search_dirs)
+                .ok_or(TskError::img_format_error(
+                    format!("Unable to find the parent of VHD {} (tried {:?}).", path.display(), candidates)
+                ))?;
+            let parent = Self::open_chain(&parent_path, search_dirs, depth + 1)?;
+
+            if parent.unique_id != parent_id {
+                return Err(TskError::img_format_error(
+                    format!(
+                        "Parent {} of VHD {} has id {} but {} was expected.",
+                        parent_path.display(), path.display(),
+                        format_guid(&parent.unique_id), format_guid(&parent_id)
+                    )
+                ));
+            }
+            if parent.size < current_size {
+                warn!("Parent {} is smaller than its child {}.", parent_path.display(), path.display());
+            }
+            reader.parent = Some(Box::new(parent));
+        }
+
+        Ok(reader)
+    }
+
+    /// Get the parent paths stored in the dynamic header. The platform
+    /// specific locators come first and the parent's file name last.
+    fn parent_locators(&mut self, header: &[u8]) -> Vec<String> {
+        let mut candidates = Vec::new();
+
+        for entry in header[576..768].chunks_exact(24) {
+            let platform_code = &entry[0..4];
+            let data_length = u32::from_be_bytes(entry[8..12].try_into().unwrap()) as usize;
+            let data_offset = u64::from_be_bytes(entry[16..24].try_into().unwrap());
+            if data_length == 0 || data_length > 65536 {
+                continue;
+            }
+
+            let mut data = vec![0u8; data_length];
+            if let Err(e) = read_exact_at(&mut self.file, data_offset, &mut data) {
+                warn!("Unable to read VHD parent locator of {}: {}", self.path.display(), e);
+                continue;
+            }
+
+            match platform_code {
+                b"W2ru" | b"W2ku" => candidates.push(utf16le_to_string(&data)),
+                b"MacX" => candidates.push(String::from_utf8_lossy(&data).to_string()),
+                _ => {}
+            }
+        }
+
+        let parent_name = utf16be_to_string(&header[64..576]);
+        if !parent_name.is_empty() {
+            candidates.push(parent_name);
+        }
+
+        candidates
+    }
+
+    /// Get the disk type
+    pub fn disk_type(&self) -> VhdDiskType {
+        self.disk_type
+    }
+
+    /// Get the size of the virtual disk in bytes
+    pub fn virtual_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get the unique id of the disk
+    pub fn unique_id(&self) -> String {
+        format_guid(&self.unique_id)
+    }
+
+    /// Get the block size of a dynamic or differencing disk (0 for fixed disks)
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Get the paths of the disk and its parents, child first
+    pub fn chain_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.path.clone()];
+        let mut parent = self.parent.as_ref();
+        while let Some(p) = parent {
+            paths.push(p.path.clone());
+            parent = p.parent.as_ref();
+        }
+        paths
+    }
+
+    /// Get the parent of a differencing disk
+    pub fn parent(&self) -> Option<&VhdReader> {
+        self.parent.as_deref()
+    }
+
+    /// Read from the parent, or zeros when there is no parent data
+    fn read_parent(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        match self.parent.as_mut() {
+            Some(parent) if offset < parent.size => {
+                let available = ((parent.size - offset) as usize).min(buf.len());
+                parent.seek(SeekFrom::Start(offset))?;
+                parent.read_exact(&mut buf[..available])?;
+                for b in buf[available..].iter_mut() {
+                    *b = 0;
+                }
+            },
+            _ => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the sector bitmap of an allocated block
+    fn block_bitmap(&mut self, block: u64, block_sector: u64) -> std::io::Result<&[u8]> {
+        if self.bitmap_cache.as_ref().map(|(b, _)| *b) != Some(block) {
+            let mut bitmap = vec![0u8; self.bitmap_size as usize];
+            read_exact_at(&mut self.file, block_sector * SECTOR_SIZE, &mut bitmap)?;
+            self.bitmap_cache = Some((block, bitmap));
+        }
+        Ok(&self.bitmap_cache.as_ref().unwrap().1)
+    }
+
+    /// Read up to the end of a block, returning the number of bytes read
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let block = offset / self.block_size;
+        let block_offset = offset % self.block_size;
+        let len = buf.len().min((self.block_size - block_offset) as usize);
+        let buf = &mut buf[..len];
+
+        let block_sector = self.bat[block as usize];
+        if block_sector == UNALLOCATED_BLOCK {
+            self.read_parent(offset, buf)?;
+            return Ok(len);
+        }
+        let data_offset = block_sector as u64 * SECTOR_SIZE + self.bitmap_size + block_offset;
+
+        if self.disk_type == VhdDiskType::Dynamic {
+            read_exact_at(&mut self.file, data_offset, buf)?;
+            return Ok(len);
+        }
+
+        // Differencing disks only contain the sectors set in the bitmap. Read
+        // runs of sectors that are in the same file.
+        let bitmap = self.block_bitmap(block, block_sector as u64)?.to_vec();
+        let in_child = |byte_offset: u64| {
+            let sector = (byte_offset / SECTOR_SIZE) as usize;
+            bitmap[sector / 8] & (0x80 >> (sector % 8)) > 0
+        };
+
+        let mut done = 0usize;
+        while done < len {
+            let run_start = block_offset + done as u64;
+            let run_in_child = in_child(run_start);
+            let mut run_end = (run_start / SECTOR_SIZE + 1) * SECTOR_SIZE;
+            while run_end < block_offset + len as u64 && in_child(run_end) == run_in_child {
+                run_end += SECTOR_SIZE;
+            }
+            let run_len = ((run_end - run_start) as usize).min(len - done);
+
+            if run_in_child {
+                read_exact_at(&mut self.file, data_offset + done as u64, &mut buf[done..done + run_len])?;
+            } else {
+                self.read_parent(offset + done as u64, &mut buf[done..done + run_len])?;
+            }
+            done += run_len;
+        }
+
+        Ok(len)
+    }
+}
+impl std::fmt::Debug for VhdReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VhdReader")
+         .field("path", &self.path)
+         .field("disk_type", &self.disk_type)
+         .field("size", &self.size)
+         .field("unique_id", &self.unique_id())
+         .field("block_size", &self.block_size)
+         .field("parent", &self.parent)
+         .finish()
+    }
+}
+impl ImgMetadataSource for VhdReader {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("VHD");
+        metadata.description = Some(self.path.display().to_string());
+        metadata.segments = self.chain_paths();
+        metadata.add_detail("Disk Type", format!("{:?}", self.disk_type));
+        metadata.add_detail("Unique Id", self.unique_id());
+        if self.block_size > 0 {
+            metadata.add_detail("Block Size", self.block_size.to_string());
+        }
+        metadata
+    }
+}
+impl Read for VhdReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = ((self.size - self.offset) as usize).min(buf.len());
+        let buf = &mut buf[..remaining];
+
+        let bytes_read = match self.disk_type {
+            VhdDiskType::Fixed => {
+                read_exact_at(&mut self.file, self.offset, buf)?;
+                buf.len()
+            },
+            _ => self.read_block(self.offset, buf)?
+        };
+
+        self.offset += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl Seek for VhdReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
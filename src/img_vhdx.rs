@@ -0,0 +1,580 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::{
+    read_exact_at,
+    file_size,
+    utf16le_to_string,
+    format_guid,
+    find_parent_file
+};
+
+
+/// VHDX file type identifier signature
+const FILE_SIGNATURE: &[u8; 8] = b"vhdxfile";
+/// Offsets of the two VHDX headers
+const HEADER_OFFSETS: [u64; 2] = [0x10000, 0x20000];
+/// Size of a VHDX header
+const HEADER_SIZE: usize = 4096;
+/// Offsets of the two region tables
+const REGION_TABLE_OFFSETS: [u64; 2] = [0x30000, 0x40000];
+/// Size of a region table
+const REGION_TABLE_SIZE: usize = 65536;
+/// Unit of the BAT file offsets
+const MIB: u64 = 1024 * 1024;
+/// Smallest payload block size allowed by the specification
+const MIN_BLOCK_SIZE: u64 = MIB;
+/// Largest payload block size allowed by the specification
+const MAX_BLOCK_SIZE: u64 = 256 * MIB;
+/// Maximum number of differencing images in a parent chain
+const MAX_PARENT_DEPTH: usize = 32;
+
+/// Region GUIDs
+const BAT_REGION: &str = "2DC27766-F623-4200-9D64-115E9BFD4A08";
+const METADATA_REGION: &str = "8B7CA206-4790-4B9A-B8FE-575F050F886E";
+
+/// Metadata item GUIDs
+const FILE_PARAMETERS: &str = "CAA16737-FA36-4D43-B3B6-33F0AA44E76B";
+const VIRTUAL_DISK_SIZE: &str = "2FA54224-CD1B-4876-B211-5DBED83BF4B8";
+const VIRTUAL_DISK_ID: &str = "BECA12AB-B2E6-4523-93EF-C309E000C746";
+const LOGICAL_SECTOR_SIZE: &str = "8141BF1D-A96F-4709-BA47-F233A8FAAB5F";
+const PHYSICAL_SECTOR_SIZE: &str = "CDA348C7-445D-4471-9CC9-E9885251C556";
+const PARENT_LOCATOR: &str = "A8D35F2B-B30B-454D-ABF7-D3D84834AB0C";
+
+/// Payload block states
+const PAYLOAD_BLOCK_NOT_PRESENT: u64 = 0;
+const PAYLOAD_BLOCK_FULLY_PRESENT: u64 = 6;
+const PAYLOAD_BLOCK_PARTIALLY_PRESENT: u64 = 7;
+/// Sector bitmap block state
+const SB_BLOCK_PRESENT: u64 = 6;
+
+
+/// CRC-32C (Castagnoli) used by the VHDX headers and region tables
+pub(crate) fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 > 0 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+
+/// Check the CRC-32C of a structure whose checksum is at offset 4
+fn checksum_valid(data: &[u8]) -> bool {
+    let stored = u32::from_le_bytes(data[4..8].try_into().unwrap());
+    let mut copy = data.to_vec();
+    copy[4..8].copy_from_slice(&[0; 4]);
+    crc32c(&copy) == stored
+}
+
+
+/// Read a GUID at an offset of a buffer
+fn guid_at(data: &[u8], offset: usize) -> String {
+    let bytes: [u8; 16] = data[offset..offset + 16].try_into().unwrap();
+    format_guid(&bytes)
+}
+
+
+/// A Read + Seek reader of the virtual disk of a fixed, dynamic or
+/// differencing VHDX. Differencing disks read blocks and sectors that are not
+/// present from their parent, which is located through the parent locator.
+///
+pub struct VhdxReader {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    block_size: u64,
+    logical_sector_size: u64,
+    physical_sector_size: u64,
+    chunk_ratio: u64,
+    has_parent: bool,
+    virtual_disk_id: Option<String>,
+    data_write_guid: String,
+    log_replay_pending: bool,
+    parent_locator: BTreeMap<String, String>,
+    bat: Vec<u64>,
+    /// The most recently read sector bitmap block (chunk number, bitmap)
+    bitmap_cache: Option<(u64, Vec<u8>)>,
+    parent: Option<Box<VhdxReader>>,
+    /// Offset of the next read in the virtual disk
+    offset: u64
+}
+impl VhdxReader {
+    /// Open a VHDX. The parents of differencing disks are looked for relative
+    /// to the child disk.
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        Self::open_with_search_dirs(path, &[])
+    }
+
+    /// Open a VHDX. The parents of differencing disks are looked for relative
+    /// to the child disk and then in the search directories.
+    ///
+    pub fn open_with_search_dirs(path: impl AsRef<Path>, search_dirs: &[PathBuf]) -> Result<Self, TskError> {
+        Self::open_chain(path.as_ref(), search_dirs, 0)
+    }
+
+    fn open_chain(path: &Path, search_dirs: &[PathBuf], depth: usize) -> Result<Self, TskError> {
+        if depth > MAX_PARENT_DEPTH {
+            return Err(TskError::img_format_error(
+                format!("VHDX parent chain of {} is deeper than {}.", path.display(), MAX_PARENT_DEPTH)
+            ));
+        }
+
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading VHDX {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let size = file_size(&file).map_err(io_error)?;
+        if size < REGION_TABLE_OFFSETS[1] + REGION_TABLE_SIZE as u64 {
+            return Err(TskError::img_format_error(
+                format!("{} is too small to be a VHDX.", path.display())
+            ));
+        }
+
+        let mut signature = [0u8; 8];
+        read_exact_at(&mut file, 0, &mut signature).map_err(io_error)?;
+        if &signature != FILE_SIGNATURE {
+            return Err(TskError::img_format_error(
+                format!("{} is not a VHDX (bad signature {:02x?}).", path.display(), signature)
+            ));
+        }
+
+        // Use the valid header with the highest sequence number
+        let mut current_header: Option<(u64, Vec<u8>)> = None;
+        for header_offset in HEADER_OFFSETS.iter() {
+            let mut header = vec![0u8; HEADER_SIZE];
+            read_exact_at(&mut file, *header_offset, &mut header).map_err(io_error)?;
+            if &header[0..4] != b"head" || !checksum_valid(&header) {
+                warn!("VHDX header at offset {} of {} is invalid.", header_offset, path.display());
+                continue;
+            }
+            let sequence_number = u64::from_le_bytes(header[8..16].try_into().unwrap());
+            if current_header.as_ref().map(|(s, _)| sequence_number > *s).unwrap_or(true) {
+                current_header = Some((sequence_number, header));
+            }
+        }
+        let header = current_header
+            .ok_or(TskError::img_format_error(
+                format!("VHDX {} has no valid header.", path.display())
+            ))?.1;
+        let data_write_guid = guid_at(&header, 32);
+        let log_replay_pending = header[48..64].iter().any(|b| *b != 0);
+        if log_replay_pending {
+            warn!("VHDX {} has a log that was not replayed, recent writes may be missing.", path.display());
+        }
+
+        // Locate the BAT and metadata regions
+        let mut regions = BTreeMap::new();
+        for table_offset in REGION_TABLE_OFFSETS.iter() {
+            let mut table = vec![0u8; REGION_TABLE_SIZE];
+            read_exact_at(&mut file, *table_offset, &mut table).map_err(io_error)?;
+            if &table[0..4] != b"regi" || !checksum_valid(&table) {
+                warn!("VHDX region table at offset {} of {} is invalid.", table_offset, path.display());
+                continue;
+            }
+            let entry_count = u32::from_le_bytes(table[8..12].try_into().unwrap()) as usize;
+            for entry in table[16..].chunks_exact(32).take(entry_count) {
+                let file_offset = u64::from_le_bytes(entry[16..24].try_into().unwrap());
+                let length = u32::from_le_bytes(entry[24..28].try_into().unwrap()) as u64;
+                regions.insert(guid_at(entry, 0), (file_offset, length));
+            }
+            break;
+        }
+        let (bat_offset, bat_length) = *regions.get(BAT_REGION)
+            .ok_or(TskError::img_format_error(
+                format!("VHDX {} has no BAT region.", path.display())
+            ))?;
+        let (metadata_offset, metadata_length) = *regions.get(METADATA_REGION)
+            .ok_or(TskError::img_format_error(
+                format!("VHDX {} has no metadata region.", path.display())
+            ))?;
+
+        // Read the metadata items
+        if metadata_offset.checked_add(metadata_length).is_none_or(|end| end > size) {
+            return Err(TskError::img_format_error(
+                format!("VHDX {} metadata region extends past the end of the file.", path.display())
+            ));
+        }
+        let mut metadata = vec![0u8; metadata_length as usize];
+        read_exact_at(&mut file, metadata_offset, &mut metadata).map_err(io_error)?;
+        if metadata.len() < 32 || &metadata[0..8] != b"metadata" {
+            return Err(TskError::img_format_error(
+                format!("VHDX {} has an invalid metadata table.", path.display())
+            ));
+        }
+        let entry_count = u16::from_le_bytes(metadata[10..12].try_into().unwrap()) as usize;
+        let mut items = BTreeMap::new();
+        for entry in metadata[32..].chunks_exact(32).take(entry_count) {
+            let offset = u32::from_le_bytes(entry[16..20].try_into().unwrap()) as usize;
+            let length = u32::from_le_bytes(entry[20..24].try_into().unwrap()) as usize;
+            match metadata.get(offset..offset + length) {
+                Some(data) => { items.insert(guid_at(entry, 0), data.to_vec()); },
+                None => return Err(TskError::img_format_error(
+                    format!("VHDX {} has a metadata item outside of the metadata region.", path.display())
+                ))
+            }
+        }
+        let item = |guid: &str, min_len: usize| -> Result<&Vec<u8>, TskError> {
+            items.get(guid)
+                .filter(|d| d.len() >= min_len)
+                .ok_or(TskError::img_format_error(
+                    format!("VHDX {} is missing metadata item {}.", path.display(), guid)
+                ))
+        };
+
+        let file_parameters = item(FILE_PARAMETERS, 8)?;
+        let block_size = u32::from_le_bytes(file_parameters[0..4].try_into().unwrap()) as u64;
+        let has_parent = file_parameters[4] & 0x02 > 0;
+        let virtual_size = u64::from_le_bytes(item(VIRTUAL_DISK_SIZE, 8)?[0..8].try_into().unwrap());
+        let logical_sector_size = u32::from_le_bytes(item(LOGICAL_SECTOR_SIZE, 4)?[0..4].try_into().unwrap()) as u64;
+        let physical_sector_size = items.get(PHYSICAL_SECTOR_SIZE)
+            .filter(|d| d.len() >= 4)
+            .map(|d| u32::from_le_bytes(d[0..4].try_into().unwrap()) as u64)
+            .unwrap_or(logical_sector_size);
+        let virtual_disk_id = items.get(VIRTUAL_DISK_ID)
+            .filter(|d| d.len() >= 16)
+            .map(|d| guid_at(d, 0));
+
+        // The specification only allows 512 or 4096 byte sectors and power of
+        // two block sizes from 1 MiB to 256 MiB, which keeps chunk_ratio non-zero.
+        if !(logical_sector_size == 512 || logical_sector_size == 4096)
+            || !block_size.is_power_of_two()
+            || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size)
+        {
+            return Err(TskError::img_format_error(
+                format!(
+                    "VHDX {} has an invalid block size {} or sector size {}.",
+                    path.display(), block_size, logical_sector_size
+                )
+            ));
+        }
+        let chunk_ratio = (1u64 << 23) * logical_sector_size / block_size;
+
+        // Read the BAT. Payload entries are interleaved with one sector bitmap
+        // entry after every chunk_ratio payload entries.
+        let data_blocks = virtual_size.div_ceil(block_size);
+        let total_entries = if has_parent {
+            data_blocks.div_ceil(chunk_ratio) * (chunk_ratio + 1)
+        } else {
+            data_blocks + (data_blocks.max(1) - 1) / chunk_ratio
+        };
+        let bat_size = total_entries.checked_mul(8)
+            .filter(|bat_size| *bat_size <= bat_length)
+            .ok_or(TskError::img_format_error(
+                format!(
+                    "VHDX {} BAT region of {} bytes is too small for {} entries.",
+                    path.display(), bat_length, total_entries
+                )
+            ))?;
+        if bat_offset.checked_add(bat_size).is_none_or(|end| end > size) {
+            return Err(TskError::img_format_error(
+                format!("VHDX {} BAT region extends past the end of the file.", path.display())
+            ));
+        }
+        let mut bat = vec![0u8; bat_size as usize];
+        read_exact_at(&mut file, bat_offset, &mut bat).map_err(io_error)?;
+        let bat = bat.chunks_exact(8)
+            .map(|e| u64::from_le_bytes(e.try_into().unwrap()))
+            .collect();
+
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            file,
+            size: virtual_size,
+            block_size,
+            logical_sector_size,
+            physical_sector_size,
+            chunk_ratio,
+            has_parent,
+            virtual_disk_id,
+            data_write_guid,
+            log_replay_pending,
+            parent_locator: BTreeMap::new(),
+            bat,
+            bitmap_cache: None,
+            parent: None,
+            offset: 0
+        };
+
+        if has_parent {
+            reader.parent_locator = parse_parent_locator(item(PARENT_LOCATOR, 20)?);
+            let candidates: Vec<String> = ["relative_path", "absolute_win32_path", "volume_path"].iter()
+                .filter_map(|k| reader.parent_locator.get(*k))
+                .cloned()
+                .collect();
+            let parent_path = find_parent_file(path, &candidates, search_dirs)
+                .ok_or(TskError::img_format_error(
+                    format!("Unable to find the parent of VHDX {} (tried {:?}).", path.display(), candidates)
+                ))?;
+            let parent = Self::open_chain(&parent_path, search_dirs, depth + 1)?;
+
+            if let Some(linkage) = reader.parent_locator.get("parent_linkage") {
+                let linkage = linkage.trim_matches(|c| c == '{' || c == '}');
+                if !linkage.eq_ignore_ascii_case(&parent.data_write_guid) {
+                    return Err(TskError::img_format_error(
+                        format!(
+                            "Parent {} of VHDX {} has data write guid {} but {} was expected.",
+                            parent_path.display(), path.display(), parent.data_write_guid, linkage
+                        )
+                    ));
+                }
+            }
+            reader.parent = Some(Box::new(parent));
+        }
+
+        Ok(reader)
+    }
+
+    /// Get the size of the virtual disk in bytes
+    pub fn virtual_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get the block size in bytes
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Get the logical sector size in bytes
+    pub fn logical_sector_size(&self) -> u64 {
+        self.logical_sector_size
+    }
+
+    /// Get the physical sector size in bytes
+    pub fn physical_sector_size(&self) -> u64 {
+        self.physical_sector_size
+    }
+
+    /// Is this a differencing disk
+    pub fn is_differencing(&self) -> bool {
+        self.has_parent
+    }
+
+    /// Get the virtual disk id
+    pub fn virtual_disk_id(&self) -> Option<&str> {
+        self.virtual_disk_id.as_deref()
+    }
+
+    /// True if the disk has a log that has not been replayed. Writes stored
+    /// only in the log are not visible through this reader.
+    pub fn log_replay_pending(&self) -> bool {
+        self.log_replay_pending
+    }
+
+    /// Get the key/value pairs of the parent locator of a differencing disk
+    pub fn parent_locator(&self) -> &BTreeMap<String, String> {
+        &self.parent_locator
+    }
+
+    /// Get the paths of the disk and its parents, child first
+    pub fn chain_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.path.clone()];
+        let mut parent = self.parent.as_ref();
+        while let Some(p) = parent {
+            paths.push(p.path.clone());
+            parent = p.parent.as_ref();
+        }
+        paths
+    }
+
+    /// Get the parent of a differencing disk
+    pub fn parent(&self) -> Option<&VhdxReader> {
+        self.parent.as_deref()
+    }
+
+    /// Read from the parent, or zeros when there is no parent data
+    fn read_parent(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        match self.parent.as_mut() {
+            Some(parent) if offset < parent.size => {
+                let available = ((parent.size - offset) as usize).min(buf.len());
+                parent.seek(SeekFrom::Start(offset))?;
+                parent.read_exact(&mut buf[..available])?;
+                for b in buf[available..].iter_mut() {
+                    *b = 0;
+                }
+            },
+            _ => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the sector bitmap block of a chunk
+    fn chunk_bitmap(&mut self, chunk: u64) -> std::io::Result<Option<&[u8]>> {
+        let entry = self.bat.get((chunk * (self.chunk_ratio + 1) + self.chunk_ratio) as usize)
+            .copied()
+            .unwrap_or(0);
+        if entry & 0x7 != SB_BLOCK_PRESENT {
+            return Ok(None);
+        }
+
+        if self.bitmap_cache.as_ref().map(|(c, _)| *c) != Some(chunk) {
+            let mut bitmap = vec![0u8; MIB as usize];
+            read_exact_at(&mut self.file, (entry >> 20) * MIB, &mut bitmap)?;
+            self.bitmap_cache = Some((chunk, bitmap));
+        }
+        Ok(Some(&self.bitmap_cache.as_ref().unwrap().1))
+    }
+
+    /// Read up to the end of a block, returning the number of bytes read
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let block = offset / self.block_size;
+        let block_offset = offset % self.block_size;
+        let len = buf.len().min((self.block_size - block_offset) as usize);
+        let buf = &mut buf[..len];
+
+        let entry = self.bat[(block + block / self.chunk_ratio) as usize];
+        let state = entry & 0x7;
+        let data_offset = (entry >> 20) * MIB + block_offset;
+
+        match state {
+            PAYLOAD_BLOCK_FULLY_PRESENT => {
+                read_exact_at(&mut self.file, data_offset, buf)?;
+            },
+            PAYLOAD_BLOCK_PARTIALLY_PRESENT if self.has_parent => {
+                let chunk = block / self.chunk_ratio;
+                let bitmap = match self.chunk_bitmap(chunk)? {
+                    Some(b) => b.to_vec(),
+                    None => return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("VHDX block {} is partially present but has no sector bitmap", block)
+                    ))
+                };
+
+                // Sector number relative to the start of the chunk
+                let sector_size = self.logical_sector_size;
+                let chunk_start = chunk * self.chunk_ratio * self.block_size;
+                let in_child = |byte_offset: u64| {
+                    let sector = ((byte_offset - chunk_start) / sector_size) as usize;
+                    bitmap[sector / 8] & (1 << (sector % 8)) > 0
+                };
+
+                let mut done = 0usize;
+                while done < len {
+                    let run_start = offset + done as u64;
+                    let run_in_child = in_child(run_start);
+                    let mut run_end = (run_start / sector_size + 1) * sector_size;
+                    while run_end < offset + len as u64 && in_child(run_end) == run_in_child {
+                        run_end += sector_size;
+                    }
+                    let run_len = ((run_end - run_start) as usize).min(len - done);
+
+                    if run_in_child {
+                        read_exact_at(&mut self.file, data_offset + done as u64, &mut buf[done..done + run_len])?;
+                    } else {
+                        self.read_parent(run_start, &mut buf[done..done + run_len])?;
+                    }
+                    done += run_len;
+                }
+            },
+            PAYLOAD_BLOCK_NOT_PRESENT if self.has_parent => {
+                self.read_parent(offset, buf)?;
+            },
+            _ => {
+                // Zero, unmapped, undefined and (without a parent) not present
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+
+        Ok(len)
+    }
+}
+impl std::fmt::Debug for VhdxReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VhdxReader")
+         .field("path", &self.path)
+         .field("size", &self.size)
+         .field("block_size", &self.block_size)
+         .field("logical_sector_size", &self.logical_sector_size)
+         .field("virtual_disk_id", &self.virtual_disk_id)
+         .field("log_replay_pending", &self.log_replay_pending)
+         .field("parent", &self.parent)
+         .finish()
+    }
+}
+impl ImgMetadataSource for VhdxReader {
+    fn sector_size(&self) -> u32 {
+        self.logical_sector_size as u32
+    }
+
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("VHDX");
+        metadata.description = Some(self.path.display().to_string());
+        metadata.segments = self.chain_paths();
+        metadata.add_optional_detail("Virtual Disk Id", self.virtual_disk_id());
+        metadata.add_detail("Block Size", self.block_size.to_string());
+        metadata.add_detail("Logical Sector Size", self.logical_sector_size.to_string());
+        metadata.add_detail("Physical Sector Size", self.physical_sector_size.to_string());
+        metadata.add_detail("Differencing", self.has_parent.to_string());
+        if self.log_replay_pending {
+            metadata.add_detail("Log Replay Pending", "true");
+        }
+        metadata
+    }
+}
+impl Read for VhdxReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = ((self.size - self.offset) as usize).min(buf.len());
+
+        let bytes_read = self.read_block(self.offset, &mut buf[..remaining])?;
+        self.offset += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl Seek for VhdxReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+
+/// Parse the key/value entries of a VHDX parent locator item
+fn parse_parent_locator(data: &[u8]) -> BTreeMap<String, String> {
+    let mut entries = BTreeMap::new();
+    let count = u16::from_le_bytes(data[18..20].try_into().unwrap()) as usize;
+
+    for entry in data[20..].chunks_exact(12).take(count) {
+        let key_offset = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as usize;
+        let value_offset = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as usize;
+        let key_length = u16::from_le_bytes(entry[8..10].try_into().unwrap()) as usize;
+        let value_length = u16::from_le_bytes(entry[10..12].try_into().unwrap()) as usize;
+
+        let key = data.get(key_offset..key_offset + key_length);
+        let value = data.get(value_offset..value_offset + value_length);
+        if let (Some(key), Some(value)) = (key, value) {
+            entries.insert(utf16le_to_string(key), utf16le_to_string(value));
+        }
+    }
+
+    entries
+}
@@ -0,0 +1,685 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use flate2::read::ZlibDecoder;
+use crate::errors::TskError;
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource};
+use crate::img_common::{
+    read_exact_at,
+    file_size,
+    find_parent_file
+};
+
+
+/// Sparse extent header magic ("KDMV")
+const SPARSE_MAGIC: &[u8; 4] = b"KDMV";
+/// ESX sparse extent (COWD) magic
+const COWD_MAGIC: &[u8; 4] = b"COWD";
+/// VMDK sector size
+const SECTOR_SIZE: u64 = 512;
+/// Grain directory offset of stream optimized extents whose real header is
+/// in the footer
+const GD_AT_END: u64 = 0xffff_ffff_ffff_ffff;
+/// Flag for compressed grains in a sparse extent header
+const FLAG_COMPRESSED: u32 = 1 << 16;
+/// Grain table entry for a grain that reads as zeros
+const GTE_ZERO: u32 = 1;
+/// CID value used when a disk has no parent
+const NO_PARENT_CID: u32 = 0xffff_ffff;
+/// Largest descriptor file that will be read
+const MAX_DESCRIPTOR_SIZE: u64 = 1024 * 1024;
+/// Maximum number of snapshots in a parent chain
+const MAX_PARENT_DEPTH: usize = 32;
+/// Largest grain that will be read, VMware uses 64 KiB
+const MAX_GRAIN_SIZE: u64 = 64 * 1024 * 1024;
+
+
+/// An extent line of a VMDK descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmdkExtentDescriptor {
+    /// Access mode (RW, RDONLY, NOACCESS)
+    pub access: String,
+    /// Size of the extent in sectors
+    pub sectors: u64,
+    /// Extent type (FLAT, SPARSE, ZERO, VMFS, VMFSSPARSE, ...)
+    pub extent_type: String,
+    /// The extent file name, relative to the descriptor
+    pub file_name: Option<String>,
+    /// Offset in sectors of the extent data in a flat extent file
+    pub offset: u64
+}
+
+
+/// A parsed VMDK descriptor file
+#[derive(Debug, Clone, Default)]
+pub struct VmdkDescriptor {
+    /// The descriptor version
+    pub version: Option<u32>,
+    /// Content id of the disk
+    pub cid: u32,
+    /// Content id of the parent disk, 0xffffffff if there is no parent
+    pub parent_cid: u32,
+    /// The disk type (monolithicSparse, twoGbMaxExtentSparse, streamOptimized, ...)
+    pub create_type: String,
+    /// Path of the parent disk of a snapshot
+    pub parent_file_name_hint: Option<String>,
+    /// The extents in disk order
+    pub extents: Vec<VmdkExtentDescriptor>,
+    /// The disk database values (ddb.*)
+    pub ddb: BTreeMap<String, String>
+}
+impl VmdkDescriptor {
+    /// Parse the text of a descriptor file
+    pub fn parse(text: &str) -> Result<Self, TskError> {
+        let mut descriptor = Self {
+            parent_cid: NO_PARENT_CID,
+            ..Default::default()
+        };
+
+        for line in text.lines() {
+            let line = line.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Extent lines start with their access mode
+            let first_word = line.split_whitespace().next().unwrap_or("");
+            if ["RW", "RDONLY", "NOACCESS"].contains(&first_word) {
+                descriptor.extents.push(parse_extent_line(line)?);
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim().trim_matches('"')),
+                None => {
+                    debug!("Ignoring VMDK descriptor line: {}", line);
+                    continue;
+                }
+            };
+            match key {
+                "version" => descriptor.version = value.parse().ok(),
+                "CID" => descriptor.cid = parse_cid(value)?,
+                "parentCID" => descriptor.parent_cid = parse_cid(value)?,
+                "createType" => descriptor.create_type = value.to_string(),
+                "parentFileNameHint" => descriptor.parent_file_name_hint = Some(value.to_string()),
+                k if k.starts_with("ddb.") => {
+                    descriptor.ddb.insert(k.to_string(), value.to_string());
+                },
+                _ => {}
+            }
+        }
+
+        if descriptor.extents.is_empty() {
+            return Err(TskError::img_format_error(
+                "VMDK descriptor has no extents.".to_string()
+            ));
+        }
+
+        Ok(descriptor)
+    }
+
+    /// True if the disk is a snapshot of a parent disk
+    pub fn has_parent(&self) -> bool {
+        self.parent_cid != NO_PARENT_CID
+    }
+}
+
+
+/// Parse a hex CID value
+fn parse_cid(value: &str) -> Result<u32, TskError> {
+    u32::from_str_radix(value, 16)
+        .map_err(|e| TskError::img_format_error(
+            format!("Invalid VMDK CID {}: {}", value, e)
+        ))
+}
+
+
+/// Parse an extent line: `RW 4192256 SPARSE "disk-s001.vmdk" [offset]`
+fn parse_extent_line(line: &str) -> Result<VmdkExtentDescriptor, TskError> {
+    let invalid = || TskError::img_format_error(
+        format!("Invalid VMDK extent line: {}", line)
+    );
+
+    // The file name is quoted and can contain spaces
+    let (head, file_name, tail) = match (line.find('"'), line.rfind('"')) {
+        (Some(start), Some(end)) if end > start => {
+            (&line[..start], Some(line[start + 1..end].to_string()), &line[end + 1..])
+        },
+        _ => (line, None, "")
+    };
+
+    let mut words = head.split_whitespace();
+    let access = words.next().ok_or_else(invalid)?.to_string();
+    let sectors = words.next()
+        .and_then(|s| s.parse::<u64>().ok())
+        .ok_or_else(invalid)?;
+    let extent_type = words.next().ok_or_else(invalid)?.to_string();
+    let offset = tail.split_whitespace()
+        .next()
+        .map(|o| o.parse::<u64>().map_err(|_| invalid()))
+        .transpose()?
+        .unwrap_or(0);
+
+    Ok(VmdkExtentDescriptor {
+        access,
+        sectors,
+        extent_type,
+        file_name,
+        offset
+    })
+}
+
+
+/// A hosted sparse extent (KDMV)
+struct SparseExtent {
+    path: PathBuf,
+    file: File,
+    /// Grain size in bytes
+    grain_size: u64,
+    gtes_per_gt: u64,
+    compressed: bool,
+    grain_directory: Vec<u32>,
+    /// The most recently used grain table (grain directory index, entries)
+    gt_cache: Option<(usize, Vec<u32>)>,
+    /// The most recently decompressed grain (grain number, data)
+    grain_cache: Option<(u64, Vec<u8>)>
+}
+impl SparseExtent {
+    /// Open a sparse extent, returning it with its capacity in bytes and the
+    /// descriptor embedded in it (if any).
+    fn open(path: &Path) -> Result<(Self, u64, Option<String>), TskError> {
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading VMDK extent {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let size = file_size(&file).map_err(io_error)?;
+
+        let mut header = [0u8; SECTOR_SIZE as usize];
+        read_exact_at(&mut file, 0, &mut header).map_err(io_error)?;
+        if &header[0..4] == COWD_MAGIC {
+            return Err(TskError::img_format_error(
+                format!("VMDK extent {} is an ESX (COWD) sparse extent which is not supported.", path.display())
+            ));
+        }
+        if &header[0..4] != SPARSE_MAGIC {
+            return Err(TskError::img_format_error(
+                format!("{} is not a VMDK sparse extent (bad magic {:02x?}).", path.display(), &header[0..4])
+            ));
+        }
+
+        // Stream optimized extents store the grain directory offset in the
+        // footer copy of the header
+        let gd_offset = u64::from_le_bytes(header[56..64].try_into().unwrap());
+        if gd_offset == GD_AT_END {
+            if size < 3 * SECTOR_SIZE {
+                return Err(TskError::img_format_error(
+                    format!("VMDK extent {} is too small for a footer.", path.display())
+                ));
+            }
+            read_exact_at(&mut file, size - 2 * SECTOR_SIZE, &mut header).map_err(io_error)?;
+            if &header[0..4] != SPARSE_MAGIC {
+                return Err(TskError::img_format_error(
+                    format!("VMDK extent {} has no valid footer.", path.display()))
+                );
+            }
+        }
+
+        // A sector count of the header in bytes
+        let sectors_at = |start: usize, name: &str| {
+            u64::from_le_bytes(header[start..start + 8].try_into().unwrap())
+                .checked_mul(SECTOR_SIZE)
+                .ok_or_else(|| TskError::img_format_error(
+                    format!("VMDK extent {} has an invalid {}.", path.display(), name)
+                ))
+        };
+        let flags = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let capacity = sectors_at(12, "capacity")?;
+        let grain_size = sectors_at(20, "grain size")?;
+        let descriptor_offset = sectors_at(28, "descriptor offset")?;
+        let descriptor_size = sectors_at(36, "descriptor size")?;
+        let gtes_per_gt = u32::from_le_bytes(header[44..48].try_into().unwrap()) as u64;
+        let gd_offset = sectors_at(56, "grain directory offset")?;
+        let compress_algorithm = u16::from_le_bytes(header[77..79].try_into().unwrap());
+
+        if grain_size == 0 || grain_size > MAX_GRAIN_SIZE || gtes_per_gt == 0 || gtes_per_gt * 4 > size {
+            return Err(TskError::img_format_error(
+                format!("VMDK extent {} has an invalid grain size or grain table size.", path.display())
+            ));
+        }
+        let compressed = flags & FLAG_COMPRESSED > 0;
+        if compressed && compress_algorithm != 1 {
+            return Err(TskError::img_format_error(
+                format!("VMDK extent {} uses unsupported compression {}.", path.display(), compress_algorithm)
+            ));
+        }
+
+        // The grain directory has to be in the extent file
+        let gd_entries = capacity.div_ceil(grain_size).div_ceil(gtes_per_gt);
+        let gd_size = gd_entries * 4;
+        if gd_offset.checked_add(gd_size).is_none_or(|end| end > size) {
+            return Err(TskError::img_format_error(
+                format!(
+                    "VMDK extent {} has a grain directory of {} bytes at offset {} beyond the end of the file.",
+                    path.display(), gd_size, gd_offset
+                )
+            ));
+        }
+        let mut grain_directory = vec![0u8; gd_size as usize];
+        read_exact_at(&mut file, gd_offset, &mut grain_directory).map_err(io_error)?;
+        let grain_directory = grain_directory.chunks_exact(4)
+            .map(|e| u32::from_le_bytes(e.try_into().unwrap()))
+            .collect();
+
+        let descriptor = if descriptor_offset > 0 && descriptor_size > 0 && descriptor_size <= MAX_DESCRIPTOR_SIZE {
+            let mut data = vec![0u8; descriptor_size as usize];
+            read_exact_at(&mut file, descriptor_offset, &mut data).map_err(io_error)?;
+            let text = String::from_utf8_lossy(&data);
+            Some(text.trim_end_matches('\0').to_string())
+        } else {
+            None
+        };
+
+        Ok((Self {
+            path: path.to_path_buf(),
+            file,
+            grain_size,
+            gtes_per_gt,
+            compressed,
+            grain_directory,
+            gt_cache: None,
+            grain_cache: None
+        }, capacity, descriptor))
+    }
+
+    /// Read up to the end of a grain. Returns the number of bytes and false
+    /// if the grain is not allocated in this extent.
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<(usize, bool)> {
+        let grain = offset / self.grain_size;
+        let grain_offset = offset % self.grain_size;
+        let len = buf.len().min((self.grain_size - grain_offset) as usize);
+        let buf = &mut buf[..len];
+
+        let gd_index = (grain / self.gtes_per_gt) as usize;
+        let gt_sector = match self.grain_directory.get(gd_index) {
+            Some(s) if *s != 0 => *s as u64,
+            _ => return Ok((len, false))
+        };
+        if self.gt_cache.as_ref().map(|(i, _)| *i) != Some(gd_index) {
+            let mut table = vec![0u8; (self.gtes_per_gt * 4) as usize];
+            read_exact_at(&mut self.file, gt_sector * SECTOR_SIZE, &mut table)?;
+            let table = table.chunks_exact(4)
+                .map(|e| u32::from_le_bytes(e.try_into().unwrap()))
+                .collect();
+            self.gt_cache = Some((gd_index, table));
+        }
+        let grain_sector = self.gt_cache.as_ref().unwrap().1[(grain % self.gtes_per_gt) as usize] as u64;
+
+        if grain_sector == 0 {
+            return Ok((len, false));
+        }
+        if grain_sector == GTE_ZERO as u64 {
+            for b in buf.iter_mut() {
+                *b = 0;
+            }
+            return Ok((len, true));
+        }
+
+        if !self.compressed {
+            read_exact_at(&mut self.file, grain_sector * SECTOR_SIZE + grain_offset, buf)?;
+            return Ok((len, true));
+        }
+
+        // Compressed grains start with the grain LBA and the compressed size
+        if self.grain_cache.as_ref().map(|(g, _)| *g) != Some(grain) {
+            let mut marker = [0u8; 12];
+            read_exact_at(&mut self.file, grain_sector * SECTOR_SIZE, &mut marker)?;
+            let compressed_size = u32::from_le_bytes(marker[8..12].try_into().unwrap()) as u64;
+            // Deflate adds a few bytes per stored block to data that does
+            // not compress, anything larger is a corrupt marker
+            if compressed_size > self.grain_size + self.grain_size / 1024 + SECTOR_SIZE {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "VMDK extent {} grain {} has an invalid compressed size of {} bytes",
+                        self.path.display(), grain, compressed_size
+                    )
+                ));
+            }
+            let mut compressed = vec![0u8; compressed_size as usize];
+            self.file.read_exact(&mut compressed)?;
+
+            let mut data = Vec::with_capacity(self.grain_size as usize);
+            ZlibDecoder::new(&compressed[..]).take(self.grain_size + 1).read_to_end(&mut data)?;
+            if data.len() as u64 > self.grain_size {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "VMDK extent {} grain {} inflates past the grain size {}",
+                        self.path.display(), grain, self.grain_size
+                    )
+                ));
+            }
+            data.resize(self.grain_size as usize, 0);
+            self.grain_cache = Some((grain, data));
+        }
+        let data = &self.grain_cache.as_ref().unwrap().1;
+        buf.copy_from_slice(&data[grain_offset as usize..grain_offset as usize + len]);
+
+        Ok((len, true))
+    }
+}
+
+
+/// The data source of an extent
+enum ExtentData {
+    Flat { file: File, offset: u64 },
+    Sparse(SparseExtent),
+    Zero
+}
+
+
+/// An extent and its place in the virtual disk
+struct VmdkExtent {
+    /// Offset of the extent in the virtual disk
+    start: u64,
+    /// Size of the extent in bytes
+    size: u64,
+    data: ExtentData
+}
+
+
+/// A Read + Seek reader of a VMDK virtual disk. The disk is opened from its
+/// descriptor file, or from a sparse extent with an embedded descriptor.
+/// Snapshots read unallocated grains from their parent disk.
+///
+pub struct VmdkReader {
+    path: PathBuf,
+    descriptor: VmdkDescriptor,
+    extents: Vec<VmdkExtent>,
+    size: u64,
+    parent: Option<Box<VmdkReader>>,
+    /// Offset of the next read in the disk
+    offset: u64
+}
+impl VmdkReader {
+    /// Open a VMDK. The parents of snapshots are looked for relative to the
+    /// child disk.
+    ///
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        Self::open_with_search_dirs(path, &[])
+    }
+
+    /// Open a VMDK. The parents of snapshots are looked for relative to the
+    /// child disk and then in the search directories.
+    ///
+    pub fn open_with_search_dirs(path: impl AsRef<Path>, search_dirs: &[PathBuf]) -> Result<Self, TskError> {
+        Self::open_chain(path.as_ref(), search_dirs, 0)
+    }
+
+    fn open_chain(path: &Path, search_dirs: &[PathBuf], depth: usize) -> Result<Self, TskError> {
+        if depth > MAX_PARENT_DEPTH {
+            return Err(TskError::img_format_error(
+                format!("VMDK parent chain of {} is deeper than {}.", path.display(), MAX_PARENT_DEPTH)
+            ));
+        }
+
+        let io_error = |e: std::io::Error| TskError::img_format_error(
+            format!("Error reading VMDK {}: {}", path.display(), e)
+        );
+        let mut file = File::open(path).map_err(io_error)?;
+        let size = file_size(&file).map_err(io_error)?;
+        let mut magic = [0u8; 4];
+        let magic_len = file.read(&mut magic).map_err(io_error)?;
+
+        // A sparse extent either embeds the descriptor or is a disk by itself
+        let mut opened_extent = None;
+        let descriptor = if magic_len == 4 && (&magic == SPARSE_MAGIC || &magic == COWD_MAGIC) {
+            let (extent, capacity, embedded) = SparseExtent::open(path)?;
+            let descriptor = match embedded {
+                Some(text) => VmdkDescriptor::parse(&text)?,
+                None => {
+                    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string());
+                    VmdkDescriptor {
+                        parent_cid: NO_PARENT_CID,
+                        create_type: "monolithicSparse".to_string(),
+                        extents: vec![VmdkExtentDescriptor {
+                            access: "RW".to_string(),
+                            sectors: capacity / SECTOR_SIZE,
+                            extent_type: "SPARSE".to_string(),
+                            file_name,
+                            offset: 0
+                        }],
+                        ..Default::default()
+                    }
+                }
+            };
+            opened_extent = Some(extent);
+            descriptor
+        } else {
+            if size > MAX_DESCRIPTOR_SIZE {
+                return Err(TskError::img_format_error(
+                    format!("{} is not a VMDK descriptor or sparse extent.", path.display())
+                ));
+            }
+            let mut data = Vec::new();
+            file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+            file.read_to_end(&mut data).map_err(io_error)?;
+            VmdkDescriptor::parse(&String::from_utf8_lossy(&data))?
+        };
+
+        let base_dir = path.parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
+        let mut extents = Vec::with_capacity(descriptor.extents.len());
+        let mut start = 0u64;
+        for extent in descriptor.extents.iter() {
+            let extent_size = extent.sectors.checked_mul(SECTOR_SIZE)
+                .ok_or(TskError::img_format_error(
+                    format!("VMDK {} has an extent of an invalid size of {} sectors.", path.display(), extent.sectors)
+                ))?;
+            if extent_size == 0 {
+                continue;
+            }
+            let extent_path = extent.file_name.as_ref().map(|n| base_dir.join(n));
+
+            let data = match (extent.extent_type.as_str(), extent_path) {
+                ("ZERO", _) => ExtentData::Zero,
+                ("FLAT", Some(extent_path)) | ("VMFS", Some(extent_path)) | ("VMFSRAW", Some(extent_path)) | ("VMFSRDM", Some(extent_path)) => {
+                    let file = File::open(&extent_path)
+                        .map_err(|e| TskError::img_format_error(
+                            format!("Unable to open VMDK extent {}: {}", extent_path.display(), e)
+                        ))?;
+                    let offset = extent.offset.checked_mul(SECTOR_SIZE)
+                        .ok_or(TskError::img_format_error(
+                            format!("VMDK extent {} has an invalid offset of {} sectors.", extent_path.display(), extent.offset)
+                        ))?;
+                    ExtentData::Flat { file, offset }
+                },
+                ("SPARSE", Some(extent_path)) => {
+                    // The extent the disk was opened from is not opened twice
+                    let sparse = match opened_extent.take() {
+                        Some(e) if e.path == extent_path || descriptor.extents.len() == 1 => e,
+                        other => {
+                            opened_extent = other;
+                            SparseExtent::open(&extent_path)?.0
+                        }
+                    };
+                    ExtentData::Sparse(sparse)
+                },
+                (extent_type, _) => return Err(TskError::img_format_error(
+                    format!("VMDK {} has an unsupported extent type {}.", path.display(), extent_type)
+                ))
+            };
+
+            extents.push(VmdkExtent {
+                start,
+                size: extent_size,
+                data
+            });
+            start = start.checked_add(extent_size)
+                .ok_or(TskError::img_format_error(
+                    format!("The extents of VMDK {} are larger than the maximum image size.", path.display())
+                ))?;
+        }
+
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            descriptor,
+            extents,
+            size: start,
+            parent: None,
+            offset: 0
+        };
+
+        if reader.descriptor.has_parent() {
+            let candidates: Vec<String> = reader.descriptor.parent_file_name_hint.iter()
+                .cloned()
+                .collect();
+            let parent_path = find_parent_file(path, &candidates, search_dirs)
+                .ok_or(TskError::img_format_error(
+                    format!("Unable to find the parent of VMDK {} (tried {:?}).", path.display(), candidates)
+                ))?;
+            let parent = Self::open_chain(&parent_path, search_dirs, depth + 1)?;
+
+            if parent.descriptor.cid != reader.descriptor.parent_cid {
+                return Err(TskError::img_format_error(
+                    format!(
+                        "Parent {} of VMDK {} has CID {:08x} but {:08x} was expected.",
+                        parent_path.display(), path.display(), parent.descriptor.cid, reader.descriptor.parent_cid
+                    )
+                ));
+            }
+            reader.parent = Some(Box::new(parent));
+        }
+
+        Ok(reader)
+    }
+
+    /// Get the descriptor of the disk
+    pub fn descriptor(&self) -> &VmdkDescriptor {
+        &self.descriptor
+    }
+
+    /// Get the size of the virtual disk in bytes
+    pub fn virtual_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get the paths of the disk and its parents, child first
+    pub fn chain_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.path.clone()];
+        let mut parent = self.parent.as_ref();
+        while let Some(p) = parent {
+            paths.push(p.path.clone());
+            parent = p.parent.as_ref();
+        }
+        paths
+    }
+
+    /// Get the parent of a snapshot
+    pub fn parent(&self) -> Option<&VmdkReader> {
+        self.parent.as_deref()
+    }
+
+    /// Read from the parent, or zeros when there is no parent data
+    fn read_parent(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        match self.parent.as_mut() {
+            Some(parent) if offset < parent.size => {
+                let available = ((parent.size - offset) as usize).min(buf.len());
+                parent.seek(SeekFrom::Start(offset))?;
+                parent.read_exact(&mut buf[..available])?;
+                for b in buf[available..].iter_mut() {
+                    *b = 0;
+                }
+            },
+            _ => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+impl std::fmt::Debug for VmdkReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VmdkReader")
+         .field("path", &self.path)
+         .field("size", &self.size)
+         .field("descriptor", &self.descriptor)
+         .field("parent", &self.parent)
+         .finish()
+    }
+}
+impl ImgMetadataSource for VmdkReader {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("VMDK");
+        metadata.description = Some(self.path.display().to_string());
+        metadata.segments = self.chain_paths();
+        metadata.add_detail("Create Type", self.descriptor.create_type.clone());
+        metadata.add_detail("CID", format!("{:08x}", self.descriptor.cid));
+        metadata.add_detail("Extents", self.descriptor.extents.len().to_string());
+        for (name, value) in &self.descriptor.ddb {
+            metadata.add_detail(name.clone(), value.clone());
+        }
+        metadata
+    }
+}
+impl Read for VmdkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let offset = self.offset;
+
+        let extent_index = match self.extents.binary_search_by(|e| e.start.cmp(&offset)) {
+            Ok(i) => i,
+            Err(i) => i - 1
+        };
+        let extent = &mut self.extents[extent_index];
+        let extent_offset = offset - extent.start;
+        let len = buf.len().min((extent.size - extent_offset) as usize);
+        let buf = &mut buf[..len];
+
+        let (bytes_read, allocated) = match &mut extent.data {
+            ExtentData::Flat { file, offset } => {
+                read_exact_at(file, *offset + extent_offset, buf)?;
+                (len, true)
+            },
+            ExtentData::Sparse(sparse) => sparse.read(extent_offset, buf)?,
+            ExtentData::Zero => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+                (len, true)
+            }
+        };
+        if !allocated {
+            self.read_parent(offset, &mut buf[..bytes_read])?;
+        }
+
+        self.offset += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+impl Seek for VmdkReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self.offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self.offset)
+            ));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
@@ -8,12 +8,16 @@ pub mod bindings;
 pub mod errors;
 /// Wrapper for TSK_IMG_INFO
 pub mod tsk_img;
+/// Implement handle for TskImg
+pub mod tsk_img_handle;
 /// Wrapper for TSK_VS_INFO
 pub mod tsk_vs;
 /// Wrapper for TSK_VS_PART_INFO
 pub mod tsk_vs_part;
 /// Implement handle for TskVsPart
 pub mod tsk_vs_part_handle;
+/// GPT details of a TskVs
+pub mod tsk_vs_gpt;
 /// Wrapper for TSK_FS_INFO
 pub mod tsk_fs;
 /// Wrapper for TSK_FS_FILE
@@ -26,10 +30,50 @@ pub mod tsk_fs_name;
 pub mod tsk_fs_meta;
 /// FileHandle for a TskFsFile.
 pub mod tsk_fs_file_handle;
+/// Owned stream over a TskFsFile for nested images.
+pub mod tsk_fs_file_stream;
 /// Wrapper for Tsk_FS_ATTR.
 pub mod tsk_fs_attr;
+/// Discovery of the volume systems and file systems of a TskImg
+pub mod tsk_discovery;
 /// Custom ReadSeek
 pub mod tsk_img_reader;
+/// LRU block cache for TskImgReadSeek
+pub mod img_cache;
+/// Bad sector policy and error map for TskImgReadSeek
+pub mod img_error_map;
+/// Read audit trail for TskImgReadSeek
+pub mod img_audit;
+/// Whole image hashing and verification
+pub mod img_hash;
+/// Descriptive metadata of image sources
+pub mod img_metadata;
+/// Helpers shared by the image format readers
+mod img_common;
+/// Pure Rust EWF (E01/Ex01) reader
+pub mod img_ewf;
+/// VHD (fixed, dynamic and differencing) reader
+pub mod img_vhd;
+/// VHDX reader
+pub mod img_vhdx;
+/// VMDK (flat, sparse and stream optimized) reader
+pub mod img_vmdk;
+/// QCOW2 reader
+pub mod img_qcow2;
+/// Deflate decoder that records gzip access points
+mod img_inflate;
+/// Seekable gzip, zstd and xz compressed images
+pub mod img_compressed;
+/// Turtle parser for the AFF4 metadata
+mod img_turtle;
+/// AFF4 volume and image stream reader
+pub mod img_aff4;
+/// LZFSE/LZVN decoder for DMG chunks
+mod img_lzfse;
+/// Apple DMG (UDIF) and sparsebundle reader
+pub mod img_dmg;
+/// Memory mapped raw image reader
+pub mod img_mmap;
 
 pub use tsk_img::TskImg;
 pub use tsk_img_reader::{ReadSeek, TskImgReadSeek};
\ No newline at end of file
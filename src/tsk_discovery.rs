@@ -0,0 +1,219 @@
+use crate::{
+    errors::TskError,
+    tsk_fs::TskFs,
+    tsk_img::TskImg,
+    tsk_vs::TskVs,
+    tsk_vs_part::TskVsPartFlag
+};
+
+
+/// Maximum nesting of volume systems (e.g. a BSD disklabel inside an MBR
+/// partition is depth 1). Guards against tables that point at themselves.
+const MAX_VS_DEPTH: usize = 4;
+
+
+/// What was found in a region of the image
+#[derive(Debug)]
+pub enum DiscoveredContent {
+    /// A volume system and its partitions
+    VolumeSystem(DiscoveredVolumeSystem),
+    /// An opened file system
+    FileSystem(TskFs),
+    /// The region was not probed, e.g. unallocated space or a partition table
+    Skipped(String),
+    /// Neither a volume system nor a file system could be opened, with the reasons
+    Failed(String)
+}
+
+
+/// A volume system found in the image
+#[derive(Debug)]
+pub struct DiscoveredVolumeSystem {
+    pub tsk_vs: TskVs,
+    /// The entries of the volume system in the order libtsk lists them
+    pub partitions: Vec<DiscoveredPartition>
+}
+
+
+/// A partition entry of a volume system and what was found in it
+#[derive(Debug)]
+pub struct DiscoveredPartition {
+    /// The index of the entry in the volume system
+    pub index: u64,
+    /// The byte offset in the image
+    pub offset: u64,
+    /// The size in bytes
+    pub size: u64,
+    pub description: String,
+    pub flags: Vec<TskVsPartFlag>,
+    pub content: DiscoveredContent
+}
+
+
+/// The volume systems, partitions and file systems of a TskImg. The tree
+/// borrows the image so that its TskVs and TskFs handles can not outlive it.
+#[derive(Debug)]
+pub struct EvidenceTree<'img> {
+    tsk_img: &'img TskImg,
+    root: DiscoveredContent
+}
+impl<'img> EvidenceTree<'img> {
+    /// Discover the contents of an image: a volume system at offset 0 and the
+    /// file systems or nested volume systems of its allocated partitions, or
+    /// a file system covering the whole image.
+    pub fn discover(tsk_img: &'img TskImg) -> Self {
+        let root = match discover_vs(tsk_img, 0, 0) {
+            Ok(volume_system) => DiscoveredContent::VolumeSystem(volume_system),
+            Err(vs_error) => match TskFs::from_fs_offset(tsk_img, 0) {
+                Ok(tsk_fs) => DiscoveredContent::FileSystem(tsk_fs),
+                Err(fs_error) => DiscoveredContent::Failed(
+                    format!("no volume system: {}; no file system: {}", vs_error.message, fs_error.message)
+                )
+            }
+        };
+        Self { tsk_img, root }
+    }
+
+    /// The discovered image
+    pub fn tsk_img(&self) -> &'img TskImg {
+        self.tsk_img
+    }
+
+    /// What was found at the start of the image
+    pub fn root(&self) -> &DiscoveredContent {
+        &self.root
+    }
+
+    /// All opened file systems with their byte offsets, depth first
+    pub fn file_systems(&self) -> Vec<(u64, &TskFs)> {
+        let mut file_systems = Vec::new();
+        self.walk(|offset, content| {
+            if let DiscoveredContent::FileSystem(tsk_fs) = content {
+                file_systems.push((offset, tsk_fs));
+            }
+        });
+        file_systems
+    }
+
+    /// All volume systems, depth first
+    pub fn volume_systems(&self) -> Vec<&DiscoveredVolumeSystem> {
+        let mut volume_systems = Vec::new();
+        self.walk(|_, content| {
+            if let DiscoveredContent::VolumeSystem(volume_system) = content {
+                volume_systems.push(volume_system);
+            }
+        });
+        volume_systems
+    }
+
+    /// The byte offsets of the regions in which nothing could be opened,
+    /// with the reasons
+    pub fn failures(&self) -> Vec<(u64, &str)> {
+        let mut failures = Vec::new();
+        self.walk(|offset, content| {
+            if let DiscoveredContent::Failed(reason) = content {
+                failures.push((offset, reason.as_str()));
+            }
+        });
+        failures
+    }
+
+    /// Call f with the offset and content of the root and every partition
+    fn walk<'t, F: FnMut(u64, &'t DiscoveredContent)>(&'t self, mut f: F) {
+        fn walk_content<'t, F: FnMut(u64, &'t DiscoveredContent)>(offset: u64, content: &'t DiscoveredContent, f: &mut F) {
+            f(offset, content);
+            if let DiscoveredContent::VolumeSystem(volume_system) = content {
+                for partition in &volume_system.partitions {
+                    walk_content(partition.offset, &partition.content, f);
+                }
+            }
+        }
+        walk_content(0, &self.root, &mut f);
+    }
+}
+impl<'img> std::fmt::Display for EvidenceTree<'img> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn write_content(f: &mut std::fmt::Formatter<'_>, content: &DiscoveredContent, depth: usize) -> std::fmt::Result {
+            let indent = "  ".repeat(depth);
+            match content {
+                DiscoveredContent::VolumeSystem(volume_system) => {
+                    let tsk_vs = &volume_system.tsk_vs;
+                    writeln!(
+                        f, "{}volume system {} at offset {} (block size {}, {} entries)",
+                        indent, tsk_vs.vs_type(), tsk_vs.offset(), tsk_vs.block_size(), volume_system.partitions.len()
+                    )?;
+                    for partition in &volume_system.partitions {
+                        writeln!(
+                            f, "{}  [{}] offset {} size {} {:?}",
+                            indent, partition.index, partition.offset, partition.size, partition.description
+                        )?;
+                        write_content(f, &partition.content, depth + 2)?;
+                    }
+                    Ok(())
+                },
+                DiscoveredContent::FileSystem(tsk_fs) => writeln!(
+                    f, "{}file system (block size {}, {} blocks)",
+                    indent, tsk_fs.block_size(), tsk_fs.block_count()
+                ),
+                DiscoveredContent::Skipped(reason) => writeln!(f, "{}skipped: {}", indent, reason),
+                DiscoveredContent::Failed(reason) => writeln!(f, "{}failed: {}", indent, reason)
+            }
+        }
+        write_content(f, &self.root, 0)
+    }
+}
+
+
+/// Open the volume system at offset and discover its partitions
+fn discover_vs(tsk_img: &TskImg, offset: u64, depth: usize) -> Result<DiscoveredVolumeSystem, TskError> {
+    let tsk_vs = TskVs::new(tsk_img, offset)?;
+    let mut partitions = Vec::new();
+
+    for (index, tsk_vs_part) in tsk_vs.get_partition_iter()?.enumerate() {
+        let part_offset = tsk_vs.offset() + tsk_vs_part.get_start_offset();
+        let content = if tsk_vs_part.is_allocated() {
+            discover_partition(tsk_img, part_offset, offset, depth)
+        } else if tsk_vs_part.is_meta() {
+            DiscoveredContent::Skipped("volume system structure".to_string())
+        } else {
+            DiscoveredContent::Skipped("unallocated space".to_string())
+        };
+
+        partitions.push(DiscoveredPartition {
+            index: index as u64,
+            offset: part_offset,
+            size: tsk_vs_part.size(),
+            description: tsk_vs_part.desc(),
+            flags: tsk_vs_part.flags(),
+            content
+        });
+    }
+
+    Ok(DiscoveredVolumeSystem { tsk_vs, partitions })
+}
+
+
+/// Open the file system of a partition, or a volume system nested in it
+fn discover_partition(tsk_img: &TskImg, offset: u64, vs_offset: u64, depth: usize) -> DiscoveredContent {
+    let fs_error = match TskFs::from_fs_offset(tsk_img, offset) {
+        Ok(tsk_fs) => return DiscoveredContent::FileSystem(tsk_fs),
+        Err(e) => e
+    };
+
+    // A partition starting at its volume system (e.g. a BSD partition
+    // covering the whole disklabel) would find the same volume system again
+    let vs_reason = if offset == vs_offset {
+        "the partition starts at its volume system".to_string()
+    } else if depth + 1 >= MAX_VS_DEPTH {
+        format!("volume systems are nested more than {} deep", MAX_VS_DEPTH)
+    } else {
+        match discover_vs(tsk_img, offset, depth + 1) {
+            Ok(volume_system) => return DiscoveredContent::VolumeSystem(volume_system),
+            Err(e) => e.message
+        }
+    };
+
+    DiscoveredContent::Failed(
+        format!("no file system: {}; no volume system: {}", fs_error.message, vs_reason)
+    )
+}
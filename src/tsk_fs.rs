@@ -1,5 +1,6 @@
 use std::ffi::CStr;
 use std::ptr::NonNull;
+use std::sync::Arc;
 use crate::{
     errors::TskError,
     tsk_img::TskImg,
@@ -16,6 +17,8 @@ pub struct TskFs {
     tsk_fs_ptr: *mut tsk::TSK_FS_INFO,
     /// We dont always want to free a file pointer
     _release: bool,
+    /// The image kept alive by a TskFs opened with from_shared_img
+    parent: Option<Arc<TskImg>>
 }
 impl TskFs {
     /// Create a TSK_FS_INFO wrapper given the TskImg and offset of the file system
@@ -43,7 +46,22 @@ impl TskFs {
             ));
         }
 
-        Ok( Self { tsk_fs_ptr, _release: true } )
+        Ok( Self { tsk_fs_ptr, _release: true, parent: None } )
+    }
+
+    /// Create a TSK_FS_INFO wrapper that keeps its TskImg alive. Files of
+    /// such a TskFs can be turned into owned streams (see TskFsFileStream),
+    /// for example to open images stored inside the file system.
+    pub fn from_shared_img(tsk_img: Arc<TskImg>, offset: u64) -> Result<TskFs, TskError> {
+        let mut tsk_fs = Self::from_fs_offset(&tsk_img, offset)?;
+        tsk_fs.parent = Some(tsk_img);
+        Ok(tsk_fs)
+    }
+
+    /// Get the TskImg kept alive by this TskFs, if it was opened with
+    /// from_shared_img
+    pub fn shared_img(&self) -> Option<&Arc<TskImg>> {
+        self.parent.as_ref()
     }
 
     /// Open a file by a given path. (use '/' as separators)
@@ -153,6 +153,14 @@ impl<'fs> TskFsFile<'fs> {
     pub fn get_fs(&'fs self) -> &'fs TskFs {
         self.tsk_fs
     }
+
+    /// Give up ownership of the TSK_FS_FILE pointer. The caller has to close
+    /// it with tsk_fs_file_close.
+    pub(crate) fn into_raw(self) -> *mut tsk::TSK_FS_FILE {
+        let tsk_fs_file_ptr = self.tsk_fs_file_ptr;
+        std::mem::forget(self);
+        tsk_fs_file_ptr
+    }
 }
 impl<'fs> Into<*mut tsk::TSK_FS_FILE> for &TskFsFile<'fs> {
     fn into(self) -> *mut tsk::TSK_FS_FILE {
@@ -0,0 +1,176 @@
+use std::convert::TryInto;
+use std::ffi::CStr;
+use std::ptr::NonNull;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use crate::{
+    errors::TskError,
+    tsk_fs::TskFs,
+    tsk_fs_file::TskFsFile,
+    tsk_img::TskImg,
+    tsk_img_reader::TskImgReadSeek,
+    bindings as tsk
+};
+
+
+/// TskFsFileStream is an owned Read + Seek over the default attribute of a
+/// file. Unlike TskFsFileHandle it does not borrow the file or file system:
+/// it keeps the TskFs, and through it the TskImg, alive. This makes it
+/// usable as the stream of a TskImgReadSeek to open images stored inside a
+/// file system.
+///
+pub struct TskFsFileStream {
+    /// The ptr to the TSK_FS_FILE struct, closed on drop
+    tsk_fs_file_ptr: NonNull<tsk::TSK_FS_FILE>,
+    /// The file system of the file, dropped after the file is closed
+    tsk_fs: Arc<TskFs>,
+    /// The path or inode the file was opened by
+    name: String,
+    attr_type: tsk::TSK_FS_ATTR_TYPE_ENUM,
+    attr_id: u16,
+    size: u64,
+    /// The read pointer
+    _offset: u64
+}
+impl TskFsFileStream {
+    /// Open the file with the given path (use '/' as separators). The TskFs
+    /// must have been opened with TskFs::from_shared_img.
+    pub fn from_path(tsk_fs: Arc<TskFs>, path: &str) -> Result<Self, TskError> {
+        Self::check_shared(&tsk_fs, path)?;
+        let tsk_fs_file = TskFsFile::from_path(&tsk_fs, path)?;
+        Self::from_file(tsk_fs.clone(), tsk_fs_file, path.to_string())
+    }
+
+    /// Open the file with the given inode. The TskFs must have been opened
+    /// with TskFs::from_shared_img.
+    pub fn from_meta(tsk_fs: Arc<TskFs>, inode: u64) -> Result<Self, TskError> {
+        let name = format!("inode {}", inode);
+        Self::check_shared(&tsk_fs, &name)?;
+        let tsk_fs_file = TskFsFile::from_meta(&tsk_fs, inode)?;
+        Self::from_file(tsk_fs.clone(), tsk_fs_file, name)
+    }
+
+    fn check_shared(tsk_fs: &TskFs, name: &str) -> Result<(), TskError> {
+        if tsk_fs.shared_img().is_none() {
+            return Err(TskError::generic(
+                format!("Cannot stream {}: the TskFs must be opened with TskFs::from_shared_img to keep its image alive.", name)
+            ));
+        }
+        Ok(())
+    }
+
+    fn from_file(tsk_fs: Arc<TskFs>, tsk_fs_file: TskFsFile, name: String) -> Result<Self, TskError> {
+        let (attr_type, attr_id, size) = {
+            let tsk_fs_attr = tsk_fs_file.get_attr()?;
+            (tsk_fs_attr.attr_type(), tsk_fs_attr.id(), tsk_fs_attr.size())
+        };
+        let tsk_fs_file_ptr = NonNull::new(tsk_fs_file.into_raw())
+            .ok_or(TskError::tsk_fs_file_error(
+                format!("Null TSK_FS_FILE pointer for {}.", name)
+            ))?;
+
+        Ok( Self {
+            tsk_fs_file_ptr,
+            tsk_fs,
+            name,
+            attr_type,
+            attr_id,
+            size: size.max(0) as u64,
+            _offset: 0
+        })
+    }
+
+    /// The size of the streamed attribute
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The path or inode the file was opened by
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The TskFs of the file
+    pub fn tsk_fs(&self) -> &Arc<TskFs> {
+        &self.tsk_fs
+    }
+
+    /// Turn the stream into a TskImg, for example to open the volume or file
+    /// system of a raw image stored in the file system
+    pub fn into_tsk_img(self) -> Result<TskImg, TskError> {
+        let source = self.name.clone();
+        let size = self.size as i64;
+        let reader = TskImgReadSeek::from_read_seek(source, Box::new(self), size)?;
+        Ok(reader.into())
+    }
+}
+impl std::fmt::Debug for TskFsFileStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TskFsFileStream")
+         .field("name", &self.name)
+         .field("attr_id", &self.attr_id)
+         .field("size", &self.size)
+         .field("offset", &self._offset)
+         .finish()
+    }
+}
+impl Drop for TskFsFileStream {
+    fn drop(&mut self) {
+        unsafe { tsk::tsk_fs_file_close(self.tsk_fs_file_ptr.as_ptr()) };
+    }
+}
+impl Read for TskFsFileStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self._offset >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = (self.size - self._offset).min(buf.len() as u64) as usize;
+
+        let bytes_read = unsafe { tsk::tsk_fs_file_read_type(
+            self.tsk_fs_file_ptr.as_ptr(),
+            self.attr_type,
+            self.attr_id,
+            self._offset as i64,
+            buf.as_mut_ptr() as *mut _,
+            len,
+            tsk::TSK_FS_FILE_READ_FLAG_ENUM::TSK_FS_FILE_READ_FLAG_NONE
+        )};
+
+        if bytes_read < 0 {
+            // Get a ptr to the error msg
+            let error_msg_ptr = unsafe { NonNull::new(tsk::tsk_error_get() as _) }
+                .ok_or_else(|| std::io::Error::other(
+                    format!("tsk_fs_file_read_type Error reading {} at offset {}. No context.", self.name, self._offset)
+                ))?;
+
+            // Get the error message from the string
+            let error_msg = unsafe { CStr::from_ptr(error_msg_ptr.as_ptr()) }.to_string_lossy();
+            return Err(std::io::Error::other(
+                format!("tsk_fs_file_read_type Error reading {} at offset {}: {}", self.name, self._offset, error_msg)
+            ));
+        }
+
+        self._offset += TryInto::<u64>::try_into(bytes_read)
+            .expect("Cannot convert bytes read into u64.");
+        Ok(bytes_read as usize)
+    }
+}
+impl Seek for TskFsFileStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self._offset as i128 + o as i128,
+            SeekFrom::End(o) => self.size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self._offset)
+            ));
+        }
+
+        self._offset = new_offset as u64;
+        Ok(self._offset)
+    }
+}
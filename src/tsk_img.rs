@@ -1,11 +1,25 @@
-use std::path::Path;
+use std::io::Cursor;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::ptr::NonNull;
 use std::ffi::{CStr, CString, c_void};
+use std::os::raw::c_char;
+use std::sync::Arc;
 use crate::{
     errors::TskError,
     bindings as tsk,
+    img_audit::{AuditLog, AuditTrail},
+    img_cache::{CacheCounters, CacheStats},
+    img_error_map::{BadSectorPolicy, BadSectorState, ErrorMap},
+    img_hash::{self, HashAlgorithm, HashProgress, HashVerification, ImageHash},
+    img_metadata::{ImgMetadata, SharedMetadata},
+    img_mmap::MmapReader,
+    tsk_img_handle::TskImgHandle,
+    tsk_img_reader::TskImgReadSeek,
     tsk_fs::TskFs,
-    tsk_vs::TskVs
+    tsk_discovery::EvidenceTree,
+    tsk_vs::{TskVs, TskVsType}
 };
 
 
@@ -21,11 +35,107 @@ type CloseCallback = Option<unsafe extern "C" fn(arg1: *mut tsk::TSK_IMG_INFO)>;
 type ImgStatCallback = Option<unsafe extern "C" fn(arg1: *mut tsk::TSK_IMG_INFO, arg2: *mut tsk::FILE)>;
 
 
+/// Image types that libtsk can open (TSK_IMG_TYPE_ENUM)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TskImgType {
+    /// Use autodetection methods
+    Detect,
+    /// Raw disk image (single or split)
+    Raw,
+    /// AFF AFF Format
+    AffAff,
+    /// AFD AFF Format
+    AffAfd,
+    /// AFM AFF Format
+    AffAfm,
+    /// Any format supported by AFFLIB
+    AffAny,
+    /// EWF version
+    Ewf,
+    /// VMDK version
+    Vmdk,
+    /// VHD version
+    Vhd,
+    /// External defined format which at least implements a read function
+    External,
+    /// Pool
+    Pool,
+    /// Unsupported disk image type
+    Unsupported
+}
+impl TskImgType {
+    /// All image types a TskImgType can represent (except Detect and Unsupported)
+    const ALL: [TskImgType; 9] = [
+        TskImgType::Raw,
+        TskImgType::AffAff,
+        TskImgType::AffAfd,
+        TskImgType::AffAfm,
+        TskImgType::AffAny,
+        TskImgType::Ewf,
+        TskImgType::Vmdk,
+        TskImgType::Vhd,
+        TskImgType::External
+    ];
+
+    /// Get the image types that the linked libtsk was built to support
+    pub fn supported() -> Vec<TskImgType> {
+        let supported = unsafe { tsk::tsk_img_type_supported() };
+        Self::ALL.iter()
+            .filter(|t| supported & tsk::TSK_IMG_TYPE_ENUM::from(**t) > 0)
+            .copied()
+            .collect()
+    }
+}
+impl From<tsk::TSK_IMG_TYPE_ENUM> for TskImgType {
+    fn from(itype: tsk::TSK_IMG_TYPE_ENUM) -> Self {
+        match itype {
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_DETECT => TskImgType::Detect,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_RAW => TskImgType::Raw,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_AFF_AFF => TskImgType::AffAff,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_AFF_AFD => TskImgType::AffAfd,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_AFF_AFM => TskImgType::AffAfm,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_AFF_ANY => TskImgType::AffAny,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_EWF_EWF => TskImgType::Ewf,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_VMDK_VMDK => TskImgType::Vmdk,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_VHD_VHD => TskImgType::Vhd,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_EXTERNAL => TskImgType::External,
+            tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_POOL => TskImgType::Pool,
+            _ => TskImgType::Unsupported
+        }
+    }
+}
+impl From<TskImgType> for tsk::TSK_IMG_TYPE_ENUM {
+    fn from(img_type: TskImgType) -> Self {
+        match img_type {
+            TskImgType::Detect => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_DETECT,
+            TskImgType::Raw => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_RAW,
+            TskImgType::AffAff => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_AFF_AFF,
+            TskImgType::AffAfd => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_AFF_AFD,
+            TskImgType::AffAfm => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_AFF_AFM,
+            TskImgType::AffAny => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_AFF_ANY,
+            TskImgType::Ewf => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_EWF_EWF,
+            TskImgType::Vmdk => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_VMDK_VMDK,
+            TskImgType::Vhd => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_VHD_VHD,
+            TskImgType::External => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_EXTERNAL,
+            TskImgType::Pool => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_POOL,
+            TskImgType::Unsupported => tsk::TSK_IMG_TYPE_ENUM_TSK_IMG_TYPE_UNSUPP
+        }
+    }
+}
+
+
 /// Wrapper for TSK_IMG_INFO
-#[derive(Debug)]
 pub struct TskImg {
     /// The ptr to the TSK_IMG_INFO struct
-    pub handle: NonNull<tsk::TSK_IMG_INFO>
+    pub handle: NonNull<tsk::TSK_IMG_INFO>,
+    /// The statistics of the block cache of a cached TskImgReadSeek
+    pub(crate) cache: Option<Arc<CacheCounters>>,
+    /// The error map of a TskImgReadSeek
+    pub(crate) bad_sectors: Option<Arc<BadSectorState>>,
+    /// The read audit of a TskImgReadSeek
+    pub(crate) audit: Option<Arc<AuditLog>>,
+    /// The metadata of the source of a TskImgReadSeek
+    pub(crate) metadata: Option<Arc<SharedMetadata>>
 }
 impl TskImg {
     /// Create TskImg from custom callbacks
@@ -72,13 +182,51 @@ impl TskImg {
             Some(h) => h
         };
 
-        Ok( Self { handle })
+        Ok( Self { handle, cache: None, bad_sectors: None, audit: None, metadata: None })
     }
 
     /// Create a TskImg wrapper from a given TSK_IMG_INFO NonNull pinter.
     /// 
     pub fn from_tsk_img_info_ptr(img_info: NonNull<tsk::TSK_IMG_INFO>) -> Self {
-        Self { handle: img_info }
+        Self { handle: img_info, cache: None, bad_sectors: None, audit: None, metadata: None }
+    }
+
+    /// Create a TskImg over an image held in memory. The TskImg owns the
+    /// buffer.
+    ///
+    pub fn from_buffer<S: Into<String>>(source: S, data: Vec<u8>) -> Result<Self, TskError> {
+        let size = data.len() as i64;
+        let reader = TskImgReadSeek::from_read_seek(source, Box::new(Cursor::new(data)), size)?;
+        Ok(reader.into())
+    }
+
+    /// Create a TskImg over a raw image that is read through a memory mapping
+    /// (see MmapReader) instead of libtsk's file reads
+    ///
+    /// # Safety
+    /// The file must not be truncated while the TskImg exists (see
+    /// `MmapReader::with_window_size`).
+    ///
+    pub unsafe fn from_mmap(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        let path = path.as_ref();
+        let reader = MmapReader::open(path)?;
+        let reader = TskImgReadSeek::from_metadata_source(path.display().to_string(), reader)?;
+        Ok(reader.into())
+    }
+
+    /// Create a TskImg over an image held in a borrowed buffer. The returned
+    /// BorrowedTskImg derefs to the TskImg and can not outlive the buffer.
+    ///
+    pub fn from_slice<S: Into<String>>(source: S, data: &[u8]) -> Result<BorrowedTskImg<'_>, TskError> {
+        // SAFETY: the stream is only read through the TskImg, which the
+        // BorrowedTskImg owns and closes (dropping the stream) before the
+        // borrow of data ends.
+        let data: &'static [u8] = unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len()) };
+        let reader = TskImgReadSeek::from_read_seek(source, Box::new(Cursor::new(data)), data.len() as i64)?;
+        Ok(BorrowedTskImg {
+            tsk_img: reader.into(),
+            _data: PhantomData
+        })
     }
 
     /// Create a TskImg wrapper from a given source.
@@ -120,7 +268,216 @@ impl TskImg {
             Some(h) => h
         };
 
-        Ok( Self { handle } )
+        Ok( Self { handle, cache: None, bad_sectors: None, audit: None, metadata: None } )
+    }
+
+    /// Create a TskImg wrapper from an ordered list of split image segments
+    /// (e.g. image.001, image.002, ...). The segments are handed to libtsk as
+    /// one raw image, in the order given.
+    /// 
+    pub fn from_utf8_split<P: AsRef<Path>>(segments: &[P]) -> Result<Self, TskError> {
+        Self::from_utf8_with_type(segments, TskImgType::Raw, 0)
+    }
+
+    /// Create a TskImg wrapper from a single path and let libtsk detect the
+    /// image type.
+    /// 
+    pub fn from_utf8_detect(path: impl AsRef<Path>) -> Result<Self, TskError> {
+        Self::from_utf8_with_type(&[path], TskImgType::Detect, 0)
+    }
+
+    /// Create a TskImg wrapper from one or more image paths (segments are given
+    /// in order) using the given image type. Use `TskImgType::Detect` to have
+    /// libtsk detect the type. A sector size of 0 uses the default (512) or
+    /// the sector size stored in the image format.
+    /// 
+    pub fn from_utf8_with_type<P: AsRef<Path>>(
+        segments: &[P],
+        img_type: TskImgType,
+        sector_size: u32
+    ) -> Result<Self, TskError> {
+        if segments.is_empty() {
+            return Err(TskError::generic(
+                "Unable to open image: no paths were provided.".to_string()
+            ));
+        }
+
+        // Create a CString for each provided segment
+        let mut c_segments: Vec<CString> = Vec::with_capacity(segments.len());
+        for segment in segments {
+            let c_segment = CString::new(segment.as_ref().to_string_lossy().as_bytes())
+                .map_err(|e| TskError::generic(format!("Unable to create CString from segment: {:?}", e)))?;
+            c_segments.push(c_segment);
+        }
+
+        // libtsk wants an array of char pointers
+        let segment_ptrs: Vec<*const c_char> = c_segments.iter()
+            .map(|s| s.as_ptr())
+            .collect();
+
+        // Get a pointer to the TSK_IMG_INFO sturct
+        let tsk_img = unsafe {tsk::tsk_img_open_utf8(
+            segment_ptrs.len() as _,
+            segment_ptrs.as_ptr(),
+            img_type.into(),
+            sector_size
+        )};
+
+        // Ensure that the ptr is not null
+        let handle = match NonNull::new(tsk_img) {
+            None => {
+                // Get a ptr to the error msg
+                let error_msg_ptr = unsafe { NonNull::new(tsk::tsk_error_get() as _) }
+                    .ok_or(
+                        TskError::lib_tsk_error(
+                            format!(
+                                "There was an error opening the {:?} img handle from {}. (no context)",
+                                img_type,
+                                segments[0].as_ref()
+                                    .to_string_lossy()
+                            )
+                        )
+                    )?;
+
+                // Get the error message from the string
+                let error_msg = unsafe { CStr::from_ptr(error_msg_ptr.as_ptr()) }.to_string_lossy();
+                // Return an error which includes the TSK error message
+                return Err(TskError::lib_tsk_error(
+                    format!("There was an error opening the {:?} img handle: {}", img_type, error_msg)
+                ));
+            },
+            Some(h) => h
+        };
+
+        Ok( Self { handle, cache: None, bad_sectors: None, audit: None, metadata: None } )
+    }
+
+    /// Create a TskImg wrapper from the first segment of a split image. The
+    /// sibling segments are discovered with `find_split_segments`.
+    /// 
+    pub fn from_utf8_split_discover(first_segment: impl AsRef<Path>) -> Result<Self, TskError> {
+        let segments = find_split_segments(first_segment);
+        Self::from_utf8_split(&segments)
+    }
+
+    /// Get the size of the image in bytes
+    pub fn size(&self) -> i64 {
+        unsafe { (*self.handle.as_ptr()).size }
+    }
+
+    /// Get the sector size of the image in bytes
+    pub fn sector_size(&self) -> u32 {
+        unsafe { (*self.handle.as_ptr()).sector_size }
+    }
+
+    /// Get the type of the image. Images created from a TskImgReadSeek (or
+    /// any other callbacks) are `TskImgType::External`.
+    pub fn img_type(&self) -> TskImgType {
+        TskImgType::from(unsafe { (*self.handle.as_ptr()).itype })
+    }
+
+    /// Get the number of image files (segments) that make up this image
+    pub fn num_img(&self) -> i32 {
+        unsafe { (*self.handle.as_ptr()).num_img }
+    }
+
+    /// Get the statistics of the block cache. Only images created from a
+    /// TskImgReadSeek with a cache have them.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|c| c.snapshot())
+    }
+
+    /// Get the ranges that were read as zeros because they could not be
+    /// read. Only images created from a TskImgReadSeek have an error map.
+    pub fn error_map(&self) -> Option<ErrorMap> {
+        self.bad_sectors.as_ref().map(|b| b.error_map())
+    }
+
+    /// Get the policy for ranges that can not be read. Only images created
+    /// from a TskImgReadSeek (e.g. `from_buffer`, `from_mmap` or a converted
+    /// `TskImgReadSeek::from_metadata_source`) have one; libtsk handles the
+    /// read errors of the images it opens itself.
+    pub fn bad_sector_policy(&self) -> Option<BadSectorPolicy> {
+        self.bad_sectors.as_ref().map(|b| b.policy())
+    }
+
+    /// Set the policy for ranges that can not be read, see
+    /// `TskImgReadSeek::set_bad_sector_policy`. Fails for images opened by
+    /// libtsk (`from_utf8_*`), which have no policy.
+    pub fn set_bad_sector_policy(&self, policy: BadSectorPolicy) -> Result<(), TskError> {
+        match &self.bad_sectors {
+            Some(bad_sectors) => {
+                bad_sectors.set_policy(policy);
+                Ok(())
+            },
+            None => Err(TskError::generic(
+                "Only images created from a TskImgReadSeek have a bad sector policy.".to_string()
+            ))
+        }
+    }
+
+    /// Get the metadata supplied by the source of an image created from a
+    /// TskImgReadSeek
+    pub fn metadata(&self) -> Option<ImgMetadata> {
+        self.metadata.as_ref().and_then(|m| m.get())
+    }
+
+    /// Start recording the ranges libtsk reads. Only the reads of images
+    /// created from a TskImgReadSeek can be audited.
+    pub fn enable_audit(&self) -> Result<(), TskError> {
+        match &self.audit {
+            Some(audit) => {
+                audit.set_enabled(true);
+                Ok(())
+            },
+            None => Err(TskError::generic(
+                "Reads can only be audited for images created from a TskImgReadSeek.".to_string()
+            ))
+        }
+    }
+
+    /// Stop recording the ranges libtsk reads. The trail is kept.
+    pub fn disable_audit(&self) {
+        if let Some(audit) = &self.audit {
+            audit.set_enabled(false);
+        }
+    }
+
+    /// Get the ranges libtsk read while auditing was enabled, or None if the
+    /// image can not be audited
+    pub fn audit_trail(&self) -> Option<AuditTrail> {
+        self.audit.as_ref().map(|a| a.trail())
+    }
+
+    /// Get a IO handle to the raw bytes of the whole image
+    pub fn get_handle(&self) -> TskImgHandle<'_> {
+        TskImgHandle::new(self)
+    }
+
+    /// Hash the whole image with several algorithms in a single pass. The
+    /// progress callback is called after every chunk and cancels hashing
+    /// (with an `ErrorType::Cancelled` error) by returning false. Reads are
+    /// recorded in the audit trail while auditing is enabled.
+    ///
+    pub fn hash<F: FnMut(HashProgress) -> bool>(
+        &self,
+        algorithms: &[HashAlgorithm],
+        mut progress: F
+    ) -> Result<Vec<ImageHash>, TskError> {
+        img_hash::hash_stream(&mut self.get_handle(), Some(self.size() as u64), algorithms, &mut progress)
+    }
+
+    /// Hash the whole image with MD5, SHA-1 and SHA-256 in a single pass and
+    /// compare the hashes with the expected ones, for example the hashes
+    /// stored in the container (`ImageHash::from_ewf_metadata`,
+    /// `ImageHash::from_aff4_hashes`)
+    ///
+    pub fn verify<F: FnMut(HashProgress) -> bool>(
+        &self,
+        expected: &[ImageHash],
+        mut progress: F
+    ) -> Result<HashVerification, TskError> {
+        img_hash::verify_stream(&mut self.get_handle(), Some(self.size() as u64), expected, &mut progress)
     }
 
     /// Get a TskVs at a given offset
@@ -128,13 +485,149 @@ impl TskImg {
         TskVs::new(&self, offset)
     }
 
+    /// Get a TskVs of the given type at a given offset
+    pub fn get_vs_from_offset_with_type(&self, offset: u64, vs_type: TskVsType) -> Result<TskVs, TskError> {
+        TskVs::open_with_type(self, offset, vs_type)
+    }
+
     /// Get a TskFs at a given offset
     pub fn get_fs_from_offset(&self, offset: u64) -> Result<TskFs, TskError> {
         TskFs::from_fs_offset(&self, offset)
     }
+
+    /// Discover the volume systems, partitions and file systems of the
+    /// image, including volume systems nested in partitions
+    pub fn discover(&self) -> EvidenceTree<'_> {
+        EvidenceTree::discover(self)
+    }
+}
+impl std::fmt::Debug for TskImg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TskImg")
+         .field("handle", &self.handle)
+         .field("img_type", &self.img_type())
+         .field("size", &self.size())
+         .field("sector_size", &self.sector_size())
+         .field("num_img", &self.num_img())
+         .field("cache_stats", &self.cache_stats())
+         .finish()
+    }
 }
 impl Drop for TskImg {
     fn drop(&mut self) {
         unsafe { tsk::tsk_img_close(self.handle.as_ptr()) };
     }
-}
\ No newline at end of file
+}
+
+
+/// A TskImg reading from a borrowed buffer (see `TskImg::from_slice`).
+/// 'a -> BorrowedTskImg can never last longer than the buffer
+///
+pub struct BorrowedTskImg<'a> {
+    tsk_img: TskImg,
+    _data: PhantomData<&'a [u8]>
+}
+impl<'a> Deref for BorrowedTskImg<'a> {
+    type Target = TskImg;
+
+    fn deref(&self) -> &TskImg {
+        &self.tsk_img
+    }
+}
+impl<'a> std::fmt::Debug for BorrowedTskImg<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("BorrowedTskImg")
+         .field(&self.tsk_img)
+         .finish()
+    }
+}
+
+
+/// Find the segments of a split image given its first segment. Numeric
+/// extensions of at least three digits starting at .000 or .001 (.001, .002,
+/// ...) and the two letter alphabetic extensions starting at .aa (.aa, .ab,
+/// ...) are understood; the width of the extension is kept. Segments are
+/// collected until the next one in sequence does not exist. Any other file
+/// (e.g. image.raw or disk.dd) is returned as the only segment, as is a
+/// first segment without siblings.
+/// 
+pub fn find_split_segments(first_segment: impl AsRef<Path>) -> Vec<PathBuf> {
+    let first_segment = first_segment.as_ref();
+    let mut segments = vec![first_segment.to_path_buf()];
+    if !is_first_segment_path(first_segment) {
+        return segments;
+    }
+
+    let mut current = first_segment.to_path_buf();
+    while let Some(next) = next_segment_path(&current) {
+        if !next.is_file() {
+            break;
+        }
+        segments.push(next.clone());
+        current = next;
+    }
+
+    segments
+}
+
+
+/// Check if the extension of `path` starts a split image sequence: .000,
+/// .001 (or wider, e.g. .0001) and .aa or .AA
+fn is_first_segment_path(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) if extension.len() >= 3 && extension.bytes().all(|b| b.is_ascii_digit()) => {
+            extension.parse::<u64>().is_ok_and(|value| value <= 1)
+        },
+        Some(extension) => extension == "aa" || extension == "AA",
+        None => false
+    }
+}
+
+
+/// Get the path of the segment that follows `path`, or None if the extension
+/// is not a split image segment extension (or the sequence is exhausted).
+fn next_segment_path(path: &Path) -> Option<PathBuf> {
+    let extension = path.extension()?.to_str()?;
+    if extension.is_empty() {
+        return None;
+    }
+
+    let next_extension = if extension.len() >= 3 && extension.bytes().all(|b| b.is_ascii_digit()) {
+        let value: u64 = extension.parse().ok()?;
+        let next = format!("{:0width$}", value + 1, width = extension.len());
+        if next.len() > extension.len() {
+            return None;
+        }
+        next
+    } else if extension.len() == 2 && (extension.bytes().all(|b| b.is_ascii_lowercase())
+        || extension.bytes().all(|b| b.is_ascii_uppercase()))
+    {
+        let (first, last) = if extension.as_bytes()[0].is_ascii_lowercase() {
+            (b'a', b'z')
+        } else {
+            (b'A', b'Z')
+        };
+
+        // Increment the extension like a base 26 number
+        let mut bytes = extension.as_bytes().to_vec();
+        let mut index = bytes.len();
+        loop {
+            if index == 0 {
+                // ran out of letters (zz -> aaa is not a valid sequence)
+                return None;
+            }
+            index -= 1;
+            if bytes[index] == last {
+                bytes[index] = first;
+            } else {
+                bytes[index] += 1;
+                break;
+            }
+        }
+        String::from_utf8(bytes).ok()?
+    } else {
+        return None;
+    };
+
+    Some(path.with_extension(next_extension))
+}
@@ -0,0 +1,100 @@
+use std::convert::TryInto;
+use std::ffi::CStr;
+use std::ptr::NonNull;
+use std::io::{Read, Seek, SeekFrom};
+use crate::tsk_img::TskImg;
+use crate::bindings as tsk;
+
+
+/// TskImgHandle reads the bytes of the whole image through tsk_img_read, so
+/// it sees the same data as libtsk, including TskImgReadSeek sources.
+/// 'img -> TskImgHandle can never last longer than the image
+///
+pub struct TskImgHandle<'img>{
+    /// The TskImg that is being read
+    tsk_img: &'img TskImg,
+    /// The read pointer
+    _offset: i64
+}
+impl<'img> TskImgHandle<'img> {
+    /// Create TskImgHandle from TskImg
+    pub fn new(
+        tsk_img: &'img TskImg
+    ) -> Self {
+        Self {
+            tsk_img,
+            _offset: 0
+        }
+    }
+}
+impl<'img> std::fmt::Debug for TskImgHandle<'img> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TskImgHandle")
+         .field("handle", &self.tsk_img.handle)
+         .field("size", &self.tsk_img.size())
+         .field("offset", &self._offset)
+         .finish()
+    }
+}
+impl<'img> Seek for TskImgHandle<'img> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let size = self.tsk_img.size();
+        let new_offset = match pos {
+            SeekFrom::Start(o) => o as i128,
+            SeekFrom::Current(o) => self._offset as i128 + o as i128,
+            SeekFrom::End(o) => size as i128 + o as i128
+        };
+
+        if new_offset < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot seek {:?} from offset {}", pos, self._offset)
+            ));
+        }
+        if new_offset > size as i128 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Offset {:?} is greater than image size {}", pos, size)
+            ));
+        }
+
+        self._offset = new_offset as i64;
+        Ok(self._offset as u64)
+    }
+}
+impl<'img> Read for TskImgHandle<'img> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // tsk_img_read fails at and beyond the end of the image
+        let size = self.tsk_img.size();
+        if self._offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = ((size - self._offset) as u64).min(buf.len() as u64) as usize;
+
+        // Read bytes
+        let bytes_read = unsafe { tsk::tsk_img_read(
+            self.tsk_img.handle.as_ptr(),
+            self._offset,
+            buf.as_mut_ptr() as *mut _,
+            len
+        )};
+
+        if bytes_read < 0 {
+            // Get a ptr to the error msg
+            let error_msg_ptr = unsafe { NonNull::new(tsk::tsk_error_get() as _) }
+                .ok_or_else(|| std::io::Error::other(
+                    format!("tsk_img_read Error at offset {}. No context.", self._offset)
+                ))?;
+
+            // Get the error message from the string
+            let error_msg = unsafe { CStr::from_ptr(error_msg_ptr.as_ptr()) }.to_string_lossy();
+            return Err(std::io::Error::other(
+                format!("tsk_img_read Error at offset {}: {}", self._offset, error_msg)
+            ));
+        }
+
+        self._offset += TryInto::<i64>::try_into(bytes_read)
+            .expect("Cannot convert bytes read into i64.");
+        Ok(bytes_read as usize)
+    }
+}
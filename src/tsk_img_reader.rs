@@ -1,13 +1,24 @@
 use std::convert::TryInto;
 use std::io::{Seek, SeekFrom, Read};
 use std::mem::MaybeUninit;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::ptr::NonNull;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
+use std::sync::Arc;
 use crate::tsk_img::TskImg;
 use crate::errors::TskError;
+use crate::img_audit::{AuditLog, AuditTrail};
+use crate::img_cache::{BlockCache, CacheConfig, CacheCounters, CacheStats};
+use crate::img_common::read_fill;
+use crate::img_error_map::{BadSectorPolicy, BadSectorReader, BadSectorState, ErrorMap};
+use crate::img_metadata::{ImgMetadata, ImgMetadataSource, SharedMetadata};
 use crate::bindings as tsk;
 
 
+/// The sector size used by from_read_seek
+const DEFAULT_SECTOR_SIZE: u32 = 512;
+
+
 pub trait ReadSeek: Read + Seek {
     fn tell(&mut self) -> std::io::Result<u64> {
         self.seek(SeekFrom::Current(0))
@@ -28,7 +39,22 @@ pub trait ReadSeek: Read + Seek {
 impl<T: Read + Seek> ReadSeek for T {}
 
 
-/// Read function for TskImgReadSeek
+/// Set the libtsk error (errno and message) so that the caller of the libtsk
+/// function that ended up in a callback sees why it failed
+fn set_tsk_error(errno: u32, message: &str) {
+    let message = CString::new(message.replace('\0', ""))
+        .unwrap_or_default();
+    unsafe {
+        tsk::tsk_error_reset();
+        tsk::tsk_error_set_errno(errno);
+        tsk::tsk_error_set_errstr(b"%s\0".as_ptr() as _, message.as_ptr());
+    }
+}
+
+
+/// Read function for TskImgReadSeek. The buffer is filled until len bytes
+/// were read or the stream ends. Returns the number of bytes read or -1 with
+/// the libtsk error set.
 unsafe extern "C" fn img_read(
     img: *mut tsk::TSK_IMG_INFO,
     off: tsk::TSK_OFF_T,
@@ -37,72 +63,117 @@ unsafe extern "C" fn img_read(
 ) -> isize {
     if img.is_null() {
         error!("img_read contained null TSK_IMG_INFO pointer!");
+        set_tsk_error(tsk::TSK_ERR_IMG_ARG, "img_read: null TSK_IMG_INFO pointer");
         return -1;
     }
-
-    // Get pointer to TskImgReadSeekInner
-    let reader = img as *mut TskImgReadSeekInner;
-    
-    // Seek inner stream to offset
-    match (*reader).stream.seek(SeekFrom::Start(off as u64)) {
-        Ok(pos) => {
-            if pos != off as u64 {
-                error!("pos {} is not equal to off {}", pos, off);
-                return 0;
-            }
-        },
-        Err(error) => {
-            eprintln!("Error seeking stream: {:?}", error);
-            return 0;
-        }
-    }
-
     // Make sure that the buffer's pointer is not null
     if buf.is_null() {
         error!("img_read contained null buffer!");
+        set_tsk_error(tsk::TSK_ERR_IMG_ARG, "img_read: null buffer");
+        return -1;
+    }
+    if off < 0 {
+        set_tsk_error(tsk::TSK_ERR_IMG_ARG, &format!("img_read: negative offset {}", off));
         return -1;
     }
+    if len == 0 {
+        return 0;
+    }
 
+    // Get pointer to TskImgReadSeekInner
+    let reader = img as *mut TskImgReadSeekInner;
     // Get slice from buf pointer
     let buffer = core::slice::from_raw_parts_mut(
         buf as *mut u8,
-        len as usize
+        len
     );
-    // Read bytes from stream into buffer
-    let bytes_read = match (*reader).stream.read(buffer) {
-        Ok(b) => b,
-        Err(e) => {
-            error!("{:?}", e);
-            return 0;
+
+    // A panic must not unwind into libtsk
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        // Seek inner stream to offset
+        match (*reader).stream.seek(SeekFrom::Start(off as u64)) {
+            Ok(pos) if pos == off as u64 => {},
+            Ok(pos) => return Err((
+                tsk::TSK_ERR_IMG_SEEK,
+                format!("img_read: seek to offset {} ended at {}", off, pos)
+            )),
+            Err(e) => return Err((
+                tsk::TSK_ERR_IMG_SEEK,
+                format!("img_read: error seeking to offset {}: {}", off, e)
+            ))
         }
-    };
 
-    std::mem::forget(buffer);
+        // Read bytes from stream into buffer
+        read_fill(&mut (*reader).stream, buffer).map_err(|e| (
+            tsk::TSK_ERR_IMG_READ,
+            format!("img_read: error reading {} bytes at offset {}: {}", len, off, e)
+        ))
+    }));
 
-    // Check if bytes read was not equal to length of data to read
-    if bytes_read != len {
-        error!("bytes_read {} is not equal to len {}", bytes_read, len);
-        return -1;
+    match result {
+        Ok(Ok(bytes_read)) => {
+            (*reader).audit.record(off as u64, bytes_read as u64);
+            if bytes_read < len {
+                debug!("img_read: stream ended after {} of {} bytes at offset {}", bytes_read, len, off);
+            }
+            bytes_read.try_into()
+                .expect("Cannot convert bytes read into isize.")
+        },
+        Ok(Err((errno, message))) => {
+            error!("{}", message);
+            set_tsk_error(errno, &message);
+            -1
+        },
+        Err(_) => {
+            let message = format!("img_read: panic while reading {} bytes at offset {}", len, off);
+            error!("{}", message);
+            set_tsk_error(tsk::TSK_ERR_IMG_READ, &message);
+            -1
+        }
     }
-
-    // Return bytes read
-    bytes_read.try_into()
-        .expect("Cannot convert bytes read into usize.")
 }
 
 
+/// Imgstat function for TskImgReadSeek. Prints the metadata of the source,
+/// or just the size and sector size if the source has none.
 unsafe extern "C" fn img_info(
-    arg1: *mut tsk::TSK_IMG_INFO,
-    arg2: *mut tsk::FILE
+    img: *mut tsk::TSK_IMG_INFO,
+    file: *mut tsk::FILE
 ) {
-    trace!("img_info() not implemented");
+    if img.is_null() || file.is_null() {
+        error!("img_info contained null pointer!");
+        return;
+    }
+    let reader = img as *mut TskImgReadSeekInner;
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let metadata = (*reader).metadata.get()
+            .unwrap_or_else(|| ImgMetadata::new("external"));
+        metadata.to_imgstat((*img).size as u64, (*img).sector_size)
+    }));
+    let output = match result {
+        Ok(output) => output,
+        Err(_) => {
+            error!("img_info: panic while rendering the image metadata");
+            return;
+        }
+    };
+
+    let output = CString::new(output.replace('\0', ""))
+        .unwrap_or_default();
+    tsk::tsk_fprintf(file, b"%s\0".as_ptr() as _, output.as_ptr());
 }
 
 
+/// Close function for TskImgReadSeek. libtsk calls this from tsk_img_close,
+/// which frees the TskImgReadSeekInner along with its stream.
 unsafe extern "C" fn img_close(
-    arg1: *mut tsk::TSK_IMG_INFO
+    img: *mut tsk::TSK_IMG_INFO
 ) {
-    trace!("img_close() not implemented");
+    if img.is_null() {
+        return;
+    }
+    drop(Box::from_raw(img as *mut TskImgReadSeekInner));
 }
 
 
@@ -110,27 +181,145 @@ unsafe extern "C" fn img_close(
 #[repr(C)]
 struct TskImgReadSeekInner {
     tsk_img_info: tsk::TSK_IMG_INFO,
-    stream: Box<dyn ReadSeek>
+    stream: Box<dyn ReadSeek>,
+    audit: Arc<AuditLog>,
+    metadata: Arc<SharedMetadata>
 }
-/// TskImgReadSeek uses a boxed read/seek trait and can be turned into a TskImg
+/// TskImgReadSeek uses a boxed read/seek trait and can be turned into a TskImg.
+/// The stream is released when the TskImg (or the TskImgReadSeek, if it was
+/// never turned into one) is dropped.
 pub struct TskImgReadSeek{
     source: String,
     inner: *mut MaybeUninit<TskImgReadSeekInner>,
-    tsk_img_info: NonNull<tsk::TSK_IMG_INFO>
+    tsk_img_info: NonNull<tsk::TSK_IMG_INFO>,
+    /// The statistics of the block cache, if reads are cached
+    cache: Option<Arc<CacheCounters>>,
+    /// The bad sector policy and error map of the stream
+    bad_sectors: Arc<BadSectorState>,
+    /// The ranges read by libtsk, recorded while auditing is enabled
+    audit: Arc<AuditLog>,
+    /// The metadata of the source
+    metadata: Arc<SharedMetadata>
 }
 impl TskImgReadSeek {
+    /// Create a TskImgReadSeek with 512 byte sectors
+    ///
     pub fn from_read_seek<S: Into<String>>(
         source: S,
         stream: Box<dyn ReadSeek>,
         size: i64
     ) -> Result<Self, TskError> {
-        let source = source.into();
-        let sector_size: std::os::raw::c_uint = 512;
+        Self::from_read_seek_with_sector_size(source, stream, size, DEFAULT_SECTOR_SIZE)
+    }
+
+    /// Create a TskImgReadSeek with the given sector size, which must be a
+    /// multiple of 512 (for example 4096 for 4Kn disks)
+    ///
+    pub fn from_read_seek_with_sector_size<S: Into<String>>(
+        source: S,
+        stream: Box<dyn ReadSeek>,
+        size: i64,
+        sector_size: u32
+    ) -> Result<Self, TskError> {
+        Self::open(source.into(), stream, size, sector_size, None)
+    }
+
+    /// Create a TskImgReadSeek whose reads go through an LRU block cache.
+    /// libtsk tends to issue many small and repeated reads, which the cache
+    /// turns into fewer, larger reads of the stream.
+    ///
+    pub fn from_read_seek_cached<S: Into<String>>(
+        source: S,
+        stream: Box<dyn ReadSeek>,
+        size: i64,
+        sector_size: u32,
+        config: CacheConfig
+    ) -> Result<Self, TskError> {
+        Self::open(source.into(), stream, size, sector_size, Some(config))
+    }
+
+    /// Create a TskImgReadSeek from a source that describes itself, such as
+    /// the format readers of this crate. Its metadata is printed by libtsk's
+    /// imgstat and returned by `metadata`, and its sector size is used as
+    /// the sector size of the image.
+    ///
+    pub fn from_metadata_source<S: Into<String>, T: ReadSeek + ImgMetadataSource + 'static>(
+        source: S,
+        stream: T
+    ) -> Result<Self, TskError> {
+        Self::open_metadata_source(source.into(), stream, None)
+    }
+
+    /// Create a TskImgReadSeek from a source that describes itself, with
+    /// reads going through an LRU block cache (see `from_read_seek_cached`)
+    ///
+    pub fn from_metadata_source_cached<S: Into<String>, T: ReadSeek + ImgMetadataSource + 'static>(
+        source: S,
+        stream: T,
+        config: CacheConfig
+    ) -> Result<Self, TskError> {
+        Self::open_metadata_source(source.into(), stream, Some(config))
+    }
+
+    fn open_metadata_source<T: ReadSeek + ImgMetadataSource + 'static>(
+        source: String,
+        mut stream: T,
+        cache_config: Option<CacheConfig>
+    ) -> Result<Self, TskError> {
+        let metadata = stream.img_metadata();
+        let sector_size = stream.sector_size();
+        let size = ReadSeek::stream_len(&mut stream)
+            .map_err(|e| TskError::generic(
+                format!("Error getting the size of {}: {}", &source, e)
+            ))?;
+        let mut reader = Self::open(source, Box::new(stream), size as i64, sector_size, cache_config)?;
+        reader.set_metadata(metadata);
+        Ok(reader)
+    }
+
+    fn open(
+        source: String,
+        stream: Box<dyn ReadSeek>,
+        size: i64,
+        sector_size: u32,
+        cache_config: Option<CacheConfig>
+    ) -> Result<Self, TskError> {
+        if sector_size == 0 || !sector_size.is_multiple_of(512) {
+            return Err(TskError::generic(
+                format!("Invalid sector size {} for {}: it must be a multiple of 512.", sector_size, &source)
+            ));
+        }
+        if size < 0 {
+            return Err(TskError::generic(
+                format!("Invalid size {} for {}.", size, &source)
+            ));
+        }
+
+        // Read errors are handled below the cache so that a bad sector does
+        // not fail a whole cache block
+        let bad_sector_reader = BadSectorReader::new(stream, size as u64, sector_size, BadSectorPolicy::Fail)?;
+        let bad_sectors = bad_sector_reader.state();
+        let (stream, cache): (Box<dyn ReadSeek>, _) = match cache_config {
+            Some(config) => {
+                let block_cache = BlockCache::new(Box::new(bad_sector_reader), size as u64, config)?;
+                let counters = block_cache.counters();
+                (Box::new(block_cache), Some(counters))
+            },
+            None => (Box::new(bad_sector_reader), None)
+        };
+        let audit = Arc::new(AuditLog::new(&source, size as u64));
+        let metadata = Arc::new(SharedMetadata::default());
 
         // Create uninitialized reader
         let mut boxed_tsk_reader = Box::<TskImgReadSeekInner>::new_uninit();
-        // Set the stream
-        unsafe { std::ptr::addr_of_mut!((*boxed_tsk_reader.as_mut_ptr()).stream).write(Box::new(stream)) };
+        unsafe {
+            // libtsk only sets some of the TSK_IMG_INFO fields
+            std::ptr::addr_of_mut!((*boxed_tsk_reader.as_mut_ptr()).tsk_img_info).write_bytes(0, 1);
+            // Set the stream
+            std::ptr::addr_of_mut!((*boxed_tsk_reader.as_mut_ptr()).stream).write(stream);
+            std::ptr::addr_of_mut!((*boxed_tsk_reader.as_mut_ptr()).audit).write(audit.clone());
+            std::ptr::addr_of_mut!((*boxed_tsk_reader.as_mut_ptr()).metadata).write(metadata.clone());
+        }
         // Get the pointer to the uninitialized struct
         let tsk_reader_ptr_unint: *mut MaybeUninit<TskImgReadSeekInner> = Box::into_raw(boxed_tsk_reader).cast();
 
@@ -158,7 +347,7 @@ impl TskImgReadSeek {
             (*(*tsk_reader_ptr_unint).as_mut_ptr()).tsk_img_info
         )};
         // Open via the external source
-        let tsk_img_info_ptr: *mut tsk::TSK_IMG_INFO = unsafe { 
+        let tsk_img_info_ptr: *mut tsk::TSK_IMG_INFO = unsafe {
             tsk::tsk_img_open_external(
                 img_addr as _,
                 size,
@@ -172,6 +361,9 @@ impl TskImgReadSeek {
         // Get non null pointer
         let tsk_img_info_ptr = match NonNull::new(tsk_img_info_ptr) {
             None => {
+                // libtsk did not take ownership, so release the stream here
+                drop(unsafe { Box::from_raw(tsk_reader_ptr_unint as *mut TskImgReadSeekInner) });
+
                 // Get a ptr to the error msg
                 let error_msg_ptr = unsafe { NonNull::new(tsk::tsk_error_get() as _) }
                     .ok_or(
@@ -200,9 +392,82 @@ impl TskImgReadSeek {
         Ok( Self{
             source,
             inner: tsk_reader_ptr_unint,
-            tsk_img_info: tsk_img_info_ptr
+            tsk_img_info: tsk_img_info_ptr,
+            cache,
+            bad_sectors,
+            audit,
+            metadata
         })
     }
+
+    /// Get the sector size of the image
+    pub fn sector_size(&self) -> u32 {
+        unsafe { (*self.tsk_img_info.as_ptr()).sector_size }
+    }
+
+    /// Get the statistics of the block cache, or None if reads are not cached
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|c| c.snapshot())
+    }
+
+    /// Get the policy for ranges of the stream that can not be read
+    pub fn bad_sector_policy(&self) -> BadSectorPolicy {
+        self.bad_sectors.policy()
+    }
+
+    /// Set the policy for ranges of the stream that can not be read. The
+    /// default policy (`BadSectorPolicy::Fail`) fails the libtsk call. The
+    /// policy can also be changed after the conversion into a TskImg with
+    /// `TskImg::set_bad_sector_policy`.
+    ///
+    /// The policy applies to the reads of the stream, which with a block
+    /// cache are whole cache blocks: `BadSectorPolicy::Skip` then zero-fills
+    /// the whole block of a failed read, including the read-ahead blocks
+    /// read with it.
+    pub fn set_bad_sector_policy(&mut self, policy: BadSectorPolicy) {
+        self.bad_sectors.set_policy(policy)
+    }
+
+    /// Get the ranges that were substituted because they could not be read
+    pub fn error_map(&self) -> ErrorMap {
+        self.bad_sectors.error_map()
+    }
+
+    /// Start recording the ranges libtsk reads. Auditing is off by default
+    /// and stays on when the reader is turned into a TskImg.
+    pub fn enable_audit(&self) {
+        self.audit.set_enabled(true)
+    }
+
+    /// Stop recording the ranges libtsk reads. The trail is kept.
+    pub fn disable_audit(&self) {
+        self.audit.set_enabled(false)
+    }
+
+    /// True if the ranges libtsk reads are being recorded
+    pub fn is_auditing(&self) -> bool {
+        self.audit.is_enabled()
+    }
+
+    /// Get the ranges libtsk read while auditing was enabled
+    pub fn audit_trail(&self) -> AuditTrail {
+        self.audit.trail()
+    }
+
+    /// Forget the recorded ranges
+    pub fn clear_audit_trail(&self) {
+        self.audit.clear()
+    }
+
+    /// Get the metadata of the source
+    pub fn metadata(&self) -> Option<ImgMetadata> {
+        self.metadata.get()
+    }
+
+    /// Set the metadata of the source, which libtsk's imgstat prints
+    pub fn set_metadata(&mut self, metadata: ImgMetadata) {
+        self.metadata.set(metadata)
+    }
 }
 impl std::fmt::Debug for TskImgReadSeek {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -210,11 +475,29 @@ impl std::fmt::Debug for TskImgReadSeek {
          .field("source", &self.source)
          .field("inner", &self.inner)
          .field("tsk_img_info", &self.tsk_img_info)
+         .field("sector_size", &self.sector_size())
+         .field("cache_stats", &self.cache_stats())
+         .field("bad_sector_policy", &self.bad_sector_policy())
+         .field("auditing", &self.is_auditing())
          .finish()
     }
 }
+impl Drop for TskImgReadSeek {
+    fn drop(&mut self) {
+        // The TskImg owns the handle once the reader was turned into one
+        if !self.inner.is_null() {
+            unsafe { tsk::tsk_img_close(self.tsk_img_info.as_ptr()) };
+        }
+    }
+}
 impl<'fs> Into<TskImg> for TskImgReadSeek {
-    fn into(self) -> TskImg {
-        TskImg::from_tsk_img_info_ptr(self.tsk_img_info)
+    fn into(mut self) -> TskImg {
+        self.inner = std::ptr::null_mut();
+        let mut img = TskImg::from_tsk_img_info_ptr(self.tsk_img_info);
+        img.cache = self.cache.take();
+        img.bad_sectors = Some(self.bad_sectors.clone());
+        img.audit = Some(self.audit.clone());
+        img.metadata = Some(self.metadata.clone());
+        img
     }
 }
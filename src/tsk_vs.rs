@@ -1,15 +1,132 @@
 use std::ptr::NonNull;
 use std::ffi::CStr;
+use std::io::{Read, Seek};
 use crate::{
     errors::TskError,
     tsk_img::TskImg,
-    tsk_vs_part::{TskVsPart, TskVsPartIterator},
+    tsk_vs_gpt::GptDetails,
+    tsk_vs_part::{TskVsPart, TskVsPartFlag, TskVsPartIterator},
     bindings as tsk
 };
 
 
+/// Enum of volume system (partition table) types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TskVsType {
+    /// Use autodetection methods
+    Detect,
+    /// DOS (MBR) partition table
+    Dos,
+    /// BSD disk label
+    Bsd,
+    /// Sun VTOC
+    Sun,
+    /// Mac (Apple Partition Map) partition table
+    Mac,
+    /// GUID partition table
+    Gpt,
+    /// APFS container
+    Apfs,
+    /// Unsupported volume system type
+    Unsupported
+}
+impl TskVsType {
+    /// All volume system types a TskVsType can represent (except Detect and Unsupported)
+    pub const ALL: [TskVsType; 6] = [
+        TskVsType::Dos,
+        TskVsType::Bsd,
+        TskVsType::Sun,
+        TskVsType::Mac,
+        TskVsType::Gpt,
+        TskVsType::Apfs
+    ];
+
+    /// The short name libtsk uses for the type (e.g. "dos", "gpt")
+    pub fn name(&self) -> &'static str {
+        match self {
+            TskVsType::Detect => "detect",
+            TskVsType::Dos => "dos",
+            TskVsType::Bsd => "bsd",
+            TskVsType::Sun => "sun",
+            TskVsType::Mac => "mac",
+            TskVsType::Gpt => "gpt",
+            TskVsType::Apfs => "apfs",
+            TskVsType::Unsupported => "unsupported"
+        }
+    }
+
+    /// Get the type for a short name. "mbr" and "apm" are accepted as
+    /// aliases of "dos" and "mac".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "detect" => Some(TskVsType::Detect),
+            "dos" | "mbr" => Some(TskVsType::Dos),
+            "bsd" => Some(TskVsType::Bsd),
+            "sun" => Some(TskVsType::Sun),
+            "mac" | "apm" => Some(TskVsType::Mac),
+            "gpt" => Some(TskVsType::Gpt),
+            "apfs" => Some(TskVsType::Apfs),
+            _ => None
+        }
+    }
+}
+impl std::fmt::Display for TskVsType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl From<tsk::TSK_VS_TYPE_ENUM> for TskVsType {
+    fn from(vstype: tsk::TSK_VS_TYPE_ENUM) -> Self {
+        match vstype {
+            tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_DETECT => TskVsType::Detect,
+            tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_DOS => TskVsType::Dos,
+            tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_BSD => TskVsType::Bsd,
+            tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_SUN => TskVsType::Sun,
+            tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_MAC => TskVsType::Mac,
+            tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_GPT => TskVsType::Gpt,
+            tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_APFS => TskVsType::Apfs,
+            _ => TskVsType::Unsupported
+        }
+    }
+}
+impl From<TskVsType> for tsk::TSK_VS_TYPE_ENUM {
+    fn from(vs_type: TskVsType) -> Self {
+        match vs_type {
+            TskVsType::Detect => tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_DETECT,
+            TskVsType::Dos => tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_DOS,
+            TskVsType::Bsd => tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_BSD,
+            TskVsType::Sun => tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_SUN,
+            TskVsType::Mac => tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_MAC,
+            TskVsType::Gpt => tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_GPT,
+            TskVsType::Apfs => tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_APFS,
+            TskVsType::Unsupported => tsk::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_UNSUPP
+        }
+    }
+}
+
+
+/// Byte order of the on disk structures of a volume system
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TskEndian {
+    /// The byte order was not determined
+    Unknown,
+    /// Little endian
+    Little,
+    /// Big endian
+    Big
+}
+impl From<tsk::TSK_ENDIAN_ENUM> for TskEndian {
+    fn from(endian: tsk::TSK_ENDIAN_ENUM) -> Self {
+        match endian {
+            tsk::TSK_ENDIAN_ENUM_TSK_LIT_ENDIAN => TskEndian::Little,
+            tsk::TSK_ENDIAN_ENUM_TSK_BIG_ENDIAN => TskEndian::Big,
+            _ => TskEndian::Unknown
+        }
+    }
+}
+
+
 /// Wrapper for TSK_VS_INFO 
-#[derive(Debug)]
 pub struct TskVs {
     /// The ptr to the TSK_VS_INFO struct
     pub handle: NonNull<tsk::TSK_VS_INFO>
@@ -17,11 +134,24 @@ pub struct TskVs {
 impl TskVs {
     /// Create a TSK_VS_INFO wrapper given the TskImg and offset of the file system
     pub fn new(tsk_img: &TskImg, offset: u64) -> Result<Self, TskError> {
+        Self::open_with_type(tsk_img, offset, TskVsType::Detect)
+    }
+
+    /// Create a TSK_VS_INFO wrapper for a volume system of the given type.
+    /// Use this when autodetection picks the wrong table, e.g. to read the
+    /// GPT of a disk with a hybrid MBR.
+    pub fn open_with_type(tsk_img: &TskImg, offset: u64, vs_type: TskVsType) -> Result<Self, TskError> {
+        if vs_type == TskVsType::Unsupported {
+            return Err(TskError::generic(
+                format!("Cannot open a volume system of type {} at offset {}.", vs_type, offset)
+            ));
+        }
+
         // Get a pointer to the TSK_VS_INFO sturct
         let tsk_vs = unsafe {tsk::tsk_vs_open(
             tsk_img.handle.as_ptr(),
             offset as _,
-            0
+            vs_type.into()
         )};
 
         // Ensure that the ptr is not null
@@ -32,7 +162,8 @@ impl TskVs {
                     .ok_or(
                         TskError::lib_tsk_error(
                             format!(
-                                "There was an error opening the TSK_VS_INFO handle at offset {}. (no context)",
+                                "There was an error opening the TSK_VS_INFO handle ({}) at offset {}. (no context)",
+                                vs_type,
                                 offset
                             )
                         )
@@ -43,7 +174,8 @@ impl TskVs {
                 // Return an error which includes the TSK error message
                 return Err(TskError::lib_tsk_error(
                     format!(
-                        "There was an error opening the TSK_VS_INFO handle at offset {}: {}",
+                        "There was an error opening the TSK_VS_INFO handle ({}) at offset {}: {}",
+                        vs_type,
                         offset,
                         error_msg
                     )
@@ -55,6 +187,56 @@ impl TskVs {
         Ok( Self { handle } )
     }
 
+    /// Get the TSK_VS_INFO struct
+    fn info(&self) -> &tsk::TSK_VS_INFO {
+        unsafe { self.handle.as_ref() }
+    }
+
+    /// Get the type of the volume system
+    pub fn vs_type(&self) -> TskVsType {
+        self.info().vstype.into()
+    }
+
+    /// Get the size of a block (sector) in bytes
+    pub fn block_size(&self) -> u32 {
+        self.info().block_size
+    }
+
+    /// Get the byte offset of the volume system in the image
+    pub fn offset(&self) -> u64 {
+        self.info().offset
+    }
+
+    /// Get the byte order of the volume system
+    pub fn endian(&self) -> TskEndian {
+        self.info().endian.into()
+    }
+
+    /// Is the volume system read from a backup table (e.g. the GPT backup header)
+    pub fn is_backup(&self) -> bool {
+        self.info().is_backup != 0
+    }
+
+    /// Get the number of partitions, including the unallocated and meta
+    /// entries libtsk adds to the list
+    pub fn partition_count(&self) -> u32 {
+        self.info().part_count
+    }
+
+    /// Read the details of a GUID partition table: the type and unique
+    /// GUIDs, names and attributes of the partitions, the disk GUID and the
+    /// consistency of the primary and backup headers. The table is read
+    /// through reader, which covers the image the volume system was opened
+    /// on (e.g. `TskImg::get_handle`).
+    pub fn gpt_details<R: Read + Seek>(&self, reader: &mut R) -> Result<GptDetails, TskError> {
+        if self.vs_type() != TskVsType::Gpt {
+            return Err(TskError::tsk_vs_part_error(
+                format!("The volume system at offset {} is {}, not gpt.", self.offset(), self.vs_type())
+            ));
+        }
+        GptDetails::from_reader(reader, self.offset(), self.block_size())
+    }
+
     /// Get a specific TskVsPart at the given index
     pub fn get_partition_at_index(&self, index: u64) -> Result<TskVsPart, TskError> {
         TskVsPart::new(self, index)
@@ -62,9 +244,40 @@ impl TskVs {
 
     /// Get a partition iterator that yields TskVsPart structs
     pub fn get_partition_iter<'vs>(&'vs self) -> Result<TskVsPartIterator<'vs>, TskError> {
-        let iterator = TskVsPart::new(self, 0)?
-            .into_iter();
-        Ok(iterator)
+        Ok(TskVsPartIterator::from_tsk_vs(self))
+    }
+
+    /// Get a partition iterator that yields the TskVsPart structs with any
+    /// of the given flags (no flags yield all partitions)
+    pub fn get_partition_iter_with_flags<'vs>(&'vs self, flags: &[TskVsPartFlag]) -> Result<TskVsPartIterator<'vs>, TskError> {
+        Ok(self.get_partition_iter()?.with_flags(flags))
+    }
+
+    /// Get an iterator over the partitions of the partition table
+    pub fn allocated_partitions<'vs>(&'vs self) -> Result<TskVsPartIterator<'vs>, TskError> {
+        self.get_partition_iter_with_flags(&[TskVsPartFlag::Alloc])
+    }
+
+    /// Get an iterator over the unallocated regions between the partitions
+    pub fn unallocated_partitions<'vs>(&'vs self) -> Result<TskVsPartIterator<'vs>, TskError> {
+        self.get_partition_iter_with_flags(&[TskVsPartFlag::Unalloc])
+    }
+
+    /// Get an iterator over the meta entries, such as the partition tables
+    pub fn meta_partitions<'vs>(&'vs self) -> Result<TskVsPartIterator<'vs>, TskError> {
+        self.get_partition_iter_with_flags(&[TskVsPartFlag::Meta])
+    }
+}
+impl std::fmt::Debug for TskVs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TskVs")
+         .field("vs_type", &self.vs_type())
+         .field("offset", &self.offset())
+         .field("block_size", &self.block_size())
+         .field("endian", &self.endian())
+         .field("is_backup", &self.is_backup())
+         .field("partition_count", &self.partition_count())
+         .finish()
     }
 }
 impl Drop for TskVs {
@@ -0,0 +1,385 @@
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom};
+use flate2::Crc;
+use crate::{
+    errors::TskError,
+    img_common::{format_guid, read_exact_at, utf16le_to_string},
+    tsk_vs_part::TskVsPart
+};
+
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_HEADER_SIZE: u32 = 92;
+const GPT_MIN_ENTRY_SIZE: u32 = 128;
+/// Upper bound of the partition entry array, protecting against malformed
+/// headers (the common array is 16 KiB)
+const GPT_MAX_ENTRIES_SIZE: u64 = 16 * 1024 * 1024;
+const UNUSED_TYPE_GUID: &str = "00000000-0000-0000-0000-000000000000";
+
+
+/// Well-known GPT partition type GUIDs and their names
+pub const GPT_PARTITION_TYPES: &[(&str, &str)] = &[
+    ("C12A7328-F81F-11D2-BA4B-00A0C93EC93B", "EFI System"),
+    ("024DEE41-33E7-11D3-9D69-0008C781F39F", "MBR partition scheme"),
+    ("21686148-6449-6E6F-744E-656564454649", "BIOS boot"),
+    ("E3C9E316-0B5C-4DB8-817D-F92DF00215AE", "Microsoft Reserved"),
+    ("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7", "Microsoft Basic Data"),
+    ("DE94BBA4-06D1-4D40-A16A-BFD50179D6AC", "Windows Recovery Environment"),
+    ("5808C8AA-7E8F-42E0-85D2-E1E90434CFB3", "Windows LDM metadata"),
+    ("AF9B60A0-1431-4F62-BC68-3311714A69AD", "Windows LDM data"),
+    ("E75CAF8F-F680-4CEE-AFA3-B001E56EFC2D", "Windows Storage Spaces"),
+    ("0FC63DAF-8483-4772-8E79-3D69D8477DE4", "Linux filesystem"),
+    ("4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709", "Linux root (x86-64)"),
+    ("933AC7E1-2EB4-4F13-B844-0E14E2AEF915", "Linux home"),
+    ("BC13C2FF-59E6-4262-A352-B275FD6F7172", "Linux extended boot"),
+    ("0657FD6D-A4AB-43C4-84E5-0933C84B4F4F", "Linux swap"),
+    ("E6D6D379-F507-44C2-A23C-238F2A3DF928", "Linux LVM"),
+    ("A19D880F-05FC-4D3B-A006-743F0F84911E", "Linux RAID"),
+    ("CA7D7CCB-63ED-4C53-861C-1742536059CC", "Linux LUKS"),
+    ("7C3457EF-0000-11AA-AA11-00306543ECAC", "Apple APFS"),
+    ("48465300-0000-11AA-AA11-00306543ECAC", "Apple HFS+"),
+    ("55465300-0000-11AA-AA11-00306543ECAC", "Apple UFS"),
+    ("52414944-0000-11AA-AA11-00306543ECAC", "Apple RAID"),
+    ("426F6F74-0000-11AA-AA11-00306543ECAC", "Apple Boot"),
+    ("53746F72-6167-11AA-AA11-00306543ECAC", "Apple Core Storage"),
+    ("6A898CC3-1DD2-11B2-99A6-080020736631", "ZFS"),
+    ("83BD6B9D-7F41-11DC-BE0B-001560B84F0F", "FreeBSD boot"),
+    ("516E7CB4-6ECF-11D6-8FF8-00022D09712B", "FreeBSD data"),
+    ("516E7CB5-6ECF-11D6-8FF8-00022D09712B", "FreeBSD swap"),
+    ("516E7CB6-6ECF-11D6-8FF8-00022D09712B", "FreeBSD UFS"),
+    ("516E7CBA-6ECF-11D6-8FF8-00022D09712B", "FreeBSD ZFS"),
+    ("FE3A2A5D-4F32-41A7-B725-ACCC3285A309", "ChromeOS kernel"),
+    ("3CB8E202-3B7E-47DD-8A3C-7FF2A13CFCEC", "ChromeOS root")
+];
+
+
+/// Get the name of a well-known GPT partition type GUID
+pub fn gpt_type_name(type_guid: &str) -> Option<&'static str> {
+    GPT_PARTITION_TYPES.iter()
+        .find(|(guid, _)| guid.eq_ignore_ascii_case(type_guid))
+        .map(|(_, name)| *name)
+}
+
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+
+fn guid_at(data: &[u8], offset: usize) -> String {
+    format_guid(data[offset..offset + 16].try_into().unwrap())
+}
+
+
+/// A GPT header (primary or backup)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    /// Does the header CRC32 match the header
+    pub header_crc_valid: bool,
+    /// The block of this header
+    pub current_lba: u64,
+    /// The block of the other header
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: String,
+    /// The first block of the partition entry array
+    pub entries_lba: u64,
+    pub entry_count: u32,
+    pub entry_size: u32,
+    pub entries_crc32: u32,
+    /// Does the entries CRC32 match the partition entry array
+    pub entries_crc_valid: bool
+}
+impl GptHeader {
+    /// Parse the header in the first block_size bytes of block. The
+    /// partition entry array is not checked (entries_crc_valid is false).
+    fn parse(block: &[u8]) -> Result<Self, TskError> {
+        if &block[0..8] != GPT_SIGNATURE {
+            return Err(TskError::tsk_vs_part_error(
+                "Invalid GPT header signature.".to_string()
+            ));
+        }
+        let header_size = u32_at(block, 12);
+        if header_size < GPT_MIN_HEADER_SIZE || header_size as usize > block.len() {
+            return Err(TskError::tsk_vs_part_error(
+                format!("Invalid GPT header size {}.", header_size)
+            ));
+        }
+        let header_crc32 = u32_at(block, 16);
+        let mut header = block[..header_size as usize].to_vec();
+        header[16..20].copy_from_slice(&[0; 4]);
+
+        Ok( Self {
+            revision: u32_at(block, 8),
+            header_size,
+            header_crc32,
+            header_crc_valid: crc32(&header) == header_crc32,
+            current_lba: u64_at(block, 24),
+            backup_lba: u64_at(block, 32),
+            first_usable_lba: u64_at(block, 40),
+            last_usable_lba: u64_at(block, 48),
+            disk_guid: guid_at(block, 56),
+            entries_lba: u64_at(block, 72),
+            entry_count: u32_at(block, 80),
+            entry_size: u32_at(block, 84),
+            entries_crc32: u32_at(block, 88),
+            entries_crc_valid: false
+        })
+    }
+
+    /// Are the header and partition entry array CRC32s valid
+    pub fn is_valid(&self) -> bool {
+        self.header_crc_valid && self.entries_crc_valid
+    }
+}
+
+
+/// An entry of the GPT partition entry array
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptPartitionEntry {
+    /// The index in the partition entry array
+    pub index: u32,
+    pub type_guid: String,
+    /// The unique partition GUID
+    pub unique_guid: String,
+    pub first_lba: u64,
+    /// The last block of the partition (inclusive)
+    pub last_lba: u64,
+    pub attributes: u64,
+    /// The partition name (UTF-16 on disk)
+    pub name: String
+}
+impl GptPartitionEntry {
+    /// Parse an entry, None if the entry is unused
+    fn parse(index: u32, entry: &[u8]) -> Option<Self> {
+        let type_guid = guid_at(entry, 0);
+        if type_guid == UNUSED_TYPE_GUID {
+            return None;
+        }
+        Some( Self {
+            index,
+            type_guid,
+            unique_guid: guid_at(entry, 16),
+            first_lba: u64_at(entry, 32),
+            last_lba: u64_at(entry, 40),
+            attributes: u64_at(entry, 48),
+            name: utf16le_to_string(&entry[56..128])
+        })
+    }
+
+    /// The name of a well-known partition type
+    pub fn type_name(&self) -> Option<&'static str> {
+        gpt_type_name(&self.type_guid)
+    }
+
+    /// The number of blocks of the partition
+    pub fn block_count(&self) -> u64 {
+        self.last_lba.saturating_add(1).saturating_sub(self.first_lba)
+    }
+
+    /// Attribute bit 0: the partition is required for the platform to function
+    pub fn is_required(&self) -> bool {
+        self.attributes & 1 != 0
+    }
+
+    /// Attribute bit 1: firmware must not produce a block IO protocol for the partition
+    pub fn no_block_io_protocol(&self) -> bool {
+        self.attributes & (1 << 1) != 0
+    }
+
+    /// Attribute bit 2: the partition is bootable by legacy BIOS
+    pub fn is_legacy_bios_bootable(&self) -> bool {
+        self.attributes & (1 << 2) != 0
+    }
+
+    /// Attribute bits 48-63, defined by the partition type (e.g. read-only
+    /// and hidden for Microsoft Basic Data)
+    pub fn type_attributes(&self) -> u16 {
+        (self.attributes >> 48) as u16
+    }
+}
+
+
+/// The details of a GUID partition table: both headers, the partition
+/// entries and the problems found comparing the headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptDetails {
+    pub block_size: u32,
+    /// The primary header, None if it is missing or unreadable
+    pub primary: Option<GptHeader>,
+    /// The backup header, None if it is missing or unreadable
+    pub backup: Option<GptHeader>,
+    /// The used partition entries of the primary table (or of the backup
+    /// table if the primary header is missing)
+    pub entries: Vec<GptPartitionEntry>,
+    /// Problems found reading or comparing the headers
+    pub issues: Vec<String>
+}
+impl GptDetails {
+    /// Read the GPT of the disk starting at offset in the reader
+    pub fn from_reader<R: Read + Seek>(reader: &mut R, offset: u64, block_size: u32) -> Result<Self, TskError> {
+        if block_size < 512 || !block_size.is_power_of_two() {
+            return Err(TskError::tsk_vs_part_error(
+                format!("Invalid GPT block size {}.", block_size)
+            ));
+        }
+        let disk_size = reader.seek(SeekFrom::End(0))
+            .map_err(|e| TskError::tsk_vs_part_error(format!("Error getting the size of the disk: {}", e)))?
+            .saturating_sub(offset);
+        let last_lba = (disk_size / block_size as u64).saturating_sub(1);
+
+        let mut issues = Vec::new();
+        let mut load = |name: &str, lba: u64, issues: &mut Vec<String>| {
+            match read_table(reader, offset, block_size, lba) {
+                Ok((header, entries)) => {
+                    if !header.header_crc_valid {
+                        issues.push(format!("The {} GPT header CRC32 does not match.", name));
+                    }
+                    if !header.entries_crc_valid {
+                        issues.push(format!("The {} GPT partition entries CRC32 does not match.", name));
+                    }
+                    if header.current_lba != lba {
+                        issues.push(format!("The {} GPT header at block {} claims to be at block {}.", name, lba, header.current_lba));
+                    }
+                    Some((header, entries))
+                },
+                Err(e) => {
+                    issues.push(format!("The {} GPT header at block {} is unreadable: {}", name, lba, e.message));
+                    None
+                }
+            }
+        };
+
+        let primary = load("primary", 1, &mut issues);
+        let backup_lba = match &primary {
+            Some((header, _)) => {
+                if header.backup_lba != last_lba {
+                    issues.push(format!(
+                        "The backup GPT header is at block {} instead of the last block {}.",
+                        header.backup_lba, last_lba
+                    ));
+                }
+                header.backup_lba
+            },
+            None => last_lba
+        };
+        let backup = load("backup", backup_lba, &mut issues);
+
+        let entries = match (&primary, &backup) {
+            (Some((_, entries)), _) | (None, Some((_, entries))) => entries.clone(),
+            (None, None) => return Err(TskError::tsk_vs_part_error(
+                format!("No GPT found at offset {}: {}", offset, issues.join(" "))
+            ))
+        };
+
+        if let (Some((primary, primary_entries)), Some((backup, backup_entries))) = (&primary, &backup) {
+            compare_headers(primary, backup, &mut issues);
+            if primary_entries != backup_entries {
+                issues.push("The primary and backup GPT partition entries differ.".to_string());
+            }
+        }
+
+        Ok( Self {
+            block_size,
+            primary: primary.map(|(header, _)| header),
+            backup: backup.map(|(header, _)| header),
+            entries,
+            issues
+        })
+    }
+
+    /// The disk GUID of the primary (or backup) header
+    pub fn disk_guid(&self) -> &str {
+        self.primary.as_ref()
+            .or(self.backup.as_ref())
+            .map(|h| h.disk_guid.as_str())
+            .unwrap_or(UNUSED_TYPE_GUID)
+    }
+
+    /// Are both headers present, valid and consistent with each other
+    pub fn is_consistent(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Get the partition entry of a partition libtsk found in the table
+    pub fn entry_for(&self, tsk_vs_part: &TskVsPart) -> Option<&GptPartitionEntry> {
+        if !tsk_vs_part.is_allocated() {
+            return None;
+        }
+        self.entries.iter()
+            .find(|e| e.first_lba == tsk_vs_part.start() && e.block_count() == tsk_vs_part.len())
+    }
+}
+
+
+/// Read the header at lba and its partition entries
+fn read_table<R: Read + Seek>(reader: &mut R, offset: u64, block_size: u32, lba: u64) -> Result<(GptHeader, Vec<GptPartitionEntry>), TskError> {
+    let io_error = |e: std::io::Error| TskError::tsk_vs_part_error(format!("{}", e));
+    let block_offset = |lba: u64| lba.checked_mul(block_size as u64)
+        .and_then(|o| o.checked_add(offset))
+        .ok_or_else(|| TskError::tsk_vs_part_error(format!("Block {} is out of range.", lba)));
+
+    let mut block = vec![0u8; block_size as usize];
+    read_exact_at(reader, block_offset(lba)?, &mut block).map_err(io_error)?;
+    let mut header = GptHeader::parse(&block)?;
+
+    if header.entry_size < GPT_MIN_ENTRY_SIZE || !header.entry_size.is_multiple_of(8) {
+        return Err(TskError::tsk_vs_part_error(
+            format!("Invalid GPT partition entry size {}.", header.entry_size)
+        ));
+    }
+    let entries_size = header.entry_count as u64 * header.entry_size as u64;
+    if entries_size > GPT_MAX_ENTRIES_SIZE {
+        return Err(TskError::tsk_vs_part_error(
+            format!("GPT partition entry array of {} bytes is too large.", entries_size)
+        ));
+    }
+
+    let mut data = vec![0u8; entries_size as usize];
+    read_exact_at(reader, block_offset(header.entries_lba)?, &mut data).map_err(io_error)?;
+    header.entries_crc_valid = crc32(&data) == header.entries_crc32;
+
+    let entries = data.chunks_exact(header.entry_size as usize)
+        .enumerate()
+        .filter_map(|(index, entry)| GptPartitionEntry::parse(index as u32, entry))
+        .collect();
+    Ok((header, entries))
+}
+
+
+/// Record the differences between the primary and backup header
+fn compare_headers(primary: &GptHeader, backup: &GptHeader, issues: &mut Vec<String>) {
+    let mut differs = |field: &str, primary: String, backup: String| {
+        if primary != backup {
+            issues.push(format!("The primary GPT header has {} {} but the backup header has {}.", field, primary, backup));
+        }
+    };
+    differs("disk GUID", primary.disk_guid.clone(), backup.disk_guid.clone());
+    differs("first usable block", primary.first_usable_lba.to_string(), backup.first_usable_lba.to_string());
+    differs("last usable block", primary.last_usable_lba.to_string(), backup.last_usable_lba.to_string());
+    differs("entry count", primary.entry_count.to_string(), backup.entry_count.to_string());
+    differs("entry size", primary.entry_size.to_string(), backup.entry_size.to_string());
+    differs("entries CRC32", format!("{:08x}", primary.entries_crc32), format!("{:08x}", backup.entries_crc32));
+    if backup.backup_lba != primary.current_lba {
+        issues.push(format!(
+            "The backup GPT header points to block {} instead of the primary header at block {}.",
+            backup.backup_lba, primary.current_lba
+        ));
+    }
+}
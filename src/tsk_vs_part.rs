@@ -1,4 +1,5 @@
 use std::ffi::CStr;
+use std::ptr::NonNull;
 use crate::{
     errors::TskError,
     tsk_vs::TskVs,
@@ -7,6 +8,52 @@ use crate::{
 };
 
 
+/// Flags of a partition entry, mirroring TSK_VS_PART_FLAG_ENUM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TskVsPartFlag {
+    /// A partition in the partition table
+    Alloc,
+    /// Unallocated space between or after the partitions
+    Unalloc,
+    /// A table or other volume system structure (e.g. the primary table)
+    Meta
+}
+impl TskVsPartFlag {
+    /// All partition flags
+    pub const ALL: [TskVsPartFlag; 3] = [
+        TskVsPartFlag::Alloc,
+        TskVsPartFlag::Unalloc,
+        TskVsPartFlag::Meta
+    ];
+
+    /// Combine flags into a TSK_VS_PART_FLAG_ENUM mask. Like the flags of
+    /// tsk_vs_part_walk, no flags select all partitions.
+    pub fn to_mask(flags: &[TskVsPartFlag]) -> tsk::TSK_VS_PART_FLAG_ENUM {
+        match flags.iter().fold(0, |mask, f| mask | tsk::TSK_VS_PART_FLAG_ENUM::from(*f)) {
+            0 => tsk::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_ALL,
+            mask => mask
+        }
+    }
+
+    /// Split a TSK_VS_PART_FLAG_ENUM mask into flags
+    pub fn from_mask(mask: tsk::TSK_VS_PART_FLAG_ENUM) -> Vec<TskVsPartFlag> {
+        Self::ALL.iter()
+            .filter(|f| mask & tsk::TSK_VS_PART_FLAG_ENUM::from(**f) > 0)
+            .copied()
+            .collect()
+    }
+}
+impl From<TskVsPartFlag> for tsk::TSK_VS_PART_FLAG_ENUM {
+    fn from(flag: TskVsPartFlag) -> Self {
+        match flag {
+            TskVsPartFlag::Alloc => tsk::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_ALLOC,
+            TskVsPartFlag::Unalloc => tsk::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_UNALLOC,
+            TskVsPartFlag::Meta => tsk::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_META
+        }
+    }
+}
+
+
 /// Wrapper for TSK_VS_PART_INFO.
 /// The TskVs reference must live for the lifetime of
 /// *const tsk::TSK_VS_PART_INFO.
@@ -15,15 +62,37 @@ pub struct TskVsPart<'vs> {
     tsk_part_info: *const tsk::TSK_VS_PART_INFO
 }
 impl<'vs> TskVsPart<'vs> {
-    /// Create a TSK_VS_PART_INFO wrapper given the TskImg and offset of the file system
-    pub fn new(tsk_vs: &'vs TskVs, offset: u64) -> Result<Self, TskError> {
+    /// Create a TSK_VS_PART_INFO wrapper given the TskVs and index of the partition
+    pub fn new(tsk_vs: &'vs TskVs, index: u64) -> Result<Self, TskError> {
+        let part_count = tsk_vs.partition_count();
+        if index >= part_count as u64 {
+            return Err(TskError::tsk_vs_part_error(
+                format!("Partition index {} is out of range, the volume system has {} partitions.", index, part_count)
+            ));
+        }
+
         // Get a pointer to the TSK_VS_PART_INFO sturct
-        // TODO: HANDLE NULL!
         let tsk_vs_part = unsafe {tsk::tsk_vs_part_get(
             tsk_vs.handle.as_ptr(),
-            offset as _
+            index as _
         )};
 
+        if tsk_vs_part.is_null() {
+            // Get a ptr to the error msg
+            let error_msg_ptr = unsafe { NonNull::new(tsk::tsk_error_get() as _) }
+                .ok_or(
+                    TskError::tsk_vs_part_error(
+                        format!("There was an error getting the partition at index {}. (no context)", index)
+                    )
+                )?;
+            // Get the error message from the string
+            let error_msg = unsafe { CStr::from_ptr(error_msg_ptr.as_ptr()) }.to_string_lossy();
+            // Return an error which includes the TSK error message
+            return Err(TskError::tsk_vs_part_error(
+                format!("There was an error getting the partition at index {}: {}", index, error_msg)
+            ));
+        }
+
         Ok( Self{
             tsk_vs: tsk_vs,
             tsk_part_info: tsk_vs_part
@@ -40,6 +109,11 @@ impl<'vs> TskVsPart<'vs> {
         unsafe{*(self.tsk_part_info)}.start * unsafe{*(*self.tsk_part_info).vs}.block_size as u64
     }
 
+    /// Get the start in blocks of this partition, relative to the volume system
+    pub fn start(&self) -> u64 {
+        unsafe {*self.tsk_part_info}.start
+    }
+
     /// Get the len in blocks of this partition
     pub fn len(&self) -> u64 {
         unsafe {*self.tsk_part_info}.len
@@ -51,20 +125,52 @@ impl<'vs> TskVsPart<'vs> {
         unsafe {(*(*self.tsk_part_info).vs).block_size} as u64
     }
 
+    /// Get the flags of the partition
+    pub fn flags(&self) -> Vec<TskVsPartFlag> {
+        TskVsPartFlag::from_mask(unsafe {*self.tsk_part_info}.flags)
+    }
+
+    /// Is this a partition of the partition table
+    pub fn is_allocated(&self) -> bool {
+        self.has_flag(TskVsPartFlag::Alloc)
+    }
+
+    /// Is this unallocated space not covered by a partition
+    pub fn is_unallocated(&self) -> bool {
+        self.has_flag(TskVsPartFlag::Unalloc)
+    }
+
+    /// Is this a volume system structure such as a partition table
+    pub fn is_meta(&self) -> bool {
+        self.has_flag(TskVsPartFlag::Meta)
+    }
+
+    fn has_flag(&self, flag: TskVsPartFlag) -> bool {
+        unsafe {*self.tsk_part_info}.flags & tsk::TSK_VS_PART_FLAG_ENUM::from(flag) > 0
+    }
+
     /// Get a IO handle to the partition
     pub fn get_handle<'p>(&'p self) -> TskVsPartHandle<'vs, 'p> {
         TskVsPartHandle::new(&self)
     }
 
-    /// Get the description string
+    /// Get the description string (empty if the partition has none)
     pub fn desc(&self) -> String {
-        let desc = unsafe { CStr::from_ptr((*self.tsk_part_info).desc) }.to_string_lossy();
+        let desc_ptr = unsafe { (*self.tsk_part_info).desc };
+        if desc_ptr.is_null() {
+            return String::new();
+        }
+        let desc = unsafe { CStr::from_ptr(desc_ptr) }.to_string_lossy();
         desc.to_string().clone()
     }
 
     /// Get an iterator based off this TskVsPart struct
     pub fn into_iter(self) -> TskVsPartIterator<'vs> {
-        TskVsPartIterator(self)
+        TskVsPartIterator {
+            remaining: self.tsk_vs.partition_count(),
+            current: self,
+            flags: tsk::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_ALL
+        }
     }
 }
 impl<'vs> Into<*const tsk::TSK_VS_PART_INFO> for &TskVsPart<'vs> {
@@ -82,7 +188,7 @@ impl<'vs> std::fmt::Debug for TskVsPart<'vs> {
         f.debug_struct("TskVsPart")
          .field("addr", &(unsafe{*self.tsk_part_info}.addr))
          .field("desc", &self.desc())
-         .field("flags", &(unsafe{*self.tsk_part_info}.flags))
+         .field("flags", &self.flags())
          .field("len", &(unsafe{*self.tsk_part_info}.len))
          .field("slot_num", &(unsafe{*self.tsk_part_info}.slot_num))
          .field("start", &(unsafe{*self.tsk_part_info}.	start))
@@ -94,35 +200,67 @@ impl<'vs> std::fmt::Debug for TskVsPart<'vs> {
 
 
 /// An iterator over a TSK_VS_PART_INFO pointer which uses the
-/// structs next attribute to iterate.
-pub struct TskVsPartIterator<'vs>(TskVsPart<'vs>);
+/// structs next attribute to iterate. By default it yields all entries,
+/// use with_flags to select allocated, unallocated or meta entries.
+/// It never follows more links than the volume system has partitions.
+pub struct TskVsPartIterator<'vs> {
+    current: TskVsPart<'vs>,
+    /// The TSK_VS_PART_FLAG_ENUM mask of entries to yield
+    flags: tsk::TSK_VS_PART_FLAG_ENUM,
+    /// The number of list entries left to visit
+    remaining: u32
+}
+impl<'vs> TskVsPartIterator<'vs> {
+    /// Create an iterator over all partitions of a TskVs, starting at the
+    /// head of its partition list. An empty volume system yields nothing.
+    pub(crate) fn from_tsk_vs(tsk_vs: &'vs TskVs) -> Self {
+        let part_list = unsafe { tsk_vs.handle.as_ref() }.part_list;
+        TskVsPart {
+            tsk_vs,
+            tsk_part_info: part_list as *const tsk::TSK_VS_PART_INFO
+        }.into_iter()
+    }
+
+    /// Only yield entries with any of the given flags, like the flags of
+    /// tsk_vs_part_walk. No flags select all entries.
+    pub fn with_flags(mut self, flags: &[TskVsPartFlag]) -> Self {
+        self.flags = TskVsPartFlag::to_mask(flags);
+        self
+    }
+}
 impl< 'vs> Iterator for TskVsPartIterator< 'vs> {
     type Item = TskVsPart<'vs>;
     
     fn next(&mut self) -> Option<TskVsPart<'vs>> {
-        // Check that the partition is not null
-        if self.0.tsk_part_info.is_null() {
-            return None;
-        }
+        loop {
+            // Check that the partition is not null and that the list is
+            // not longer than the partition count
+            if self.current.tsk_part_info.is_null() || self.remaining == 0 {
+                return None;
+            }
+            self.remaining -= 1;
 
-        // Get current pointer
-        let current = self.0.tsk_part_info;
-
-        // Get the next pointer
-        let next = unsafe {
-            *self.0.tsk_part_info
-        }.next as *const tsk::TSK_VS_PART_INFO;
-
-        // Create a TskVsPartion that represents the current node
-        let cur3nt_vs_part = TskVsPart{
-            tsk_vs: self.0.tsk_vs,
-            tsk_part_info: current
-        };
-
-        // Set the iterators pointer to the next node
-        self.0.tsk_part_info = next;
-        
-        // Return current partition
-        Some(cur3nt_vs_part)
+            // Get current pointer
+            let current = self.current.tsk_part_info;
+
+            // Get the next pointer
+            let next = unsafe {
+                *self.current.tsk_part_info
+            }.next as *const tsk::TSK_VS_PART_INFO;
+
+            // Set the iterators pointer to the next node
+            self.current.tsk_part_info = next;
+
+            // Skip partitions that are not selected
+            if unsafe {*current}.flags & self.flags == 0 {
+                continue;
+            }
+
+            // Return a TskVsPartion that represents the current node
+            return Some(TskVsPart{
+                tsk_vs: self.current.tsk_vs,
+                tsk_part_info: current
+            });
+        }
     }
 }
@@ -0,0 +1,47 @@
+//! Fixtures shared by the integration tests
+#![allow(dead_code)]
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+
+/// A directory in the temp dir for the fixtures of a test. Fixtures left
+/// behind by an earlier run are removed when it is created, and the
+/// directory is removed when it is dropped, also when the test panics.
+pub struct TestDir(PathBuf);
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("libtsk-rs-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("Error removing stale test dir.");
+        }
+        fs::create_dir_all(&dir).expect("Error creating test dir.");
+        TestDir(dir)
+    }
+}
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+
+/// Image content that does not repeat within the images of the tests, so
+/// that reads of the wrong offset do not go unnoticed. Images of the same
+/// test use different seeds.
+pub fn pattern(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(11) ^ seed ^ (i / 512) as u8 ^ (i / 7919) as u8).collect()
+}
@@ -0,0 +1,252 @@
+mod common;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use flate2::{Compression, Crc};
+use flate2::write::DeflateEncoder;
+use tsk::img_aff4::{Aff4Hash, Aff4Reader, Aff4Volume};
+use common::{TestDir, pattern};
+
+
+const CHUNK_SIZE: usize = 4096;
+const CHUNKS_IN_SEGMENT: usize = 4;
+const STREAM_CHUNKS: usize = 7;
+const MAP_SIZE: usize = 9 * CHUNK_SIZE;
+
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+
+/// Write a zip file of (name, data, deflate) members
+fn write_zip(members: &[(String, Vec<u8>, bool)]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data, compress) in members {
+        let stored = if *compress { deflate(data) } else { data.clone() };
+        let mut crc = Crc::new();
+        crc.update(data);
+        let method: u16 = if *compress { 8 } else { 0 };
+
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&0u32.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&common);
+        directory.extend_from_slice(&[0u8; 6]);
+        directory.extend_from_slice(&0u32.to_le_bytes());
+        directory.extend_from_slice(&(zip.len() as u32).to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+
+        zip.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        zip.extend_from_slice(&common);
+        zip.extend_from_slice(name.as_bytes());
+        zip.extend_from_slice(&stored);
+    }
+
+    let directory_offset = zip.len() as u32;
+    zip.extend_from_slice(&directory);
+    zip.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    zip.extend_from_slice(&[0u8; 4]);
+    zip.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&directory_offset.to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+    zip
+}
+
+
+/// A snappy block of data that repeats every 16 bytes
+fn snappy_repeating(data: &[u8]) -> Vec<u8> {
+    let mut block = vec![0x80, 0x20];
+    block.push(15 << 2);
+    block.extend_from_slice(&data[..16]);
+    let mut remaining = data.len() - 16;
+    while remaining > 0 {
+        let length = remaining.min(64);
+        block.push(((length - 1) << 2 | 2) as u8);
+        block.extend_from_slice(&16u16.to_le_bytes());
+        remaining -= length;
+    }
+    block
+}
+
+
+/// An lz4 block of data that repeats every 16 bytes
+fn lz4_repeating(data: &[u8]) -> Vec<u8> {
+    let match_length = data.len() - 16 - 8;
+    let mut block = vec![0xff, 1];
+    block.extend_from_slice(&data[..16]);
+    block.extend_from_slice(&16u16.to_le_bytes());
+    let mut extra = match_length - 4 - 15;
+    while extra >= 255 {
+        block.push(255);
+        extra -= 255;
+    }
+    block.push(extra as u8);
+    block.push(0x80);
+    block.extend_from_slice(&data[data.len() - 8..]);
+    block
+}
+
+
+/// Write an AFF4 volume with a disk image read through a map of a deflate
+/// ImageStream and a symbolic stream, and two single chunk streams.
+/// Returns the stream data and the expected image.
+fn write_volume(path: &PathBuf) -> (Vec<u8>, Vec<u8>) {
+    let stream = pattern(1, STREAM_CHUNKS * CHUNK_SIZE);
+    let mut members = vec![("container.description".to_string(), b"aff4://volume-1".to_vec(), false)];
+
+    for bevy in 0..STREAM_CHUNKS.div_ceil(CHUNKS_IN_SEGMENT) {
+        let mut data = Vec::new();
+        let mut index = Vec::new();
+        for chunk in bevy * CHUNKS_IN_SEGMENT..((bevy + 1) * CHUNKS_IN_SEGMENT).min(STREAM_CHUNKS) {
+            let raw = &stream[chunk * CHUNK_SIZE..(chunk + 1) * CHUNK_SIZE];
+            // Chunk 2 is stored without compression
+            let stored = if chunk == 2 { raw.to_vec() } else { deflate(raw) };
+            index.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            index.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            data.extend_from_slice(&stored);
+        }
+        members.push((format!("aff4%3A%2F%2Fstream-1/{:08}", bevy), data, false));
+        members.push((format!("aff4%3A%2F%2Fstream-1/{:08}.index", bevy), index, true));
+    }
+
+    // Map: stream 0..16K, UnknownData, stream 16K..28K, then a gap
+    let mut image = Vec::new();
+    image.extend_from_slice(&stream[..4 * CHUNK_SIZE]);
+    image.extend(b"UNKNOWN".iter().cycle().skip(4 * CHUNK_SIZE % 7).take(CHUNK_SIZE));
+    image.extend_from_slice(&stream[4 * CHUNK_SIZE..]);
+    image.resize(MAP_SIZE, 0);
+    let mut map = Vec::new();
+    for (mapped, length, target_offset, target) in [
+        (5 * CHUNK_SIZE, 3 * CHUNK_SIZE, 4 * CHUNK_SIZE, 0u32),
+        (0, 4 * CHUNK_SIZE, 0, 0),
+        (4 * CHUNK_SIZE, CHUNK_SIZE, 4 * CHUNK_SIZE, 1)
+    ].iter() {
+        map.extend_from_slice(&(*mapped as u64).to_le_bytes());
+        map.extend_from_slice(&(*length as u64).to_le_bytes());
+        map.extend_from_slice(&(*target_offset as u64).to_le_bytes());
+        map.extend_from_slice(&target.to_le_bytes());
+    }
+    members.push(("disk-map/map".to_string(), map, true));
+    members.push(("disk-map/idx".to_string(), b"aff4://stream-1\nhttp://aff4.org/Schema#UnknownData\n".to_vec(), false));
+
+    let small = pattern(2, 16).repeat(CHUNK_SIZE / 16);
+    let mut index = 0u64.to_le_bytes().to_vec();
+    let snappy = snappy_repeating(&small);
+    index.extend_from_slice(&(snappy.len() as u32).to_le_bytes());
+    members.push(("aff4%3A%2F%2Fsnappy-1/00000000".to_string(), snappy, false));
+    members.push(("aff4%3A%2F%2Fsnappy-1/00000000.index".to_string(), index, false));
+    let mut index = 0u64.to_le_bytes().to_vec();
+    let lz4 = lz4_repeating(&small);
+    index.extend_from_slice(&(lz4.len() as u32).to_le_bytes());
+    members.push(("aff4%3A%2F%2Flz4-1/00000000".to_string(), lz4, false));
+    members.push(("aff4%3A%2F%2Flz4-1/00000000.index".to_string(), index, false));
+
+    let turtle = format!(r#"@prefix aff4: <http://aff4.org/Schema#> .
+@prefix xsd: <http://www.w3.org/2001/XMLSchema#> .
+@prefix rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#> .
+
+# The disk image
+<aff4://image-1>
+    a aff4:DiskImage, aff4:Image ;
+    aff4:dataStream <aff4://volume-1/disk-map> ;
+    aff4:size "{map_size}"^^xsd:long ;
+    aff4:hash "D41D8CD98F00B204E9800998ECF8427E"^^aff4:MD5,
+        "da39a3ee5e6b4b0d3255bfef95601890afd80709"^^aff4:SHA1 ;
+    aff4:model "Example \"SSD\"" ;
+    aff4:acquisitionCompletionState aff4:Completed ;
+    aff4:timestamps [ aff4:startTime "2020-01-01T00:00:00Z"^^xsd:dateTime ] .
+
+<aff4://volume-1/disk-map>
+    a aff4:Map ;
+    aff4:size "{map_size}"^^xsd:long ;
+    aff4:target <aff4://image-1> ;
+    aff4:dependentStream <aff4://stream-1> .
+
+<aff4://stream-1>
+    a aff4:ImageStream ;
+    aff4:size {stream_size} ;
+    aff4:chunkSize "{chunk_size}"^^xsd:int ;
+    aff4:chunksInSegment "{chunks}"^^xsd:int ;
+    aff4:compressionMethod <https://www.ietf.org/rfc/rfc1951.txt> ;
+    aff4:stored <aff4://volume-1> .
+
+<aff4://snappy-1> a aff4:ImageStream ; aff4:size {chunk_size} ; aff4:chunkSize {chunk_size} ;
+    aff4:chunksInSegment {chunks} ; aff4:compressionMethod <http://code.google.com/p/snappy/> .
+<aff4://lz4-1> a aff4:ImageStream ; aff4:size {chunk_size} ; aff4:chunkSize {chunk_size} ;
+    aff4:chunksInSegment {chunks} ; aff4:compressionMethod <https://code.google.com/p/lz4/> .
+"#,
+        map_size = MAP_SIZE,
+        stream_size = stream.len(),
+        chunk_size = CHUNK_SIZE,
+        chunks = CHUNKS_IN_SEGMENT
+    );
+    members.push(("information.turtle".to_string(), turtle.into_bytes(), true));
+
+    fs::write(path, write_zip(&members)).unwrap();
+    (small, image)
+}
+
+
+#[test]
+fn test_aff4_volume() {
+    let dir = TestDir::new("aff4_volume");
+    let path = dir.join("disk.aff4");
+    let (small, image) = write_volume(&path);
+
+    let volume = Aff4Volume::open(&path).unwrap();
+    assert_eq!(volume.volume_urn(), "aff4://volume-1");
+    let images = volume.images();
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].urn, "aff4://image-1");
+    assert!(images[0].is_a("DiskImage"));
+    assert_eq!(images[0].size(), Some(MAP_SIZE as u64));
+    assert_eq!(images[0].value("model"), Some("Example \"SSD\""));
+    assert_eq!(images[0].hashes(), vec![
+        Aff4Hash { algorithm: "MD5".to_string(), value: "d41d8cd98f00b204e9800998ecf8427e".to_string() },
+        Aff4Hash { algorithm: "SHA1".to_string(), value: "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string() }
+    ]);
+    let timestamps = images[0].value("timestamps").unwrap();
+    assert_eq!(volume.object(timestamps).unwrap().value("startTime"), Some("2020-01-01T00:00:00Z"));
+
+    // The image through its map
+    let mut reader = Aff4Reader::open(&path).unwrap();
+    assert_eq!(reader.urn(), "aff4://image-1");
+    assert_eq!(reader.size(), MAP_SIZE as u64);
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    assert!(contents == image);
+
+    reader.seek(SeekFrom::Start(3 * CHUNK_SIZE as u64 + 100)).unwrap();
+    let mut buf = vec![0u8; 3 * CHUNK_SIZE];
+    reader.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == image[3 * CHUNK_SIZE + 100..6 * CHUNK_SIZE + 100]);
+
+    // The snappy and lz4 streams
+    for urn in &["aff4://snappy-1", "aff4://lz4-1"] {
+        let mut reader = volume.open_stream(urn).unwrap();
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).unwrap();
+        assert!(contents == small, "{}", urn);
+    }
+
+    assert!(volume.open_stream("aff4://missing").is_err());
+    fs::write(&path, b"not a zip").unwrap();
+    assert!(Aff4Volume::open(&path).is_err());
+}
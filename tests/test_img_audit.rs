@@ -0,0 +1,75 @@
+use std::io::Cursor;
+use tsk::img_audit::{AuditRange, AuditTrail, HashAlgorithm, ImageHash};
+
+
+#[test]
+fn test_audit_trail_coalescing() {
+    let mut trail = AuditTrail::new("disk.raw", 8192);
+    assert!(trail.is_empty());
+
+    trail.record(1024, 512);
+    trail.record(0, 512);
+    // Adjacent to the first range
+    trail.record(512, 512);
+    // Repeated read
+    trail.record(0, 1024);
+    trail.record(4096, 512);
+    // Ignored
+    trail.record(6000, 0);
+
+    assert_eq!(trail.ranges(), &[
+        AuditRange { offset: 0, length: 1536 },
+        AuditRange { offset: 4096, length: 512 }
+    ]);
+    assert_eq!(trail.read_count(), 5);
+    assert_eq!(trail.bytes_read(), 3072);
+    assert_eq!(trail.covered_bytes(), 2048);
+
+    // Bridges both ranges
+    trail.record(1000, 4000);
+    assert_eq!(trail.ranges(), &[AuditRange { offset: 0, length: 5000 }]);
+
+    trail.clear();
+    assert!(trail.is_empty());
+    assert_eq!(trail.read_count(), 0);
+}
+
+
+#[test]
+fn test_image_hash() {
+    let data = b"abc".to_vec();
+    let md5 = ImageHash::compute(&mut Cursor::new(data.clone()), HashAlgorithm::Md5).unwrap();
+    assert_eq!(md5.value, "900150983cd24fb0d6963f7d28e17f72");
+    let sha1 = ImageHash::compute(&mut Cursor::new(data.clone()), HashAlgorithm::Sha1).unwrap();
+    assert_eq!(sha1.value, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    let sha256 = ImageHash::compute(&mut Cursor::new(data), HashAlgorithm::Sha256).unwrap();
+    assert_eq!(sha256.value, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(sha256.algorithm.to_string(), "sha256");
+}
+
+
+#[test]
+fn test_audit_trail_export() {
+    let mut trail = AuditTrail::new("C:\\cases\\\"disk\", 1.raw", 4096);
+    trail.record(0, 512);
+    trail.record(2048, 1024);
+    let hash = ImageHash { algorithm: HashAlgorithm::Sha1, value: "0123abcd".to_string() };
+
+    let json = trail.to_json(Some(&hash));
+    println!("{}", json);
+    assert!(json.contains("\"source\": \"C:\\\\cases\\\\\\\"disk\\\", 1.raw\""));
+    assert!(json.contains("\"image_size\": 4096"));
+    assert!(json.contains("\"covered_bytes\": 1536"));
+    assert!(json.contains("\"hash\": {\"algorithm\": \"sha1\", \"value\": \"0123abcd\"}"));
+    assert!(json.contains("{\"offset\": 0, \"length\": 512},\n    {\"offset\": 2048, \"length\": 1024}"));
+    assert!(AuditTrail::new("empty", 0).to_json(None).contains("\"hash\": null"));
+
+    let csv = trail.to_csv(Some(&hash));
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines, vec![
+        "source,image_size,hash_algorithm,hash,offset,length",
+        "\"C:\\cases\\\"\"disk\"\", 1.raw\",4096,sha1,0123abcd,0,512",
+        "\"C:\\cases\\\"\"disk\"\", 1.raw\",4096,sha1,0123abcd,2048,1024"
+    ]);
+    assert!(trail.to_csv(None).ends_with(",4096,,,2048,1024\n"));
+}
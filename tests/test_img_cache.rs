@@ -0,0 +1,141 @@
+mod common;
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use tsk::img_cache::{BlockCache, CacheConfig};
+use common::pattern;
+
+
+const BLOCK_SIZE: usize = 4096;
+
+
+/// A reader that records the (offset, length) of every read
+struct RecordingReader {
+    inner: Cursor<Vec<u8>>,
+    reads: Arc<Mutex<Vec<(u64, usize)>>>
+}
+impl Read for RecordingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let offset = self.inner.position();
+        let bytes_read = self.inner.read(buf)?;
+        self.reads.lock().unwrap().push((offset, bytes_read));
+        Ok(bytes_read)
+    }
+}
+impl Seek for RecordingReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// A reader that fails every read overlapping a bad block
+struct BadBlockReader {
+    inner: Cursor<Vec<u8>>,
+    bad_block: u64
+}
+impl Read for BadBlockReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = self.inner.position();
+        let end = start + buf.len() as u64;
+        let bad_start = self.bad_block * BLOCK_SIZE as u64;
+        if start < bad_start + BLOCK_SIZE as u64 && end > bad_start {
+            return Err(std::io::Error::other("bad block"));
+        }
+        self.inner.read(buf)
+    }
+}
+impl Seek for BadBlockReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+
+#[test]
+fn test_block_cache() {
+    // The last block is short
+    let data = pattern(0, 10 * BLOCK_SIZE + 100);
+    let reads = Arc::new(Mutex::new(Vec::new()));
+    let source = RecordingReader { inner: Cursor::new(data.clone()), reads: reads.clone() };
+    let config = CacheConfig {
+        cache_size: 4 * BLOCK_SIZE,
+        block_size: BLOCK_SIZE,
+        read_ahead: 1
+    };
+    let mut cache = BlockCache::new(Box::new(source), data.len() as u64, config).unwrap();
+    assert_eq!(cache.config(), config);
+
+    // A miss reads the block and the one after it in one read
+    let mut buf = vec![0u8; 512];
+    cache.seek(SeekFrom::Start(512)).unwrap();
+    cache.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == data[512..1024]);
+    assert_eq!(*reads.lock().unwrap(), vec![(0, 2 * BLOCK_SIZE)]);
+
+    // Repeated and read ahead blocks are hits
+    cache.seek(SeekFrom::Start(0)).unwrap();
+    cache.read_exact(&mut buf).unwrap();
+    cache.seek(SeekFrom::Start(BLOCK_SIZE as u64 + 10)).unwrap();
+    cache.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == data[BLOCK_SIZE + 10..BLOCK_SIZE + 522]);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.read_ahead_blocks), (2, 1, 1));
+    assert_eq!(stats.source_reads, 1);
+    assert_eq!(stats.source_bytes, 2 * BLOCK_SIZE as u64);
+
+    // A read spanning blocks, up to the short last block
+    cache.seek(SeekFrom::Start(8 * BLOCK_SIZE as u64 - 50)).unwrap();
+    let mut contents = Vec::new();
+    cache.read_to_end(&mut contents).unwrap();
+    assert!(contents[..] == data[8 * BLOCK_SIZE - 50..]);
+
+    // Blocks 0 and 1 were evicted to make room for 7 through 10
+    let stats = cache.stats();
+    assert_eq!(stats.evictions, 2);
+    reads.lock().unwrap().clear();
+    cache.seek(SeekFrom::Start(0)).unwrap();
+    cache.read_exact(&mut buf).unwrap();
+    assert_eq!(reads.lock().unwrap().len(), 1);
+    assert_eq!(cache.stats().misses, 4);
+    assert!(cache.stats().hit_ratio() > 0.5);
+
+    // A full read through the cache matches the source
+    cache.seek(SeekFrom::Start(0)).unwrap();
+    let mut contents = Vec::new();
+    cache.read_to_end(&mut contents).unwrap();
+    assert!(contents == data);
+
+    assert!(cache.seek(SeekFrom::Current(-1_000_000)).is_err());
+    assert!(BlockCache::new(Box::new(Cursor::new(data)), 100, CacheConfig {
+        cache_size: 100,
+        block_size: BLOCK_SIZE,
+        read_ahead: 0
+    }).is_err());
+}
+
+
+#[test]
+fn test_block_cache_read_ahead_error() {
+    let data = pattern(1, 4 * BLOCK_SIZE);
+    let source = BadBlockReader { inner: Cursor::new(data.clone()), bad_block: 2 };
+    let config = CacheConfig {
+        cache_size: 4 * BLOCK_SIZE,
+        block_size: BLOCK_SIZE,
+        read_ahead: 2
+    };
+    let mut cache = BlockCache::new(Box::new(source), data.len() as u64, config).unwrap();
+
+    // The read ahead into the bad block fails, the requested block does not
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    cache.seek(SeekFrom::Start(BLOCK_SIZE as u64)).unwrap();
+    cache.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == data[BLOCK_SIZE..2 * BLOCK_SIZE]);
+    assert_eq!(cache.stats().read_ahead_blocks, 0);
+
+    // Only a read of the bad block itself fails
+    cache.seek(SeekFrom::Start(2 * BLOCK_SIZE as u64)).unwrap();
+    assert!(cache.read_exact(&mut buf).is_err());
+    cache.seek(SeekFrom::Start(3 * BLOCK_SIZE as u64)).unwrap();
+    cache.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == data[3 * BLOCK_SIZE..]);
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tsk::img_compressed::{CompressedFormat, CompressedReader, GzipIndex};
+use common::{TestDir, pattern};
+
+
+/// Read ranges spread over the image, going backwards and forwards
+fn check_random_reads(reader: &mut CompressedReader, expected: &[u8]) {
+    let len = expected.len() as u64;
+    let offsets = [len - 1000, 0, len / 2, 17, len / 3 + 5, len / 2 - 4096, len - 1];
+    for offset in offsets.iter() {
+        let wanted = (len - offset).min(10_000) as usize;
+        let mut buf = vec![0u8; wanted];
+        reader.seek(SeekFrom::Start(*offset)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert!(buf[..] == expected[*offset as usize..*offset as usize + wanted], "read at {}", offset);
+    }
+
+    let mut buf = [0u8; 16];
+    reader.seek(SeekFrom::End(0)).unwrap();
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+
+#[test]
+fn test_gzip_reader() {
+    let dir = TestDir::new("gzip_reader");
+    let first = pattern(1, 700_000);
+    let second = pattern(2, 500_000);
+    let mut expected = first.clone();
+    expected.extend_from_slice(&second);
+
+    // Two members, the second one stored without compression
+    let mut data = Vec::new();
+    for (member, level) in [(&first, Compression::default()), (&second, Compression::none())].iter() {
+        let mut encoder = GzEncoder::new(Vec::new(), *level);
+        encoder.write_all(member).unwrap();
+        data.extend_from_slice(&encoder.finish().unwrap());
+    }
+    let path = dir.join("disk.raw.gz");
+    fs::write(&path, &data).unwrap();
+
+    let mut reader = CompressedReader::open(&path).unwrap();
+    assert_eq!(reader.format(), CompressedFormat::Gzip);
+    assert_eq!(reader.uncompressed_size(), expected.len() as u64);
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    assert!(contents == expected);
+
+    // A small span gives many access points
+    let index = GzipIndex::build(&path, 64 * 1024).unwrap();
+    assert!(index.point_count() > 5);
+    let index_path = dir.join("disk.raw.gz.idx");
+    index.save(&index_path).unwrap();
+    let loaded = GzipIndex::load(&index_path).unwrap();
+    assert_eq!(loaded.point_count(), index.point_count());
+    assert_eq!(loaded.uncompressed_size(), expected.len() as u64);
+
+    let mut reader = CompressedReader::from_gzip_index(&path, loaded).unwrap();
+    check_random_reads(&mut reader, &expected);
+
+    // An index for another file is rebuilt
+    fs::write(&index_path, b"TSKGZIX2").unwrap();
+    let mut reader = CompressedReader::open_gzip_indexed(&path, &index_path).unwrap();
+    check_random_reads(&mut reader, &expected);
+    assert!(GzipIndex::load(&index_path).is_ok());
+
+    // An index of another file of the same size is rejected
+    let mut touched = data.clone();
+    touched[4] ^= 0xff;
+    fs::write(&path, &touched).unwrap();
+    assert!(CompressedReader::from_gzip_index(&path, index.clone()).is_err());
+
+    // Corrupted data fails the member crc
+    let mut corrupt = data.clone();
+    let last = corrupt.len() - 9;
+    corrupt[last] ^= 0xff;
+    fs::write(&path, &corrupt).unwrap();
+    assert!(GzipIndex::build(&path, 64 * 1024).is_err());
+}
+
+
+#[test]
+fn test_zstd_reader() {
+    let dir = TestDir::new("zstd_reader");
+    let expected = pattern(3, 1_000_000);
+    let frame_size = 96 * 1024;
+
+    // Seekable format: independent frames followed by the seek table
+    let mut data = Vec::new();
+    let mut table = Vec::new();
+    for chunk in expected.chunks(frame_size) {
+        let frame = zstd::bulk::compress(chunk, 3).unwrap();
+        table.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        table.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        data.extend_from_slice(&frame);
+    }
+    let frames_only = data.clone();
+    let frame_count = expected.chunks(frame_size).count() as u32;
+    data.extend_from_slice(&0x184d_2a5eu32.to_le_bytes());
+    data.extend_from_slice(&(table.len() as u32 + 9).to_le_bytes());
+    data.extend_from_slice(&table);
+    data.extend_from_slice(&frame_count.to_le_bytes());
+    data.push(0);
+    data.extend_from_slice(&0x8f92_eab1u32.to_le_bytes());
+
+    let path = dir.join("disk.raw.zst");
+    fs::write(&path, &data).unwrap();
+    let mut reader = CompressedReader::open(&path).unwrap();
+    assert_eq!(reader.format(), CompressedFormat::Zstd);
+    assert_eq!(reader.access_point_count(), frame_count as usize);
+    assert_eq!(reader.uncompressed_size(), expected.len() as u64);
+    check_random_reads(&mut reader, &expected);
+
+    // Without a seek table the frames are found from their headers
+    fs::write(&path, &frames_only).unwrap();
+    let mut reader = CompressedReader::open_zstd(&path).unwrap();
+    assert_eq!(reader.access_point_count(), frame_count as usize);
+    check_random_reads(&mut reader, &expected);
+}
+
+
+#[test]
+fn test_xz_reader() {
+    let dir = TestDir::new("xz_reader");
+    let first = pattern(4, 600_000);
+    let second = pattern(5, 300_000);
+    let mut expected = first.clone();
+    expected.extend_from_slice(&second);
+
+    // A multi block stream, stream padding and a second single block stream
+    let stream = xz2::stream::MtStreamBuilder::new()
+        .threads(1)
+        .block_size(128 * 1024)
+        .preset(1)
+        .encoder()
+        .unwrap();
+    let mut encoder = xz2::write::XzEncoder::new_stream(Vec::new(), stream);
+    encoder.write_all(&first).unwrap();
+    let mut data = encoder.finish().unwrap();
+    data.extend_from_slice(&[0u8; 8]);
+    let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+    encoder.write_all(&second).unwrap();
+    data.extend_from_slice(&encoder.finish().unwrap());
+
+    let path = dir.join("disk.raw.xz");
+    fs::write(&path, &data).unwrap();
+    let mut reader = CompressedReader::open(&path).unwrap();
+    assert_eq!(reader.format(), CompressedFormat::Xz);
+    assert_eq!(reader.access_point_count(), 6);
+    assert_eq!(reader.uncompressed_size(), expected.len() as u64);
+    check_random_reads(&mut reader, &expected);
+
+    // Not a compressed file
+    fs::write(&path, &expected[..4096]).unwrap();
+    assert!(CompressedReader::open(&path).is_err());
+}
@@ -0,0 +1,326 @@
+mod common;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use bzip2::write::BzEncoder;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use tsk::img_dmg::{DmgReader, SparseBundleReader};
+use common::{TestDir, pattern};
+
+
+const SECTOR_SIZE: usize = 512;
+/// Sectors of the HFS partition, the last two are not described by chunks
+const HFS_SECTORS: usize = 10;
+
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for group in data.chunks(3) {
+        let value = (group[0] as u32) << 16 |
+            (*group.get(1).unwrap_or(&0) as u32) << 8 |
+            *group.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= group.len() {
+                text.push(ALPHABET[(value >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+
+/// An ADC encoding of a sector that repeats every 16 bytes
+fn adc_repeating(data: &[u8]) -> Vec<u8> {
+    let mut adc = vec![0x80 | 15];
+    adc.extend_from_slice(&data[..16]);
+    // A short match of 18 bytes at distance 16
+    adc.extend_from_slice(&[15 << 2, 15]);
+    let mut remaining = SECTOR_SIZE - 34;
+    while remaining > 0 {
+        let length = remaining.min(67);
+        adc.push(0x40 | (length - 4) as u8);
+        adc.extend_from_slice(&15u16.to_be_bytes());
+        remaining -= length;
+    }
+    adc
+}
+
+
+/// An LZFSE stream of an uncompressed, an LZVN and an FSE (v2) block.
+/// Returns the stream and the data it decodes to.
+fn lzfse_stream() -> (Vec<u8>, Vec<u8>) {
+    let mut stream = Vec::new();
+    let mut expected = Vec::new();
+
+    stream.extend_from_slice(b"bvx-");
+    stream.extend_from_slice(&10u32.to_le_bytes());
+    stream.extend_from_slice(b"0123456789");
+    expected.extend_from_slice(b"0123456789");
+
+    // Literal "ABCD" and a match of 10 at distance 4
+    let lzvn = [0xe4, b'A', b'B', b'C', b'D', 7 << 3, 4, 0x06, 0, 0, 0, 0, 0, 0, 0];
+    stream.extend_from_slice(b"bvxn");
+    stream.extend_from_slice(&14u32.to_le_bytes());
+    stream.extend_from_slice(&(lzvn.len() as u32).to_le_bytes());
+    stream.extend_from_slice(&lzvn);
+    expected.extend_from_slice(b"ABCDABCDABCDAB");
+
+    // Four 'Z' literals then a match of 12 at distance 1. Every table has a
+    // single symbol, so no state bits are needed.
+    let mut frequencies = vec![0u16; 20 + 20 + 64 + 256];
+    frequencies[4] = 64;
+    frequencies[20 + 12] = 64;
+    frequencies[40 + 1] = 256;
+    frequencies[104 + b'Z' as usize] = 1024;
+    let mut table = Vec::new();
+    let mut accum = 0u64;
+    let mut accum_bits = 0;
+    for frequency in frequencies {
+        if frequency == 0 {
+            accum_bits += 2;
+        } else {
+            accum |= (15 | (frequency as u64 - 24) << 4) << accum_bits;
+            accum_bits += 14;
+        }
+        while accum_bits >= 8 {
+            table.push(accum as u8);
+            accum >>= 8;
+            accum_bits -= 8;
+        }
+    }
+    if accum_bits > 0 {
+        table.push(accum as u8);
+    }
+    let header_size = 32 + table.len() as u64;
+    stream.extend_from_slice(b"bvx2");
+    stream.extend_from_slice(&16u32.to_le_bytes());
+    stream.extend_from_slice(&(4u64 | 8 << 20 | 1 << 40 | 7 << 60).to_le_bytes());
+    stream.extend_from_slice(&(8u64 << 40 | 7 << 60).to_le_bytes());
+    stream.extend_from_slice(&header_size.to_le_bytes());
+    stream.extend_from_slice(&table);
+    stream.extend_from_slice(&[0u8; 16]);
+    expected.extend_from_slice(&[b'Z'; 16]);
+
+    stream.extend_from_slice(b"bvx$");
+    (stream, expected)
+}
+
+
+/// Build a mish block
+fn mish(first_sector: u64, sector_count: u64, data_offset: u64, chunks: &[(u32, u64, u64, u64, u64)]) -> Vec<u8> {
+    let mut block = vec![0u8; 0xcc];
+    block[0..4].copy_from_slice(b"mish");
+    block[4..8].copy_from_slice(&1u32.to_be_bytes());
+    block[0x08..0x10].copy_from_slice(&first_sector.to_be_bytes());
+    block[0x10..0x18].copy_from_slice(&sector_count.to_be_bytes());
+    block[0x18..0x20].copy_from_slice(&data_offset.to_be_bytes());
+    block[0xc8..0xcc].copy_from_slice(&(chunks.len() as u32).to_be_bytes());
+    for (kind, sector, count, offset, length) in chunks {
+        block.extend_from_slice(&kind.to_be_bytes());
+        block.extend_from_slice(&0u32.to_be_bytes());
+        block.extend_from_slice(&sector.to_be_bytes());
+        block.extend_from_slice(&count.to_be_bytes());
+        block.extend_from_slice(&offset.to_be_bytes());
+        block.extend_from_slice(&length.to_be_bytes());
+    }
+    block
+}
+
+
+/// Write a dmg with a raw MBR partition and an HFS partition using every
+/// chunk type. Returns the expected disk image.
+fn write_dmg(path: &PathBuf) -> Vec<u8> {
+    let mbr = pattern(1, SECTOR_SIZE);
+    let raw = pattern(2, SECTOR_SIZE);
+    let zlib = pattern(3, SECTOR_SIZE);
+    let bzip2 = pattern(4, SECTOR_SIZE);
+    let adc = pattern(5, 16).repeat(SECTOR_SIZE / 16);
+    let (lzfse, mut lzfse_data) = lzfse_stream();
+    lzfse_data.resize(SECTOR_SIZE, 0);
+    let lzma = pattern(6, SECTOR_SIZE);
+
+    let mut zlib_encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib_encoder.write_all(&zlib).unwrap();
+    let mut bzip2_encoder = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    bzip2_encoder.write_all(&bzip2).unwrap();
+    let mut lzma_encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+    lzma_encoder.write_all(&lzma).unwrap();
+
+    let mut fork = mbr.clone();
+    let hfs_data_offset = fork.len() as u64;
+    let mut hfs_chunks = vec![(0x7fff_fffe, 0, 0, 0, 0)];
+    // (type, sector, sector count, data)
+    let stored: Vec<(u32, u64, u64, Vec<u8>)> = vec![
+        (0x0000_0001, 0, 1, raw.clone()),
+        (0x0000_0000, 1, 2, Vec::new()),
+        (0x8000_0005, 3, 1, zlib_encoder.finish().unwrap()),
+        (0x8000_0006, 4, 1, bzip2_encoder.finish().unwrap()),
+        (0x8000_0004, 5, 1, adc_repeating(&adc)),
+        (0x8000_0007, 6, 1, lzfse),
+        (0x8000_0008, 7, 1, lzma_encoder.finish().unwrap())
+    ];
+    for (kind, sector, count, data) in stored {
+        let offset = fork.len() as u64 - hfs_data_offset;
+        hfs_chunks.push((kind, sector, count, offset, data.len() as u64));
+        fork.extend_from_slice(&data);
+    }
+    hfs_chunks.push((0xffff_ffff, HFS_SECTORS as u64, 0, 0, 0));
+
+    let mbr_mish = mish(0, 1, 0, &[(1, 0, 1, 0, SECTOR_SIZE as u64), (0xffff_ffff, 1, 0, 0, 0)]);
+    let hfs_mish = mish(1, HFS_SECTORS as u64, hfs_data_offset, &hfs_chunks);
+    let xml = format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>resource-fork</key>
+	<dict>
+		<key>blkx</key>
+		<array>
+			<dict>
+				<key>Attributes</key>
+				<string>0x0050</string>
+				<key>CFName</key>
+				<string>Protective Master Boot Record (MBR : 0)</string>
+				<key>Data</key>
+				<data>
+				{}
+				</data>
+				<key>ID</key>
+				<string>-1</string>
+			</dict>
+			<dict>
+				<key>Attributes</key>
+				<string>0x0050</string>
+				<key>Data</key>
+				<data>
+				{}
+				</data>
+				<key>ID</key>
+				<string>0</string>
+				<key>Name</key>
+				<string>disk image (Apple_HFS &amp; more : 1)</string>
+			</dict>
+		</array>
+		<key>plst</key>
+		<array>
+		</array>
+	</dict>
+</dict>
+</plist>
+"#, base64(&mbr_mish), base64(&hfs_mish));
+
+    let mut dmg = fork.clone();
+    let mut koly = vec![0u8; 512];
+    koly[0..4].copy_from_slice(b"koly");
+    koly[4..8].copy_from_slice(&4u32.to_be_bytes());
+    koly[8..12].copy_from_slice(&512u32.to_be_bytes());
+    koly[0x20..0x28].copy_from_slice(&(fork.len() as u64).to_be_bytes());
+    koly[0x38..0x3c].copy_from_slice(&1u32.to_be_bytes());
+    koly[0x3c..0x40].copy_from_slice(&1u32.to_be_bytes());
+    koly[0xd8..0xe0].copy_from_slice(&(dmg.len() as u64).to_be_bytes());
+    koly[0xe0..0xe8].copy_from_slice(&(xml.len() as u64).to_be_bytes());
+    koly[0x1ec..0x1f4].copy_from_slice(&(1 + HFS_SECTORS as u64).to_be_bytes());
+    dmg.extend_from_slice(xml.as_bytes());
+    dmg.extend_from_slice(&koly);
+    fs::write(path, &dmg).unwrap();
+
+    let mut expected = mbr;
+    expected.extend_from_slice(&raw);
+    expected.extend_from_slice(&[0u8; 2 * SECTOR_SIZE]);
+    expected.extend_from_slice(&zlib);
+    expected.extend_from_slice(&bzip2);
+    expected.extend_from_slice(&adc);
+    expected.extend_from_slice(&lzfse_data);
+    expected.extend_from_slice(&lzma);
+    expected.extend_from_slice(&[0u8; 2 * SECTOR_SIZE]);
+    expected
+}
+
+
+#[test]
+fn test_dmg_reader() {
+    let dir = TestDir::new("dmg_reader");
+    let expected = write_dmg(&dir.join("disk.dmg"));
+
+    let mut reader = DmgReader::open(dir.join("disk.dmg")).unwrap();
+    assert_eq!(reader.size(), expected.len() as u64);
+    let partitions = reader.partitions();
+    assert_eq!(partitions.len(), 2);
+    assert_eq!(partitions[0].name, "Protective Master Boot Record (MBR : 0)");
+    assert_eq!(partitions[1].name, "disk image (Apple_HFS & more : 1)");
+    assert_eq!(partitions[1].first_sector, 1);
+    assert_eq!(partitions[1].sector_count, HFS_SECTORS as u64);
+
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    assert!(contents == expected);
+
+    // Reads that span chunks of different types
+    for start in &[SECTOR_SIZE - 7, 3 * SECTOR_SIZE + 100, 5 * SECTOR_SIZE + 3, 8 * SECTOR_SIZE + 511] {
+        reader.seek(SeekFrom::Start(*start as u64)).unwrap();
+        let mut buf = vec![0u8; SECTOR_SIZE + 20];
+        reader.read_exact(&mut buf).unwrap();
+        assert!(buf[..] == expected[*start..*start + SECTOR_SIZE + 20]);
+    }
+    assert!(reader.seek(SeekFrom::Current(-100_000)).is_err());
+
+    // Encrypted images are rejected
+    let mut encrypted = fs::read(dir.join("disk.dmg")).unwrap();
+    encrypted[0..8].copy_from_slice(b"encrcdsa");
+    fs::write(dir.join("encrypted.dmg"), &encrypted).unwrap();
+    assert!(DmgReader::open(dir.join("encrypted.dmg")).is_err());
+}
+
+
+#[test]
+fn test_sparsebundle_reader() {
+    let dir = TestDir::new("sparsebundle_reader");
+    let bundle = dir.join("disk.sparsebundle");
+    fs::create_dir_all(bundle.join("bands")).unwrap();
+    let band_size = 8192;
+    let size = 3 * band_size + 1000;
+    fs::write(bundle.join("Info.plist"), format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>CFBundleInfoDictionaryVersion</key>
+	<string>6.0</string>
+	<key>band-size</key>
+	<integer>{}</integer>
+	<key>bundle-backingstore-version</key>
+	<integer>1</integer>
+	<key>diskimage-bundle-type</key>
+	<string>com.apple.diskimage.sparsebundle</string>
+	<key>size</key>
+	<integer>{}</integer>
+</dict>
+</plist>
+"#, band_size, size)).unwrap();
+
+    // Band 1 and 3 are missing and band 2 is short
+    let mut expected = vec![0u8; size];
+    let band0 = pattern(1, band_size);
+    let band2 = pattern(2, band_size / 2);
+    fs::write(bundle.join("bands").join("0"), &band0).unwrap();
+    fs::write(bundle.join("bands").join("2"), &band2).unwrap();
+    expected[..band_size].copy_from_slice(&band0);
+    expected[2 * band_size..2 * band_size + band2.len()].copy_from_slice(&band2);
+
+    let mut reader = SparseBundleReader::open(&bundle).unwrap();
+    assert_eq!(reader.size(), size as u64);
+    assert_eq!(reader.band_size(), band_size as u64);
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    assert!(contents == expected);
+
+    reader.seek(SeekFrom::Start(band_size as u64 - 10)).unwrap();
+    let mut buf = vec![0u8; 2 * band_size];
+    reader.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == expected[band_size - 10..3 * band_size - 10]);
+
+    assert!(SparseBundleReader::open(&dir).is_err());
+}
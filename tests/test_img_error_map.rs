@@ -0,0 +1,97 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use tsk::img_error_map::{BadSectorPolicy, BadSectorReader, ErrorMap};
+
+
+const SECTOR_SIZE: usize = 512;
+
+
+/// A stream that fails reads touching the bad sectors
+struct DamagedStream {
+    inner: Cursor<Vec<u8>>,
+    bad_sectors: Vec<u64>
+}
+impl Read for DamagedStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = self.inner.position() / SECTOR_SIZE as u64;
+        let end = (self.inner.position() + buf.len() as u64).div_ceil(SECTOR_SIZE as u64);
+        if self.bad_sectors.iter().any(|s| (start..end).contains(s)) {
+            return Err(std::io::Error::other("medium error"));
+        }
+        self.inner.read(buf)
+    }
+}
+impl Seek for DamagedStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+
+fn damaged_reader(data: &[u8], policy: BadSectorPolicy) -> BadSectorReader {
+    let stream = DamagedStream { inner: Cursor::new(data.to_vec()), bad_sectors: vec![3, 4, 9] };
+    BadSectorReader::new(Box::new(stream), data.len() as u64, SECTOR_SIZE as u32, policy).unwrap()
+}
+
+
+#[test]
+fn test_bad_sector_policies() {
+    let data: Vec<u8> = (0..16 * SECTOR_SIZE).map(|i| (i % 251) as u8 + 1).collect();
+
+    // Fail passes the error on
+    let mut reader = damaged_reader(&data, BadSectorPolicy::Fail);
+    let mut buf = vec![0u8; 4 * SECTOR_SIZE];
+    reader.seek(SeekFrom::Start(2 * SECTOR_SIZE as u64)).unwrap();
+    assert!(reader.read(&mut buf).is_err());
+    assert!(reader.error_map().is_empty());
+
+    // ZeroFill only loses the bad sectors
+    reader.set_policy(BadSectorPolicy::ZeroFill);
+    assert_eq!(reader.policy(), BadSectorPolicy::ZeroFill);
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    let mut expected = data.clone();
+    for sector in &[3usize, 4, 9] {
+        expected[sector * SECTOR_SIZE..(sector + 1) * SECTOR_SIZE].iter_mut().for_each(|b| *b = 0);
+    }
+    assert!(contents == expected);
+    let map = reader.error_map();
+    let ranges: Vec<(u64, u64)> = map.ranges().iter().map(|r| (r.offset, r.length)).collect();
+    assert_eq!(ranges, vec![(3 * 512, 2 * 512), (9 * 512, 512)]);
+    assert_eq!(map.total_bytes(), 3 * 512);
+    assert_eq!(map.ranges()[0].error, "medium error");
+    assert!(map.overlaps(4 * 512 + 100, 10));
+    assert!(!map.overlaps(5 * 512, 4 * 512));
+    assert_eq!(map.ranges_in(0, 16 * 512).len(), 2);
+
+    // Skip loses the whole failed read
+    let mut reader = damaged_reader(&data, BadSectorPolicy::Skip);
+    reader.seek(SeekFrom::Start(8 * SECTOR_SIZE as u64)).unwrap();
+    let mut buf = vec![0u8; 4 * SECTOR_SIZE];
+    assert_eq!(reader.read(&mut buf).unwrap(), buf.len());
+    assert!(buf.iter().all(|b| *b == 0));
+    let ranges: Vec<(u64, u64)> = reader.error_map().ranges().iter().map(|r| (r.offset, r.length)).collect();
+    assert_eq!(ranges, vec![(8 * 512, 4 * 512)]);
+}
+
+
+#[test]
+fn test_error_map_merge() {
+    let mut map = ErrorMap::default();
+    map.insert(1000, 100, "first");
+    map.insert(2000, 100, "second");
+    map.insert(1100, 50, "adjacent");
+    map.insert(0, 10, "start");
+    assert_eq!(map.ranges().len(), 3);
+    assert_eq!((map.ranges()[1].offset, map.ranges()[1].length), (1000, 150));
+    assert_eq!(map.ranges()[1].error, "first");
+
+    // A range covering several merges them
+    map.insert(900, 1300, "wide");
+    assert_eq!(map.ranges().len(), 2);
+    assert_eq!((map.ranges()[1].offset, map.ranges()[1].end()), (900, 2200));
+    assert_eq!(map.total_bytes(), 10 + 1300);
+
+    map.clear();
+    assert!(map.is_empty());
+}
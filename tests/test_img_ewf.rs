@@ -0,0 +1,219 @@
+mod common;
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use md5::{Md5, Digest};
+use sha1::Sha1;
+use tsk::img_ewf::{EwfReader, EwfFormat, find_ewf_segments};
+use common::TestDir;
+
+
+const BYTES_PER_SECTOR: u32 = 512;
+const SECTORS_PER_CHUNK: u32 = 2;
+const CHUNK_SIZE: usize = (BYTES_PER_SECTOR * SECTORS_PER_CHUNK) as usize;
+
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+
+/// Builds the sections of a single EWF1 segment file
+struct SegmentWriter {
+    data: Vec<u8>
+}
+impl SegmentWriter {
+    fn new(segment_number: u16) -> Self {
+        let mut data = b"EVF\x09\x0d\x0a\xff\x00\x01".to_vec();
+        data.extend_from_slice(&segment_number.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        Self { data }
+    }
+
+    fn section(&mut self, section_type: &str, section_data: &[u8]) {
+        let offset = self.data.len() as u64;
+        let size = 76 + section_data.len() as u64;
+        let next = if section_type == "done" || section_type == "next" { offset } else { offset + size };
+
+        let mut descriptor = vec![0u8; 72];
+        descriptor[..section_type.len()].copy_from_slice(section_type.as_bytes());
+        descriptor[16..24].copy_from_slice(&next.to_le_bytes());
+        descriptor[24..32].copy_from_slice(&size.to_le_bytes());
+        let checksum = adler32(&descriptor);
+        descriptor.extend_from_slice(&checksum.to_le_bytes());
+
+        self.data.extend_from_slice(&descriptor);
+        self.data.extend_from_slice(section_data);
+    }
+
+    /// Write a sectors section and its table. Every other chunk is compressed.
+    fn chunks(&mut self, chunks: &[&[u8]]) {
+        let sectors_offset = self.data.len() as u64 + 76;
+        let mut sectors = Vec::new();
+        let mut entries = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let offset = (sectors_offset + sectors.len() as u64) as u32;
+            if i % 2 == 0 {
+                entries.extend_from_slice(&(offset | 0x8000_0000).to_le_bytes());
+                sectors.extend_from_slice(&zlib(chunk));
+            } else {
+                entries.extend_from_slice(&offset.to_le_bytes());
+                sectors.extend_from_slice(chunk);
+                sectors.extend_from_slice(&adler32(chunk).to_le_bytes());
+            }
+        }
+        self.section("sectors", &sectors);
+
+        let mut table = vec![0u8; 20];
+        table[0..4].copy_from_slice(&(chunks.len() as u32).to_le_bytes());
+        let checksum = adler32(&table);
+        table.extend_from_slice(&checksum.to_le_bytes());
+        let entries_checksum = adler32(&entries);
+        table.extend_from_slice(&entries);
+        table.extend_from_slice(&entries_checksum.to_le_bytes());
+        self.section("table", &table);
+    }
+}
+
+
+/// Write a two segment E01 set of the given media and return the first segment
+fn write_ewf_set(dir: &Path, media: &[u8], store_hashes: bool) -> PathBuf {
+    let sector_count = (media.len() / BYTES_PER_SECTOR as usize) as u64;
+    let chunks: Vec<&[u8]> = media.chunks(CHUNK_SIZE).collect();
+    let (first_chunks, second_chunks) = chunks.split_at(3);
+
+    let header = zlib(b"1\r\nmain\r\nc\tn\ta\te\tt\tav\tov\tm\tu\tp\tr\r\ncase-1\tev-7\ttest media\texaminer\tnotes\t1.0\tLinux\t2020 1 2 3 4 5\t2020 1 2 3 4 5\t0\tf\r\n\r\n");
+    let mut volume = vec![0u8; 1052];
+    volume[0] = 0x01;
+    volume[4..8].copy_from_slice(&(chunks.len() as u32).to_le_bytes());
+    volume[8..12].copy_from_slice(&SECTORS_PER_CHUNK.to_le_bytes());
+    volume[12..16].copy_from_slice(&BYTES_PER_SECTOR.to_le_bytes());
+    volume[16..24].copy_from_slice(&sector_count.to_le_bytes());
+    volume[64..80].copy_from_slice(&[0xab; 16]);
+
+    let mut first = SegmentWriter::new(1);
+    first.section("header", &header);
+    first.section("volume", &volume);
+    first.chunks(first_chunks);
+    first.section("next", &[]);
+
+    let mut second = SegmentWriter::new(2);
+    second.section("data", &volume);
+    second.chunks(second_chunks);
+    if store_hashes {
+        let mut digest = Vec::new();
+        digest.extend_from_slice(&Md5::digest(media));
+        digest.extend_from_slice(&Sha1::digest(media));
+        digest.extend_from_slice(&[0u8; 40]);
+        second.section("digest", &digest);
+    }
+    second.section("done", &[]);
+
+    let first_path = dir.join("image.E01");
+    File::create(&first_path).unwrap().write_all(&first.data).unwrap();
+    File::create(dir.join("image.E02")).unwrap().write_all(&second.data).unwrap();
+    first_path
+}
+
+
+/// Media with a distinct pattern in every sector. The last chunk is partial.
+fn test_media() -> Vec<u8> {
+    (0..9 * BYTES_PER_SECTOR as usize)
+        .map(|i| ((i / BYTES_PER_SECTOR as usize) as u8).wrapping_mul(31) ^ (i as u8))
+        .collect()
+}
+
+
+#[test]
+fn test_ewf_reader() {
+    let dir = TestDir::new("ewf_reader");
+    let media = test_media();
+    let first_segment = write_ewf_set(&dir, &media, true);
+
+    assert_eq!(find_ewf_segments(&first_segment), vec![
+        dir.join("image.E01"),
+        dir.join("image.E02")
+    ]);
+
+    let mut reader = EwfReader::open(&first_segment)
+        .expect("Error opening EWF set.");
+    assert_eq!(reader.format(), EwfFormat::Ewf1);
+    assert_eq!(reader.media_size(), media.len() as u64);
+    assert_eq!(reader.bytes_per_sector(), BYTES_PER_SECTOR);
+    assert_eq!(reader.chunk_size(), CHUNK_SIZE as u64);
+
+    let metadata = reader.metadata();
+    assert_eq!(metadata.case_number.as_deref(), Some("case-1"));
+    assert_eq!(metadata.evidence_number.as_deref(), Some("ev-7"));
+    assert_eq!(metadata.examiner_name.as_deref(), Some("examiner"));
+    assert_eq!(metadata.set_identifier, Some([0xab; 16]));
+    assert_eq!(metadata.stored_md5_hex(), Some(format!("{:x}", Md5::digest(&media))));
+
+    // Whole media
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).unwrap();
+    assert_eq!(data, media);
+
+    // A read across the chunk and segment boundary
+    reader.seek(SeekFrom::Start(2 * CHUNK_SIZE as u64 + 100)).unwrap();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &media[2 * CHUNK_SIZE + 100..3 * CHUNK_SIZE + 100]);
+
+    // Reads stop at the end of the media
+    reader.seek(SeekFrom::End(-10)).unwrap();
+    let mut buf = vec![0u8; 100];
+    assert_eq!(reader.read(&mut buf).unwrap(), 10);
+
+    let verification = reader.verify().unwrap();
+    assert_eq!(verification.md5_matches(), Some(true));
+    assert_eq!(verification.sha1_matches(), Some(true));
+    assert!(verification.is_verified());
+}
+
+
+#[test]
+fn test_ewf_reader_errors() {
+    let dir = TestDir::new("ewf_reader_errors");
+    let media = test_media();
+    let first_segment = write_ewf_set(&dir, &media, false);
+
+    // No stored hashes means nothing can be verified
+    let mut reader = EwfReader::open(&first_segment).unwrap();
+    let verification = reader.verify().unwrap();
+    assert_eq!(verification.md5_matches(), None);
+    assert!(!verification.is_verified());
+
+    // Without its second segment the set is incomplete
+    assert!(EwfReader::from_segments(&[&first_segment]).is_err());
+
+    // Corrupt an uncompressed chunk (chunk 1 in the first segment)
+    let mut data = fs::read(&first_segment).unwrap();
+    let chunk_1 = &media[CHUNK_SIZE..2 * CHUNK_SIZE];
+    let position = data.windows(CHUNK_SIZE)
+        .position(|w| w == chunk_1)
+        .unwrap();
+    data[position] ^= 0xff;
+    fs::write(&first_segment, &data).unwrap();
+
+    let mut reader = EwfReader::open(&first_segment).unwrap();
+    reader.seek(SeekFrom::Start(CHUNK_SIZE as u64)).unwrap();
+    let mut buf = vec![0u8; 16];
+    assert!(reader.read(&mut buf).is_err());
+    assert!(reader.verify().is_err());
+}
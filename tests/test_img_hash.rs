@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::Cursor;
+use std::path::PathBuf;
+use tsk::errors::ErrorType;
+use tsk::img_aff4::Aff4Hash;
+use tsk::img_hash::{self, HashAlgorithm, HashProgress, ImageHash};
+use tsk::tsk_img::TskImg;
+
+
+const ABC_MD5: &str = "900150983cd24fb0d6963f7d28e17f72";
+const ABC_SHA1: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+
+#[test]
+fn test_hash_stream() {
+    let mut updates = Vec::new();
+    let hashes = img_hash::hash_stream(
+        &mut Cursor::new(b"abc".to_vec()),
+        Some(3),
+        &HashAlgorithm::all(),
+        &mut |p| { updates.push(p); true }
+    ).expect("Error hashing.");
+    assert_eq!(hashes, vec![
+        ImageHash::new(HashAlgorithm::Md5, ABC_MD5),
+        ImageHash::new(HashAlgorithm::Sha1, ABC_SHA1),
+        ImageHash::new(HashAlgorithm::Sha256, ABC_SHA256)
+    ]);
+    assert_eq!(updates, vec![HashProgress { bytes_hashed: 3, total_bytes: Some(3) }]);
+    assert_eq!(updates[0].fraction(), Some(1.0));
+
+    // The stream is shorter than expected
+    assert!(img_hash::hash_stream(
+        &mut Cursor::new(b"abc".to_vec()), Some(4), &[HashAlgorithm::Md5], &mut |_| true
+    ).is_err());
+    assert!(img_hash::hash_stream(
+        &mut Cursor::new(b"abc".to_vec()), None, &[], &mut |_| true
+    ).is_err());
+}
+
+
+#[test]
+fn test_hash_stream_cancel() {
+    let data = vec![0u8; 3 * 1024 * 1024];
+    let mut calls = 0;
+    let error = img_hash::hash_stream(
+        &mut Cursor::new(data),
+        None,
+        &[HashAlgorithm::Sha256],
+        &mut |p| { calls += 1; p.bytes_hashed < 1024 * 1024 }
+    ).expect_err("Hashing was not cancelled.");
+    assert!(matches!(error.kind, ErrorType::Cancelled));
+    assert_eq!(calls, 1);
+}
+
+
+#[test]
+fn test_verify_stream() {
+    let expected = vec![
+        ImageHash::new(HashAlgorithm::Md5, ABC_MD5.to_uppercase()),
+        ImageHash::new(HashAlgorithm::Sha1, ABC_SHA1)
+    ];
+    let verification = img_hash::verify_stream(
+        &mut Cursor::new(b"abc".to_vec()), Some(3), &expected, &mut |_| true
+    ).expect("Error verifying.");
+    assert!(verification.is_verified());
+    assert_eq!(verification.bytes_hashed, 3);
+    assert_eq!(verification.result(HashAlgorithm::Sha256).and_then(|r| r.matches()), None);
+    assert_eq!(verification.computed().len(), 3);
+
+    let wrong = vec![ImageHash::new(HashAlgorithm::Sha256, ABC_MD5)];
+    let verification = img_hash::verify_stream(
+        &mut Cursor::new(b"abc".to_vec()), Some(3), &wrong, &mut |_| true
+    ).expect("Error verifying.");
+    assert!(!verification.is_verified());
+    assert_eq!(verification.mismatches().len(), 1);
+    assert_eq!(verification.mismatches()[0].algorithm, HashAlgorithm::Sha256);
+
+    // Nothing expected
+    let verification = img_hash::verify_stream(
+        &mut Cursor::new(b"abc".to_vec()), None, &[], &mut |_| true
+    ).expect("Error verifying.");
+    assert!(!verification.is_verified());
+}
+
+
+#[test]
+fn test_expected_from_container() {
+    let aff4 = vec![
+        Aff4Hash { algorithm: "SHA1".to_string(), value: ABC_SHA1.to_string() },
+        Aff4Hash { algorithm: "SHA512".to_string(), value: "00".to_string() },
+        Aff4Hash { algorithm: "MD5".to_string(), value: ABC_MD5.to_string() }
+    ];
+    assert_eq!(ImageHash::from_aff4_hashes(&aff4), vec![
+        ImageHash::new(HashAlgorithm::Sha1, ABC_SHA1),
+        ImageHash::new(HashAlgorithm::Md5, ABC_MD5)
+    ]);
+    assert_eq!(HashAlgorithm::from_name("SHA-256"), Some(HashAlgorithm::Sha256));
+    assert_eq!(HashAlgorithm::from_name("blake2b"), None);
+}
+
+
+#[test]
+fn test_tsk_img_hash() {
+    let source = PathBuf::from(format!("{}/samples/mbr.raw", env!("CARGO_MANIFEST_DIR")));
+    let expected = ImageHash::compute(
+        &mut File::open(&source).expect("Error opening file."),
+        HashAlgorithm::Sha256
+    ).expect("Error hashing the file.");
+
+    let tsk_img = TskImg::from_utf8_sing(&source)
+        .expect("Could not create TskImg");
+    let mut last_progress = None;
+    let hashes = tsk_img.hash(&[HashAlgorithm::Sha256], |p| { last_progress = Some(p); true })
+        .expect("Error hashing the image.");
+    assert_eq!(hashes, vec![expected.clone()]);
+    assert_eq!(last_progress.and_then(|p| p.fraction()), Some(1.0));
+
+    let verification = tsk_img.verify(&[expected], |_| true)
+        .expect("Error verifying the image.");
+    assert!(verification.is_verified());
+    assert_eq!(verification.bytes_hashed, tsk_img.size() as u64);
+}
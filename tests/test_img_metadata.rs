@@ -0,0 +1,117 @@
+mod common;
+
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tsk::img_cache::CacheConfig;
+use tsk::img_compressed::CompressedReader;
+use tsk::img_metadata::{ImgMetadata, ImgMetadataSource};
+use tsk::tsk_img::TskImg;
+use tsk::tsk_img_reader::TskImgReadSeek;
+use common::TestDir;
+
+
+/// A source that describes itself, with its sector size
+struct DescribedStream(Cursor<Vec<u8>>, u32);
+impl std::io::Read for DescribedStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+impl std::io::Seek for DescribedStream {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+impl ImgMetadataSource for DescribedStream {
+    fn img_metadata(&self) -> ImgMetadata {
+        let mut metadata = ImgMetadata::new("test");
+        metadata.description = Some("floppy from an email".to_string());
+        metadata.segments.push(PathBuf::from("floppy.img"));
+        metadata.add_detail("Examiner Name", "J. Doe");
+        metadata
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.1
+    }
+}
+
+
+#[test]
+fn test_imgstat_rendering() {
+    let mut metadata = ImgMetadata::new("VHDX");
+    metadata.description = Some("/cases/disk.vhdx".to_string());
+    metadata.segments = vec![PathBuf::from("/cases/disk.vhdx"), PathBuf::from("/cases/base.vhdx")];
+    metadata.add_detail("Block Size", "33554432");
+    metadata.add_optional_detail("Virtual Disk Id", None::<String>);
+    assert_eq!(metadata.detail("Block Size"), Some("33554432"));
+    assert_eq!(metadata.detail("Virtual Disk Id"), None);
+
+    let output = metadata.to_imgstat(1048576, 512);
+    println!("{}", output);
+    assert!(output.starts_with("IMAGE FILE INFORMATION\n"));
+    assert!(output.contains("Image Type:\t\tVHDX\n"));
+    assert!(output.contains("Size of data in bytes:\t1048576\n"));
+    assert!(output.contains("Sector size:\t512\n"));
+    assert!(output.contains("  1: /cases/base.vhdx\n"));
+    assert!(output.contains("  Block Size: 33554432\n"));
+    assert!(!ImgMetadata::new("raw").to_imgstat(0, 512).contains("Segments"));
+}
+
+
+#[test]
+fn test_reader_metadata() {
+    let dir = TestDir::new("metadata");
+    let path = dir.join("image.raw.gz");
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&[7u8; 4096]).unwrap();
+    fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+    let reader = CompressedReader::open(&path).expect("Error opening gzip image.");
+    let metadata = reader.img_metadata();
+    assert_eq!(metadata.format, "gzip");
+    assert_eq!(metadata.segments, vec![path.clone()]);
+    assert_eq!(metadata.detail("Uncompressed Size"), Some("4096"));
+}
+
+
+#[test]
+fn test_tsk_img_metadata() {
+    let stream = DescribedStream(Cursor::new(vec![0u8; 8192]), 512);
+    let reader = TskImgReadSeek::from_metadata_source("floppy", stream)
+        .expect("Error creating TskImgReadSeek.");
+    assert_eq!(reader.metadata().map(|m| m.format), Some("test".to_string()));
+
+    let tsk_img: TskImg = reader.into();
+    assert_eq!(tsk_img.size(), 8192);
+    let metadata = tsk_img.metadata().expect("No metadata.");
+    assert_eq!(metadata.detail("Examiner Name"), Some("J. Doe"));
+
+    // Images without a described source have no metadata
+    let tsk_img = TskImg::from_buffer("plain", vec![0u8; 512])
+        .expect("Error creating TskImg.");
+    assert!(tsk_img.metadata().is_none());
+}
+
+
+#[test]
+fn test_tsk_img_metadata_source_sector_size() {
+    let stream = DescribedStream(Cursor::new(vec![0u8; 65536]), 4096);
+    let tsk_img: TskImg = TskImgReadSeek::from_metadata_source("4Kn", stream)
+        .expect("Error creating TskImgReadSeek.")
+        .into();
+    assert_eq!(tsk_img.sector_size(), 4096);
+
+    let stream = DescribedStream(Cursor::new(vec![0u8; 65536]), 4096);
+    let reader = TskImgReadSeek::from_metadata_source_cached("4Kn cached", stream, CacheConfig::default())
+        .expect("Error creating cached TskImgReadSeek.");
+    assert_eq!(reader.sector_size(), 4096);
+    assert!(reader.cache_stats().is_some());
+
+    // Sector sizes libtsk can not use are rejected
+    let stream = DescribedStream(Cursor::new(vec![0u8; 65536]), 520);
+    assert!(TskImgReadSeek::from_metadata_source("520", stream).is_err());
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use tsk::img_metadata::ImgMetadataSource;
+use tsk::img_mmap::MmapReader;
+use tsk::tsk_img::{TskImg, TskImgType};
+use common::{TestDir, pattern};
+
+
+#[test]
+fn test_mmap_reader_windows() {
+    let dir = TestDir::new("mmap-windows");
+    let path = dir.join("disk.raw");
+    let data = pattern(0, 5 * 65536 + 1000);
+    fs::write(&path, &data).unwrap();
+
+    // SAFETY: the file is not truncated while it is mapped
+    let mut reader = unsafe { MmapReader::with_window_size(&path, 2 * 65536) }
+        .expect("Error opening image.");
+    assert_eq!(reader.size(), data.len() as u64);
+    assert_eq!(reader.img_metadata().detail("Backend"), Some("mmap"));
+
+    // Reads crossing windows, going backwards and forwards
+    for offset in [0u64, 131000, 65536 * 4 + 12, 70000, data.len() as u64 - 500].iter() {
+        let mut buf = vec![0u8; 3000.min(data.len() - *offset as usize)];
+        reader.seek(SeekFrom::Start(*offset)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert!(buf[..] == data[*offset as usize..*offset as usize + buf.len()], "read at {}", offset);
+    }
+
+    let mut content = Vec::new();
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    reader.read_to_end(&mut content).unwrap();
+    assert!(content == data);
+    assert!(reader.seek(SeekFrom::Current(-(data.len() as i64) - 1)).is_err());
+    assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), data.len() as u64);
+    assert!(reader.seek(SeekFrom::End(1)).is_err());
+    assert!(reader.seek(SeekFrom::Start(data.len() as u64 + 1)).is_err());
+
+    assert!(unsafe { MmapReader::with_window_size(&path, 1000) }.is_err());
+    assert!(unsafe { MmapReader::with_window_size(&path, 0) }.is_err());
+}
+
+
+#[test]
+fn test_mmap_reader_truncated() {
+    let dir = TestDir::new("mmap-truncated");
+    let path = dir.join("disk.raw");
+    let data = pattern(0, 4 * 65536);
+    fs::write(&path, &data).unwrap();
+
+    // SAFETY: the file is only truncated to the end of the mapped first
+    // window, the windows after it fail to map
+    let mut reader = unsafe { MmapReader::with_window_size(&path, 65536) }
+        .expect("Error opening image.");
+    let mut buf = vec![0u8; 512];
+    reader.read_exact(&mut buf).unwrap();
+
+    OpenOptions::new().write(true).open(&path).unwrap()
+        .set_len(65536).unwrap();
+    // Still present
+    reader.seek(SeekFrom::Start(1024)).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == data[1024..1536]);
+    // Cut off
+    reader.seek(SeekFrom::Start(3 * 65536)).unwrap();
+    let error = reader.read(&mut buf).expect_err("Read of the truncated range succeeded.");
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+    // An empty file reads nothing
+    let empty = dir.join("empty.raw");
+    fs::write(&empty, b"").unwrap();
+    // SAFETY: the empty file is not truncated
+    let mut reader = unsafe { MmapReader::open(&empty) }.expect("Error opening empty image.");
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+
+#[test]
+fn test_tsk_img_from_mmap() {
+    let source = PathBuf::from(format!("{}/samples/ntfs.raw", env!("CARGO_MANIFEST_DIR")));
+    // SAFETY: the sample image is not modified by the tests
+    let tsk_img = unsafe { TskImg::from_mmap(&source) }
+        .expect("Could not create TskImg");
+    assert_eq!(tsk_img.img_type(), TskImgType::External);
+    assert_eq!(tsk_img.size() as u64, source.metadata().unwrap().len());
+    assert_eq!(tsk_img.metadata().map(|m| m.format), Some("raw".to_string()));
+
+    let tsk_fs = tsk_img.get_fs_from_offset(0)
+        .expect("Could not open TskFs at offset 0");
+    let _mft_fh = tsk_fs.file_open_meta(0)
+        .expect("Could not open $MFT");
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use tsk::img_qcow2::Qcow2Reader;
+use common::{TestDir, pattern};
+
+
+const CLUSTER_BITS: u32 = 12;
+const CLUSTER_SIZE: usize = 1 << CLUSTER_BITS;
+const DISK_SIZE: usize = 8 * CLUSTER_SIZE;
+
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+
+fn put_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_be_bytes());
+}
+
+
+/// Write a version 3 qcow2 image.
+///
+/// Cluster layout: 0 header, 1 L1 table, 2 L2 table, 3 snapshot table,
+/// 4 snapshot L1 table, 5 snapshot L2 table, 6.. data clusters.
+fn write_qcow2(path: &PathBuf, backing: Option<&str>) -> (Vec<u8>, Vec<u8>) {
+    let mut data = vec![0u8; 6 * CLUSTER_SIZE];
+    let header = &mut data[..CLUSTER_SIZE];
+    header[0..4].copy_from_slice(b"QFI\xfb");
+    put_u32(header, 4, 3);
+    put_u32(header, 20, CLUSTER_BITS);
+    put_u64(header, 24, DISK_SIZE as u64);
+    put_u32(header, 36, 1);
+    put_u64(header, 40, CLUSTER_SIZE as u64);
+    put_u64(header, 48, 0);
+    put_u32(header, 60, 1);
+    put_u64(header, 64, 3 * CLUSTER_SIZE as u64);
+    put_u32(header, 96, 4);
+    put_u32(header, 100, 104);
+
+    // Backing format extension, end of extensions and the backing file name
+    put_u32(header, 104, 0xe279_2aca);
+    put_u32(header, 108, 3);
+    header[112..115].copy_from_slice(b"raw");
+    if let Some(name) = backing {
+        put_u64(header, 8, 512);
+        put_u32(header, 16, name.len() as u32);
+        header[512..512 + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    // L1 tables
+    put_u64(&mut data, CLUSTER_SIZE, 2 * CLUSTER_SIZE as u64);
+    put_u64(&mut data, 4 * CLUSTER_SIZE, (5 * CLUSTER_SIZE as u64) | (1 << 63));
+
+    // Active image: cluster 0 data, 1 compressed, 2 zero flag, 4 data, the
+    // others come from the backing file
+    let cluster_0 = pattern(1, CLUSTER_SIZE);
+    let cluster_1 = pattern(2, CLUSTER_SIZE);
+    let cluster_4 = pattern(3, CLUSTER_SIZE);
+    let mut active = vec![0u8; DISK_SIZE];
+    active[..CLUSTER_SIZE].copy_from_slice(&cluster_0);
+    active[CLUSTER_SIZE..2 * CLUSTER_SIZE].copy_from_slice(&cluster_1);
+    active[4 * CLUSTER_SIZE..5 * CLUSTER_SIZE].copy_from_slice(&cluster_4);
+
+    let l2 = 2 * CLUSTER_SIZE;
+    put_u64(&mut data, l2, (6 * CLUSTER_SIZE as u64) | (1 << 63));
+    put_u64(&mut data, l2 + 2 * 8, 1);
+    put_u64(&mut data, l2 + 4 * 8, 7 * CLUSTER_SIZE as u64);
+    data.extend_from_slice(&cluster_0);
+    data.extend_from_slice(&cluster_4);
+
+    // The compressed cluster starts 100 bytes into cluster 8
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&cluster_1).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let host_offset = 8 * CLUSTER_SIZE as u64 + 100;
+    let sectors = ((host_offset as usize % 512) + compressed.len()).div_ceil(512) - 1;
+    let offset_bits = 62 - (CLUSTER_BITS - 8);
+    put_u64(&mut data, l2 + 8, (1 << 62) | ((sectors as u64) << offset_bits) | host_offset);
+    data.extend_from_slice(&[0u8; 100]);
+    data.extend_from_slice(&compressed);
+    data.resize(8 * CLUSTER_SIZE + (sectors + 1) * 512, 0);
+
+    // Snapshot "before-update" sees only cluster 3
+    let cluster_3 = pattern(4, CLUSTER_SIZE);
+    let mut snapshot = vec![0u8; DISK_SIZE];
+    snapshot[3 * CLUSTER_SIZE..4 * CLUSTER_SIZE].copy_from_slice(&cluster_3);
+    let data_offset = data.len() as u64;
+    data.extend_from_slice(&cluster_3);
+    put_u64(&mut data, 5 * CLUSTER_SIZE + 3 * 8, data_offset);
+
+    let entry = 3 * CLUSTER_SIZE;
+    put_u64(&mut data, entry, 4 * CLUSTER_SIZE as u64);
+    put_u32(&mut data, entry + 8, 1);
+    data[entry + 12..entry + 14].copy_from_slice(&1u16.to_be_bytes());
+    data[entry + 14..entry + 16].copy_from_slice(&13u16.to_be_bytes());
+    put_u32(&mut data, entry + 16, 1_600_000_000);
+    put_u32(&mut data, entry + 36, 16);
+    put_u64(&mut data, entry + 48, (DISK_SIZE / 2) as u64);
+    data[entry + 56..entry + 57].copy_from_slice(b"1");
+    data[entry + 57..entry + 70].copy_from_slice(b"before-update");
+
+    fs::write(path, data).unwrap();
+    (active, snapshot)
+}
+
+
+#[test]
+fn test_qcow2_reader() {
+    let dir = TestDir::new("qcow2_reader");
+    let base = pattern(9, DISK_SIZE);
+    fs::write(dir.join("base.raw"), &base).unwrap();
+    let (mut active, mut snapshot) = write_qcow2(&dir.join("disk.qcow2"), Some("base.raw"));
+
+    // Unallocated clusters come from the backing file
+    for cluster in &[3usize, 5, 6, 7] {
+        let range = cluster * CLUSTER_SIZE..(cluster + 1) * CLUSTER_SIZE;
+        active[range.clone()].copy_from_slice(&base[range]);
+    }
+    for cluster in &[0usize, 1, 2] {
+        let range = cluster * CLUSTER_SIZE..(cluster + 1) * CLUSTER_SIZE;
+        snapshot[range.clone()].copy_from_slice(&base[range]);
+    }
+    snapshot.truncate(DISK_SIZE / 2);
+
+    let mut reader = Qcow2Reader::open(dir.join("disk.qcow2")).unwrap();
+    assert_eq!(reader.version(), 3);
+    assert_eq!(reader.cluster_size(), CLUSTER_SIZE as u64);
+    assert_eq!(reader.virtual_size(), DISK_SIZE as u64);
+    assert_eq!(reader.backing_file(), Some("base.raw"));
+    assert_eq!(reader.backing_format(), Some("raw"));
+    assert_eq!(reader.backing_path(), Some(dir.join("base.raw").as_path()));
+
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    assert!(contents == active);
+
+    reader.seek(SeekFrom::Start(CLUSTER_SIZE as u64 - 10)).unwrap();
+    let mut buf = vec![0u8; 2 * CLUSTER_SIZE];
+    reader.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == active[CLUSTER_SIZE - 10..3 * CLUSTER_SIZE - 10]);
+
+    // Internal snapshot by name
+    assert_eq!(reader.snapshots().len(), 1);
+    assert_eq!(reader.snapshots()[0].id, "1");
+    reader.select_snapshot("before-update").unwrap();
+    assert_eq!(reader.selected_snapshot().map(|s| s.name.as_str()), Some("before-update"));
+    assert_eq!(reader.virtual_size(), (DISK_SIZE / 2) as u64);
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    assert!(contents == snapshot);
+
+    assert!(reader.select_snapshot("missing").is_err());
+    reader.select_active();
+    assert_eq!(reader.virtual_size(), DISK_SIZE as u64);
+
+    // Missing backing file
+    fs::remove_file(dir.join("base.raw")).unwrap();
+    assert!(Qcow2Reader::open(dir.join("disk.qcow2")).is_err());
+}
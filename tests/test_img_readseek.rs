@@ -1,6 +1,12 @@
 use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::PathBuf;
-use tsk::tsk_img::TskImg;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tsk::tsk_img::{TskImg, TskImgType};
+use tsk::img_audit::{HashAlgorithm, ImageHash};
+use tsk::img_cache::CacheConfig;
+use tsk::img_error_map::BadSectorPolicy;
 use tsk::tsk_img_reader::TskImgReadSeek;
 
 
@@ -18,6 +24,9 @@ fn test_tsk_reader() {
     println!("{:?}", reader);
 
     let tsk_img: TskImg = reader.into();
+    assert_eq!(tsk_img.img_type(), TskImgType::External);
+    assert_eq!(tsk_img.size() as u64, source_size);
+    assert_eq!(tsk_img.sector_size(), 512);
 
     let tsk_fs = tsk_img.get_fs_from_offset(0)
         .expect("Could not open TskFs at offset 0");
@@ -43,4 +52,175 @@ fn test_tsk_reader() {
         // debug print the run
         println!("{:?}", run);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_tsk_reader_cached() {
+    let source = PathBuf::from(format!("{}/samples/ntfs.raw", env!("CARGO_MANIFEST_DIR")));
+    let source_size = source.metadata().unwrap().len();
+    let handle = File::open(source).expect("Error opening file.");
+    let reader = TskImgReadSeek::from_read_seek_cached(
+        "Cached File IO",
+        Box::new(handle),
+        source_size as i64,
+        512,
+        CacheConfig::default()
+    ).expect("Error creating cached TskImgReadSeek.");
+    assert_eq!(reader.cache_stats().map(|s| s.misses), Some(0));
+
+    let tsk_img: TskImg = reader.into();
+    let tsk_fs = tsk_img.get_fs_from_offset(0)
+        .expect("Could not open TskFs at offset 0");
+    let _mft_fh = tsk_fs.file_open_meta(0)
+        .expect("Could not open $MFT");
+
+    // libtsk reads the boot sector and MFT records more than once
+    let stats = tsk_img.cache_stats().expect("No cache stats.");
+    println!("{:?}", stats);
+    assert!(stats.misses > 0);
+    assert!(stats.hits > 0);
+    assert!(stats.source_reads <= stats.misses);
+}
+
+
+#[test]
+fn test_tsk_reader_audit() {
+    let source = PathBuf::from(format!("{}/samples/ntfs.raw", env!("CARGO_MANIFEST_DIR")));
+    let source_size = source.metadata().unwrap().len();
+    let handle = File::open(&source).expect("Error opening file.");
+    let reader = TskImgReadSeek::from_read_seek(
+        "Audited File IO",
+        Box::new(handle),
+        source_size as i64
+    ).expect("Error creating TskImgReadSeek.");
+    assert!(!reader.is_auditing());
+
+    let tsk_img: TskImg = reader.into();
+    tsk_img.enable_audit().expect("Error enabling the audit.");
+    let tsk_fs = tsk_img.get_fs_from_offset(0)
+        .expect("Could not open TskFs at offset 0");
+    let _mft_fh = tsk_fs.file_open_meta(0)
+        .expect("Could not open $MFT");
+    tsk_img.disable_audit();
+
+    let trail = tsk_img.audit_trail().expect("No audit trail.");
+    assert_eq!(trail.image_size(), source_size);
+    // The boot sector was read, but not the whole image
+    assert_eq!(trail.ranges()[0].offset, 0);
+    assert!(trail.covered_bytes() > 0);
+    assert!(trail.covered_bytes() < source_size);
+    assert!(trail.bytes_read() >= trail.covered_bytes());
+
+    let mut evidence = File::open(&source).expect("Error opening file.");
+    let hash = ImageHash::compute(&mut evidence, HashAlgorithm::Sha256)
+        .expect("Error hashing the image.");
+    assert!(trail.to_json(Some(&hash)).contains(&hash.value));
+
+    // Images opened by libtsk can not be audited
+    let img = TskImg::from_utf8_sing(&source).expect("Could not create TskImg");
+    assert!(img.enable_audit().is_err());
+    assert!(img.audit_trail().is_none());
+}
+
+
+/// A stream that fails every read and records when it is dropped
+struct FailingStream {
+    dropped: Arc<AtomicBool>
+}
+impl Read for FailingStream {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("device not ready"))
+    }
+}
+impl Seek for FailingStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Start(o) => Ok(o),
+            _ => Ok(0)
+        }
+    }
+}
+impl Drop for FailingStream {
+    fn drop(&mut self) {
+        self.dropped.store(true, Ordering::SeqCst);
+    }
+}
+
+
+#[test]
+fn test_tsk_reader_errors_and_close() {
+    // Read errors are reported through libtsk
+    let dropped = Arc::new(AtomicBool::new(false));
+    let reader = TskImgReadSeek::from_read_seek(
+        "Failing IO",
+        Box::new(FailingStream { dropped: dropped.clone() }),
+        1024 * 1024
+    ).expect("Error creating TskImgReadSeek.");
+    let tsk_img: TskImg = reader.into();
+    let error = tsk_img.get_fs_from_offset(0)
+        .expect_err("Opened a file system on a failing stream.");
+    println!("{:?}", error);
+    assert!(error.message.contains("device not ready"));
+
+    // The stream is released with the image
+    assert!(!dropped.load(Ordering::SeqCst));
+    drop(tsk_img);
+    assert!(dropped.load(Ordering::SeqCst));
+
+    // ... or with the reader when it never became an image
+    let dropped = Arc::new(AtomicBool::new(false));
+    let reader = TskImgReadSeek::from_read_seek(
+        "Failing IO",
+        Box::new(FailingStream { dropped: dropped.clone() }),
+        1024 * 1024
+    ).expect("Error creating TskImgReadSeek.");
+    drop(reader);
+    assert!(dropped.load(Ordering::SeqCst));
+
+    // With a zero fill policy the unreadable ranges end up in the error map
+    let mut reader = TskImgReadSeek::from_read_seek(
+        "Failing IO",
+        Box::new(FailingStream { dropped: Arc::new(AtomicBool::new(false)) }),
+        1024 * 1024
+    ).expect("Error creating TskImgReadSeek.");
+    reader.set_bad_sector_policy(BadSectorPolicy::ZeroFill);
+    let tsk_img: TskImg = reader.into();
+    assert_eq!(tsk_img.bad_sector_policy(), Some(BadSectorPolicy::ZeroFill));
+    assert!(tsk_img.get_fs_from_offset(0).is_err());
+    let error_map = tsk_img.error_map().expect("No error map.");
+    assert!(error_map.overlaps(0, 512));
+
+    // ... and the policy can be changed after the conversion
+    let tsk_img: TskImg = TskImgReadSeek::from_read_seek(
+        "Failing IO",
+        Box::new(FailingStream { dropped: Arc::new(AtomicBool::new(false)) }),
+        1024 * 1024
+    ).expect("Error creating TskImgReadSeek.").into();
+    assert_eq!(tsk_img.bad_sector_policy(), Some(BadSectorPolicy::Fail));
+    tsk_img.set_bad_sector_policy(BadSectorPolicy::Skip)
+        .expect("Error setting the bad sector policy.");
+    assert!(tsk_img.get_fs_from_offset(0).is_err());
+    assert!(tsk_img.error_map().expect("No error map.").overlaps(0, 512));
+}
+
+
+#[test]
+fn test_tsk_reader_sector_size() {
+    let data = vec![0u8; 64 * 1024];
+    let reader = TskImgReadSeek::from_read_seek_with_sector_size(
+        "4Kn",
+        Box::new(Cursor::new(data.clone())),
+        data.len() as i64,
+        4096
+    ).expect("Error creating TskImgReadSeek.");
+    assert_eq!(reader.sector_size(), 4096);
+    let tsk_img: TskImg = reader.into();
+    assert_eq!(tsk_img.sector_size(), 4096);
+
+    assert!(TskImgReadSeek::from_read_seek_with_sector_size(
+        "Bad sector size",
+        Box::new(Cursor::new(data)),
+        64 * 1024,
+        1000
+    ).is_err());
+}
@@ -0,0 +1,390 @@
+mod common;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use tsk::img_vhd::{VhdReader, VhdDiskType};
+use tsk::img_vhdx::VhdxReader;
+use common::{TestDir, pattern};
+
+
+const MIB: usize = 1024 * 1024;
+
+
+fn utf16le(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect()
+}
+
+
+/************************* VHD *************************/
+
+const VHD_BLOCK_SIZE: usize = 4096;
+const VHD_DISK_SIZE: usize = 4 * VHD_BLOCK_SIZE;
+
+
+fn vhd_checksum(data: &[u8]) -> u32 {
+    !data.iter().fold(0u32, |sum, b| sum.wrapping_add(*b as u32))
+}
+
+
+fn vhd_footer(disk_type: u32, size: u64, data_offset: u64, unique_id: [u8; 16]) -> Vec<u8> {
+    let mut footer = vec![0u8; 512];
+    footer[0..8].copy_from_slice(b"conectix");
+    footer[8..12].copy_from_slice(&2u32.to_be_bytes());
+    footer[12..16].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    footer[16..24].copy_from_slice(&data_offset.to_be_bytes());
+    footer[40..48].copy_from_slice(&size.to_be_bytes());
+    footer[48..56].copy_from_slice(&size.to_be_bytes());
+    footer[60..64].copy_from_slice(&disk_type.to_be_bytes());
+    footer[68..84].copy_from_slice(&unique_id);
+    let checksum = vhd_checksum(&footer);
+    footer[64..68].copy_from_slice(&checksum.to_be_bytes());
+    footer
+}
+
+
+/// Write a dynamic (or differencing) VHD. Each allocated block has its
+/// sector bitmap and data.
+fn write_dynamic_vhd(
+    path: &PathBuf,
+    unique_id: [u8; 16],
+    blocks: &[Option<(u8, Vec<u8>)>],
+    parent: Option<([u8; 16], &str)>
+) {
+    let disk_type = if parent.is_some() { 4 } else { 3 };
+    let footer = vhd_footer(disk_type, VHD_DISK_SIZE as u64, 512, unique_id);
+
+    let mut data = footer.clone();
+    let mut header = vec![0u8; 1024];
+    header[0..8].copy_from_slice(b"cxsparse");
+    header[8..16].copy_from_slice(&u64::MAX.to_be_bytes());
+    header[16..24].copy_from_slice(&1536u64.to_be_bytes());
+    header[24..28].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    header[28..32].copy_from_slice(&(blocks.len() as u32).to_be_bytes());
+    header[32..36].copy_from_slice(&(VHD_BLOCK_SIZE as u32).to_be_bytes());
+
+    // BAT at 1536, one sector
+    let mut bat = vec![0xffu8; 512];
+    let mut block_data = Vec::new();
+    let mut next_sector = (1536 + 512) / 512;
+    for (i, block) in blocks.iter().enumerate() {
+        if let Some((bitmap, contents)) = block {
+            bat[i * 4..i * 4 + 4].copy_from_slice(&(next_sector as u32).to_be_bytes());
+            let mut sector_bitmap = vec![0u8; 512];
+            sector_bitmap[0] = *bitmap;
+            block_data.extend_from_slice(&sector_bitmap);
+            block_data.extend_from_slice(contents);
+            next_sector += (512 + VHD_BLOCK_SIZE) / 512;
+        }
+    }
+
+    let mut locator_data = Vec::new();
+    if let Some((parent_id, parent_name)) = parent {
+        header[40..56].copy_from_slice(&parent_id);
+        let name: Vec<u8> = parent_name.encode_utf16().flat_map(|c| c.to_be_bytes().to_vec()).collect();
+        header[64..64 + name.len()].copy_from_slice(&name);
+
+        // W2ru locator after the blocks
+        locator_data = utf16le(&format!(".\\{}", parent_name));
+        let locator_offset = (next_sector * 512) as u64;
+        header[576..580].copy_from_slice(b"W2ru");
+        header[580..584].copy_from_slice(&512u32.to_be_bytes());
+        header[584..588].copy_from_slice(&(locator_data.len() as u32).to_be_bytes());
+        header[592..600].copy_from_slice(&locator_offset.to_be_bytes());
+        locator_data.resize(512, 0);
+    }
+    let checksum = vhd_checksum(&header);
+    header[36..40].copy_from_slice(&checksum.to_be_bytes());
+
+    data.extend_from_slice(&header);
+    data.extend_from_slice(&bat);
+    data.extend_from_slice(&block_data);
+    data.extend_from_slice(&locator_data);
+    data.extend_from_slice(&footer);
+    fs::write(path, data).unwrap();
+}
+
+
+#[test]
+fn test_vhd_fixed() {
+    let dir = TestDir::new("vhd_fixed");
+    let disk = pattern(1, VHD_DISK_SIZE);
+    let mut data = disk.clone();
+    data.extend_from_slice(&vhd_footer(2, VHD_DISK_SIZE as u64, u64::MAX, [1; 16]));
+    fs::write(dir.join("fixed.vhd"), data).unwrap();
+
+    let mut reader = VhdReader::open(dir.join("fixed.vhd")).unwrap();
+    assert_eq!(reader.disk_type(), VhdDiskType::Fixed);
+    assert_eq!(reader.virtual_size(), VHD_DISK_SIZE as u64);
+
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, disk);
+}
+
+
+#[test]
+fn test_vhd_differencing() {
+    let dir = TestDir::new("vhd_differencing");
+    let parent_id = [0x11; 16];
+
+    // Parent: blocks 0 and 2 allocated
+    let parent_block_0 = pattern(2, VHD_BLOCK_SIZE);
+    let parent_block_2 = pattern(3, VHD_BLOCK_SIZE);
+    write_dynamic_vhd(&dir.join("parent.vhd"), parent_id, &[
+        Some((0xff, parent_block_0.clone())),
+        None,
+        Some((0xff, parent_block_2.clone())),
+        None
+    ], None);
+
+    // Child: block 1 fully written, sectors 0 and 2 of block 2 written
+    let child_block_1 = pattern(4, VHD_BLOCK_SIZE);
+    let child_block_2 = pattern(5, VHD_BLOCK_SIZE);
+    write_dynamic_vhd(&dir.join("child.vhd"), [0x22; 16], &[
+        None,
+        Some((0xff, child_block_1.clone())),
+        Some((0b1010_0000, child_block_2.clone())),
+        None
+    ], Some((parent_id, "parent.vhd")));
+
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&parent_block_0);
+    expected.extend_from_slice(&child_block_1);
+    for sector in 0..VHD_BLOCK_SIZE / 512 {
+        let source = if sector == 0 || sector == 2 { &child_block_2 } else { &parent_block_2 };
+        expected.extend_from_slice(&source[sector * 512..(sector + 1) * 512]);
+    }
+    expected.extend_from_slice(&vec![0u8; VHD_BLOCK_SIZE]);
+
+    let mut parent = VhdReader::open(dir.join("parent.vhd")).unwrap();
+    assert_eq!(parent.disk_type(), VhdDiskType::Dynamic);
+    let mut contents = Vec::new();
+    parent.read_to_end(&mut contents).unwrap();
+    assert_eq!(&contents[..VHD_BLOCK_SIZE], &parent_block_0[..]);
+    assert_eq!(&contents[VHD_BLOCK_SIZE..2 * VHD_BLOCK_SIZE], &vec![0u8; VHD_BLOCK_SIZE][..]);
+
+    let mut child = VhdReader::open(dir.join("child.vhd")).unwrap();
+    assert_eq!(child.disk_type(), VhdDiskType::Differencing);
+    assert_eq!(child.chain_paths(), vec![dir.join("child.vhd"), dir.join("parent.vhd")]);
+
+    let mut contents = Vec::new();
+    child.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, expected);
+
+    // Unaligned read across the mixed sectors of block 2
+    child.seek(SeekFrom::Start(2 * VHD_BLOCK_SIZE as u64 + 300)).unwrap();
+    let mut buf = vec![0u8; 1000];
+    child.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &expected[2 * VHD_BLOCK_SIZE + 300..2 * VHD_BLOCK_SIZE + 1300]);
+
+    // A parent with a different id is refused
+    write_dynamic_vhd(&dir.join("parent.vhd"), [0x33; 16], &[None, None, None, None], None);
+    assert!(VhdReader::open(dir.join("child.vhd")).is_err());
+}
+
+
+/************************* VHDX *************************/
+
+const VHDX_BLOCK_SIZE: usize = MIB;
+const VHDX_DISK_SIZE: usize = 4 * VHDX_BLOCK_SIZE;
+const PARENT_DATA_WRITE_GUID: &str = "6B2B6A46-56A8-4A5C-9B0E-3E5B8D1A2C01";
+
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 > 0 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+
+/// Get the on-disk bytes of a GUID string
+fn guid(s: &str) -> Vec<u8> {
+    let hex: Vec<u8> = s.split('-')
+        .flat_map(|p| p.as_bytes().chunks(2).map(|c| u8::from_str_radix(std::str::from_utf8(c).unwrap(), 16).unwrap()).collect::<Vec<u8>>())
+        .collect();
+    let mut bytes = Vec::new();
+    bytes.extend(hex[0..4].iter().rev());
+    bytes.extend(hex[4..6].iter().rev());
+    bytes.extend(hex[6..8].iter().rev());
+    bytes.extend_from_slice(&hex[8..16]);
+    bytes
+}
+
+
+fn set_checksum(data: &mut [u8]) {
+    let checksum = crc32c(data);
+    data[4..8].copy_from_slice(&checksum.to_le_bytes());
+}
+
+
+/// Write a VHDX with 1 MiB blocks. BAT entries are (state, file offset in MiB).
+fn write_vhdx(
+    path: &PathBuf,
+    data_write_guid: &str,
+    bat_entries: &[(usize, (u64, u64))],
+    payload: &[(usize, Vec<u8>)],
+    parent_locator: Option<&[(&str, &str)]>
+) {
+    let file_size = payload.iter().map(|(o, d)| o + d.len()).max().unwrap_or(0).max(3 * MIB);
+    let mut data = vec![0u8; file_size];
+    data[0..8].copy_from_slice(b"vhdxfile");
+
+    for (offset, sequence) in &[(0x10000usize, 2u64), (0x20000, 1)] {
+        let header = &mut data[*offset..*offset + 4096];
+        header[0..4].copy_from_slice(b"head");
+        header[8..16].copy_from_slice(&sequence.to_le_bytes());
+        header[32..48].copy_from_slice(&guid(data_write_guid));
+        header[66..68].copy_from_slice(&1u16.to_le_bytes());
+        set_checksum(header);
+    }
+
+    for offset in &[0x30000usize, 0x40000] {
+        let table = &mut data[*offset..*offset + 65536];
+        table[0..4].copy_from_slice(b"regi");
+        table[8..12].copy_from_slice(&2u32.to_le_bytes());
+        table[16..32].copy_from_slice(&guid("2DC27766-F623-4200-9D64-115E9BFD4A08"));
+        table[32..40].copy_from_slice(&(MIB as u64).to_le_bytes());
+        table[40..44].copy_from_slice(&(MIB as u32).to_le_bytes());
+        table[48..64].copy_from_slice(&guid("8B7CA206-4790-4B9A-B8FE-575F050F886E"));
+        table[64..72].copy_from_slice(&(2 * MIB as u64).to_le_bytes());
+        table[72..76].copy_from_slice(&(MIB as u32).to_le_bytes());
+        set_checksum(table);
+    }
+
+    // Metadata items
+    let has_parent = parent_locator.is_some();
+    let mut items: Vec<(&str, Vec<u8>)> = vec![
+        ("CAA16737-FA36-4D43-B3B6-33F0AA44E76B", {
+            let mut p = (VHDX_BLOCK_SIZE as u32).to_le_bytes().to_vec();
+            p.extend_from_slice(&(if has_parent { 2u32 } else { 0 }).to_le_bytes());
+            p
+        }),
+        ("2FA54224-CD1B-4876-B211-5DBED83BF4B8", (VHDX_DISK_SIZE as u64).to_le_bytes().to_vec()),
+        ("8141BF1D-A96F-4709-BA47-F233A8FAAB5F", 512u32.to_le_bytes().to_vec()),
+        ("CDA348C7-445D-4471-9CC9-E9885251C556", 4096u32.to_le_bytes().to_vec())
+    ];
+    if let Some(entries) = parent_locator {
+        let mut locator = guid("B04AEFB7-D19E-4A81-B789-25B8E9445913");
+        locator.extend_from_slice(&0u16.to_le_bytes());
+        locator.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        let mut strings = Vec::new();
+        let strings_start = 20 + 12 * entries.len();
+        for (key, value) in entries {
+            let key = utf16le(key);
+            let value = utf16le(value);
+            locator.extend_from_slice(&((strings_start + strings.len()) as u32).to_le_bytes());
+            locator.extend_from_slice(&((strings_start + strings.len() + key.len()) as u32).to_le_bytes());
+            locator.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            locator.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            strings.extend_from_slice(&key);
+            strings.extend_from_slice(&value);
+        }
+        locator.extend_from_slice(&strings);
+        items.push(("A8D35F2B-B30B-454D-ABF7-D3D84834AB0C", locator));
+    }
+
+    let metadata = &mut data[2 * MIB..3 * MIB];
+    metadata[0..8].copy_from_slice(b"metadata");
+    metadata[10..12].copy_from_slice(&(items.len() as u16).to_le_bytes());
+    let mut item_offset = 65536;
+    for (i, (id, item)) in items.iter().enumerate() {
+        let entry = &mut metadata[32 + i * 32..64 + i * 32];
+        entry[0..16].copy_from_slice(&guid(id));
+        entry[16..20].copy_from_slice(&(item_offset as u32).to_le_bytes());
+        entry[20..24].copy_from_slice(&(item.len() as u32).to_le_bytes());
+        metadata[item_offset..item_offset + item.len()].copy_from_slice(item);
+        item_offset += item.len();
+    }
+
+    for (index, (state, offset_mib)) in bat_entries {
+        let entry = state | (offset_mib << 20);
+        data[MIB + index * 8..MIB + index * 8 + 8].copy_from_slice(&entry.to_le_bytes());
+    }
+
+    for (offset, contents) in payload {
+        data[*offset..*offset + contents.len()].copy_from_slice(contents);
+    }
+
+    fs::write(path, data).unwrap();
+}
+
+
+#[test]
+fn test_vhdx_differencing() {
+    let dir = TestDir::new("vhdx_differencing");
+
+    // Parent: blocks 0 and 3 present, block 1 not present, block 2 zero
+    let parent_block_0 = pattern(6, VHDX_BLOCK_SIZE);
+    let parent_block_3 = pattern(7, VHDX_BLOCK_SIZE);
+    write_vhdx(&dir.join("parent.vhdx"), PARENT_DATA_WRITE_GUID, &[
+        (0, (6, 3)),
+        (1, (0, 0)),
+        (2, (2, 0)),
+        (3, (6, 4))
+    ], &[
+        (3 * MIB, parent_block_0.clone()),
+        (4 * MIB, parent_block_3.clone())
+    ], None);
+
+    // Child: sectors 0 and 2 of block 0, all of block 1
+    let child_block_0 = pattern(8, VHDX_BLOCK_SIZE);
+    let child_block_1 = pattern(9, VHDX_BLOCK_SIZE);
+    let mut bitmap = vec![0u8; MIB];
+    bitmap[0] = 0b0000_0101;
+    let parent_linkage = format!("{{{}}}", PARENT_DATA_WRITE_GUID.to_lowercase());
+    write_vhdx(&dir.join("child.vhdx"), "0E0A4A3B-1111-2222-3333-444455556666", &[
+        (0, (7, 3)),
+        (1, (6, 5)),
+        (2, (0, 0)),
+        (3, (0, 0)),
+        (4096, (6, 4))
+    ], &[
+        (3 * MIB, child_block_0.clone()),
+        (4 * MIB, bitmap),
+        (5 * MIB, child_block_1.clone())
+    ], Some(&[
+        ("parent_linkage", &parent_linkage),
+        ("relative_path", ".\\parent.vhdx"),
+        ("absolute_win32_path", "\\\\?\\C:\\images\\parent.vhdx")
+    ]));
+
+    let mut expected = Vec::new();
+    for sector in 0..VHDX_BLOCK_SIZE / 512 {
+        let source = if sector == 0 || sector == 2 { &child_block_0 } else { &parent_block_0 };
+        expected.extend_from_slice(&source[sector * 512..(sector + 1) * 512]);
+    }
+    expected.extend_from_slice(&child_block_1);
+    expected.extend_from_slice(&vec![0u8; VHDX_BLOCK_SIZE]);
+    expected.extend_from_slice(&parent_block_3);
+
+    let parent = VhdxReader::open(dir.join("parent.vhdx")).unwrap();
+    assert!(!parent.is_differencing());
+    assert_eq!(parent.virtual_size(), VHDX_DISK_SIZE as u64);
+    assert_eq!(parent.logical_sector_size(), 512);
+    assert_eq!(parent.physical_sector_size(), 4096);
+
+    let mut child = VhdxReader::open(dir.join("child.vhdx")).unwrap();
+    assert!(child.is_differencing());
+    assert_eq!(child.parent_locator().get("relative_path").map(|s| s.as_str()), Some(".\\parent.vhdx"));
+    assert_eq!(child.chain_paths(), vec![dir.join("child.vhdx"), dir.join("parent.vhdx")]);
+
+    let mut contents = Vec::new();
+    child.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents.len(), expected.len());
+    assert!(contents == expected);
+
+    child.seek(SeekFrom::Start(100)).unwrap();
+    let mut buf = vec![0u8; 2000];
+    child.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &expected[100..2100]);
+
+    // A parent with a different data write guid is refused
+    write_vhdx(&dir.join("parent.vhdx"), "00000000-1111-2222-3333-444455556666", &[], &[], None);
+    assert!(VhdxReader::open(dir.join("child.vhdx")).is_err());
+}
@@ -0,0 +1,242 @@
+mod common;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use tsk::img_vmdk::{VmdkReader, VmdkDescriptor};
+use common::{TestDir, pattern};
+
+
+/// Grain size in sectors
+const GRAIN_SECTORS: u64 = 8;
+const GRAIN_SIZE: usize = (GRAIN_SECTORS * 512) as usize;
+const GTES_PER_GT: u32 = 4;
+
+
+fn pad_to_sector(data: &mut Vec<u8>) {
+    let len = data.len().div_ceil(512) * 512;
+    data.resize(len, 0);
+}
+
+
+/// Build a sparse extent header
+fn sparse_header(flags: u32, capacity_sectors: u64, descriptor: (u64, u64), gd_sector: u64) -> Vec<u8> {
+    let mut header = vec![0u8; 512];
+    header[0..4].copy_from_slice(b"KDMV");
+    header[4..8].copy_from_slice(&(if flags & (1 << 16) > 0 { 3u32 } else { 1 }).to_le_bytes());
+    header[8..12].copy_from_slice(&flags.to_le_bytes());
+    header[12..20].copy_from_slice(&capacity_sectors.to_le_bytes());
+    header[20..28].copy_from_slice(&GRAIN_SECTORS.to_le_bytes());
+    header[28..36].copy_from_slice(&descriptor.0.to_le_bytes());
+    header[36..44].copy_from_slice(&descriptor.1.to_le_bytes());
+    header[44..48].copy_from_slice(&GTES_PER_GT.to_le_bytes());
+    header[56..64].copy_from_slice(&gd_sector.to_le_bytes());
+    header[73..77].copy_from_slice(b"\n \r\n");
+    if flags & (1 << 16) > 0 {
+        header[77..79].copy_from_slice(&1u16.to_le_bytes());
+    }
+    header
+}
+
+
+/// Write a sparse extent. Grains are (grain number, Some(data)) for data,
+/// or (grain number, None) for a zero grain.
+fn write_sparse_extent(path: &PathBuf, capacity_sectors: u64, grains: &[(usize, Option<Vec<u8>>)], descriptor: Option<&str>) {
+    let grain_count = (capacity_sectors / GRAIN_SECTORS) as usize;
+    let gt_count = grain_count.div_ceil(GTES_PER_GT as usize);
+
+    // Header, descriptor, grain directory, grain tables, grains
+    let descriptor_sectors = descriptor.map(|d| (d.len() as u64).div_ceil(512)).unwrap_or(0);
+    let gd_sector = 1 + descriptor_sectors;
+    let gt_sector = gd_sector + 1;
+    let mut grain_sector = gt_sector + gt_count as u64;
+
+    let mut data = sparse_header(0, capacity_sectors, (if descriptor.is_some() { 1 } else { 0 }, descriptor_sectors), gd_sector);
+    if let Some(d) = descriptor {
+        data.extend_from_slice(d.as_bytes());
+        pad_to_sector(&mut data);
+    }
+
+    let mut directory = Vec::new();
+    for i in 0..gt_count {
+        directory.extend_from_slice(&((gt_sector + i as u64) as u32).to_le_bytes());
+    }
+    data.extend_from_slice(&directory);
+    pad_to_sector(&mut data);
+
+    let mut tables = vec![0u32; gt_count * GTES_PER_GT as usize];
+    let mut grain_data = Vec::new();
+    for (grain, contents) in grains {
+        match contents {
+            Some(contents) => {
+                tables[*grain] = grain_sector as u32;
+                grain_data.extend_from_slice(contents);
+                grain_sector += GRAIN_SECTORS;
+            },
+            None => tables[*grain] = 1
+        }
+    }
+    for table in tables.chunks(GTES_PER_GT as usize) {
+        for entry in table {
+            data.extend_from_slice(&entry.to_le_bytes());
+        }
+        pad_to_sector(&mut data);
+    }
+    data.extend_from_slice(&grain_data);
+
+    fs::write(path, data).unwrap();
+}
+
+
+/// Write a stream optimized extent with compressed grains and the real header in the footer
+fn write_stream_optimized(path: &PathBuf, capacity_sectors: u64, grains: &[(usize, Vec<u8>)], descriptor: &str) {
+    let flags = (1 << 16) | (1 << 17);
+    let descriptor_sectors = (descriptor.len() as u64).div_ceil(512);
+    let mut data = sparse_header(flags, capacity_sectors, (1, descriptor_sectors), u64::MAX);
+    data.extend_from_slice(descriptor.as_bytes());
+    pad_to_sector(&mut data);
+
+    let mut table = vec![0u32; GTES_PER_GT as usize];
+    for (grain, contents) in grains {
+        table[*grain] = (data.len() / 512) as u32;
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents).unwrap();
+        let compressed = encoder.finish().unwrap();
+        data.extend_from_slice(&(*grain as u64 * GRAIN_SECTORS).to_le_bytes());
+        data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&compressed);
+        pad_to_sector(&mut data);
+    }
+
+    // Grain table marker and table, directory marker and directory
+    let gt_sector = data.len() / 512 + 1;
+    data.extend_from_slice(&vec![0u8; 512]);
+    for entry in &table {
+        data.extend_from_slice(&entry.to_le_bytes());
+    }
+    pad_to_sector(&mut data);
+    let gd_sector = data.len() / 512 + 1;
+    data.extend_from_slice(&vec![0u8; 512]);
+    data.extend_from_slice(&(gt_sector as u32).to_le_bytes());
+    pad_to_sector(&mut data);
+
+    // Footer marker, footer, end of stream marker
+    data.extend_from_slice(&vec![0u8; 512]);
+    data.extend_from_slice(&sparse_header(flags, capacity_sectors, (1, descriptor_sectors), gd_sector as u64));
+    data.extend_from_slice(&vec![0u8; 512]);
+
+    fs::write(path, data).unwrap();
+}
+
+
+#[test]
+fn test_vmdk_descriptor() {
+    let descriptor = VmdkDescriptor::parse(
+        "# Disk DescriptorFile\nversion=1\nCID=1234abcd\nparentCID=ffffffff\ncreateType=\"twoGbMaxExtentSparse\"\n\n\
+        # Extent description\nRW 4192256 SPARSE \"disk s001.vmdk\"\nRDONLY 2048 FLAT \"flat.vmdk\" 128\nRW 100 ZERO\n\n\
+        # The Disk Data Base\nddb.adapterType = \"lsilogic\"\n"
+    ).unwrap();
+
+    assert_eq!(descriptor.version, Some(1));
+    assert_eq!(descriptor.cid, 0x1234abcd);
+    assert!(!descriptor.has_parent());
+    assert_eq!(descriptor.create_type, "twoGbMaxExtentSparse");
+    assert_eq!(descriptor.extents.len(), 3);
+    assert_eq!(descriptor.extents[0].file_name.as_deref(), Some("disk s001.vmdk"));
+    assert_eq!(descriptor.extents[1].offset, 128);
+    assert_eq!(descriptor.extents[2].extent_type, "ZERO");
+    assert_eq!(descriptor.ddb.get("ddb.adapterType").map(|s| s.as_str()), Some("lsilogic"));
+
+    assert!(VmdkDescriptor::parse("version=1\n").is_err());
+}
+
+
+#[test]
+fn test_vmdk_split_sparse_with_snapshot() {
+    let dir = TestDir::new("vmdk_split_sparse");
+
+    // Base disk: two sparse extents of 4 grains each and a flat extent
+    let grain_0 = pattern(1, GRAIN_SIZE);
+    let grain_5 = pattern(2, GRAIN_SIZE);
+    let flat = pattern(3, 2 * GRAIN_SIZE);
+    write_sparse_extent(&dir.join("base-s001.vmdk"), 4 * GRAIN_SECTORS, &[(0, Some(grain_0.clone())), (2, None)], None);
+    write_sparse_extent(&dir.join("base-s002.vmdk"), 4 * GRAIN_SECTORS, &[(1, Some(grain_5.clone()))], None);
+    let mut flat_file = vec![0u8; 1024];
+    flat_file.extend_from_slice(&flat);
+    fs::write(dir.join("base-flat.vmdk"), flat_file).unwrap();
+    fs::write(dir.join("base.vmdk"), format!(
+        "# Disk DescriptorFile\nversion=1\nCID=0000aaaa\nparentCID=ffffffff\ncreateType=\"twoGbMaxExtentSparse\"\n\
+        RW {0} SPARSE \"base-s001.vmdk\"\nRW {0} SPARSE \"base-s002.vmdk\"\nRW {1} FLAT \"base-flat.vmdk\" 2\nRW {2} ZERO\n",
+        4 * GRAIN_SECTORS, 2 * GRAIN_SECTORS, GRAIN_SECTORS
+    )).unwrap();
+
+    let mut expected = vec![0u8; 11 * GRAIN_SIZE];
+    expected[..GRAIN_SIZE].copy_from_slice(&grain_0);
+    expected[5 * GRAIN_SIZE..6 * GRAIN_SIZE].copy_from_slice(&grain_5);
+    expected[8 * GRAIN_SIZE..10 * GRAIN_SIZE].copy_from_slice(&flat);
+
+    let mut base = VmdkReader::open(dir.join("base.vmdk")).unwrap();
+    assert_eq!(base.virtual_size(), expected.len() as u64);
+    let mut contents = Vec::new();
+    base.read_to_end(&mut contents).unwrap();
+    assert!(contents == expected);
+
+    // Snapshot: monolithic sparse with an embedded descriptor. Grain 1 is
+    // new data and grain 0 is zeroed, the rest comes from the base disk.
+    let snapshot_grain_1 = pattern(4, GRAIN_SIZE);
+    let descriptor = format!(
+        "# Disk DescriptorFile\nversion=1\nCID=0000bbbb\nparentCID=0000aaaa\ncreateType=\"monolithicSparse\"\n\
+        parentFileNameHint=\"C:\\\\VMs\\\\base.vmdk\"\nRW {} SPARSE \"snapshot.vmdk\"\n",
+        11 * GRAIN_SECTORS
+    );
+    write_sparse_extent(&dir.join("snapshot.vmdk"), 11 * GRAIN_SECTORS, &[(0, None), (1, Some(snapshot_grain_1.clone()))], Some(&descriptor));
+    expected[..GRAIN_SIZE].copy_from_slice(&vec![0u8; GRAIN_SIZE]);
+    expected[GRAIN_SIZE..2 * GRAIN_SIZE].copy_from_slice(&snapshot_grain_1);
+
+    let mut snapshot = VmdkReader::open(dir.join("snapshot.vmdk")).unwrap();
+    assert_eq!(snapshot.descriptor().parent_cid, 0xaaaa);
+    assert_eq!(snapshot.chain_paths(), vec![dir.join("snapshot.vmdk"), dir.join("base.vmdk")]);
+    let mut contents = Vec::new();
+    snapshot.read_to_end(&mut contents).unwrap();
+    assert!(contents == expected);
+
+    // Reads that cross the extent boundaries
+    snapshot.seek(SeekFrom::Start(4 * GRAIN_SIZE as u64 - 100)).unwrap();
+    let mut buf = vec![0u8; 5 * GRAIN_SIZE];
+    snapshot.read_exact(&mut buf).unwrap();
+    assert!(buf[..] == expected[4 * GRAIN_SIZE - 100..9 * GRAIN_SIZE - 100]);
+
+    // A parent with another CID is refused
+    fs::write(dir.join("base.vmdk"), "version=1\nCID=0000cccc\nparentCID=ffffffff\nRW 8 ZERO\n").unwrap();
+    assert!(VmdkReader::open(dir.join("snapshot.vmdk")).is_err());
+}
+
+
+#[test]
+fn test_vmdk_stream_optimized() {
+    let dir = TestDir::new("vmdk_stream_optimized");
+    let grain_1 = pattern(5, GRAIN_SIZE);
+    let grain_3 = pattern(6, GRAIN_SIZE);
+    let descriptor = format!(
+        "# Disk DescriptorFile\nversion=1\nCID=12345678\nparentCID=ffffffff\ncreateType=\"streamOptimized\"\nRW {} SPARSE \"stream.vmdk\"\n",
+        4 * GRAIN_SECTORS
+    );
+    write_stream_optimized(&dir.join("stream.vmdk"), 4 * GRAIN_SECTORS, &[(1, grain_1.clone()), (3, grain_3.clone())], &descriptor);
+
+    let mut expected = vec![0u8; 4 * GRAIN_SIZE];
+    expected[GRAIN_SIZE..2 * GRAIN_SIZE].copy_from_slice(&grain_1);
+    expected[3 * GRAIN_SIZE..].copy_from_slice(&grain_3);
+
+    let mut reader = VmdkReader::open(dir.join("stream.vmdk")).unwrap();
+    assert_eq!(reader.descriptor().create_type, "streamOptimized");
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents).unwrap();
+    assert!(contents == expected);
+
+    reader.seek(SeekFrom::Start(GRAIN_SIZE as u64 + 10)).unwrap();
+    let mut buf = vec![0u8; 100];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &grain_1[10..110]);
+}
@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+use tsk::tsk_img::TskImg;
+use tsk::tsk_vs::TskVsType;
+use tsk::tsk_discovery::DiscoveredContent;
+
+
+/// Write a DOS partition table with one Linux partition into the first
+/// sector of disk
+fn write_mbr(disk: &mut [u8], start: u32, sectors: u32) {
+    let entry = &mut disk[446..462];
+    entry[4] = 0x83;
+    entry[8..12].copy_from_slice(&start.to_le_bytes());
+    entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    disk[510] = 0x55;
+    disk[511] = 0xAA;
+}
+
+
+/// A 4 MiB disk with a DOS partition at 1 MiB that holds another DOS
+/// partition table (as a BSD disklabel would), whose partition is empty
+fn nested_image() -> Vec<u8> {
+    let mut data = vec![0u8; 4 * 1024 * 1024];
+    write_mbr(&mut data, 2048, 4096);
+    write_mbr(&mut data[1024 * 1024..], 2048, 2048);
+    data
+}
+
+
+#[test]
+fn test_discover_nested_volume_systems() {
+    let tsk_img = TskImg::from_buffer("nested in memory", nested_image())
+        .expect("Could not create TskImg from buffer");
+    let evidence = tsk_img.discover();
+    println!("{}", evidence);
+
+    let volume_systems = evidence.volume_systems();
+    assert_eq!(volume_systems.len(), 2);
+    assert_eq!(volume_systems[0].tsk_vs.vs_type(), TskVsType::Dos);
+    assert_eq!(volume_systems[0].tsk_vs.offset(), 0);
+    assert_eq!(volume_systems[1].tsk_vs.offset(), 1024 * 1024);
+
+    let outer = match evidence.root() {
+        DiscoveredContent::VolumeSystem(volume_system) => volume_system,
+        other => panic!("Expected a volume system, found {:?}", other)
+    };
+    let partition = outer.partitions.iter()
+        .find(|p| p.offset == 1024 * 1024)
+        .expect("Outer partition not found");
+    assert!(matches!(partition.content, DiscoveredContent::VolumeSystem(_)));
+    assert!(outer.partitions.iter().any(|p| matches!(p.content, DiscoveredContent::Skipped(_))));
+
+    // The inner partition is empty
+    assert!(evidence.file_systems().is_empty());
+    let failures = evidence.failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, 2 * 1024 * 1024);
+    assert!(failures[0].1.contains("no file system"), "{}", failures[0].1);
+}
+
+
+#[test]
+fn test_discover_nothing() {
+    let tsk_img = TskImg::from_buffer("zeros in memory", vec![0u8; 1024 * 1024])
+        .expect("Could not create TskImg from buffer");
+    let evidence = tsk_img.discover();
+
+    match evidence.root() {
+        DiscoveredContent::Failed(reason) => {
+            assert!(reason.contains("no volume system"), "{}", reason);
+            assert!(reason.contains("no file system"), "{}", reason);
+        },
+        other => panic!("Expected a failure, found {:?}", other)
+    }
+    assert!(evidence.volume_systems().is_empty());
+    assert_eq!(evidence.failures().len(), 1);
+}
+
+
+#[test]
+fn test_discover_whole_disk_fs() {
+    let source = PathBuf::from(format!("{}/samples/ntfs.raw", env!("CARGO_MANIFEST_DIR")));
+    let tsk_img = TskImg::from_utf8_sing(source)
+        .expect("Could not create TskImg");
+    let evidence = tsk_img.discover();
+    println!("{}", evidence);
+
+    assert!(matches!(evidence.root(), DiscoveredContent::FileSystem(_)));
+    let file_systems = evidence.file_systems();
+    assert_eq!(file_systems.len(), 1);
+    assert_eq!(file_systems[0].0, 0);
+    file_systems[0].1.file_open_meta(0)
+        .expect("Could not open $MFT");
+}
+
+
+#[test]
+fn test_discover_partitioned_sample() {
+    let source = PathBuf::from(format!("{}/samples/mbr.raw", env!("CARGO_MANIFEST_DIR")));
+    let tsk_img = TskImg::from_utf8_sing(source)
+        .expect("Could not create TskImg");
+    let evidence = tsk_img.discover();
+    println!("{}", evidence);
+
+    assert_eq!(evidence.volume_systems().len(), 1);
+    for (offset, tsk_fs) in evidence.file_systems() {
+        assert!(offset > 0);
+        tsk_fs.iter_file_names()
+            .expect("Could not iterate the file system");
+    }
+}
@@ -0,0 +1,68 @@
+// TskImg and TskFs are not Send or Sync, but the shared image API takes an Arc
+#![allow(clippy::arc_with_non_send_sync)]
+
+use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+use tsk::tsk_fs::TskFs;
+use tsk::tsk_fs_file_stream::TskFsFileStream;
+use tsk::tsk_img::TskImg;
+
+
+fn ntfs_img() -> Arc<TskImg> {
+    let source = PathBuf::from(format!("{}/samples/ntfs.raw", env!("CARGO_MANIFEST_DIR")));
+    Arc::new(TskImg::from_utf8_sing(source).expect("Could not create TskImg"))
+}
+
+
+#[test]
+fn test_tsk_fs_file_stream() {
+    let tsk_fs = Arc::new(TskFs::from_shared_img(ntfs_img(), 0)
+        .expect("Could not open TskFs at offset 0"));
+
+    let mut stream = TskFsFileStream::from_path(tsk_fs.clone(), "/$MFT")
+        .expect("Could not stream $MFT");
+    println!("{:?}", stream);
+    let mut signature = [0_u8; 4];
+    stream.read_exact(&mut signature)
+        .expect("Error reading $MFT.");
+    assert_eq!(&signature, b"FILE");
+
+    // MFT records are 1024 bytes
+    let by_inode = TskFsFileStream::from_meta(tsk_fs.clone(), 0)
+        .expect("Could not stream inode 0");
+    assert_eq!(by_inode.size(), stream.size());
+    stream.seek(SeekFrom::Start(1024))
+        .expect("Error seeking.");
+    stream.read_exact(&mut signature)
+        .expect("Error reading $MFT.");
+    assert_eq!(&signature, b"FILE");
+
+    // A file system that does not keep its image alive can not be streamed
+    let img = ntfs_img();
+    let unshared = Arc::new(TskFs::from_fs_offset(&img, 0)
+        .expect("Could not open TskFs at offset 0"));
+    assert!(TskFsFileStream::from_path(unshared, "/$MFT").is_err());
+}
+
+
+#[test]
+fn test_nested_tsk_img() {
+    let tsk_fs = Arc::new(TskFs::from_shared_img(ntfs_img(), 0)
+        .expect("Could not open TskFs at offset 0"));
+    let stream = TskFsFileStream::from_path(tsk_fs.clone(), "/$MFT")
+        .expect("Could not stream $MFT");
+    let size = stream.size();
+
+    // The nested image keeps the file system and outer image alive
+    let nested = stream.into_tsk_img()
+        .expect("Could not open the nested TskImg");
+    drop(tsk_fs);
+    assert_eq!(nested.size() as u64, size);
+
+    let mut handle = nested.get_handle();
+    let mut record = vec![0_u8; 1024];
+    handle.read_exact(&mut record)
+        .expect("Error reading the nested image.");
+    assert_eq!(&record[..4], b"FILE");
+}
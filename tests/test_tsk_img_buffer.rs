@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom};
+use tsk::tsk_img::{TskImg, TskImgType};
+
+
+fn sample(name: &str) -> Vec<u8> {
+    let source = PathBuf::from(format!("{}/samples/{}", env!("CARGO_MANIFEST_DIR"), name));
+    fs::read(source).expect("Error reading sample.")
+}
+
+
+#[test]
+fn test_tsk_img_from_buffer() {
+    let data = sample("ntfs.raw");
+    let size = data.len();
+    let tsk_img = TskImg::from_buffer("ntfs.raw in memory", data)
+        .expect("Could not create TskImg from buffer");
+    assert_eq!(tsk_img.img_type(), TskImgType::External);
+    assert_eq!(tsk_img.size() as usize, size);
+
+    let tsk_fs = tsk_img.get_fs_from_offset(0)
+        .expect("Could not open TskFs at offset 0");
+    let _mft_fh = tsk_fs.file_open_meta(0)
+        .expect("Could not open $MFT");
+}
+
+
+#[test]
+fn test_tsk_img_from_slice() {
+    let data = sample("mbr.raw");
+    let tsk_img = TskImg::from_slice("mbr.raw in memory", &data)
+        .expect("Could not create TskImg from slice");
+    println!("{:?}", tsk_img);
+
+    let tsk_vs = tsk_img.get_vs_from_offset(0)
+        .expect("Could not open TskVs at offset 0");
+    let tsk_part = tsk_vs.get_partition_at_index(2)
+        .expect("Could not get partion at index 2");
+    let mut tsk_part_handle = tsk_part.get_handle();
+    tsk_part_handle.seek(SeekFrom::Start(22528))
+        .expect("Error seeking to offset.");
+    let mut buffer = vec![0_u8; 19];
+    tsk_part_handle.read_exact(&mut buffer)
+        .expect("Error reading bytes.");
+    assert_eq!(&buffer, b"place,user,password");
+
+    let mut tsk_img_handle = tsk_img.get_handle();
+    let mut boot_sector = vec![0_u8; 512];
+    tsk_img_handle.read_exact(&mut boot_sector)
+        .expect("Error reading the boot sector.");
+    assert_eq!(boot_sector, &data[..512]);
+}
@@ -0,0 +1,66 @@
+use std::fs::File;
+use std::path::PathBuf;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use tsk::tsk_img::TskImg;
+use tsk::tsk_img_reader::TskImgReadSeek;
+
+
+#[test]
+fn test_tsk_img_handle() {
+    let source = PathBuf::from(format!("{}/samples/mbr.raw", env!("CARGO_MANIFEST_DIR")));
+    let mut expected = Vec::new();
+    File::open(&source).expect("Error opening file.")
+        .read_to_end(&mut expected)
+        .expect("Error reading file.");
+
+    let tsk_img = TskImg::from_utf8_sing(&source)
+        .expect("Could not create TskImg");
+    let mut tsk_img_handle = tsk_img.get_handle();
+    println!("{:?}", tsk_img_handle);
+
+    // The MBR boot signature
+    let mut buffer = [0_u8; 512];
+    tsk_img_handle.read_exact(&mut buffer)
+        .expect("Error reading the boot sector.");
+    assert_eq!(&buffer[510..], &[0x55, 0xaa]);
+
+    // Reads stop at the end of the image
+    let size = tsk_img_handle.seek(SeekFrom::End(-100))
+        .expect("Error seeking to the end.");
+    assert_eq!(size, expected.len() as u64 - 100);
+    let mut tail = Vec::new();
+    tsk_img_handle.read_to_end(&mut tail)
+        .expect("Error reading the end of the image.");
+    assert_eq!(tail, &expected[expected.len() - 100..]);
+
+    assert!(tsk_img_handle.seek(SeekFrom::Current(1)).is_err());
+    assert!(tsk_img_handle.seek(SeekFrom::Current(-(expected.len() as i64) - 1)).is_err());
+
+    // The whole image
+    tsk_img_handle.seek(SeekFrom::Start(0))
+        .expect("Error seeking to the start.");
+    let mut content = Vec::new();
+    tsk_img_handle.read_to_end(&mut content)
+        .expect("Error reading the image.");
+    assert!(content == expected);
+}
+
+
+#[test]
+fn test_tsk_img_handle_read_seek() {
+    let data: Vec<u8> = (0..8192_u32).map(|i| (i % 251) as u8).collect();
+    let reader = TskImgReadSeek::from_read_seek(
+        "Memory",
+        Box::new(Cursor::new(data.clone())),
+        data.len() as i64
+    ).expect("Error creating TskImgReadSeek.");
+    let tsk_img: TskImg = reader.into();
+
+    let mut tsk_img_handle = tsk_img.get_handle();
+    tsk_img_handle.seek(SeekFrom::Start(1000))
+        .expect("Error seeking to offset.");
+    let mut buffer = vec![0_u8; 3000];
+    tsk_img_handle.read_exact(&mut buffer)
+        .expect("Error reading bytes.");
+    assert_eq!(buffer, &data[1000..4000]);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use tsk::tsk_img::{TskImg, find_split_segments};
+use common::TestDir;
+
+
+#[test]
+fn test_find_split_segments() {
+    let dir = TestDir::new("find_split_segments");
+    for name in &["image.001", "image.002", "image.003", "image.005", "disk.aa", "disk.ab"] {
+        File::create(dir.join(name)).expect("Error creating segment.");
+    }
+
+    // .004 is missing so .005 is not part of the set
+    let segments = find_split_segments(dir.join("image.001"));
+    assert_eq!(segments, vec![
+        dir.join("image.001"),
+        dir.join("image.002"),
+        dir.join("image.003")
+    ]);
+
+    let segments = find_split_segments(dir.join("disk.aa"));
+    assert_eq!(segments, vec![dir.join("disk.aa"), dir.join("disk.ab")]);
+
+    // A single file is its own (only) segment
+    let segments = find_split_segments(dir.join("image.005"));
+    assert_eq!(segments, vec![dir.join("image.005")]);
+
+    // Files that do not start a split sequence are not followed by their
+    // namesakes
+    for (name, namesake) in &[("image.raw", "image.rax"), ("disk.dd", "disk.de"), ("disk.ab", "disk.ac"), ("image.01", "image.02")] {
+        File::create(dir.join(name)).expect("Error creating file.");
+        File::create(dir.join(namesake)).expect("Error creating file.");
+        assert_eq!(find_split_segments(dir.join(name)), vec![dir.join(name)]);
+    }
+}
+
+
+#[test]
+fn test_tsk_img_split() {
+    let source = PathBuf::from(format!("{}/samples/ntfs.raw", env!("CARGO_MANIFEST_DIR")));
+    let mut data = Vec::new();
+    File::open(&source).unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+
+    // Split the sample into three segments
+    let dir = TestDir::new("tsk_img_split");
+    let chunk_size = data.len().div_ceil(3);
+    for (i, chunk) in data.chunks(chunk_size).enumerate() {
+        let mut segment = File::create(dir.join(format!("ntfs.{:03}", i + 1))).unwrap();
+        segment.write_all(chunk).unwrap();
+    }
+
+    let tsk_img = TskImg::from_utf8_split_discover(dir.join("ntfs.001"))
+        .expect("Could not create split TskImg");
+    println!("{:?}", tsk_img);
+
+    let tsk_fs = tsk_img.get_fs_from_offset(0)
+        .expect("Could not open TskFs at offset 0");
+
+    let mft_fh = tsk_fs.file_open_meta(0)
+        .expect("Could not open $MFT");
+    println!("{:?}", mft_fh);
+
+    drop(mft_fh);
+    drop(tsk_fs);
+    drop(tsk_img);
+}
@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+use tsk::bindings as tsk_sys;
+use tsk::errors::ErrorType;
+use tsk::tsk_img::TskImg;
+use tsk::tsk_vs::{TskVs, TskVsType, TskEndian};
+use tsk::tsk_vs_part::TskVsPartFlag;
+
+
+/// A 2 MiB image with a DOS partition table holding one Linux partition
+/// from sector 2048 to the end of the image
+fn mbr_image() -> Vec<u8> {
+    let mut data = vec![0u8; 2 * 1024 * 1024];
+    let entry = &mut data[446..462];
+    entry[4] = 0x83;
+    entry[8..12].copy_from_slice(&2048u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&2048u32.to_le_bytes());
+    data[510] = 0x55;
+    data[511] = 0xAA;
+    data
+}
+
+
+#[test]
+fn test_vs_type_names() {
+    for vs_type in TskVsType::ALL.iter() {
+        assert_eq!(TskVsType::from_name(vs_type.name()), Some(*vs_type));
+        assert_eq!(vs_type.to_string(), vs_type.name());
+    }
+    assert_eq!(TskVsType::from_name("MBR"), Some(TskVsType::Dos));
+    assert_eq!(TskVsType::from_name("apm"), Some(TskVsType::Mac));
+    assert_eq!(TskVsType::from_name("detect"), Some(TskVsType::Detect));
+    assert_eq!(TskVsType::from_name("lvm"), None);
+}
+
+
+#[test]
+fn test_vs_type_conversion() {
+    for vs_type in TskVsType::ALL.iter() {
+        let raw: tsk_sys::TSK_VS_TYPE_ENUM = (*vs_type).into();
+        assert_eq!(TskVsType::from(raw), *vs_type);
+    }
+    assert_eq!(
+        TskVsType::from(tsk_sys::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_DETECT),
+        TskVsType::Detect
+    );
+    assert_eq!(
+        TskVsType::from(tsk_sys::TSK_VS_TYPE_ENUM_TSK_VS_TYPE_DBFILLER),
+        TskVsType::Unsupported
+    );
+    assert_eq!(TskEndian::from(tsk_sys::TSK_ENDIAN_ENUM_TSK_BIG_ENDIAN), TskEndian::Big);
+    assert_eq!(TskEndian::from(tsk_sys::TSK_ENDIAN_ENUM_TSK_UNKNOWN_ENDIAN), TskEndian::Unknown);
+}
+
+
+#[test]
+fn test_vs_part_flag_mask() {
+    assert_eq!(
+        TskVsPartFlag::to_mask(&[TskVsPartFlag::Alloc, TskVsPartFlag::Meta]),
+        tsk_sys::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_ALLOC | tsk_sys::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_META
+    );
+    assert_eq!(TskVsPartFlag::to_mask(&[]), tsk_sys::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_ALL);
+    assert_eq!(
+        TskVsPartFlag::from_mask(tsk_sys::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_ALL),
+        TskVsPartFlag::ALL.to_vec()
+    );
+    assert_eq!(
+        TskVsPartFlag::from_mask(tsk_sys::TSK_VS_PART_FLAG_ENUM_TSK_VS_PART_FLAG_UNALLOC),
+        vec![TskVsPartFlag::Unalloc]
+    );
+}
+
+
+#[test]
+fn test_tsk_vs_metadata() {
+    let tsk_img = TskImg::from_buffer("mbr in memory", mbr_image())
+        .expect("Could not create TskImg from buffer");
+
+    let tsk_vs = tsk_img.get_vs_from_offset(0)
+        .expect("Could not open TskVs at offset 0");
+    println!("{:?}", tsk_vs);
+    assert_eq!(tsk_vs.vs_type(), TskVsType::Dos);
+    assert_eq!(tsk_vs.block_size(), 512);
+    assert_eq!(tsk_vs.offset(), 0);
+    assert_eq!(tsk_vs.endian(), TskEndian::Little);
+    assert!(!tsk_vs.is_backup());
+
+    let partitions: Vec<_> = tsk_vs.get_partition_iter()
+        .expect("Could not get partition iterator for TskVs")
+        .collect();
+    assert_eq!(partitions.len() as u32, tsk_vs.partition_count());
+    assert!(partitions.iter().any(|p| p.get_start_offset() == 2048 * 512 && p.len() == 2048));
+}
+
+
+#[test]
+fn test_tsk_vs_open_with_type() {
+    let tsk_img = TskImg::from_buffer("mbr in memory", mbr_image())
+        .expect("Could not create TskImg from buffer");
+
+    let tsk_vs = TskVs::open_with_type(&tsk_img, 0, TskVsType::Dos)
+        .expect("Could not open the DOS partition table");
+    assert_eq!(tsk_vs.vs_type(), TskVsType::Dos);
+
+    // There is no GPT behind the MBR
+    let error = tsk_img.get_vs_from_offset_with_type(0, TskVsType::Gpt)
+        .expect_err("Opened a GPT that does not exist");
+    assert!(error.message.contains("gpt"), "{}", error.message);
+
+    assert!(TskVs::open_with_type(&tsk_img, 0, TskVsType::Unsupported).is_err());
+}
+
+
+#[test]
+fn test_tsk_vs_part_flags() {
+    let tsk_img = TskImg::from_buffer("mbr in memory", mbr_image())
+        .expect("Could not create TskImg from buffer");
+    let tsk_vs = tsk_img.get_vs_from_offset(0)
+        .expect("Could not open TskVs at offset 0");
+
+    let allocated: Vec<_> = tsk_vs.allocated_partitions()
+        .expect("Could not get allocated partitions")
+        .collect();
+    assert_eq!(allocated.len(), 1);
+    assert_eq!(allocated[0].flags(), vec![TskVsPartFlag::Alloc]);
+    assert_eq!(allocated[0].get_start_offset(), 2048 * 512);
+
+    let unallocated: Vec<_> = tsk_vs.unallocated_partitions()
+        .expect("Could not get unallocated partitions")
+        .collect();
+    assert!(!unallocated.is_empty());
+    assert!(unallocated.iter().all(|p| p.is_unallocated() && !p.is_allocated()));
+
+    let meta: Vec<_> = tsk_vs.meta_partitions()
+        .expect("Could not get meta partitions")
+        .collect();
+    assert!(meta.iter().any(|p| p.is_meta() && p.get_start_offset() == 0));
+
+    assert_eq!(
+        (allocated.len() + unallocated.len() + meta.len()) as u32,
+        tsk_vs.partition_count()
+    );
+
+    let not_meta = tsk_vs.get_partition_iter_with_flags(&[TskVsPartFlag::Alloc, TskVsPartFlag::Unalloc])
+        .expect("Could not get partitions")
+        .count();
+    assert_eq!(not_meta, allocated.len() + unallocated.len());
+    let all = tsk_vs.get_partition_iter_with_flags(&[])
+        .expect("Could not get partitions")
+        .count();
+    assert_eq!(all as u32, tsk_vs.partition_count());
+}
+
+
+#[test]
+fn test_tsk_vs_part_index() {
+    let tsk_img = TskImg::from_buffer("mbr in memory", mbr_image())
+        .expect("Could not create TskImg from buffer");
+    let tsk_vs = tsk_img.get_vs_from_offset(0)
+        .expect("Could not open TskVs at offset 0");
+    let part_count = tsk_vs.partition_count() as u64;
+
+    for index in 0..part_count {
+        let tsk_vs_part = tsk_vs.get_partition_at_index(index)
+            .expect("Could not get partition");
+        assert!(tsk_vs_part.size() > 0);
+        println!("{:?}", tsk_vs_part);
+    }
+
+    for index in [part_count, part_count + 1, 255, u32::MAX as u64, u64::MAX].iter() {
+        let error = tsk_vs.get_partition_at_index(*index)
+            .expect_err("Got a partition that is out of range");
+        assert!(matches!(error.kind, ErrorType::TskVsPart));
+        assert!(error.message.contains("out of range"), "{}", error.message);
+    }
+
+    // Iterating from a partition never yields more than the partition count
+    let from_last = tsk_vs.get_partition_at_index(part_count - 1)
+        .expect("Could not get last partition")
+        .into_iter()
+        .count();
+    assert_eq!(from_last, 1);
+}
+
+
+#[test]
+fn test_tsk_vs_sample() {
+    let source = PathBuf::from(format!("{}/samples/mbr.raw", env!("CARGO_MANIFEST_DIR")));
+    let tsk_img = TskImg::from_utf8_sing(source)
+        .expect("Could not create TskImg");
+
+    let tsk_vs = tsk_img.get_vs_from_offset(0)
+        .expect("Could not open TskVs at offset 0");
+    assert_eq!(tsk_vs.vs_type(), TskVsType::Dos);
+    assert_eq!(tsk_vs.block_size(), 512);
+    assert!(tsk_vs.partition_count() > 0);
+}
@@ -0,0 +1,225 @@
+use std::io::Cursor;
+use flate2::Crc;
+use tsk::tsk_img::TskImg;
+use tsk::tsk_vs::TskVsType;
+use tsk::tsk_vs_gpt::{GptDetails, gpt_type_name};
+
+
+const BLOCK_SIZE: usize = 512;
+const BLOCKS: u64 = 2048;
+const DISK_GUID: &str = "6E5C7E1D-8A36-4F42-9B1B-0C1D2E3F4A5B";
+const EFI_GUID: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+const LVM_GUID: &str = "E6D6D379-F507-44C2-A23C-238F2A3DF928";
+const EFI_UNIQUE_GUID: &str = "0A1B2C3D-4E5F-6071-8293-A4B5C6D7E8F9";
+const LVM_UNIQUE_GUID: &str = "F9E8D7C6-B5A4-9382-7160-5F4E3D2C1B0A";
+
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+
+/// Encode a GUID string in its on-disk (mixed endian) layout
+fn guid_bytes(guid: &str) -> [u8; 16] {
+    let hex: String = guid.chars().filter(|c| *c != '-').collect();
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    bytes
+}
+
+
+fn header(current: u64, backup: u64, entries_lba: u64, entries_crc: u32) -> Vec<u8> {
+    let mut header = vec![0u8; BLOCK_SIZE];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&current.to_le_bytes());
+    header[32..40].copy_from_slice(&backup.to_le_bytes());
+    header[40..48].copy_from_slice(&34u64.to_le_bytes());
+    header[48..56].copy_from_slice(&(BLOCKS - 34).to_le_bytes());
+    header[56..72].copy_from_slice(&guid_bytes(DISK_GUID));
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+
+fn entry(type_guid: &str, unique_guid: &str, first: u64, last: u64, attributes: u64, name: &str) -> Vec<u8> {
+    let mut entry = vec![0u8; 128];
+    entry[0..16].copy_from_slice(&guid_bytes(type_guid));
+    entry[16..32].copy_from_slice(&guid_bytes(unique_guid));
+    entry[32..40].copy_from_slice(&first.to_le_bytes());
+    entry[40..48].copy_from_slice(&last.to_le_bytes());
+    entry[48..56].copy_from_slice(&attributes.to_le_bytes());
+    for (i, c) in name.encode_utf16().enumerate() {
+        entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+    }
+    entry
+}
+
+
+/// A 1 MiB disk with a protective MBR, an EFI System partition and a Linux
+/// LVM partition, and matching primary and backup tables
+fn gpt_image() -> Vec<u8> {
+    let mut data = vec![0u8; BLOCKS as usize * BLOCK_SIZE];
+
+    // Protective MBR
+    data[446 + 4] = 0xEE;
+    data[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    data[446 + 12..446 + 16].copy_from_slice(&((BLOCKS - 1) as u32).to_le_bytes());
+    data[510] = 0x55;
+    data[511] = 0xAA;
+
+    let mut entries = vec![0u8; 128 * 128];
+    entries[0..128].copy_from_slice(&entry(EFI_GUID, EFI_UNIQUE_GUID, 40, 103, 1, "EFI system partition"));
+    entries[128..256].copy_from_slice(&entry(LVM_GUID, LVM_UNIQUE_GUID, 128, 1919, 1 << 60, "Linux LVM \u{00e9}"));
+    let entries_crc = crc32(&entries);
+
+    let block = |lba: u64| lba as usize * BLOCK_SIZE;
+    data[block(1)..block(2)].copy_from_slice(&header(1, BLOCKS - 1, 2, entries_crc));
+    data[block(2)..block(34)].copy_from_slice(&entries);
+    data[block(BLOCKS - 33)..block(BLOCKS - 1)].copy_from_slice(&entries);
+    data[block(BLOCKS - 1)..].copy_from_slice(&header(BLOCKS - 1, 1, BLOCKS - 33, entries_crc));
+    data
+}
+
+
+#[test]
+fn test_gpt_type_names() {
+    assert_eq!(gpt_type_name(EFI_GUID), Some("EFI System"));
+    assert_eq!(gpt_type_name(&LVM_GUID.to_lowercase()), Some("Linux LVM"));
+    assert_eq!(gpt_type_name("EBD0A0A2-B9E5-4433-87C0-68B6B72699C7"), Some("Microsoft Basic Data"));
+    assert_eq!(gpt_type_name("7C3457EF-0000-11AA-AA11-00306543ECAC"), Some("Apple APFS"));
+    assert_eq!(gpt_type_name("00000000-0000-0000-0000-000000000001"), None);
+}
+
+
+#[test]
+fn test_gpt_details() {
+    let details = GptDetails::from_reader(&mut Cursor::new(gpt_image()), 0, 512)
+        .expect("Error reading GPT");
+    println!("{:#?}", details);
+
+    assert!(details.is_consistent(), "{:?}", details.issues);
+    assert_eq!(details.disk_guid(), DISK_GUID);
+    let primary = details.primary.as_ref().expect("No primary header");
+    let backup = details.backup.as_ref().expect("No backup header");
+    assert!(primary.is_valid());
+    assert!(backup.is_valid());
+    assert_eq!(primary.backup_lba, BLOCKS - 1);
+    assert_eq!(backup.current_lba, BLOCKS - 1);
+
+    assert_eq!(details.entries.len(), 2);
+    let efi = &details.entries[0];
+    assert_eq!(efi.index, 0);
+    assert_eq!(efi.type_guid, EFI_GUID);
+    assert_eq!(efi.type_name(), Some("EFI System"));
+    assert_eq!(efi.unique_guid, EFI_UNIQUE_GUID);
+    assert_eq!(efi.name, "EFI system partition");
+    assert_eq!(efi.block_count(), 64);
+    assert!(efi.is_required());
+    assert!(!efi.is_legacy_bios_bootable());
+
+    let lvm = &details.entries[1];
+    assert_eq!(lvm.index, 1);
+    assert_eq!(lvm.type_name(), Some("Linux LVM"));
+    assert_eq!(lvm.unique_guid, LVM_UNIQUE_GUID);
+    assert_eq!(lvm.name, "Linux LVM \u{00e9}");
+    assert!(!lvm.is_required());
+    assert_eq!(lvm.type_attributes(), 1 << 12);
+}
+
+
+#[test]
+fn test_gpt_details_corrupt_backup() {
+    let mut data = gpt_image();
+    // Change the name of the first entry of the backup table
+    let backup_entries = (BLOCKS as usize - 33) * BLOCK_SIZE;
+    data[backup_entries + 56] = b'X';
+
+    let details = GptDetails::from_reader(&mut Cursor::new(data), 0, 512)
+        .expect("Error reading GPT");
+    assert!(!details.is_consistent());
+    assert!(details.primary.as_ref().unwrap().is_valid());
+    let backup = details.backup.as_ref().unwrap();
+    assert!(backup.header_crc_valid);
+    assert!(!backup.entries_crc_valid);
+    assert!(details.issues.iter().any(|i| i.contains("backup GPT partition entries CRC32")), "{:?}", details.issues);
+    assert!(details.issues.iter().any(|i| i.contains("entries differ")), "{:?}", details.issues);
+    // The entries come from the primary table
+    assert_eq!(details.entries[0].name, "EFI system partition");
+}
+
+
+#[test]
+fn test_gpt_details_missing_primary() {
+    let mut data = gpt_image();
+    for byte in data[BLOCK_SIZE..BLOCK_SIZE * 2].iter_mut() {
+        *byte = 0;
+    }
+
+    let details = GptDetails::from_reader(&mut Cursor::new(data), 0, 512)
+        .expect("Error reading GPT");
+    assert!(details.primary.is_none());
+    assert!(!details.is_consistent());
+    assert_eq!(details.disk_guid(), DISK_GUID);
+    assert_eq!(details.entries.len(), 2);
+}
+
+
+#[test]
+fn test_gpt_details_no_gpt() {
+    let data = vec![0u8; BLOCKS as usize * BLOCK_SIZE];
+    let error = GptDetails::from_reader(&mut Cursor::new(data), 0, 512)
+        .expect_err("Found a GPT on an empty disk");
+    assert!(error.message.contains("No GPT"), "{}", error.message);
+
+    assert!(GptDetails::from_reader(&mut Cursor::new(gpt_image()), 0, 500).is_err());
+}
+
+
+#[test]
+fn test_gpt_details_malformed_entry_array() {
+    let mut data = gpt_image();
+    // An entry count that would need a 512 GiB entry array
+    let block = &mut data[BLOCK_SIZE..BLOCK_SIZE * 2];
+    block[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    let details = GptDetails::from_reader(&mut Cursor::new(data), 0, 512)
+        .expect("Error reading GPT");
+    assert!(details.primary.is_none());
+    assert!(details.issues.iter().any(|i| i.contains("too large")), "{:?}", details.issues);
+    assert_eq!(details.entries.len(), 2);
+}
+
+
+#[test]
+fn test_tsk_vs_gpt_details() {
+    let tsk_img = TskImg::from_buffer("gpt in memory", gpt_image())
+        .expect("Could not create TskImg from buffer");
+    let tsk_vs = tsk_img.get_vs_from_offset(0)
+        .expect("Could not open TskVs at offset 0");
+    assert_eq!(tsk_vs.vs_type(), TskVsType::Gpt);
+
+    let details = tsk_vs.gpt_details(&mut tsk_img.get_handle())
+        .expect("Error reading GPT details");
+    assert!(details.is_consistent(), "{:?}", details.issues);
+    assert_eq!(details.disk_guid(), DISK_GUID);
+
+    let names: Vec<String> = tsk_vs.allocated_partitions()
+        .expect("Could not get allocated partitions")
+        .filter_map(|p| details.entry_for(&p).map(|e| e.name.clone()))
+        .collect();
+    assert_eq!(names, vec!["EFI system partition", "Linux LVM \u{00e9}"]);
+}
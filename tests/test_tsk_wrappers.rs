@@ -1,7 +1,7 @@
 extern crate tsk;
 use std::path::PathBuf;
 use std::io::{Read, Write, Seek, SeekFrom};
-use tsk::tsk_img::TskImg;
+use tsk::tsk_img::{TskImg, TskImgType};
 use tsk::tsk_fs_dir::TskFsDir;
 use tsk::tsk_fs_attr::TskFsAttr;
 use tsk::bindings;
@@ -32,6 +32,28 @@ fn test_tsk_wrappers_dir() {
 }
 
 
+#[test]
+fn test_tsk_img_info() {
+    let source = PathBuf::from(format!("{}/samples/ntfs.raw", env!("CARGO_MANIFEST_DIR")));
+    let source_size = source.metadata().unwrap().len();
+
+    let tsk_img = TskImg::from_utf8_detect(&source)
+        .expect("Could not create TskImg");
+    println!("{:?}", tsk_img);
+
+    assert_eq!(tsk_img.img_type(), TskImgType::Raw);
+    assert_eq!(tsk_img.size() as u64, source_size);
+    assert_eq!(tsk_img.sector_size(), 512);
+    assert_eq!(tsk_img.num_img(), 1);
+
+    let tsk_img = TskImg::from_utf8_with_type(&[&source], TskImgType::Raw, 4096)
+        .expect("Could not create TskImg");
+    assert_eq!(tsk_img.sector_size(), 4096);
+
+    assert!(TskImgType::supported().contains(&TskImgType::Raw));
+}
+
+
 #[test]
 fn test_tsk_iterate_root() {
     let source = PathBuf::from(format!("{}/samples/ntfs.raw", env!("CARGO_MANIFEST_DIR")));